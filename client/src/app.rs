@@ -1,3 +1,4 @@
+use crate::launch::LaunchView;
 use crate::state::State;
 use std::sync::Arc;
 use log::error;
@@ -5,26 +6,35 @@ use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalSize;
 use winit::event::{ElementState, KeyEvent, WindowEvent};
 #[allow(unused_imports)]
-use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{Window, WindowId};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+/// MSAA samples requested for the window (lowered if the GPU can't do it)
+const MSAA_SAMPLES: u32 = 4;
+
 pub struct App {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<State>>,
+    /// Initial view given on the command line or in the environment
+    launch_view: LaunchView,
     state: Option<State>,
 }
 
 impl App {
-    pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
+    pub fn new(
+        #[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>,
+        launch_view: LaunchView,
+    ) -> Self {
         #[cfg(target_arch = "wasm32")]
         let proxy = Some(event_loop.create_proxy());
         Self {
             #[cfg(target_arch = "wasm32")]
             proxy,
+            launch_view,
             state: None,
         }
     }
@@ -53,7 +63,12 @@ impl ApplicationHandler<State> for App {
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            self.state = Some(pollster::block_on(State::new(window.clone())).unwrap());
+            let state = pollster::block_on(State::new(
+                window.clone(),
+                MSAA_SAMPLES,
+                self.launch_view,
+            ));
+            self.state = Some(state.unwrap());
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -61,11 +76,12 @@ impl ApplicationHandler<State> for App {
             // Run the future asynchronously and use the
             // proxy to send the results to the event loop
             if let Some(proxy) = self.proxy.take() {
+                let launch_view = self.launch_view;
                 wasm_bindgen_futures::spawn_local(async move {
                     assert!(
                         proxy
                             .send_event(
-                                State::new(window)
+                                State::new(window, MSAA_SAMPLES, launch_view)
                                     .await
                                     .expect("Unable to create canvas!!!")
                             )
@@ -108,31 +124,45 @@ impl ApplicationHandler<State> for App {
             WindowEvent::Resized(PhysicalSize { width, height }) => {
                 state.resize(width, height);
             }
+            // Moved to a display of another density; not every platform
+            // follows up with a resize
+            WindowEvent::ScaleFactorChanged { .. } => {
+                let PhysicalSize { width, height } = state.window.inner_size();
+                state.resize(width, height);
+            }
             WindowEvent::RedrawRequested => {
                 state.update();
-                match state.render() {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.config.width, state.config.height)
-                    }
-                    Err(e) => {
-                        error!("render: {:?}", e);
-                    }
+                // Lost/outdated surfaces are recovered inside render
+                if let Err(e) = state.render() {
+                    error!("render: {:?}", e);
                 }
             },
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
-                        physical_key: PhysicalKey::Code(key_code),
+                        physical_key: PhysicalKey::Code(KeyCode::Escape),
                         state: ElementState::Pressed,
                         ..
                     },
                 ..
-            } => match key_code {
-                KeyCode::Escape => event_loop.exit(),
-                _ => {}
-            },
+            } => event_loop.exit(),
             _ => {}
         }
     }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(state) = &self.state else {
+            return;
+        };
+
+        // Sleep until the next requested frame instead of redrawing continuously
+        match state.next_frame() {
+            Some(at) if at <= web_time::Instant::now() => {
+                state.window.request_redraw();
+                event_loop.set_control_flow(ControlFlow::Wait);
+            }
+            Some(at) => event_loop.set_control_flow(ControlFlow::WaitUntil(at)),
+            None => event_loop.set_control_flow(ControlFlow::Wait),
+        }
+    }
 }
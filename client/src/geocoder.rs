@@ -0,0 +1,385 @@
+//! Reverse geocoding with Nominatim: the address nearest a point
+//!
+//! Lookups run in the background (on a worker thread natively, with
+//! `fetch` on the web) and are spaced at least `REQUEST_INTERVAL` apart, as
+//! the Nominatim usage policy asks. Results are cached on a grid of about
+//! 100 m, so looking up nearby points again doesn't reach the service.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use serde_json::Value;
+use web_time::Instant;
+
+/// Public Nominatim instance
+pub const DEFAULT_ENDPOINT: &str = "https://nominatim.openstreetmap.org";
+
+/// Shortest time between two requests
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time after which a failed lookup is tried again
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Cache grid cells per degree (0.001°, ~100 m)
+const CACHE_CELLS_PER_DEGREE: f64 = 1000.0;
+
+/// Most cached lookups; the oldest are evicted first
+const MAX_CACHED: usize = 256;
+
+/// Address lookup state of a point
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    /// Waiting for the service
+    Pending,
+    /// Short label, e.g. "12 Sejong-daero, Seoul, South Korea"
+    Found(String),
+    /// Nothing to name there (e.g. open sea)
+    NotFound,
+    Failed(String),
+}
+
+/// Cache grid cell of a point
+type CacheKey = (i64, i64);
+
+fn cache_key(lon: f64, lat: f64) -> CacheKey {
+    (
+        (lon * CACHE_CELLS_PER_DEGREE).round() as i64,
+        (lat * CACHE_CELLS_PER_DEGREE).round() as i64,
+    )
+}
+
+/// Lookup to run: its cache key and URL
+type LookupRequest = (CacheKey, String);
+
+/// Finished lookup
+type LookupResult = (CacheKey, Address);
+
+#[cfg(not(target_arch = "wasm32"))]
+type ResultReceiver = std::sync::mpsc::Receiver<LookupResult>;
+#[cfg(target_arch = "wasm32")]
+type ResultReceiver = std::sync::Arc<std::sync::Mutex<Vec<LookupResult>>>;
+
+/// Reverse geocoder for a Nominatim server
+pub struct Geocoder {
+    endpoint: String,
+    result_rx: ResultReceiver,
+    #[cfg(not(target_arch = "wasm32"))]
+    request_tx: std::sync::mpsc::Sender<LookupRequest>,
+    /// Finished lookups with when they finished
+    cache: HashMap<CacheKey, (Address, Instant)>,
+    /// Cache keys, oldest first
+    cache_order: VecDeque<CacheKey>,
+    /// Point to look up once a request may be sent, replaced by newer ones
+    queued: Option<(CacheKey, f64, f64)>,
+    in_flight: Option<CacheKey>,
+    last_request: Option<Instant>,
+}
+
+impl Geocoder {
+    /// Create a geocoder for the public Nominatim instance
+    ///
+    /// Nominatim requires an identifying User-Agent (browsers send their own).
+    pub fn new(user_agent: &str) -> Self {
+        Self::with_endpoint(DEFAULT_ENDPOINT, user_agent)
+    }
+
+    /// Create a geocoder for another Nominatim server
+    pub fn with_endpoint(endpoint: &str, user_agent: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (request_tx, request_rx) = std::sync::mpsc::channel::<LookupRequest>();
+            let (result_tx, result_rx) = std::sync::mpsc::channel::<LookupResult>();
+            let user_agent = user_agent.to_string();
+            std::thread::spawn(move || {
+                Self::worker_thread(request_rx, result_tx, user_agent);
+            });
+
+            Self {
+                endpoint,
+                result_rx,
+                request_tx,
+                cache: HashMap::new(),
+                cache_order: VecDeque::new(),
+                queued: None,
+                in_flight: None,
+                last_request: None,
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = user_agent;
+            Self {
+                endpoint,
+                result_rx: Default::default(),
+                cache: HashMap::new(),
+                cache_order: VecDeque::new(),
+                queued: None,
+                in_flight: None,
+                last_request: None,
+            }
+        }
+    }
+
+    /// Address nearest a point, looking it up if it isn't cached
+    ///
+    /// Returns `Address::Pending` until the lookup finishes; keep calling
+    /// `poll` meanwhile. Only the last point asked for is queued, so moving
+    /// a cursor around doesn't pile up requests.
+    pub fn reverse(&mut self, lon: f64, lat: f64, now: Instant) -> Address {
+        self.collect_results(now);
+        let key = cache_key(lon, lat);
+        match self.cache.get(&key) {
+            Some((Address::Failed(_), at))
+                if now.saturating_duration_since(*at) >= RETRY_INTERVAL => {}
+            Some((address, _)) => return address.clone(),
+            None => {}
+        }
+        if self.in_flight != Some(key) {
+            self.queued = Some((key, lon, lat));
+        }
+        self.send_queued(now);
+        Address::Pending
+    }
+
+    /// Collect finished lookups and start the queued one if the rate
+    /// limit allows
+    pub fn poll(&mut self, now: Instant) {
+        self.collect_results(now);
+        self.send_queued(now);
+    }
+
+    fn collect_results(&mut self, now: Instant) {
+        for (key, address) in self.take_results() {
+            if self.in_flight == Some(key) {
+                self.in_flight = None;
+            }
+            if self.queued.is_some_and(|(queued, _, _)| queued == key) {
+                self.queued = None;
+            }
+            self.insert(key, address, now);
+        }
+    }
+
+    fn send_queued(&mut self, now: Instant) {
+        if self.in_flight.is_some() {
+            return;
+        }
+        let ready = self
+            .last_request
+            .is_none_or(|last| now.saturating_duration_since(last) >= REQUEST_INTERVAL);
+        if !ready {
+            return;
+        }
+        let Some((key, lon, lat)) = self.queued.take() else {
+            return;
+        };
+        let url = format!(
+            "{}/reverse?format=jsonv2&lat={:.5}&lon={:.5}&zoom=18&addressdetails=1",
+            self.endpoint, lat, lon
+        );
+        self.in_flight = Some(key);
+        self.last_request = Some(now);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.request_tx.send((key, url)).is_err() {
+            self.in_flight = None;
+            self.insert(key, Address::Failed("Geocoder stopped".into()), now);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        self.spawn_wasm_fetch((key, url));
+    }
+
+    /// Check if a lookup is running or waiting for the rate limit
+    pub fn is_busy(&self) -> bool {
+        self.in_flight.is_some() || self.queued.is_some()
+    }
+
+    fn insert(&mut self, key: CacheKey, address: Address, now: Instant) {
+        if self.cache.insert(key, (address, now)).is_none() {
+            self.cache_order.push_back(key);
+        }
+        while self.cache_order.len() > MAX_CACHED {
+            if let Some(oldest) = self.cache_order.pop_front() {
+                self.cache.remove(&oldest);
+            }
+        }
+    }
+
+    fn take_results(&mut self) -> Vec<LookupResult> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.result_rx.try_iter().collect()
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            std::mem::take(&mut *self.result_rx.lock().unwrap())
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn worker_thread(
+        request_rx: std::sync::mpsc::Receiver<LookupRequest>,
+        result_tx: std::sync::mpsc::Sender<LookupResult>,
+        user_agent: String,
+    ) {
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(&user_agent)
+            .build()
+            .expect("Failed to create HTTP client");
+
+        while let Ok((key, url)) = request_rx.recv() {
+            let address = match client.get(&url).send() {
+                Ok(response) if response.status().is_success() => match response.text() {
+                    Ok(text) => parse_reverse(&text),
+                    Err(e) => Address::Failed(e.to_string()),
+                },
+                Ok(response) => Address::Failed(format!("HTTP {}", response.status())),
+                Err(e) => Address::Failed(e.to_string()),
+            };
+            if result_tx.send((key, address)).is_err() {
+                break; // Geocoder dropped
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spawn_wasm_fetch(&self, (key, url): LookupRequest) {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen_futures::JsFuture;
+        use web_sys::Response;
+
+        let results = self.result_rx.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let text = async {
+                let window = web_sys::window().ok_or("No window object")?;
+                let response: Response = JsFuture::from(window.fetch_with_str(&url))
+                    .await
+                    .map_err(|e| format!("Fetch failed: {:?}", e))?
+                    .dyn_into()
+                    .map_err(|_| "Response is not a Response object")?;
+                if !response.ok() {
+                    return Err(format!("HTTP {}", response.status()));
+                }
+                let text = response
+                    .text()
+                    .map_err(|e| format!("Failed to read response: {:?}", e))?;
+                JsFuture::from(text)
+                    .await
+                    .map_err(|e| format!("Failed to read response: {:?}", e))?
+                    .as_string()
+                    .ok_or_else(|| "Response is not text".to_string())
+            }
+            .await;
+
+            let address = match text {
+                Ok(text) => parse_reverse(&text),
+                Err(e) => Address::Failed(e),
+            };
+            if let Ok(mut results) = results.lock() {
+                results.push((key, address));
+            }
+        });
+    }
+}
+
+/// Read a Nominatim `reverse` response (`format=jsonv2`)
+fn parse_reverse(text: &str) -> Address {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(e) => return Address::Failed(format!("Invalid response: {}", e)),
+    };
+    // Points without an address answer {"error": "Unable to geocode"}
+    if value.get("error").is_some() {
+        return Address::NotFound;
+    }
+
+    let field = |key: &str| {
+        value
+            .get("address")
+            .and_then(|address| address.get(key))
+            .and_then(Value::as_str)
+    };
+    let street = match (field("road"), field("house_number")) {
+        (Some(road), Some(number)) => Some(format!("{} {}", number, road)),
+        (road, _) => road.map(str::to_string),
+    };
+    let place = ["city", "town", "village", "hamlet", "suburb"]
+        .into_iter()
+        .find_map(field)
+        .map(str::to_string);
+    let country = field("country").map(str::to_string);
+
+    let parts: Vec<String> = [street, place, country].into_iter().flatten().collect();
+    if !parts.is_empty() {
+        return Address::Found(parts.join(", "));
+    }
+    match value.get("display_name").and_then(Value::as_str) {
+        Some(name) => Address::Found(name.to_string()),
+        None => Address::NotFound,
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_parse_reverse() {
+        let response = r#"{"display_name": "12, Sejong-daero, Jung-gu, Seoul, 04524, South Korea",
+            "address": {"house_number": "12", "road": "Sejong-daero", "city": "Seoul",
+                        "postcode": "04524", "country": "South Korea"}}"#;
+        assert_eq!(
+            parse_reverse(response),
+            Address::Found("12 Sejong-daero, Seoul, South Korea".into())
+        );
+        assert_eq!(
+            parse_reverse(r#"{"display_name": "Pacific Ocean", "address": {}}"#),
+            Address::Found("Pacific Ocean".into())
+        );
+        assert_eq!(
+            parse_reverse(r#"{"error": "Unable to geocode"}"#),
+            Address::NotFound
+        );
+        assert!(matches!(parse_reverse("<html>"), Address::Failed(_)));
+    }
+
+    #[test]
+    fn test_nearby_lookups_share_one_request() {
+        let (request_tx, requests) = mpsc::channel();
+        let (results, result_rx) = mpsc::channel();
+        let mut geocoder = Geocoder {
+            endpoint: "https://nominatim.example".into(),
+            result_rx,
+            request_tx,
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+            queued: None,
+            in_flight: None,
+            last_request: None,
+        };
+
+        let now = Instant::now();
+        assert_eq!(geocoder.reverse(126.9781, 37.5662, now), Address::Pending);
+        // Only the last point waiting for the running lookup is kept
+        geocoder.reverse(2.35, 48.85, now);
+        geocoder.reverse(13.4, 52.52, now);
+        let sent: Vec<LookupRequest> = requests.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].1.starts_with("https://nominatim.example/reverse?"));
+
+        let found = Address::Found("Seoul".into());
+        results.send((sent[0].0, found.clone())).unwrap();
+        let later = now + Duration::from_millis(100);
+        assert_eq!(geocoder.reverse(126.9779, 37.5658, later), found);
+        // The queued point waits for the rate limit
+        assert_eq!(requests.try_iter().count(), 0);
+        geocoder.poll(now + REQUEST_INTERVAL);
+        assert_eq!(requests.try_iter().count(), 1);
+    }
+}
@@ -0,0 +1,227 @@
+//! Initial view chosen on the command line or in the environment
+//!
+//! `--lon`, `--lat` and `--zoom` (as `--lon 2.35` or `--lon=2.35`) take
+//! precedence over the `CPLACE_LON`, `CPLACE_LAT` and `CPLACE_ZOOM`
+//! environment variables; what neither sets keeps the map's default.
+
+use std::fmt;
+
+use crate::map::MapSystemConfig;
+use crate::map::camera::MAX_ZOOM;
+
+const USAGE: &str = "usage: client [--lon <degrees>] [--lat <degrees>] [--zoom <level>]";
+
+/// A launch setting: its argument name, environment variable and valid range
+struct Setting {
+    name: &'static str,
+    env: &'static str,
+    min: f64,
+    max: f64,
+}
+
+const LON: Setting = Setting {
+    name: "lon",
+    env: "CPLACE_LON",
+    min: -180.0,
+    max: 180.0,
+};
+const LAT: Setting = Setting {
+    name: "lat",
+    env: "CPLACE_LAT",
+    min: -90.0,
+    max: 90.0,
+};
+const ZOOM: Setting = Setting {
+    name: "zoom",
+    env: "CPLACE_ZOOM",
+    min: 0.0,
+    max: MAX_ZOOM as f64,
+};
+
+/// Invalid launch arguments or environment variables
+#[derive(Debug, PartialEq)]
+pub enum LaunchError {
+    UnknownArgument(String),
+    MissingValue(&'static str),
+    NotANumber {
+        name: &'static str,
+        value: String,
+    },
+    OutOfRange {
+        name: &'static str,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::UnknownArgument(arg) => {
+                write!(f, "unknown argument {:?}\n{}", arg, USAGE)
+            }
+            LaunchError::MissingValue(name) => write!(f, "--{} needs a value\n{}", name, USAGE),
+            LaunchError::NotANumber { name, value } => {
+                write!(f, "{} must be a number, got {:?}", name, value)
+            }
+            LaunchError::OutOfRange {
+                name,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} must be between {} and {}, got {}",
+                name, min, max, value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+/// Initial camera settings given at launch, each None if not given
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LaunchView {
+    pub lon: Option<f64>,
+    pub lat: Option<f64>,
+    pub zoom: Option<f64>,
+}
+
+impl LaunchView {
+    /// Read the process arguments and environment
+    pub fn from_env() -> Result<Self, LaunchError> {
+        Self::parse(std::env::args().skip(1), |name| std::env::var(name).ok())
+    }
+
+    /// Read `args` (without the program name), then `env` for what they
+    /// don't set
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, LaunchError> {
+        let mut view = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let (setting, slot) = match name {
+                "--lon" => (&LON, &mut view.lon),
+                "--lat" => (&LAT, &mut view.lat),
+                "--zoom" => (&ZOOM, &mut view.zoom),
+                _ => return Err(LaunchError::UnknownArgument(arg)),
+            };
+            let value = inline
+                .or_else(|| args.next())
+                .ok_or(LaunchError::MissingValue(setting.name))?;
+            *slot = Some(setting.parse(&value)?);
+        }
+
+        for (setting, slot) in [
+            (&LON, &mut view.lon),
+            (&LAT, &mut view.lat),
+            (&ZOOM, &mut view.zoom),
+        ] {
+            if slot.is_none()
+                && let Some(value) = env(setting.env)
+            {
+                *slot = Some(setting.parse(&value)?);
+            }
+        }
+        Ok(view)
+    }
+
+    /// `config` with the settings given at launch
+    pub fn apply(&self, config: MapSystemConfig) -> MapSystemConfig {
+        let (lon, lat) = config.center;
+        let zoom = config.zoom;
+        config
+            .center(self.lon.unwrap_or(lon), self.lat.unwrap_or(lat))
+            .zoom(self.zoom.unwrap_or(zoom))
+    }
+}
+
+impl Setting {
+    fn parse(&self, value: &str) -> Result<f64, LaunchError> {
+        let number: f64 = value.trim().parse().map_err(|_| LaunchError::NotANumber {
+            name: self.name,
+            value: value.to_string(),
+        })?;
+        // Also rejects NaN
+        if !(self.min..=self.max).contains(&number) {
+            return Err(LaunchError::OutOfRange {
+                name: self.name,
+                value: number,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        Ok(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<LaunchView, LaunchError> {
+        let env: Vec<(String, String)> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        LaunchView::parse(args.iter().map(|arg| arg.to_string()), |name| {
+            env.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        })
+    }
+
+    #[test]
+    fn test_arguments_override_environment() {
+        let view = parse(
+            &["--lon", "2.3522", "--zoom=14"],
+            &[("CPLACE_LON", "10"), ("CPLACE_LAT", "48.8566")],
+        )
+        .unwrap();
+        assert_eq!(
+            view,
+            LaunchView {
+                lon: Some(2.3522),
+                lat: Some(48.8566),
+                zoom: Some(14.0),
+            }
+        );
+
+        // Unset settings keep the defaults
+        let config = parse(&["--zoom", "3"], &[])
+            .unwrap()
+            .apply(MapSystemConfig::default());
+        assert_eq!(config.center, MapSystemConfig::default().center);
+        assert_eq!(config.zoom, 3.0);
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        let error = parse(&["--lat", "91"], &[]).unwrap_err();
+        assert_eq!(error.to_string(), "lat must be between -90 and 90, got 91");
+        assert!(matches!(
+            parse(&[], &[("CPLACE_ZOOM", "NaN")]),
+            Err(LaunchError::OutOfRange { name: "zoom", .. })
+        ));
+        assert!(matches!(
+            parse(&["--lon", "east"], &[]),
+            Err(LaunchError::NotANumber { name: "lon", .. })
+        ));
+        assert_eq!(
+            parse(&["--zoom"], &[]),
+            Err(LaunchError::MissingValue("zoom"))
+        );
+        assert!(matches!(
+            parse(&["--center"], &[]),
+            Err(LaunchError::UnknownArgument(_))
+        ));
+    }
+}
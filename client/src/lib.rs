@@ -1,12 +1,16 @@
-use log::info;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use winit::event_loop::EventLoop;
 use crate::app::App;
+use crate::launch::LaunchView;
 
 mod state;
 mod app;
-mod map;
+pub mod geocoder;
+pub mod launch;
+pub mod map;
+pub mod net;
+pub mod projection;
 
 pub fn run() -> anyhow::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
@@ -16,13 +20,15 @@ pub fn run() -> anyhow::Result<()> {
     #[cfg(target_arch = "wasm32")]
     {
         console_log::init_with_level(log::Level::Info).unwrap_throw();
-        info!("Starting...");
+        log::info!("Starting...");
     }
 
+    let launch_view = LaunchView::from_env()?;
     let event_loop = EventLoop::with_user_event().build()?;
     let mut app = App::new(
         #[cfg(target_arch = "wasm32")]
-        &event_loop
+        &event_loop,
+        launch_view,
     );
     event_loop.run_app(&mut app)?;
 
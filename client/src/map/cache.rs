@@ -1,52 +1,152 @@
-//! LRU tile cache for GPU textures
+//! Tile cache for GPU textures, evicting least recently or frequently used tiles
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use web_time::Instant;
 
 use super::tile::TileId;
 
+/// Default maximum number of cached tiles
+pub const DEFAULT_MAX_TILES: usize = 256;
+
+/// Default GPU memory budget for cached tiles (64MB)
+pub const DEFAULT_MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Default number of downloaded tile files kept for re-upload
+pub const DEFAULT_MAX_BYTE_TILES: usize = 2048;
+
+/// Default budget for downloaded tile files (64MB, roughly 2000 PNG tiles)
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Entry that can be stored in a `TileCache`
+pub trait CacheEntry {
+    /// Memory accounted against the cache budget, in bytes
+    fn memory_size(&self) -> usize;
+}
+
 /// Cached tile with GPU resources
 pub struct CachedTile {
     pub texture: wgpu::Texture,
     pub texture_view: wgpu::TextureView,
     pub bind_group: wgpu::BindGroup,
+    /// Image size in pixels (width, height)
+    pub size: (u32, u32),
     pub memory_size: usize,
     pub created_at: Instant,
 }
 
-/// LRU cache for map tiles
-pub struct TileCache {
-    tiles: HashMap<TileId, Arc<CachedTile>>,
+impl CacheEntry for CachedTile {
+    fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+}
+
+/// Downloaded (still compressed) tile file
+///
+/// Kept in a second, larger cache so tiles evicted from the GPU can be
+/// decoded again without another download.
+pub struct TileBytes(pub Vec<u8>);
+
+impl CacheEntry for TileBytes {
+    fn memory_size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Which tile a full cache evicts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// The least recently used tile
+    #[default]
+    Lru,
+    /// The least frequently used tile, the least recently used among equals
+    ///
+    /// Keeps tiles shown over and over (e.g. on a kiosk) over tiles seen
+    /// once on the way. Counts never decay: a tile that was popular stays
+    /// until the cache is cleared.
+    Lfu,
+}
+
+/// Size-bounded cache for map tiles
+///
+/// Generic over the entry type so the eviction logic can be exercised
+/// without GPU resources; the map uses `CachedTile`.
+pub struct TileCache<T = CachedTile> {
+    tiles: HashMap<TileId, Arc<T>>,
     access_order: Vec<TileId>,
+    /// Inserts and gets of each cached tile, for LFU eviction
+    uses: HashMap<TileId, u64>,
+    /// Tiles never evicted, whether cached yet or not
+    pinned: HashSet<TileId>,
+    policy: EvictionPolicy,
     max_tiles: usize,
     current_memory: usize,
     max_memory: usize,
 }
 
-impl TileCache {
-    /// Create a new tile cache
+impl<T: CacheEntry> TileCache<T> {
+    /// Create a new LRU tile cache
     /// - max_tiles: Maximum number of tiles to cache (e.g., 256)
     /// - max_memory: Maximum GPU memory in bytes (e.g., 64MB)
     pub fn new(max_tiles: usize, max_memory: usize) -> Self {
+        Self::with_policy(max_tiles, max_memory, EvictionPolicy::default())
+    }
+
+    /// Create a new tile cache evicting by `policy`
+    pub fn with_policy(max_tiles: usize, max_memory: usize, policy: EvictionPolicy) -> Self {
         Self {
             tiles: HashMap::with_capacity(max_tiles),
             access_order: Vec::with_capacity(max_tiles),
+            uses: HashMap::with_capacity(max_tiles),
+            pinned: HashSet::new(),
+            policy,
             max_tiles,
             current_memory: 0,
             max_memory,
         }
     }
 
+    pub fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    /// Keep a tile from being evicted, now or once it is inserted
+    ///
+    /// At most half of `max_tiles` can be pinned, leaving room for the
+    /// tiles of the current view; returns false past that. Pins outlast
+    /// `clear` and `remove`.
+    pub fn pin(&mut self, tile_id: TileId) -> bool {
+        if !self.pinned.contains(&tile_id) && self.pinned.len() >= self.max_tiles / 2 {
+            log::warn!("Not pinning tile {:?}: too many pinned tiles", tile_id);
+            return false;
+        }
+        self.pinned.insert(tile_id);
+        true
+    }
+
+    /// Let a pinned tile be evicted again
+    pub fn unpin(&mut self, tile_id: &TileId) {
+        self.pinned.remove(tile_id);
+    }
+
+    pub fn unpin_all(&mut self) {
+        self.pinned.clear();
+    }
+
+    pub fn is_pinned(&self, tile_id: &TileId) -> bool {
+        self.pinned.contains(tile_id)
+    }
+
     /// Check if tile exists in cache
     pub fn contains(&self, tile_id: &TileId) -> bool {
         self.tiles.contains_key(tile_id)
     }
 
     /// Get a tile from cache, updating access order
-    pub fn get(&mut self, tile_id: &TileId) -> Option<Arc<CachedTile>> {
+    pub fn get(&mut self, tile_id: &TileId) -> Option<Arc<T>> {
         if self.tiles.contains_key(tile_id) {
             self.update_access_order(*tile_id);
+            *self.uses.entry(*tile_id).or_default() += 1;
             self.tiles.get(tile_id).cloned()
         } else {
             None
@@ -54,30 +154,45 @@ impl TileCache {
     }
 
     /// Get a tile without updating access order (for read-only checks)
-    pub fn peek(&self, tile_id: &TileId) -> Option<Arc<CachedTile>> {
+    pub fn peek(&self, tile_id: &TileId) -> Option<Arc<T>> {
         self.tiles.get(tile_id).cloned()
     }
 
     /// Insert a new tile into cache, evicting old tiles if necessary
-    pub fn insert(&mut self, tile_id: TileId, tile: CachedTile) {
-        let memory_size = tile.memory_size;
+    pub fn insert(&mut self, tile_id: TileId, tile: T) {
+        let memory_size = tile.memory_size();
+
+        // A tile over the whole budget can't fit however many others go, so
+        // only the count limit evicts for it; the next insert reclaims memory
+        let needed = if memory_size > self.max_memory {
+            log::warn!(
+                "Tile {:?} needs {} bytes, over the {} byte cache budget",
+                tile_id,
+                memory_size,
+                self.max_memory
+            );
+            0
+        } else {
+            memory_size
+        };
+
+        // Remove if already exists (update case), before eviction counts it
+        if let Some(old) = self.tiles.remove(&tile_id) {
+            self.current_memory -= old.memory_size();
+            self.access_order.retain(|id| id != &tile_id);
+        }
 
         // Evict tiles if we're over capacity
-        while self.should_evict(memory_size) {
+        while self.should_evict(needed) {
             if !self.evict_oldest() {
                 break;
             }
         }
 
-        // Remove if already exists (update case)
-        if let Some(old) = self.tiles.remove(&tile_id) {
-            self.current_memory -= old.memory_size;
-            self.access_order.retain(|id| id != &tile_id);
-        }
-
         self.current_memory += memory_size;
         self.tiles.insert(tile_id, Arc::new(tile));
         self.access_order.push(tile_id);
+        *self.uses.entry(tile_id).or_default() += 1;
     }
 
     /// Check if we need to evict tiles
@@ -87,15 +202,29 @@ impl TileCache {
                 || self.current_memory + new_tile_memory > self.max_memory)
     }
 
-    /// Evict the oldest (least recently used) tile
+    /// Evict the unpinned tile the policy picks: the least recently or the
+    /// least frequently used one
     fn evict_oldest(&mut self) -> bool {
-        if let Some(oldest_id) = self.access_order.first().cloned() {
-            if let Some(tile) = self.tiles.remove(&oldest_id) {
-                self.current_memory -= tile.memory_size;
-                self.access_order.remove(0);
-                log::debug!("Evicted tile {:?}", oldest_id);
-                return true;
-            }
+        // The access order runs from least to most recent, and `min_by_key`
+        // keeps the first of equal counts
+        let mut candidates = self
+            .access_order
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| !self.pinned.contains(id));
+        let position = match self.policy {
+            EvictionPolicy::Lru => candidates.next(),
+            EvictionPolicy::Lfu => candidates.min_by_key(|(_, id)| self.uses.get(id)),
+        }
+        .map(|(position, _)| position);
+        if let Some(position) = position
+            && let Some(tile) = self.tiles.remove(&self.access_order[position])
+        {
+            let oldest_id = self.access_order.remove(position);
+            self.current_memory -= tile.memory_size();
+            self.uses.remove(&oldest_id);
+            log::debug!("Evicted tile {:?}", oldest_id);
+            return true;
         }
         false
     }
@@ -109,10 +238,11 @@ impl TileCache {
     }
 
     /// Remove a specific tile from cache
-    pub fn remove(&mut self, tile_id: &TileId) -> Option<Arc<CachedTile>> {
+    pub fn remove(&mut self, tile_id: &TileId) -> Option<Arc<T>> {
         if let Some(tile) = self.tiles.remove(tile_id) {
-            self.current_memory -= tile.memory_size;
+            self.current_memory -= tile.memory_size();
             self.access_order.retain(|id| id != tile_id);
+            self.uses.remove(tile_id);
             Some(tile)
         } else {
             None
@@ -123,6 +253,7 @@ impl TileCache {
     pub fn clear(&mut self) {
         self.tiles.clear();
         self.access_order.clear();
+        self.uses.clear();
         self.current_memory = 0;
     }
 
@@ -179,9 +310,159 @@ impl CacheStats {
     }
 }
 
-impl Default for TileCache {
+impl<T: CacheEntry> Default for TileCache<T> {
     fn default() -> Self {
-        // Default: 256 tiles, 64MB max
-        Self::new(256, 64 * 1024 * 1024)
+        Self::new(DEFAULT_MAX_TILES, DEFAULT_MAX_MEMORY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GPU-free cache entry for testing eviction logic
+    struct TestTile(usize);
+
+    impl CacheEntry for TestTile {
+        fn memory_size(&self) -> usize {
+            self.0
+        }
+    }
+
+    fn id(x: u32) -> TileId {
+        TileId::new(x, 0, 10)
+    }
+
+    #[test]
+    fn test_lru_eviction_by_count() {
+        let mut cache = TileCache::new(3, usize::MAX);
+        cache.insert(id(0), TestTile(1));
+        cache.insert(id(1), TestTile(1));
+        cache.insert(id(2), TestTile(1));
+
+        // Touch tile 0 so tile 1 becomes the least recently used
+        assert!(cache.get(&id(0)).is_some());
+        cache.insert(id(3), TestTile(1));
+
+        assert!(cache.contains(&id(0)));
+        assert!(!cache.contains(&id(1)));
+        assert!(cache.contains(&id(2)));
+        assert!(cache.contains(&id(3)));
+    }
+
+    #[test]
+    fn test_reinsert_evicts_nothing() {
+        let mut cache = TileCache::new(3, 9);
+        for x in 0..3 {
+            cache.insert(id(x), TestTile(3));
+        }
+
+        // Full on both count and memory, but an update replaces in place
+        cache.insert(id(0), TestTile(3));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.stats().memory_used, 9);
+
+        // The updated tile became the most recently used
+        cache.insert(id(3), TestTile(3));
+        assert!(cache.contains(&id(0)));
+        assert!(!cache.contains(&id(1)));
+        assert!(cache.contains(&id(2)));
+    }
+
+    #[test]
+    fn test_oversize_tile_keeps_others() {
+        let mut cache = TileCache::new(10, 10);
+        cache.insert(id(0), TestTile(3));
+        cache.insert(id(1), TestTile(3));
+
+        // Evicting everything still wouldn't make room, so nothing goes
+        cache.insert(id(2), TestTile(20));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.stats().memory_used, 26);
+
+        // The next insert brings memory back under budget
+        cache.insert(id(3), TestTile(3));
+        assert!(cache.stats().memory_used <= 10);
+        assert!(cache.contains(&id(3)));
+    }
+
+    #[test]
+    fn test_lfu_keeps_frequently_used_tiles() {
+        for (policy, evicted) in [(EvictionPolicy::Lru, 0), (EvictionPolicy::Lfu, 2)] {
+            let mut cache = TileCache::with_policy(3, usize::MAX, policy);
+            cache.insert(id(0), TestTile(1));
+            for _ in 0..5 {
+                assert!(cache.get(&id(0)).is_some());
+            }
+            cache.insert(id(1), TestTile(1));
+            cache.insert(id(2), TestTile(1));
+            assert!(cache.get(&id(1)).is_some());
+
+            // Tile 0 is the least recently used, tile 2 the least often
+            cache.insert(id(3), TestTile(1));
+            assert!(!cache.contains(&id(evicted)), "{:?}", policy);
+            assert_eq!(cache.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_lfu_keeps_tiles_drawn_often() {
+        // Each map update gets every drawn tile once
+        let mut cache = TileCache::with_policy(2, usize::MAX, EvictionPolicy::Lfu);
+        cache.insert(id(0), TestTile(1));
+        for _ in 0..10 {
+            assert!(cache.get(&id(0)).is_some());
+        }
+        cache.insert(id(1), TestTile(1));
+        assert!(cache.get(&id(1)).is_some());
+
+        // The newer tile drawn once goes first, not the oldest insert
+        cache.insert(id(2), TestTile(1));
+        assert!(cache.contains(&id(0)));
+        assert!(!cache.contains(&id(1)));
+    }
+
+    #[test]
+    fn test_pinned_tiles_are_not_evicted() {
+        let mut cache = TileCache::new(4, usize::MAX);
+        assert!(cache.pin(id(0)));
+        assert!(cache.pin(id(1)));
+        assert!(!cache.pin(id(2)), "at most half the cache can be pinned");
+
+        for x in 0..6 {
+            cache.insert(id(x), TestTile(1));
+        }
+        assert!(cache.contains(&id(0)) && cache.contains(&id(1)));
+        assert!(!cache.contains(&id(2)) && !cache.contains(&id(3)));
+
+        cache.unpin(&id(0));
+        cache.insert(id(6), TestTile(1));
+        assert!(!cache.contains(&id(0)));
+        assert!(cache.contains(&id(1)));
+    }
+
+    #[test]
+    fn test_eviction_by_memory() {
+        let mut cache = TileCache::new(100, 10);
+        cache.insert(id(0), TestTile(4));
+        cache.insert(id(1), TestTile(4));
+        cache.insert(id(2), TestTile(4));
+
+        let stats = cache.stats();
+        assert_eq!(stats.tile_count, 2);
+        assert_eq!(stats.memory_used, 8);
+        assert!(!cache.contains(&id(0)));
+    }
+
+    #[test]
+    fn test_byte_cache_accounts_file_size() {
+        let mut cache = TileCache::new(100, 1000);
+        cache.insert(id(0), TileBytes(vec![0; 600]));
+        cache.insert(id(1), TileBytes(vec![0; 300]));
+        assert_eq!(cache.stats().memory_used, 900);
+
+        cache.insert(id(2), TileBytes(vec![0; 200]));
+        assert!(!cache.contains(&id(0)));
+        assert_eq!(cache.stats().memory_used, 500);
     }
 }
@@ -1,13 +1,39 @@
-//! Map camera for viewport management, panning, and zooming
+//! Map camera for viewport management, panning, zooming, and rotation
+
+use std::collections::HashSet;
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
 
 use super::tile::{
-    clamp_latitude, is_valid_tile_y, lon_lat_to_tile_f64, normalize_longitude, wrap_tile_x, TileId,
+    TileId, clamp_latitude, is_valid_tile_y, lon_lat_to_tile_f64, normalize_longitude,
+    tile_to_lon_lat_f64, wrap_tile_x,
 };
 
-/// Tile size in pixels (standard OSM tile size)
+/// Default tile size in pixels (standard OSM raster tiles)
 pub const TILE_SIZE: f64 = 256.0;
 
+/// Default number of tile rings preloaded around the viewport
+pub const DEFAULT_PREFETCH_BUFFER: u32 = 1;
+
+/// Maximum zoom level unless the tile source sets another
+pub const MAX_ZOOM: u8 = 19;
+
+/// Fraction of a zoom level after which the next level starts fading in
+const BLEND_START: f64 = 0.5;
+
+/// Where the camera looks, as plain data for saving, sharing and restoring
+///
+/// The viewport size is left out since it follows the window.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    /// Center position (longitude, latitude)
+    pub center: (f64, f64),
+    pub zoom: f64,
+}
+
 /// Map camera state
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MapCamera {
     /// Center position (longitude, latitude)
     pub center: (f64, f64),
@@ -18,22 +44,130 @@ pub struct MapCamera {
     /// Viewport size in pixels
     pub viewport_width: u32,
     pub viewport_height: u32,
+
+    /// Tile rings preloaded around the viewport by `visible_tiles`
+    pub prefetch_buffer: u32,
+
+    /// Map rotation in radians, clockwise on screen (0 = north up)
+    pub rotation: f32,
+
+    /// Zoom limits, normally the tile source's levels
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+
+    /// Tile image size in pixels (256, or 512 for many vector-rendered sources)
+    pub tile_size: f64,
 }
 
 impl MapCamera {
+    /// Create a camera looking at (`lon`, `lat`)
+    ///
+    /// A non-finite center or zoom is replaced by (0, 0) or level 0 with a
+    /// warning.
     pub fn new(lon: f64, lat: f64, zoom: f64, width: u32, height: u32) -> Self {
+        let (lon, lat) = if lon.is_finite() && lat.is_finite() {
+            (lon, lat)
+        } else {
+            log::warn!("Non-finite camera center ({}, {}), using (0, 0)", lon, lat);
+            (0.0, 0.0)
+        };
+        let zoom = if zoom.is_finite() {
+            zoom
+        } else {
+            log::warn!("Non-finite camera zoom {}, using 0", zoom);
+            0.0
+        };
         Self {
             center: (normalize_longitude(lon), clamp_latitude(lat)),
-            zoom: zoom.clamp(0.0, 19.0),
-            viewport_width: width,
-            viewport_height: height,
+            zoom: zoom.clamp(0.0, MAX_ZOOM as f64),
+            viewport_width: width.max(1),
+            viewport_height: height.max(1),
+            prefetch_buffer: DEFAULT_PREFETCH_BUFFER,
+            rotation: 0.0,
+            min_zoom: 0,
+            max_zoom: MAX_ZOOM,
+            tile_size: TILE_SIZE,
+        }
+    }
+
+    /// Center and zoom of the view
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            center: self.center,
+            zoom: self.zoom,
+        }
+    }
+
+    /// Move to a saved view, normalizing the center and clamping the zoom
+    ///
+    /// A state with non-finite values is ignored with a warning.
+    pub fn apply_state(&mut self, state: &CameraState) {
+        let (lon, lat) = state.center;
+        if !lon.is_finite() || !lat.is_finite() || !state.zoom.is_finite() {
+            log::warn!("Ignoring non-finite camera state {:?}", state);
+            return;
+        }
+        self.center = (normalize_longitude(lon), clamp_latitude(lat));
+        self.zoom = self.clamp_zoom(state.zoom);
+    }
+
+    /// Set the tile image size in pixels (the source's tiles are drawn 1:1
+    /// at integer zoom levels)
+    ///
+    /// Zero would divide the projection by zero and is ignored.
+    pub fn set_tile_size(&mut self, pixels: u32) {
+        if pixels == 0 {
+            log::warn!("Ignoring tile size of 0 pixels");
+            return;
+        }
+        self.tile_size = pixels as f64;
+    }
+
+    /// Limit zooming to `min..=max`, clamping the current zoom
+    pub fn set_zoom_range(&mut self, min: u8, max: u8) {
+        self.min_zoom = min.min(max);
+        self.max_zoom = max;
+        self.zoom = self.clamp_zoom(self.zoom);
+    }
+
+    /// Clamp a zoom level to the allowed range
+    pub fn clamp_zoom(&self, zoom: f64) -> f64 {
+        zoom.clamp(self.min_zoom as f64, self.max_zoom as f64)
+    }
+
+    /// Set map rotation in radians (clockwise, wrapped to [0, 2π))
+    pub fn set_rotation(&mut self, radians: f32) {
+        self.rotation = radians.rem_euclid(TAU);
+        if self.rotation >= TAU {
+            self.rotation = 0.0; // rem_euclid can round up to TAU
+        }
+    }
+
+    /// Rotate an unrotated screen offset into screen space
+    fn rotate(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.rotation == 0.0 {
+            return (x, y);
+        }
+        let (sin, cos) = (self.rotation as f64).sin_cos();
+        (x * cos - y * sin, x * sin + y * cos)
+    }
+
+    /// Inverse of `rotate`: screen offset back to north-up
+    fn unrotate(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.rotation == 0.0 {
+            return (x, y);
         }
+        let (sin, cos) = (self.rotation as f64).sin_cos();
+        (x * cos + y * sin, -x * sin + y * cos)
     }
 
     /// Update viewport size
+    ///
+    /// A zero size (e.g. a minimized window) is treated as 1 pixel, so
+    /// projecting never divides by zero.
     pub fn set_viewport(&mut self, width: u32, height: u32) {
-        self.viewport_width = width;
-        self.viewport_height = height;
+        self.viewport_width = width.max(1);
+        self.viewport_height = height.max(1);
     }
 
     /// Get the integer zoom level for tile loading
@@ -41,83 +175,145 @@ impl MapCamera {
         self.zoom.floor() as u8
     }
 
+    /// Check if this view shows different tiles than `previous` altogether:
+    /// another tile level, or a center more than a viewport away
+    pub fn is_far_from(&self, previous: &MapCamera) -> bool {
+        if self.tile_zoom() != previous.tile_zoom() {
+            return true;
+        }
+        let (x, y) = self.world_to_screen(previous.center.0, previous.center.1);
+        let (w, h) = (self.viewport_width as f32, self.viewport_height as f32);
+        (x - w / 2.0).abs() > w || (y - h / 2.0).abs() > h
+    }
+
     /// Get scale factor for current fractional zoom
     pub fn zoom_scale(&self) -> f64 {
         2.0_f64.powf(self.zoom - self.zoom.floor())
     }
 
+    /// Next tile level to cross-fade in, with its opacity
+    ///
+    /// Past the middle of a zoom level the next level is fetched and blended
+    /// over the current one, reaching full opacity at the integer boundary
+    /// so switching `tile_zoom` is seamless.
+    pub fn blend_level(&self) -> Option<(u8, f32)> {
+        let z = self.tile_zoom();
+        let frac = self.zoom - self.zoom.floor();
+        if z >= self.max_zoom || frac <= BLEND_START {
+            return None;
+        }
+        let opacity = (frac - BLEND_START) / (1.0 - BLEND_START);
+        Some((z + 1, opacity as f32))
+    }
+
     /// Meters per pixel at current zoom and latitude
     pub fn meters_per_pixel(&self) -> f64 {
         let earth_circumference = 40075016.686; // meters
         let lat_rad = self.center.1.to_radians();
-        earth_circumference * lat_rad.cos() / (TILE_SIZE * 2.0_f64.powf(self.zoom))
+        earth_circumference * lat_rad.cos() / (self.tile_size * 2.0_f64.powf(self.zoom))
     }
 
     /// Pan the map by pixel delta
+    ///
+    /// Works in Mercator tile space so the grabbed point stays under the
+    /// cursor at every latitude.
     pub fn pan(&mut self, dx_pixels: f32, dy_pixels: f32) {
-        let meters_per_pixel = self.meters_per_pixel();
-
-        // Longitude change (X axis - wraps infinitely)
-        let cos_lat = self.center.1.to_radians().cos().max(0.01);
-        let lon_delta = (dx_pixels as f64) * meters_per_pixel / (111320.0 * cos_lat);
-        self.center.0 = normalize_longitude(self.center.0 - lon_delta);
+        let z = self.tile_zoom();
+        let scaled_tile_size = self.tile_size * self.zoom_scale();
+        let (dx, dy) = self.unrotate(dx_pixels as f64, dy_pixels as f64);
 
-        // Latitude change (Y axis - clamped)
-        let lat_delta = (dy_pixels as f64) * meters_per_pixel / 111320.0;
-        self.center.1 = clamp_latitude(self.center.1 + lat_delta);
+        let (cx, cy) = lon_lat_to_tile_f64(self.center.0, self.center.1, z);
+        let (lon, lat) = tile_to_lon_lat_f64(
+            cx - dx / scaled_tile_size,
+            cy - dy / scaled_tile_size,
+            z,
+        );
+
+        // X axis wraps infinitely, Y axis is clamped
+        self.center = (normalize_longitude(lon), clamp_latitude(lat));
     }
 
     /// Zoom at a specific screen point
+    ///
+    /// The cursor is converted to a zoom-0 tile coordinate before the zoom
+    /// changes, then the center is moved so that coordinate projects back to
+    /// the same screen pixel.
     pub fn zoom_at(&mut self, delta: f64, screen_x: f32, screen_y: f32) {
         let old_zoom = self.zoom;
-        self.zoom = (self.zoom + delta).clamp(0.0, 19.0);
-
-        if (self.zoom - old_zoom).abs() < 0.001 {
+        let new_zoom = self.clamp_zoom(self.zoom + delta);
+        if new_zoom == old_zoom {
             return;
         }
 
-        // Calculate the world position under the cursor before zoom
-        let offset_x = screen_x - (self.viewport_width as f32 / 2.0);
-        let offset_y = screen_y - (self.viewport_height as f32 / 2.0);
-
-        // Adjust center to keep the point under cursor stationary
-        let scale_change = 2.0_f64.powf(self.zoom - old_zoom);
-        let new_offset_x = offset_x as f64 * (1.0 - 1.0 / scale_change);
-        let new_offset_y = offset_y as f64 * (1.0 - 1.0 / scale_change);
-
-        // Convert pixel offset to geo offset
-        let meters_per_pixel = self.meters_per_pixel();
-        let cos_lat = self.center.1.to_radians().cos().max(0.01);
-
-        let lon_delta = new_offset_x * meters_per_pixel / (111320.0 * cos_lat);
-        let lat_delta = new_offset_y * meters_per_pixel / 111320.0;
-
-        self.center.0 = normalize_longitude(self.center.0 + lon_delta);
-        self.center.1 = clamp_latitude(self.center.1 - lat_delta);
+        let (offset_x, offset_y) = self.unrotate(
+            screen_x as f64 - (self.viewport_width as f64 / 2.0),
+            screen_y as f64 - (self.viewport_height as f64 / 2.0),
+        );
+
+        // Fractional tile coordinate under the cursor before zooming
+        let (cx, cy) = lon_lat_to_tile_f64(self.center.0, self.center.1, 0);
+        let old_world_size = self.tile_size * 2.0_f64.powf(old_zoom);
+        let anchor_x = cx + offset_x / old_world_size;
+        let anchor_y = cy + offset_y / old_world_size;
+
+        // Move the center so the anchor lands back under the cursor
+        self.zoom = new_zoom;
+        let new_world_size = self.tile_size * 2.0_f64.powf(new_zoom);
+        let (lon, lat) = tile_to_lon_lat_f64(
+            anchor_x - offset_x / new_world_size,
+            anchor_y - offset_y / new_world_size,
+            0,
+        );
+
+        self.center = (normalize_longitude(lon), clamp_latitude(lat));
     }
 
     /// Simple zoom (centered)
     pub fn zoom_by(&mut self, delta: f64) {
-        self.zoom = (self.zoom + delta).clamp(0.0, 19.0);
+        self.zoom = self.clamp_zoom(self.zoom + delta);
+    }
+
+    /// Set how many tile rings to preload around the viewport
+    ///
+    /// Each ring adds roughly `2 * (width + height) / tile_size` tiles per
+    /// view: wider rings hide pop-in at the edges while panning on fast
+    /// connections, zero only fetches what is on screen (metered connections).
+    pub fn set_prefetch_buffer(&mut self, rings: u32) {
+        self.prefetch_buffer = rings;
     }
 
-    /// Get list of visible tiles with buffer for pre-loading
+    /// Get list of visible tiles with `prefetch_buffer` rings for pre-loading
     pub fn visible_tiles(&self) -> Vec<TileId> {
-        self.visible_tiles_with_buffer(1)
+        self.visible_tiles_with_buffer(self.prefetch_buffer as i32)
     }
 
     /// Get visible tiles with specified buffer tiles around viewport
+    ///
+    /// Tiles are sorted by distance from the viewport center (nearest first,
+    /// ties broken by row then column), so load order is deterministic and
+    /// the center of the view is requested first.
     pub fn visible_tiles_with_buffer(&self, buffer: i32) -> Vec<TileId> {
-        let z = self.tile_zoom();
-        let scale = self.zoom_scale();
-        let scaled_tile_size = TILE_SIZE * scale;
+        self.visible_tiles_at_level(self.tile_zoom(), buffer)
+    }
+
+    /// Get visible tiles of an arbitrary tile level (e.g. the `blend_level`)
+    ///
+    /// Where the world repeats across the viewport (at low zoom), each tile
+    /// is listed once, and only if the copy `tile_corners` places reaches
+    /// within `buffer` tiles of the viewport.
+    pub fn visible_tiles_at_level(&self, z: u8, buffer: i32) -> Vec<TileId> {
+        let scaled_tile_size = self.level_tile_size(z);
 
         // Center tile position (fractional)
         let (cx, cy) = lon_lat_to_tile_f64(self.center.0, self.center.1, z);
 
-        // How many tiles fit in the viewport
-        let tiles_x = (self.viewport_width as f64 / scaled_tile_size).ceil() as i32 + 1;
-        let tiles_y = (self.viewport_height as f64 / scaled_tile_size).ceil() as i32 + 1;
+        // How many tiles fit in the (north-up bounding box of the) viewport
+        let (sin, cos) = (self.rotation as f64).sin_cos();
+        let (w, h) = (self.viewport_width as f64, self.viewport_height as f64);
+        let extent_x = w * cos.abs() + h * sin.abs();
+        let extent_y = w * sin.abs() + h * cos.abs();
+        let tiles_x = (extent_x / scaled_tile_size).ceil() as i32 + 1;
+        let tiles_y = (extent_y / scaled_tile_size).ceil() as i32 + 1;
 
         // Calculate tile range
         let half_tiles_x = tiles_x / 2 + buffer;
@@ -128,70 +324,141 @@ impl MapCamera {
         let min_y = cy.floor() as i32 - half_tiles_y;
         let max_y = cy.ceil() as i32 + half_tiles_y;
 
-        // Collect tiles with X-axis wrapping
+        // Collect tiles with X-axis wrapping, keyed by squared distance from
+        // the center to the tile's midpoint (using the unwrapped position)
         let mut tiles = Vec::new();
         for ty in min_y..=max_y {
             if !is_valid_tile_y(ty, z) {
                 continue;
             }
             for tx in min_x..=max_x {
+                let dx = tx as f64 + 0.5 - cx;
+                let dy = ty as f64 + 0.5 - cy;
                 let wrapped_x = wrap_tile_x(tx, z);
-                tiles.push(TileId::new(wrapped_x, ty as u32, z));
+                tiles.push((
+                    dx * dx + dy * dy,
+                    ty,
+                    tx,
+                    TileId::new(wrapped_x, ty as u32, z),
+                ));
             }
         }
 
+        tiles.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        let margin = buffer as f64 * scaled_tile_size;
+        let mut seen = HashSet::new();
         tiles
+            .into_iter()
+            .map(|(_, _, _, tile)| tile)
+            .filter(|tile| seen.insert(*tile) && self.is_on_screen(tile, margin))
+            .collect()
+    }
+
+    /// Check if the bounding box of a tile's corners overlaps the viewport
+    /// grown by `margin` pixels on every side
+    fn is_on_screen(&self, tile: &TileId, margin: f64) -> bool {
+        let corners = self.tile_corners(tile);
+        let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+        let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (x, y) in corners {
+            min_x = min_x.min(x as f64);
+            min_y = min_y.min(y as f64);
+            max_x = max_x.max(x as f64);
+            max_y = max_y.max(y as f64);
+        }
+        max_x > -margin
+            && max_y > -margin
+            && min_x < self.viewport_width as f64 + margin
+            && min_y < self.viewport_height as f64 + margin
     }
 
     /// Convert tile coordinates to screen position (top-left corner)
     pub fn tile_to_screen(&self, tile: &TileId) -> (f32, f32) {
-        let z = self.tile_zoom();
-        let scale = self.zoom_scale();
-        let scaled_tile_size = TILE_SIZE * scale;
+        self.tile_corners(tile)[0]
+    }
+
+    /// Screen positions of a tile's corners
+    ///
+    /// Order: top-left, top-right, bottom-right, bottom-left (of the tile
+    /// image, which differs from the screen orientation when rotated).
+    ///
+    /// Corners are computed from integer grid lines, so neighboring tiles
+    /// get bit-identical shared edges and no seam opens between them.
+    pub fn tile_corners(&self, tile: &TileId) -> [(f32, f32); 4] {
+        let z = tile.z;
+        let scaled_tile_size = self.level_tile_size(z);
 
         // Center tile position (fractional)
         let (cx, cy) = lon_lat_to_tile_f64(self.center.0, self.center.1, z);
 
-        // Tile position relative to center
-        let mut rel_x = tile.x as f64 - cx;
-        let mut rel_y = tile.y as f64 - cy;
-
-        // Handle world wrapping for X axis
-        let max_tiles = (1_u64 << z) as f64;
-        if rel_x > max_tiles / 2.0 {
-            rel_x -= max_tiles;
-        } else if rel_x < -max_tiles / 2.0 {
-            rel_x += max_tiles;
+        // Handle world wrapping for X axis: use the copy nearest the center
+        let max_tiles = 1_i64 << z;
+        let mut column = tile.x as i64;
+        let rel_x = column as f64 - cx;
+        if rel_x > max_tiles as f64 / 2.0 {
+            column -= max_tiles;
+        } else if rel_x < -(max_tiles as f64) / 2.0 {
+            column += max_tiles;
         }
+        let row = tile.y as i64;
 
         // Convert to screen coordinates
-        let screen_x = (self.viewport_width as f64 / 2.0) + (rel_x * scaled_tile_size);
-        let screen_y = (self.viewport_height as f64 / 2.0) + (rel_y * scaled_tile_size);
-
-        (screen_x as f32, screen_y as f32)
+        [(0, 0), (1, 0), (1, 1), (0, 1)].map(|(u, v)| {
+            let (x, y) = self.rotate(
+                ((column + u) as f64 - cx) * scaled_tile_size,
+                ((row + v) as f64 - cy) * scaled_tile_size,
+            );
+            (
+                ((self.viewport_width as f64 / 2.0) + x) as f32,
+                ((self.viewport_height as f64 / 2.0) + y) as f32,
+            )
+        })
     }
 
     /// Get the screen size of a tile at current zoom
     pub fn tile_screen_size(&self) -> f32 {
         let scale = self.zoom_scale();
-        (TILE_SIZE * scale) as f32
+        (self.tile_size * scale) as f32
+    }
+
+    /// Screen size of a tile of level `z` at the current zoom
+    fn level_tile_size(&self, z: u8) -> f64 {
+        self.tile_size * 2.0_f64.powf(self.zoom - z as f64)
+    }
+
+    /// Convert world coordinates (lon, lat) to screen position in pixels
+    pub fn world_to_screen(&self, lon: f64, lat: f64) -> (f32, f32) {
+        let z = self.tile_zoom();
+        let scaled_tile_size = self.tile_size * self.zoom_scale();
+
+        let (tx, ty) = lon_lat_to_tile_f64(lon, clamp_latitude(lat), z);
+        let (cx, cy) = lon_lat_to_tile_f64(self.center.0, self.center.1, z);
+
+        let (x, y) = self.rotate((tx - cx) * scaled_tile_size, (ty - cy) * scaled_tile_size);
+        let screen_x = (self.viewport_width as f64 / 2.0) + x;
+        let screen_y = (self.viewport_height as f64 / 2.0) + y;
+
+        (screen_x as f32, screen_y as f32)
     }
 
     /// Convert screen coordinates to world coordinates (lon, lat)
     pub fn screen_to_world(&self, screen_x: f32, screen_y: f32) -> (f64, f64) {
-        let meters_per_pixel = self.meters_per_pixel();
-        let cos_lat = self.center.1.to_radians().cos().max(0.01);
-
-        let offset_x = screen_x - (self.viewport_width as f32 / 2.0);
-        let offset_y = screen_y - (self.viewport_height as f32 / 2.0);
+        let z = self.tile_zoom();
+        let scaled_tile_size = self.tile_size * self.zoom_scale();
 
-        let lon_delta = (offset_x as f64) * meters_per_pixel / (111320.0 * cos_lat);
-        let lat_delta = (offset_y as f64) * meters_per_pixel / 111320.0;
+        let (offset_x, offset_y) = self.unrotate(
+            screen_x as f64 - (self.viewport_width as f64 / 2.0),
+            screen_y as f64 - (self.viewport_height as f64 / 2.0),
+        );
 
-        let lon = normalize_longitude(self.center.0 + lon_delta);
-        let lat = clamp_latitude(self.center.1 - lat_delta);
+        let (cx, cy) = lon_lat_to_tile_f64(self.center.0, self.center.1, z);
+        let (lon, lat) = tile_to_lon_lat_f64(
+            cx + offset_x / scaled_tile_size,
+            cy + offset_y / scaled_tile_size,
+            z,
+        );
 
-        (lon, lat)
+        (normalize_longitude(lon), clamp_latitude(lat))
     }
 }
 
@@ -201,3 +468,359 @@ impl Default for MapCamera {
         Self::new(126.9780, 37.5665, 10.0, 800, 600)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::renderer::screen_to_ndc;
+    use crate::map::tile::lon_lat_to_tile;
+    use std::f64::consts::PI;
+
+    /// Reference Web Mercator inverse: global pixel coordinates to lon/lat
+    fn mercator_inverse(px: f64, py: f64, zoom: f64) -> (f64, f64) {
+        let world = TILE_SIZE * 2.0_f64.powf(zoom);
+        let lon = px / world * 360.0 - 180.0;
+        let lat = (PI * (1.0 - 2.0 * py / world)).sinh().atan().to_degrees();
+        (lon, lat)
+    }
+
+    fn mercator_forward(lon: f64, lat: f64, zoom: f64) -> (f64, f64) {
+        let world = TILE_SIZE * 2.0_f64.powf(zoom);
+        let px = (lon + 180.0) / 360.0 * world;
+        let py = (1.0 - lat.to_radians().tan().asinh() / PI) / 2.0 * world;
+        (px, py)
+    }
+
+    #[test]
+    fn test_pan_matches_mercator_inverse() {
+        for &(lon, lat, zoom) in &[(0.0, 0.0, 3.0), (126.978, 37.5665, 12.4), (10.0, 70.0, 8.7)] {
+            let mut camera = MapCamera::new(lon, lat, zoom, 800, 600);
+            camera.pan(120.0, -45.0);
+
+            let (px, py) = mercator_forward(lon, lat, zoom);
+            let expected = mercator_inverse(px - 120.0, py + 45.0, zoom);
+
+            assert!(
+                (camera.center.0 - expected.0).abs() < 1e-9,
+                "lon at lat {}",
+                lat
+            );
+            assert!(
+                (camera.center.1 - expected.1).abs() < 1e-9,
+                "lat at lat {}",
+                lat
+            );
+        }
+    }
+
+    #[test]
+    fn test_pan_keeps_grabbed_point_under_cursor() {
+        let mut camera = MapCamera::new(24.0, 78.0, 10.3, 1024, 768);
+        let grabbed = camera.screen_to_world(700.0, 200.0);
+
+        camera.pan(-150.0, 90.0);
+        let after = camera.screen_to_world(550.0, 290.0);
+
+        assert!((grabbed.0 - after.0).abs() < 1e-6);
+        assert!((grabbed.1 - after.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_zoom_at_keeps_cursor_point_fixed() {
+        let mut camera = MapCamera::new(126.978, 37.5665, 11.3, 1280, 720);
+        let cursor = (1010.0, 95.0);
+
+        for delta in [0.5, 0.137, -1.25, 2.0, -0.01, 3.7] {
+            let before = camera.screen_to_world(cursor.0, cursor.1);
+            camera.zoom_at(delta, cursor.0, cursor.1);
+            let after = camera.screen_to_world(cursor.0, cursor.1);
+
+            assert!((before.0 - after.0).abs() < 1e-7, "lon drift for {}", delta);
+            assert!((before.1 - after.1).abs() < 1e-7, "lat drift for {}", delta);
+        }
+    }
+
+    #[test]
+    fn test_visible_tiles_center_first() {
+        let configs = [
+            (126.978, 37.5665, 12.0, 800, 600),
+            (-74.006, 40.7128, 15.6, 1920, 1080),
+            (3.0, 5.0, 2.3, 640, 480),
+            (179.9, -60.0, 7.0, 300, 900),
+        ];
+
+        for (lon, lat, zoom, width, height) in configs {
+            let camera = MapCamera::new(lon, lat, zoom, width, height);
+            let tiles = camera.visible_tiles();
+            let (x, y) = lon_lat_to_tile(lon, lat, camera.tile_zoom());
+
+            assert_eq!(tiles[0], TileId::new(x, y, camera.tile_zoom()));
+        }
+    }
+
+    #[test]
+    fn test_visible_tiles_sorted_by_distance() {
+        let camera = MapCamera::new(126.978, 37.5665, 12.5, 1024, 768);
+        let (cx, cy) = lon_lat_to_tile_f64(camera.center.0, camera.center.1, camera.tile_zoom());
+
+        let distances: Vec<f64> = camera
+            .visible_tiles()
+            .iter()
+            .map(|t| {
+                let dx = t.x as f64 + 0.5 - cx;
+                let dy = t.y as f64 + 0.5 - cy;
+                dx * dx + dy * dy
+            })
+            .collect();
+
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(camera.visible_tiles(), camera.visible_tiles());
+    }
+
+    #[test]
+    fn test_prefetch_buffer_controls_ring() {
+        let mut camera = MapCamera::new(126.978, 37.5665, 12.0, 800, 600);
+        assert_eq!(camera.visible_tiles(), camera.visible_tiles_with_buffer(1));
+
+        camera.set_prefetch_buffer(0);
+        let none = camera.visible_tiles();
+        camera.set_prefetch_buffer(2);
+        let wide = camera.visible_tiles();
+
+        assert!(none.len() < wide.len());
+        assert!(none.iter().all(|t| wide.contains(t)));
+    }
+
+    #[test]
+    fn test_blend_level_fades_in_next_level() {
+        let mut camera = MapCamera::new(126.978, 37.5665, 12.3, 800, 600);
+        assert_eq!(camera.blend_level(), None);
+
+        camera.zoom = 12.75;
+        let (z, opacity) = camera.blend_level().unwrap();
+        assert_eq!(z, 13);
+        assert!((opacity - 0.5).abs() < 1e-6);
+
+        camera.zoom = 19.0;
+        assert_eq!(camera.blend_level(), None);
+
+        // Nothing to blend past the source's last level
+        camera.set_zoom_range(0, 17);
+        camera.zoom_by(5.0);
+        assert_eq!(camera.zoom, 17.0);
+        camera.zoom = 16.75;
+        assert_eq!(camera.blend_level().map(|(z, _)| z), Some(17));
+        camera.zoom = 17.0;
+        assert_eq!(camera.blend_level(), None);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let camera = MapCamera::new(126.978, 37.5665, 12.5, 800, 600);
+        let json = serde_json::to_string(&camera.state()).unwrap();
+        let state: CameraState = serde_json::from_str(&json).unwrap();
+
+        let mut restored = MapCamera::new(0.0, 0.0, 2.0, 1024, 768);
+        restored.apply_state(&state);
+        assert_eq!(restored.center, camera.center);
+        assert_eq!(restored.zoom, 12.5);
+        assert_eq!((restored.viewport_width, restored.viewport_height), (1024, 768));
+
+        // Out-of-range values are normalized
+        restored.set_zoom_range(0, 18);
+        restored.apply_state(&CameraState {
+            center: (190.0, 37.0),
+            zoom: 25.0,
+        });
+        assert!((restored.center.0 + 170.0).abs() < 1e-9);
+        assert_eq!(restored.zoom, 18.0);
+    }
+
+    #[test]
+    fn test_non_finite_new_falls_back() {
+        let camera = MapCamera::new(f64::NAN, 37.0, f64::INFINITY, 800, 600);
+        assert_eq!(camera.center, (0.0, 0.0));
+        assert_eq!(camera.zoom, 0.0);
+        assert!(camera.world_to_screen(10.0, 10.0).0.is_finite());
+    }
+
+    #[test]
+    fn test_degenerate_viewport_projects_finite() {
+        let mut camera = MapCamera::new(126.978, 37.5665, 12.0, 0, 0);
+        assert_eq!((camera.viewport_width, camera.viewport_height), (1, 1));
+
+        camera.set_viewport(800, 0);
+        let (x, y) = camera.world_to_screen(127.0, 37.6);
+        let (ndc_x, ndc_y) = screen_to_ndc(x, y, 800, 0);
+        assert!(ndc_x.is_finite() && ndc_y.is_finite());
+        let (lon, lat) = camera.screen_to_world(0.0, 0.0);
+        assert!(lon.is_finite() && lat.is_finite());
+        assert!(!camera.visible_tiles().is_empty());
+    }
+
+    #[test]
+    fn test_tile_size_scales_projection() {
+        let small = MapCamera::new(126.978, 37.5665, 12.0, 800, 600);
+        let mut large = small;
+        large.set_tile_size(512);
+
+        assert_eq!(large.tile_screen_size(), 2.0 * small.tile_screen_size());
+        assert!((large.meters_per_pixel() * 2.0 - small.meters_per_pixel()).abs() < 1e-9);
+        // Fewer, larger tiles cover the same viewport
+        assert!(large.visible_tiles().len() < small.visible_tiles().len());
+
+        // A point 256 pixels east of the center is twice as far with 512px tiles
+        let (lon, lat) = small.screen_to_world(656.0, 300.0);
+        let (x, y) = large.world_to_screen(lon, lat);
+        assert!((x - 912.0).abs() < 1e-2 && (y - 300.0).abs() < 1e-2);
+
+        large.set_tile_size(0);
+        assert_eq!(large.tile_size, 512.0);
+    }
+
+    #[test]
+    fn test_adjacent_tiles_share_edges() {
+        // Fractional zooms, next to the antimeridian and rotated
+        let views = [(126.978, 12.37, 0.0), (179.99, 5.71, 0.0), (-3.2, 9.5, 0.4)];
+        for (lon, zoom, rotation) in views {
+            let mut camera = MapCamera::new(lon, 37.5665, zoom, 1280, 720);
+            camera.set_rotation(rotation);
+            let z = camera.tile_zoom();
+            for tile in camera.visible_tiles() {
+                let corners = camera.tile_corners(&tile);
+                let east = TileId::new(wrap_tile_x(tile.x as i32 + 1, z), tile.y, z);
+                let south = TileId::new(tile.x, tile.y + 1, z);
+
+                // Exactly equal, also across the antimeridian
+                let east_corners = camera.tile_corners(&east);
+                assert_eq!((corners[1], corners[2]), (east_corners[0], east_corners[3]));
+                if tile.y + 1 < 1 << z {
+                    let south_corners = camera.tile_corners(&south);
+                    assert_eq!([corners[3], corners[2]], south_corners[..2]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_child_tiles_align_with_parent() {
+        let camera = MapCamera::new(126.978, 37.5665, 12.8, 800, 600);
+        let parent = camera.visible_tiles()[0];
+        let parent_corners = camera.tile_corners(&parent);
+
+        let child = TileId::new(parent.x * 2, parent.y * 2, parent.z + 1);
+        let child_corners = camera.tile_corners(&child);
+        assert!((parent_corners[0].0 - child_corners[0].0).abs() < 1e-3);
+        assert!((parent_corners[0].1 - child_corners[0].1).abs() < 1e-3);
+
+        // Two child tiles span one parent tile
+        let size = parent_corners[1].0 - parent_corners[0].0;
+        let child_size = child_corners[1].0 - child_corners[0].0;
+        assert!((size - 2.0 * child_size).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_rotation_is_clockwise_and_invertible() {
+        let mut camera = MapCamera::new(126.978, 37.5665, 14.0, 800, 600);
+        let east = (126.99, 37.5665);
+        let (x, y) = camera.world_to_screen(east.0, east.1);
+        assert!(x > 400.0 && (y - 300.0).abs() < 1e-3);
+
+        // A quarter turn clockwise moves east to below the center
+        camera.set_rotation(std::f32::consts::FRAC_PI_2);
+        let (x, y) = camera.world_to_screen(east.0, east.1);
+        assert!((x - 400.0).abs() < 1e-2 && y > 300.0);
+
+        camera.set_rotation(0.7);
+        for (sx, sy) in [(0.0, 0.0), (123.0, 456.0), (799.0, 10.0)] {
+            let (lon, lat) = camera.screen_to_world(sx, sy);
+            let (rx, ry) = camera.world_to_screen(lon, lat);
+            assert!((rx - sx).abs() < 1e-2 && (ry - sy).abs() < 1e-2);
+        }
+
+        camera.set_rotation(-std::f32::consts::FRAC_PI_2);
+        assert!((camera.rotation - 3.0 * std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rotated_pan_and_zoom_keep_point_under_cursor() {
+        let mut camera = MapCamera::new(24.0, 60.0, 10.3, 1024, 768);
+        camera.set_rotation(2.2);
+
+        let grabbed = camera.screen_to_world(700.0, 200.0);
+        camera.pan(-150.0, 90.0);
+        let after = camera.screen_to_world(550.0, 290.0);
+        assert!((grabbed.0 - after.0).abs() < 1e-6);
+        assert!((grabbed.1 - after.1).abs() < 1e-6);
+
+        let before = camera.screen_to_world(100.0, 650.0);
+        camera.zoom_at(1.3, 100.0, 650.0);
+        let after = camera.screen_to_world(100.0, 650.0);
+        assert!((before.0 - after.0).abs() < 1e-7);
+        assert!((before.1 - after.1).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_rotated_visible_tiles_cover_viewport() {
+        let mut camera = MapCamera::new(126.978, 37.5665, 12.5, 1280, 320);
+        camera.set_rotation(std::f32::consts::FRAC_PI_4);
+        camera.set_prefetch_buffer(0);
+
+        let tiles = camera.visible_tiles();
+        let z = camera.tile_zoom();
+        for (x, y) in [(0.0, 0.0), (1279.0, 0.0), (0.0, 319.0), (1279.0, 319.0)] {
+            let (lon, lat) = camera.screen_to_world(x, y);
+            let (tx, ty) = lon_lat_to_tile(lon, lat, z);
+            assert!(tiles.contains(&TileId::new(tx, ty, z)), "corner ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_wrapped_tiles_listed_once_and_on_screen() {
+        for (zoom, width) in [(1.0, 1200), (1.5, 1920), (2.0, 3000)] {
+            let camera = MapCamera::new(170.0, 20.0, zoom, width, 600);
+            let z = camera.tile_zoom();
+            for buffer in [0, 1] {
+                let tiles = camera.visible_tiles_with_buffer(buffer);
+                let unique: HashSet<_> = tiles.iter().collect();
+                assert_eq!(unique.len(), tiles.len(), "zoom {} lists a tile twice", zoom);
+                assert!(tiles.len() <= 1 << (2 * z));
+
+                let margin = buffer as f64 * camera.level_tile_size(z);
+                for tile in &tiles {
+                    assert!(camera.is_on_screen(tile, margin), "{:?} at zoom {}", tile, zoom);
+                }
+            }
+
+            // Every point of the view still has its tile
+            let tiles = camera.visible_tiles_with_buffer(0);
+            for x in (0..width).step_by(50) {
+                let (lon, lat) = camera.screen_to_world(x as f32, 300.0);
+                let (tx, ty) = lon_lat_to_tile(lon, lat, z);
+                assert!(tiles.contains(&TileId::new(tx, ty, z)), "x {} at zoom {}", x, zoom);
+            }
+        }
+
+        // A narrow view at zoom 2 drops tiles of the far side of the world
+        let camera = MapCamera::new(0.0, 0.0, 2.0, 300, 300);
+        let tiles = camera.visible_tiles_with_buffer(0);
+        assert!(tiles.iter().all(|tile| tile.x == 1 || tile.x == 2));
+    }
+
+    #[test]
+    fn test_is_far_from() {
+        let start = MapCamera::new(126.9780, 37.5665, 12.0, 800, 600);
+
+        let mut camera = start;
+        camera.pan(700.0, 0.0);
+        assert!(!camera.is_far_from(&start));
+        camera.pan(200.0, 0.0);
+        assert!(camera.is_far_from(&start));
+
+        let mut camera = start;
+        camera.zoom_by(0.5);
+        assert!(!camera.is_far_from(&start));
+        camera.zoom_by(0.6);
+        assert!(camera.is_far_from(&start));
+    }
+}
@@ -0,0 +1,315 @@
+//! Map system settings chosen by the embedder
+
+use std::time::Duration;
+
+use super::cache::{
+    DEFAULT_MAX_BYTE_TILES, DEFAULT_MAX_BYTES, DEFAULT_MAX_MEMORY, DEFAULT_MAX_TILES,
+    EvictionPolicy,
+};
+use super::camera::DEFAULT_PREFETCH_BUFFER;
+use super::decode::{DEFAULT_MAX_PARALLEL_DECODES, DEFAULT_MAX_TILE_DIMENSION};
+use super::grid::DEFAULT_MAX_PIXELS;
+use super::loader::{DEFAULT_MAX_PENDING, DEFAULT_USER_AGENT, tile_memory_size};
+use super::renderer::DEFAULT_TILE_VERTEX_SLOTS;
+use super::source::TileSource;
+use super::zoom::DEFAULT_SETTLE_DELAY;
+
+/// Smallest cache that still holds a typical viewport
+pub const MIN_CACHED_TILES: usize = 16;
+
+/// Default pixel grid cell size in degrees (~10m at the equator)
+pub const DEFAULT_CELL_SIZE: f64 = 0.0001;
+
+/// Settings for `MapSystem::from_config`
+///
+/// Start from `default()` and chain the setters for what differs:
+///
+/// ```
+/// # use client::map::MapSystemConfig;
+/// let config = MapSystemConfig::default()
+///     .viewport(1280, 720)
+///     .center(2.3522, 48.8566)
+///     .zoom(14.0)
+///     .cache_size(512, 128 * 1024 * 1024);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapSystemConfig {
+    /// Initial center (longitude, latitude)
+    pub center: (f64, f64),
+    /// Initial zoom level
+    pub zoom: f64,
+    /// Viewport size in pixels
+    pub viewport: (u32, u32),
+    /// Where tiles are downloaded from
+    pub tile_source: TileSource,
+    /// Maximum number of cached tiles
+    pub max_tiles: usize,
+    /// GPU memory budget for cached tiles in bytes
+    pub max_memory: usize,
+    /// Maximum number of downloaded tile files kept for re-upload
+    pub max_byte_tiles: usize,
+    /// Budget for downloaded tile files in bytes (independent of `max_memory`)
+    pub max_bytes: usize,
+    /// Which tiles the texture and file caches evict when full
+    pub eviction_policy: EvictionPolicy,
+    /// Pixel grid cell size in degrees
+    pub cell_size: f64,
+    /// Corner of pixel grid cell (0, 0), in degrees
+    pub grid_origin: (f64, f64),
+    /// Pixels kept before the least recently set are evicted (None = unbounded)
+    pub max_pixels: Option<usize>,
+    /// Tile rings preloaded around the viewport
+    pub prefetch_buffer: u32,
+    /// User-Agent sent with tile requests
+    pub user_agent: String,
+    /// MSAA samples of the render target (1 = no multisampling)
+    ///
+    /// Must match the target the map is drawn into and be supported by the
+    /// adapter for the surface format.
+    pub msaa_samples: u32,
+    /// Tile vertex buffers created with the renderer (more follow as needed)
+    pub tile_vertex_slots: usize,
+    /// Tiles decoded at once, to bound CPU use when many finish together
+    pub max_parallel_decodes: usize,
+    /// Widest or tallest tile image decoded, in pixels
+    ///
+    /// Lowered to the device's texture size limit when the map has one.
+    pub max_tile_dimension: u32,
+    /// Tile requests waiting for a download at once
+    pub max_pending_tiles: usize,
+    /// Rest after zooming before easing to the nearest whole level (None = off)
+    pub settle_delay: Option<Duration>,
+}
+
+impl Default for MapSystemConfig {
+    fn default() -> Self {
+        Self {
+            // Seoul at zoom 12
+            center: (126.9780, 37.5665),
+            zoom: 12.0,
+            viewport: (800, 600),
+            tile_source: TileSource::default(),
+            max_tiles: DEFAULT_MAX_TILES,
+            max_memory: DEFAULT_MAX_MEMORY,
+            max_byte_tiles: DEFAULT_MAX_BYTE_TILES,
+            max_bytes: DEFAULT_MAX_BYTES,
+            eviction_policy: EvictionPolicy::default(),
+            cell_size: DEFAULT_CELL_SIZE,
+            grid_origin: (0.0, 0.0),
+            max_pixels: Some(DEFAULT_MAX_PIXELS),
+            prefetch_buffer: DEFAULT_PREFETCH_BUFFER,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            msaa_samples: 1,
+            tile_vertex_slots: DEFAULT_TILE_VERTEX_SLOTS,
+            max_parallel_decodes: DEFAULT_MAX_PARALLEL_DECODES,
+            max_tile_dimension: DEFAULT_MAX_TILE_DIMENSION,
+            max_pending_tiles: DEFAULT_MAX_PENDING,
+            settle_delay: None,
+        }
+    }
+}
+
+impl MapSystemConfig {
+    pub fn center(mut self, lon: f64, lat: f64) -> Self {
+        self.center = (lon, lat);
+        self
+    }
+
+    pub fn zoom(mut self, zoom: f64) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn viewport(mut self, width: u32, height: u32) -> Self {
+        self.viewport = (width, height);
+        self
+    }
+
+    pub fn tile_source(mut self, source: TileSource) -> Self {
+        self.tile_source = source;
+        self
+    }
+
+    /// Tile cache limits (count and GPU memory in bytes)
+    pub fn cache_size(mut self, max_tiles: usize, max_memory: usize) -> Self {
+        self.max_tiles = max_tiles;
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Limits of the cache of downloaded tile files (count and bytes)
+    ///
+    /// Tiles evicted from the GPU are decoded from here instead of being
+    /// downloaded again; this is worth making larger than the texture cache.
+    pub fn byte_cache_size(mut self, max_tiles: usize, max_bytes: usize) -> Self {
+        self.max_byte_tiles = max_tiles;
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Evict the least recently (default) or least frequently used tiles
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    pub fn cell_size(mut self, degrees: f64) -> Self {
+        self.cell_size = degrees;
+        self
+    }
+
+    /// Align the pixel grid to a cell corner at (lon, lat) instead of (0, 0)
+    pub fn grid_origin(mut self, lon: f64, lat: f64) -> Self {
+        self.grid_origin = (lon, lat);
+        self
+    }
+
+    /// Limit the pixels stored in the grid (see `PixelGrid::set_capacity`)
+    pub fn max_pixels(mut self, pixels: Option<usize>) -> Self {
+        self.max_pixels = pixels;
+        self
+    }
+
+    pub fn prefetch_buffer(mut self, rings: u32) -> Self {
+        self.prefetch_buffer = rings;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    pub fn msaa_samples(mut self, samples: u32) -> Self {
+        self.msaa_samples = samples;
+        self
+    }
+
+    /// Number of tiles whose vertex buffers are created up front
+    ///
+    /// Buffers are reused across frames, so this only saves allocations
+    /// in the first frames; views with more tiles create the rest then.
+    pub fn tile_vertex_slots(mut self, slots: usize) -> Self {
+        self.tile_vertex_slots = slots;
+        self
+    }
+
+    /// Number of tile images decoded at once (at least 1)
+    ///
+    /// Natively each is decoded on its own thread; on the web this caps the
+    /// decodes per update instead.
+    pub fn max_parallel_decodes(mut self, decodes: usize) -> Self {
+        self.max_parallel_decodes = decodes;
+        self
+    }
+
+    /// Largest tile width or height decoded, in pixels (at least 1)
+    ///
+    /// Larger images are rejected from their header and the tile is marked
+    /// broken, so a bad source can't exhaust memory or the GPU's textures.
+    pub fn max_tile_dimension(mut self, pixels: u32) -> Self {
+        self.max_tile_dimension = pixels;
+        self
+    }
+
+    /// Number of tile requests waiting for a download at once (at least 1)
+    ///
+    /// Past this, the tiles wanted least recently (e.g. of a view panned
+    /// away from) are dropped before they are downloaded.
+    pub fn max_pending_tiles(mut self, requests: usize) -> Self {
+        self.max_pending_tiles = requests;
+        self
+    }
+
+    /// Ease to the nearest whole zoom level shortly after zooming stops,
+    /// where tiles look crisp (see `MapSystem::set_zoom_settle`)
+    pub fn settle_zoom(mut self, enabled: bool) -> Self {
+        self.settle_delay = enabled.then_some(DEFAULT_SETTLE_DELAY);
+        self
+    }
+
+    /// Raise limits too small to hold `MIN_CACHED_TILES` tiles, replace a
+    /// cell size that isn't a positive number, and clamp the zoom to the
+    /// tile source's levels
+    ///
+    /// A smaller cache would evict tiles of the current view as soon as
+    /// they load.
+    pub fn validated(mut self) -> Self {
+        let tile_size = self.tile_source.tile_size;
+        let min_memory = MIN_CACHED_TILES * tile_memory_size(tile_size, tile_size, 1);
+        if self.max_tiles < MIN_CACHED_TILES {
+            log::warn!(
+                "Tile cache limit of {} tiles is too small, using {}",
+                self.max_tiles,
+                MIN_CACHED_TILES
+            );
+            self.max_tiles = MIN_CACHED_TILES;
+        }
+        if self.max_memory < min_memory {
+            log::warn!(
+                "Tile cache budget of {} bytes is too small, using {}",
+                self.max_memory,
+                min_memory
+            );
+            self.max_memory = min_memory;
+        }
+        // Also catches NaN
+        if !(self.cell_size.is_finite() && self.cell_size > 0.0) {
+            log::warn!(
+                "Pixel grid cell size {} is not a positive number, using {}",
+                self.cell_size,
+                DEFAULT_CELL_SIZE
+            );
+            self.cell_size = DEFAULT_CELL_SIZE;
+        }
+        let (min_zoom, max_zoom) = self.tile_source.zoom_range();
+        let (min_zoom, max_zoom) = (min_zoom as f64, max_zoom as f64);
+        let zoom = if self.zoom.is_finite() {
+            self.zoom.clamp(min_zoom, max_zoom)
+        } else {
+            min_zoom
+        };
+        if zoom != self.zoom {
+            log::warn!(
+                "Zoom {} is outside the source's levels {}-{}, using {}",
+                self.zoom,
+                min_zoom,
+                max_zoom,
+                zoom
+            );
+            self.zoom = zoom;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validated_raises_degenerate_limits() {
+        assert_eq!(MapSystemConfig::default().validated(), MapSystemConfig::default());
+
+        let tiny = MapSystemConfig::default().cache_size(1, 1024).validated();
+        assert_eq!(tiny.max_tiles, MIN_CACHED_TILES);
+        assert_eq!(tiny.max_memory, MIN_CACHED_TILES * 256 * 256 * 4);
+
+        for cell_size in [0.0, -0.001, f64::NAN, f64::INFINITY] {
+            let config = MapSystemConfig::default().cell_size(cell_size).validated();
+            assert_eq!(config.cell_size, DEFAULT_CELL_SIZE);
+        }
+
+        let source = TileSource::default().with_zoom_range(2, 16);
+        let config = MapSystemConfig::default().tile_source(source);
+        assert_eq!(config.clone().zoom(20.0).validated().zoom, 16.0);
+        assert_eq!(config.clone().zoom(-1.0).validated().zoom, 2.0);
+        assert_eq!(config.clone().zoom(f64::NAN).validated().zoom, 2.0);
+        assert_eq!(config.zoom(7.5).validated().zoom, 7.5);
+
+        // An inverted range set on the fields still clamps
+        let mut config = MapSystemConfig::default().zoom(20.0);
+        (config.tile_source.min_zoom, config.tile_source.max_zoom) = (16, 2);
+        assert_eq!(config.validated().zoom, 16.0);
+    }
+}
@@ -0,0 +1,376 @@
+//! Tile image decoding with a limit on how many decodes run at once
+//!
+//! Natively a small pool of threads decodes tiles off the main thread. The
+//! web has no threads, so tiles decode on the main thread there and the
+//! limit caps the decodes per update instead.
+
+use std::collections::HashMap;
+#[cfg(target_arch = "wasm32")]
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+use super::loader::decode_tile_image;
+use super::tile::TileId;
+
+/// Default number of tiles decoded at once
+pub const DEFAULT_MAX_PARALLEL_DECODES: usize = 2;
+
+/// Default cap on the width and height of tile images, in pixels
+///
+/// Tiles are 256 or 512 pixels, 1024 for the largest high-DPI ones; much
+/// larger images come from a misconfigured or hostile source.
+pub const DEFAULT_MAX_TILE_DIMENSION: u32 = 4096;
+
+/// Why a tile file couldn't be decoded
+#[derive(Debug)]
+pub enum TileDecodeError {
+    /// The file ends early, as after an interrupted download; worth retrying
+    Truncated(image::ImageError),
+    /// The file is damaged or not an image the map supports; retrying won't help
+    Corrupt(image::ImageError),
+    /// The image is wider or taller than `max` pixels; it is not decoded
+    TooLarge { width: u32, height: u32, max: u32 },
+}
+
+impl TileDecodeError {
+    /// Check if downloading the tile again may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, TileDecodeError::Truncated(_))
+    }
+}
+
+impl fmt::Display for TileDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileDecodeError::Truncated(e) => write!(f, "truncated image: {}", e),
+            TileDecodeError::Corrupt(e) => write!(f, "invalid image: {}", e),
+            TileDecodeError::TooLarge { width, height, max } => {
+                write!(f, "{}x{} image over the {} pixel limit", width, height, max)
+            }
+        }
+    }
+}
+
+impl Error for TileDecodeError {}
+
+impl From<image::ImageError> for TileDecodeError {
+    fn from(e: image::ImageError) -> Self {
+        if is_truncation(&e) {
+            TileDecodeError::Truncated(e)
+        } else {
+            TileDecodeError::Corrupt(e)
+        }
+    }
+}
+
+/// Check if a decoder ran out of data
+///
+/// PNG reports an unexpected EOF; the JPEG decoder only describes it in
+/// its message.
+fn is_truncation(e: &image::ImageError) -> bool {
+    match e {
+        image::ImageError::IoError(io) => io.kind() == std::io::ErrorKind::UnexpectedEof,
+        image::ImageError::Decoding(decoding) => {
+            let mut source = decoding.source();
+            while let Some(error) = source {
+                if let Some(io) = error.downcast_ref::<std::io::Error>() {
+                    return io.kind() == std::io::ErrorKind::UnexpectedEof;
+                }
+                let message = error.to_string().to_lowercase();
+                if message.contains("not enough bytes") || message.contains("cannot satisfy read") {
+                    return true;
+                }
+                source = error.source();
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+/// A tile file with its decoded image, or why it couldn't be decoded
+pub struct DecodedTile {
+    pub tile_id: TileId,
+    /// The file itself, to decode again if the texture is evicted
+    pub data: Vec<u8>,
+    pub image: Result<image::RgbaImage, TileDecodeError>,
+    /// Span current when the tile was submitted, entered while decoding
+    pub span: tracing::Span,
+}
+
+/// Queued decode with the pool generation it was submitted in
+struct DecodeJob {
+    generation: u64,
+    tile_id: TileId,
+    data: Vec<u8>,
+    /// Largest width or height accepted
+    max_dimension: u32,
+    span: tracing::Span,
+}
+
+impl DecodeJob {
+    /// Decode the file in its span, logging how long it took
+    fn run(self) -> (u64, DecodedTile) {
+        let image = self.span.in_scope(|| {
+            let started = web_time::Instant::now();
+            let image = decode_tile_image(&self.data, self.max_dimension);
+            let elapsed = started.elapsed();
+            match &image {
+                Ok(image) => log::debug!(
+                    "Decoded {:?} ({:?} pixels in {:?})",
+                    self.tile_id,
+                    image.dimensions(),
+                    elapsed
+                ),
+                Err(err) => log::debug!(
+                    "Decoding {:?} failed after {:?}: {}",
+                    self.tile_id,
+                    elapsed,
+                    err
+                ),
+            }
+            image
+        });
+        let decoded = DecodedTile {
+            tile_id: self.tile_id,
+            data: self.data,
+            image,
+            span: self.span,
+        };
+        (self.generation, decoded)
+    }
+}
+
+/// Decodes submitted tile files, at most `max_parallel` at a time
+pub struct DecodePool {
+    /// Submitted tiles without a result yet, with their generation
+    decoding: HashMap<TileId, u64>,
+    /// Advanced by `discard_pending`; older results are dropped
+    generation: u64,
+    /// Largest tile width or height decoded
+    max_dimension: u32,
+    #[cfg(not(target_arch = "wasm32"))]
+    job_tx: std::sync::mpsc::Sender<DecodeJob>,
+    #[cfg(not(target_arch = "wasm32"))]
+    result_rx: std::sync::mpsc::Receiver<(u64, DecodedTile)>,
+    #[cfg(target_arch = "wasm32")]
+    queue: VecDeque<DecodeJob>,
+    #[cfg(target_arch = "wasm32")]
+    max_parallel: usize,
+}
+
+impl DecodePool {
+    /// Create a pool decoding at most `max_parallel` tiles at once (at least 1)
+    pub fn new(max_parallel: usize) -> Self {
+        let max_parallel = max_parallel.max(1);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::sync::{Arc, Mutex, mpsc};
+
+            let (job_tx, job_rx) = mpsc::channel::<DecodeJob>();
+            let (result_tx, result_rx) = mpsc::channel();
+            let job_rx = Arc::new(Mutex::new(job_rx));
+            for i in 0..max_parallel {
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                std::thread::Builder::new()
+                    .name(format!("tile-decode-{}", i))
+                    .spawn(move || {
+                        loop {
+                            // The lock is only held while waiting for a job
+                            let job = match job_rx.lock() {
+                                Ok(rx) => rx.recv(),
+                                Err(_) => break,
+                            };
+                            let Ok(job) = job else {
+                                break; // Pool dropped
+                            };
+                            if result_tx.send(job.run()).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .expect("Failed to spawn tile decode thread");
+            }
+
+            Self {
+                decoding: HashMap::new(),
+                generation: 0,
+                max_dimension: DEFAULT_MAX_TILE_DIMENSION,
+                job_tx,
+                result_rx,
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            Self {
+                decoding: HashMap::new(),
+                generation: 0,
+                max_dimension: DEFAULT_MAX_TILE_DIMENSION,
+                queue: VecDeque::new(),
+                max_parallel,
+            }
+        }
+    }
+
+    /// Queue a tile file for decoding
+    ///
+    /// The decode is traced in the current span, e.g. the tile's `tile_span`.
+    pub fn submit(&mut self, tile_id: TileId, data: Vec<u8>) {
+        let job = DecodeJob {
+            generation: self.generation,
+            tile_id,
+            data,
+            max_dimension: self.max_dimension,
+            span: tracing::Span::current(),
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.job_tx.send(job).is_err() {
+            log::error!("Tile decode threads stopped, dropping tile {:?}", tile_id);
+            return;
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        self.queue.push_back(job);
+
+        self.decoding.insert(tile_id, self.generation);
+    }
+
+    /// Take the tiles decoded since the last call
+    pub fn finished(&mut self) -> Vec<DecodedTile> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let results: Vec<(u64, DecodedTile)> = self.result_rx.try_iter().collect();
+
+        #[cfg(target_arch = "wasm32")]
+        let results: Vec<(u64, DecodedTile)> = {
+            let count = self.queue.len().min(self.max_parallel);
+            self.queue.drain(..count).map(DecodeJob::run).collect()
+        };
+
+        results
+            .into_iter()
+            .filter(|(generation, decoded)| {
+                if self.decoding.get(&decoded.tile_id) == Some(generation) {
+                    self.decoding.remove(&decoded.tile_id);
+                }
+                *generation == self.generation
+            })
+            .map(|(_, decoded)| decoded)
+            .collect()
+    }
+
+    /// Reject tile images wider or taller than `pixels` (at least 1), e.g.
+    /// the device's texture size limit
+    ///
+    /// Applies to tiles submitted from now on.
+    pub fn set_max_dimension(&mut self, pixels: u32) {
+        self.max_dimension = pixels.max(1);
+    }
+
+    /// Get the largest tile width or height decoded
+    pub fn max_dimension(&self) -> u32 {
+        self.max_dimension
+    }
+
+    /// Check if a tile is queued or being decoded
+    pub fn is_decoding(&self, tile_id: &TileId) -> bool {
+        self.decoding.contains_key(tile_id)
+    }
+
+    /// Number of tiles queued or being decoded
+    pub fn pending_count(&self) -> usize {
+        self.decoding.len()
+    }
+
+    /// Drop the results of all decodes submitted so far
+    pub fn discard_pending(&mut self) {
+        self.generation += 1;
+        self.decoding.clear();
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Collect results until `count` arrived or a few seconds passed
+    fn wait_for(pool: &mut DecodePool, count: usize) -> Vec<DecodedTile> {
+        let mut decoded = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while decoded.len() < count && Instant::now() < deadline {
+            decoded.extend(pool.finished());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        decoded
+    }
+
+    fn encode(format: image::ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        let image = image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([x as u8, y as u8, 7]));
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(&mut std::io::Cursor::new(&mut data), format)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_truncated_files_are_retryable() {
+        let max = DEFAULT_MAX_TILE_DIMENSION;
+        let png = encode(image::ImageFormat::Png);
+        let jpeg = encode(image::ImageFormat::Jpeg);
+        for data in [&png[..png.len() / 2], &jpeg[..40]] {
+            assert!(decode_tile_image(data, max).unwrap_err().is_retryable());
+        }
+
+        assert!(!decode_tile_image(b"<html>Not found</html>", max).unwrap_err().is_retryable());
+        let mut corrupt = png.clone();
+        corrupt[40..60].iter_mut().for_each(|b| *b ^= 0xff);
+        assert!(!decode_tile_image(&corrupt, max).unwrap_err().is_retryable());
+    }
+
+    #[test]
+    fn test_oversize_images_are_rejected() {
+        let png = encode(image::ImageFormat::Png);
+        let err = decode_tile_image(&png, 32).unwrap_err();
+        assert!(matches!(err, TileDecodeError::TooLarge { width: 64, height: 64, max: 32 }));
+        assert!(!err.is_retryable());
+        assert!(decode_tile_image(&png, 64).is_ok());
+
+        let mut pool = DecodePool::new(1);
+        pool.set_max_dimension(32);
+        pool.submit(TileId::new(0, 0, 1), png);
+        let decoded = wait_for(&mut pool, 1);
+        assert!(matches!(decoded[0].image, Err(TileDecodeError::TooLarge { .. })));
+    }
+
+    #[test]
+    fn test_decodes_and_discards() {
+        let png = encode(image::ImageFormat::Png);
+
+        let mut pool = DecodePool::new(2);
+        let (good, bad) = (TileId::new(0, 0, 1), TileId::new(1, 0, 1));
+        pool.submit(good, png.clone());
+        pool.submit(bad, b"not an image".to_vec());
+        assert!(pool.is_decoding(&good));
+
+        let mut decoded = wait_for(&mut pool, 2);
+        decoded.sort_by_key(|tile| tile.tile_id.x);
+        assert_eq!(decoded[0].image.as_ref().unwrap().dimensions(), (64, 64));
+        assert!(decoded[1].image.is_err());
+        assert_eq!(pool.pending_count(), 0);
+
+        // Only the decode submitted after discarding comes back
+        pool.submit(good, png);
+        pool.discard_pending();
+        assert!(!pool.is_decoding(&good));
+        pool.submit(bad, Vec::new());
+        let decoded = wait_for(&mut pool, 1);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].tile_id, bad);
+    }
+}
@@ -0,0 +1,70 @@
+//! Events reported to an embedding application
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use super::tile::TileId;
+
+/// Something that happened inside the map system
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapEvent {
+    /// A tile was downloaded, decoded and cached
+    TileLoaded(TileId),
+    /// A tile failed to download or decode
+    TileFailed(TileId, String),
+}
+
+/// Optional event listener
+///
+/// Without a subscriber events are never constructed, so an embedder that
+/// doesn't listen pays nothing.
+#[derive(Debug, Default)]
+pub struct EventSink {
+    tx: Option<Sender<MapEvent>>,
+}
+
+impl EventSink {
+    /// Start listening, replacing any previous subscriber
+    pub fn subscribe(&mut self) -> Receiver<MapEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.tx = Some(tx);
+        rx
+    }
+
+    /// Check if someone is listening
+    pub fn is_listening(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Send the event built by `event` if someone is listening
+    ///
+    /// A dropped receiver unsubscribes.
+    pub fn emit(&mut self, event: impl FnOnce() -> MapEvent) {
+        if let Some(tx) = &self.tx
+            && tx.send(event()).is_err()
+        {
+            self.tx = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_only_with_subscriber() {
+        let mut sink = EventSink::default();
+        sink.emit(|| unreachable!("no subscriber"));
+
+        let rx = sink.subscribe();
+        let tile = TileId::new(1, 2, 3);
+        sink.emit(|| MapEvent::TileLoaded(tile));
+        sink.emit(|| MapEvent::TileFailed(tile, "404".to_owned()));
+        assert_eq!(rx.try_recv(), Ok(MapEvent::TileLoaded(tile)));
+        assert_eq!(rx.try_recv(), Ok(MapEvent::TileFailed(tile, "404".to_owned())));
+
+        drop(rx);
+        sink.emit(|| MapEvent::TileLoaded(tile));
+        assert!(!sink.is_listening());
+    }
+}
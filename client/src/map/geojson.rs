@@ -0,0 +1,419 @@
+//! GeoJSON parsing into map overlays
+//!
+//! Points become markers, LineStrings become polylines and Polygons become
+//! filled shapes. Styling follows the simplestyle-spec `properties` keys
+//! (`marker-color`, `marker-size`, `stroke`, `stroke-width`, `stroke-opacity`,
+//! `fill`, `fill-opacity`) where present.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use super::overlay::{Marker, Polygon, Polyline};
+
+const DEFAULT_MARKER_COLOR: [f32; 4] = [0.906, 0.298, 0.235, 1.0]; // #e74c3c
+const DEFAULT_STROKE_COLOR: [f32; 4] = [0.333, 0.333, 0.333, 1.0]; // #555555
+const DEFAULT_STROKE_WIDTH: f32 = 2.0;
+const DEFAULT_FILL_OPACITY: f32 = 0.6;
+
+/// GeoJSON parse error
+#[derive(Debug)]
+pub enum GeoJsonError {
+    /// Input is not valid JSON
+    Json(serde_json::Error),
+    /// JSON is valid but not a supported GeoJSON structure
+    Invalid(String),
+}
+
+impl fmt::Display for GeoJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoJsonError::Json(e) => write!(f, "invalid JSON: {}", e),
+            GeoJsonError::Invalid(msg) => write!(f, "invalid GeoJSON: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for GeoJsonError {}
+
+impl From<serde_json::Error> for GeoJsonError {
+    fn from(e: serde_json::Error) -> Self {
+        GeoJsonError::Json(e)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> GeoJsonError {
+    GeoJsonError::Invalid(msg.into())
+}
+
+/// Overlay features parsed from a GeoJSON document
+#[derive(Debug, Default)]
+pub struct GeoJsonOverlays {
+    pub markers: Vec<Marker>,
+    pub polylines: Vec<Polyline>,
+    pub polygons: Vec<Polygon>,
+}
+
+/// Parse a GeoJSON FeatureCollection, Feature or bare geometry
+pub fn parse(input: &str) -> Result<GeoJsonOverlays, GeoJsonError> {
+    let root: Value = serde_json::from_str(input)?;
+    let mut overlays = GeoJsonOverlays::default();
+
+    match type_of(&root)? {
+        "FeatureCollection" => {
+            let features = root
+                .get("features")
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid("FeatureCollection without a features array"))?;
+            for feature in features {
+                parse_feature(feature, &mut overlays)?;
+            }
+        }
+        "Feature" => parse_feature(&root, &mut overlays)?,
+        _ => parse_geometry(&root, &Style::default(), &mut overlays)?,
+    }
+
+    Ok(overlays)
+}
+
+fn type_of(value: &Value) -> Result<&str, GeoJsonError> {
+    value
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("object without a type member"))
+}
+
+fn parse_feature(feature: &Value, overlays: &mut GeoJsonOverlays) -> Result<(), GeoJsonError> {
+    if type_of(feature)? != "Feature" {
+        return Err(invalid("features must be of type Feature"));
+    }
+
+    let style = feature
+        .get("properties")
+        .and_then(Value::as_object)
+        .map(Style::from_properties)
+        .unwrap_or_default();
+
+    match feature.get("geometry") {
+        // Features without geometry are valid but have nothing to draw
+        None | Some(Value::Null) => Ok(()),
+        Some(geometry) => parse_geometry(geometry, &style, overlays),
+    }
+}
+
+fn parse_geometry(
+    geometry: &Value,
+    style: &Style,
+    overlays: &mut GeoJsonOverlays,
+) -> Result<(), GeoJsonError> {
+    let kind = type_of(geometry)?;
+
+    if kind == "GeometryCollection" {
+        let geometries = geometry
+            .get("geometries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("GeometryCollection without a geometries array"))?;
+        for child in geometries {
+            parse_geometry(child, style, overlays)?;
+        }
+        return Ok(());
+    }
+
+    let coordinates = geometry
+        .get("coordinates")
+        .ok_or_else(|| invalid(format!("{} without coordinates", kind)))?;
+
+    match kind {
+        "Point" => overlays
+            .markers
+            .push(style.marker(parse_position(coordinates)?)),
+        "MultiPoint" => {
+            for position in as_array(coordinates)? {
+                overlays
+                    .markers
+                    .push(style.marker(parse_position(position)?));
+            }
+        }
+        "LineString" => overlays
+            .polylines
+            .push(style.polyline(parse_line(coordinates)?)),
+        "MultiLineString" => {
+            for line in as_array(coordinates)? {
+                overlays.polylines.push(style.polyline(parse_line(line)?));
+            }
+        }
+        "Polygon" => overlays
+            .polygons
+            .push(style.polygon(parse_polygon(coordinates)?)),
+        "MultiPolygon" => {
+            for polygon in as_array(coordinates)? {
+                overlays
+                    .polygons
+                    .push(style.polygon(parse_polygon(polygon)?));
+            }
+        }
+        other => return Err(invalid(format!("unsupported geometry type {}", other))),
+    }
+
+    Ok(())
+}
+
+fn as_array(value: &Value) -> Result<&Vec<Value>, GeoJsonError> {
+    value
+        .as_array()
+        .ok_or_else(|| invalid("coordinates must be an array"))
+}
+
+/// Parse a `[lon, lat, (alt)]` position
+fn parse_position(value: &Value) -> Result<(f64, f64), GeoJsonError> {
+    let position = as_array(value)?;
+    if position.len() < 2 {
+        return Err(invalid("position needs at least two numbers"));
+    }
+
+    let lon = position[0].as_f64();
+    let lat = position[1].as_f64();
+    match (lon, lat) {
+        (Some(lon), Some(lat)) if lon.is_finite() && lat.is_finite() => Ok((lon, lat)),
+        _ => Err(invalid("position must contain finite numbers")),
+    }
+}
+
+fn parse_line(value: &Value) -> Result<Vec<(f64, f64)>, GeoJsonError> {
+    let points = as_array(value)?
+        .iter()
+        .map(parse_position)
+        .collect::<Result<Vec<_>, _>>()?;
+    if points.len() < 2 {
+        return Err(invalid("LineString needs at least two positions"));
+    }
+    Ok(points)
+}
+
+/// Parse a linear ring, dropping the repeated closing position
+fn parse_ring(value: &Value) -> Result<Vec<(f64, f64)>, GeoJsonError> {
+    let mut ring = parse_line(value)?;
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    if ring.len() < 3 {
+        return Err(invalid(
+            "polygon ring needs at least three distinct positions",
+        ));
+    }
+    Ok(ring)
+}
+
+fn parse_polygon(value: &Value) -> Result<Vec<Vec<(f64, f64)>>, GeoJsonError> {
+    let rings = as_array(value)?
+        .iter()
+        .map(parse_ring)
+        .collect::<Result<Vec<_>, _>>()?;
+    if rings.is_empty() {
+        return Err(invalid("Polygon needs an exterior ring"));
+    }
+    Ok(rings)
+}
+
+/// Per-feature style read from `properties`
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    marker_color: [f32; 4],
+    marker_size: f32,
+    stroke: [f32; 4],
+    stroke_width: f32,
+    fill: [f32; 4],
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        let mut fill = DEFAULT_STROKE_COLOR;
+        fill[3] = DEFAULT_FILL_OPACITY;
+        Self {
+            marker_color: DEFAULT_MARKER_COLOR,
+            marker_size: 12.0,
+            stroke: DEFAULT_STROKE_COLOR,
+            stroke_width: DEFAULT_STROKE_WIDTH,
+            fill,
+        }
+    }
+}
+
+impl Style {
+    fn from_properties(properties: &Map<String, Value>) -> Self {
+        let mut style = Style::default();
+        let color = |key: &str| {
+            properties
+                .get(key)
+                .and_then(Value::as_str)
+                .and_then(parse_hex_color)
+        };
+        let number = |key: &str| {
+            properties
+                .get(key)
+                .and_then(Value::as_f64)
+                .map(|v| v as f32)
+        };
+
+        if let Some(c) = color("marker-color") {
+            style.marker_color = c;
+        }
+        if let Some(size) = properties.get("marker-size").and_then(Value::as_str) {
+            style.marker_size = match size {
+                "small" => 8.0,
+                "large" => 16.0,
+                _ => 12.0,
+            };
+        }
+        if let Some(c) = color("stroke") {
+            style.stroke = c;
+        }
+        if let Some(opacity) = number("stroke-opacity") {
+            style.stroke[3] = opacity.clamp(0.0, 1.0);
+        }
+        if let Some(width) = number("stroke-width") {
+            style.stroke_width = width.max(0.0);
+        }
+        if let Some(c) = color("fill") {
+            style.fill = [c[0], c[1], c[2], style.fill[3]];
+        }
+        if let Some(opacity) = number("fill-opacity") {
+            style.fill[3] = opacity.clamp(0.0, 1.0);
+        }
+
+        style
+    }
+
+    fn marker(&self, position: (f64, f64)) -> Marker {
+        Marker {
+            position,
+            color: self.marker_color,
+            size: self.marker_size,
+        }
+    }
+
+    fn polyline(&self, points: Vec<(f64, f64)>) -> Polyline {
+        Polyline {
+            points,
+            color: self.stroke,
+            width: self.stroke_width,
+        }
+    }
+
+    fn polygon(&self, mut rings: Vec<Vec<(f64, f64)>>) -> Polygon {
+        let exterior = rings.remove(0);
+        Polygon {
+            exterior,
+            holes: rings,
+            fill_color: self.fill,
+            stroke_color: self.stroke,
+            stroke_width: self.stroke_width,
+        }
+    }
+}
+
+/// Parse `#rgb` or `#rrggbb` into an opaque RGBA color
+fn parse_hex_color(hex: &str) -> Option<[f32; 4]> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    // Also makes the byte slicing below safe for non-ASCII text
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok().map(|v| v as f32 / 255.0);
+
+    match digits.len() {
+        3 => {
+            let expand = |i: usize| channel(&digits[i..i + 1].repeat(2));
+            Some([expand(0)?, expand(1)?, expand(2)?, 1.0])
+        }
+        6 => Some([
+            channel(&digits[0..2])?,
+            channel(&digits[2..4])?,
+            channel(&digits[4..6])?,
+            1.0,
+        ]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r##"{
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "marker-color": "#00ff00", "marker-size": "large" },
+                "geometry": { "type": "Point", "coordinates": [126.978, 37.5665] }
+            },
+            {
+                "type": "Feature",
+                "properties": { "stroke": "#f00", "stroke-width": 4 },
+                "geometry": { "type": "LineString", "coordinates": [[126.9, 37.5], [127.0, 37.6]] }
+            },
+            {
+                "type": "Feature",
+                "properties": { "fill": "#0000ff", "fill-opacity": 0.25 },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [
+                        [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                        [[2, 2], [4, 2], [4, 4], [2, 2]]
+                    ]
+                }
+            },
+            { "type": "Feature", "properties": null, "geometry": null }
+        ]
+    }"##;
+
+    #[test]
+    fn test_parse_feature_collection() {
+        let overlays = parse(SAMPLE).unwrap();
+        assert_eq!(overlays.markers.len(), 1);
+        assert_eq!(overlays.polylines.len(), 1);
+        assert_eq!(overlays.polygons.len(), 1);
+
+        let marker = &overlays.markers[0];
+        assert_eq!(marker.position, (126.978, 37.5665));
+        assert_eq!(marker.color, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(marker.size, 16.0);
+
+        let line = &overlays.polylines[0];
+        assert_eq!(line.points.len(), 2);
+        assert_eq!(line.color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(line.width, 4.0);
+
+        let polygon = &overlays.polygons[0];
+        assert_eq!(polygon.exterior.len(), 4); // closing point dropped
+        assert_eq!(polygon.holes.len(), 1);
+        assert_eq!(polygon.fill_color, [0.0, 0.0, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        assert!(matches!(parse("{ not json"), Err(GeoJsonError::Json(_))));
+        assert!(matches!(
+            parse(r#"{ "type": "FeatureCollection" }"#),
+            Err(GeoJsonError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse(r#"{ "type": "Point", "coordinates": ["a", 1] }"#),
+            Err(GeoJsonError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse(r#"{ "type": "LineString", "coordinates": [[0, 0]] }"#),
+            Err(GeoJsonError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(parse_hex_color("#f00"), Some([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(parse_hex_color("0000ff"), Some([0.0, 0.0, 1.0, 1.0]));
+        for bad in ["#é1", "#aé", "#ééé", "#12345", "#+1+2+3", "red"] {
+            assert_eq!(parse_hex_color(bad), None, "{}", bad);
+        }
+    }
+}
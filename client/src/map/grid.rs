@@ -1,10 +1,91 @@
 //! Pixel grid overlay for drawing on the map
+//!
+//! The grid is owned and mutated by the render thread. Other threads (e.g.
+//! a network task receiving remote edits) get a `PixelSender` from
+//! `PixelGrid::sender` and queue `PixelOp`s on it; `PixelGrid::update`
+//! applies them in order before rebuilding the instances, so the store is
+//! never shared and needs no locking.
 
 use bytemuck::{Pod, Zeroable};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::f32::consts::TAU;
+use std::sync::mpsc;
+use std::time::Duration;
 use wgpu::include_wgsl;
 use wgpu::util::DeviceExt;
 
+use super::history::UndoUnit;
+use super::renderer::{BlendMode, target_blend};
+use super::snapshot::{self, SnapshotError};
+
+/// Side length of a spatial index chunk in grid cells
+const CHUNK_SIZE: i64 = 64;
+
+/// Fill color of the selection highlight
+const SELECTION_COLOR: [f32; 4] = [0.2, 0.6, 1.0, 0.3];
+
+/// Tint of the cell under the cursor
+const HOVER_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.4];
+
+/// Color and width of guide lines
+const GUIDE_STYLE: GridOutline = GridOutline {
+    color: [0.0, 0.8, 1.0, 0.9],
+    width: 1.0,
+};
+
+/// Duration of one fade out and back in of the highlights, in seconds
+const HIGHLIGHT_PERIOD: f32 = 1.6;
+
+/// Lowest highlight opacity during a pulse, relative to its color's alpha
+const HIGHLIGHT_MIN_ALPHA: f32 = 0.5;
+
+/// Smallest on-screen cell width, in pixels, at which cells are drawn one
+/// by one
+///
+/// Further out the cells would be sub-pixel specks, and there could be
+/// millions of them on screen, so they are merged into blocks.
+pub const MIN_CELL_PIXELS: f64 = 1.0;
+
+/// Largest rectangle, in cells, that `fill_region` fills at once
+pub const MAX_FILL_CELLS: u64 = 256 * 256;
+
+/// Default limit of stored pixels before the least recently set are evicted
+pub const DEFAULT_MAX_PIXELS: usize = 1 << 20;
+
+/// Smallest on-screen width of a block of merged cells, in pixels
+///
+/// Bounds the number of blocks to a few per screen pixel area of this size.
+const MIN_BLOCK_PIXELS: f64 = 4.0;
+
+/// Smallest on-screen cell width, in pixels, at which cell outlines are drawn
+///
+/// Closer together the lines would cover the cells they outline.
+const MIN_OUTLINE_CELL_PIXELS: f64 = 4.0;
+
+/// Most outline lines drawn in each direction, a bound for views spanning
+/// the antimeridian
+const MAX_OUTLINE_LINES: i64 = 2048;
+
+/// Changes a `PixelSender` can queue before `send` waits for the grid to
+/// apply them, and the most applied per `apply_queued`
+pub const OP_QUEUE_CAPACITY: usize = 4096;
+
+/// Number of instance buffers rebuilt in rotation
+const INSTANCE_BUFFER_RING: usize = 3;
+
+/// Smallest instance buffer allocated, in cells
+const MIN_BUFFER_INSTANCES: u64 = 1024;
+
+/// Unit quad shared by all cells, as triangles (0, 0)-(1, 0)-(1, 1) and (0, 0)-(1, 1)-(0, 1)
+const UNIT_QUAD: [[f32; 2]; 6] = [
+    [0.0, 0.0],
+    [1.0, 0.0],
+    [1.0, 1.0],
+    [0.0, 0.0],
+    [1.0, 1.0],
+    [0.0, 1.0],
+];
+
 /// Grid vertex for colored quads
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -28,10 +109,149 @@ impl GridVertex {
     }
 }
 
+/// How placed pixels are drawn within their cell
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelShape {
+    /// Fill the whole cell
+    #[default]
+    Square,
+    /// Round dot touching the cell's edges
+    Circle,
+}
+
+/// Lines drawn along the cell edges
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridOutline {
+    /// sRGB line color, like pixel colors
+    pub color: [f32; 4],
+    /// Line width in screen pixels, the same at any zoom
+    pub width: f32,
+}
+
+impl Default for GridOutline {
+    /// Subtle light gray, 1 pixel wide
+    fn default() -> Self {
+        Self {
+            color: [0.85, 0.85, 0.85, 0.35],
+            width: 1.0,
+        }
+    }
+}
+
+/// Guide line along a lattice line, drawn across the whole view to help
+/// align drawings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Guide {
+    /// Along the west edge of column `x`
+    Vertical(i64),
+    /// Along the south edge of row `y`
+    Horizontal(i64),
+}
+
+/// Pixel change queued from another thread (see `PixelSender`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelOp {
+    /// `set_pixel`
+    Set(GridCoord, [f32; 4]),
+    /// `blend_pixel`
+    Blend(GridCoord, [f32; 4]),
+    /// `remove_pixel`
+    Remove(GridCoord),
+    /// `clear_bounds`
+    ClearBounds(GridCoord, GridCoord),
+}
+
+/// Queues pixel changes for a grid from any thread
+///
+/// Changes take effect on the grid's next `update` (or `apply_queued`).
+/// At most `OP_QUEUE_CAPACITY` wait at once, so a flood of remote edits
+/// slows its sender down instead of growing memory and stalling a frame.
+#[derive(Clone, Debug)]
+pub struct PixelSender(mpsc::SyncSender<PixelOp>);
+
+impl PixelSender {
+    /// Queue a change, returning false if the grid was dropped
+    ///
+    /// Waits while the queue is full, so don't call it on the thread that
+    /// updates the grid.
+    pub fn send(&self, op: PixelOp) -> bool {
+        self.0.send(op).is_ok()
+    }
+}
+
+/// Per-instance data for one grid cell
+///
+/// The cell is drawn as the parallelogram `origin + u * axis_x + v * axis_y`
+/// for `u, v` in 0..1, all in NDC. Cells are small enough that projecting
+/// three corners is indistinguishable from projecting all four.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GridInstance {
+    pub origin: [f32; 2],
+    pub axis_x: [f32; 2],
+    pub axis_y: [f32; 2],
+    pub color: [f32; 4],
+    /// `PixelShape` as its discriminant (0 = square, 1 = circle)
+    pub shape: u32,
+}
+
+impl GridInstance {
+    const SIZE: u64 = std::mem::size_of::<GridInstance>() as u64;
+
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        1 => Float32x2,
+        2 => Float32x2,
+        3 => Float32x2,
+        4 => Float32x4,
+        5 => Uint32,
+    ];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: Self::SIZE as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Uniforms of the grid pipeline (padded to 16 bytes for WebGL)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct GridUniforms {
+    /// Alpha multiplier of the selection and hover highlights
+    highlight_alpha: f32,
+    /// Index of the first highlight instance; pixels come before it
+    first_highlight: u32,
+    /// Nonzero to smooth the edges of square cells
+    edge_antialiasing: u32,
+    _padding: u32,
+}
+
+/// Highlight alpha multiplier `elapsed` into a pulse, easing between
+/// `HIGHLIGHT_MIN_ALPHA` and 1
+pub fn highlight_pulse(elapsed: Duration) -> f32 {
+    let phase = (elapsed.as_secs_f32() / HIGHLIGHT_PERIOD).fract();
+    let wave = 0.5 + 0.5 * (phase * TAU).cos();
+    HIGHLIGHT_MIN_ALPHA + (1.0 - HIGHLIGHT_MIN_ALPHA) * wave
+}
+
+/// Layout of the shared unit quad (location 0)
+fn unit_quad_desc() -> wgpu::VertexBufferLayout<'static> {
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x2];
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &ATTRIBS,
+    }
+}
+
 /// A single pixel in the grid
 #[derive(Clone, Copy, Debug)]
 pub struct Pixel {
-    pub color: [f32; 4], // RGBA
+    /// sRGB-encoded RGBA with straight alpha, as in hex codes: `#FF8000`
+    /// is `[1.0, 128.0 / 255.0, 0.0, 1.0]`
+    pub color: [f32; 4],
 }
 
 impl Default for Pixel {
@@ -53,22 +273,90 @@ impl GridCoord {
     pub fn new(x: i64, y: i64) -> Self {
         Self { x, y }
     }
+
+    /// Spatial index chunk containing this cell
+    fn chunk(&self) -> (i64, i64) {
+        (self.x.div_euclid(CHUNK_SIZE), self.y.div_euclid(CHUNK_SIZE))
+    }
 }
 
 /// Pixel grid overlay system
 pub struct PixelGrid {
-    /// Stored pixels (sparse storage)
-    pixels: HashMap<GridCoord, Pixel>,
+    /// Stored pixels (sparse storage) with their last-set stamp
+    pixels: HashMap<GridCoord, (Pixel, u64)>,
+
+    /// Spatial index: occupied cells per chunk
+    chunks: HashMap<(i64, i64), HashSet<GridCoord>>,
+
+    /// Set order for LRU eviction (stamp -> coord), oldest first
+    recency: BTreeMap<u64, GridCoord>,
+    next_stamp: u64,
+
+    /// Maximum number of stored pixels (None = unbounded)
+    capacity: Option<usize>,
 
     /// Grid cell size in world units (degrees)
     pub cell_size: f64,
 
-    /// Render pipeline
-    render_pipeline: wgpu::RenderPipeline,
+    /// World position (lon, lat) of the corner of cell (0, 0)
+    origin: (f64, f64),
+
+    /// Render pipeline (None when headless)
+    render_pipeline: Option<wgpu::RenderPipeline>,
+    /// Format and sample count the pipeline draws into
+    target: Option<(wgpu::TextureFormat, u32)>,
+    /// How cell colors combine with the layers below
+    blend_mode: BlendMode,
+    /// The pipeline was built for another blend mode
+    pipeline_stale: bool,
+
+    /// Unit quad instanced once per cell (None when headless)
+    quad_buffer: Option<wgpu::Buffer>,
+
+    /// Holds the `GridUniforms` (None when headless)
+    uniform_buffer: Option<wgpu::Buffer>,
+    uniform_bind_group: Option<wgpu::BindGroup>,
+    /// Uniforms last written to the uniform buffer
+    written_uniforms: Option<GridUniforms>,
+
+    /// Reusable instance buffers, written in turn so a rebuild never touches
+    /// the buffer the previous frame is drawing from
+    instance_buffers: [Option<wgpu::Buffer>; INSTANCE_BUFFER_RING],
+    /// Ring slot holding the current instances (None when there is nothing to draw)
+    instance_buffer: Option<usize>,
+    instance_count: u32,
+    /// Instance buffers created so far, for frame stats
+    buffers_created: u32,
+
+    /// Selected rectangle (inclusive min, max), drawn highlighted
+    selection: Option<(GridCoord, GridCoord)>,
+
+    /// Cell under the cursor, drawn tinted
+    hover: Option<GridCoord>,
+
+    /// Shape of placed pixels (highlights are always rectangles)
+    shape: PixelShape,
+
+    /// Smooth square cell edges in the shader
+    edge_antialiasing: bool,
+
+    /// Lines along the cell edges (None = not drawn)
+    outline: Option<GridOutline>,
+
+    /// Guide lines, in the order they were added
+    guides: Vec<Guide>,
 
-    /// Cached vertex buffer (rebuilt when pixels change)
-    vertex_buffer: Option<wgpu::Buffer>,
-    vertex_count: u32,
+    /// Changes queued by `PixelSender`s, applied on update
+    op_tx: mpsc::SyncSender<PixelOp>,
+    op_rx: mpsc::Receiver<PixelOp>,
+
+    /// Current alpha multiplier of the highlights (see `highlight_pulse`)
+    highlight_alpha: f32,
+    /// Number of pixel instances, drawn before the highlights
+    highlight_start: u32,
+
+    /// Camera used for the last rebuild
+    last_camera: Option<super::camera::MapCamera>,
 
     /// Dirty flag for buffer rebuild
     dirty: bool,
@@ -77,125 +365,543 @@ pub struct PixelGrid {
 impl PixelGrid {
     /// Create a new pixel grid
     /// cell_size: size of each pixel in degrees (e.g., 0.0001 for ~10m at equator)
-    pub fn new(device: &wgpu::Device, texture_format: wgpu::TextureFormat, cell_size: f64) -> Self {
-        let shader = device.create_shader_module(include_wgsl!("../shader/grid.wgsl"));
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Grid Pipeline Layout"),
-            bind_group_layouts: &[],
-            push_constant_ranges: &[],
-        });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Grid Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[GridVertex::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: texture_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+    /// sample_count: MSAA samples of the render target (1 = no multisampling)
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+        cell_size: f64,
+    ) -> Self {
+        let mut grid = Self::new_headless(cell_size);
+        grid.recreate_pipeline(device, texture_format, sample_count);
+        grid
+    }
 
+    /// Create a pixel grid without GPU resources (storage and coordinate math only)
+    pub fn new_headless(cell_size: f64) -> Self {
+        let (op_tx, op_rx) = mpsc::sync_channel(OP_QUEUE_CAPACITY);
         Self {
             pixels: HashMap::new(),
+            chunks: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_stamp: 0,
+            capacity: None,
             cell_size,
-            render_pipeline,
-            vertex_buffer: None,
-            vertex_count: 0,
+            origin: (0.0, 0.0),
+            render_pipeline: None,
+            target: None,
+            blend_mode: BlendMode::default(),
+            pipeline_stale: false,
+            quad_buffer: None,
+            uniform_buffer: None,
+            uniform_bind_group: None,
+            written_uniforms: None,
+            instance_buffers: Default::default(),
+            instance_buffer: None,
+            instance_count: 0,
+            buffers_created: 0,
+            selection: None,
+            hover: None,
+            shape: PixelShape::default(),
+            edge_antialiasing: false,
+            outline: None,
+            guides: Vec::new(),
+            op_tx,
+            op_rx,
+            highlight_alpha: 1.0,
+            highlight_start: 0,
+            last_camera: None,
             dirty: false,
         }
     }
 
     /// Set a pixel at grid coordinates
+    ///
+    /// If a capacity is set and exceeded, the least recently set pixels are
+    /// evicted.
     pub fn set_pixel(&mut self, coord: GridCoord, color: [f32; 4]) {
-        self.pixels.insert(coord, Pixel { color });
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+
+        match self.pixels.insert(coord, (Pixel { color }, stamp)) {
+            Some((_, old_stamp)) => {
+                self.recency.remove(&old_stamp);
+            }
+            None => {
+                self.chunks.entry(coord.chunk()).or_default().insert(coord);
+            }
+        }
+        self.recency.insert(stamp, coord);
         self.dirty = true;
+
+        self.evict_to_capacity();
+    }
+
+    /// Get a handle for queueing pixel changes from other threads
+    pub fn sender(&self) -> PixelSender {
+        PixelSender(self.op_tx.clone())
+    }
+
+    /// Apply the changes queued by `PixelSender`s, returning how many
+    ///
+    /// Applies at most `OP_QUEUE_CAPACITY`, leaving the rest (e.g. sent
+    /// while applying) for the next call. Called by `update`; headless
+    /// grids call it themselves.
+    pub fn apply_queued(&mut self) -> usize {
+        let mut applied = 0;
+        while applied < OP_QUEUE_CAPACITY
+            && let Ok(op) = self.op_rx.try_recv()
+        {
+            match op {
+                PixelOp::Set(coord, color) => self.set_pixel(coord, color),
+                PixelOp::Blend(coord, color) => self.blend_pixel(coord, color),
+                PixelOp::Remove(coord) => {
+                    self.remove_pixel(&coord);
+                }
+                PixelOp::ClearBounds(min, max) => {
+                    self.clear_bounds(min, max);
+                }
+            }
+            applied += 1;
+        }
+        applied
+    }
+
+    /// Alpha-composite a color over the pixel at grid coordinates
+    ///
+    /// Uses source-over with straight (non-premultiplied) alpha; a missing
+    /// pixel counts as transparent. `set_pixel` overwrites instead.
+    pub fn blend_pixel(&mut self, coord: GridCoord, color: [f32; 4]) {
+        let existing = self.get_pixel(&coord).copied().unwrap_or_default();
+        self.set_pixel(coord, composite_over(color, existing.color));
     }
 
     /// Get a pixel at grid coordinates
     pub fn get_pixel(&self, coord: &GridCoord) -> Option<&Pixel> {
-        self.pixels.get(coord)
+        self.pixels.get(coord).map(|(pixel, _)| pixel)
     }
 
     /// Remove a pixel
     pub fn remove_pixel(&mut self, coord: &GridCoord) -> Option<Pixel> {
         self.dirty = true;
-        self.pixels.remove(coord)
+        let (pixel, stamp) = self.pixels.remove(coord)?;
+        self.recency.remove(&stamp);
+        self.unindex(coord);
+        Some(pixel)
+    }
+
+    /// Drop a cell from the spatial index
+    fn unindex(&mut self, coord: &GridCoord) {
+        let chunk = coord.chunk();
+        if let Some(cells) = self.chunks.get_mut(&chunk) {
+            cells.remove(coord);
+            if cells.is_empty() {
+                self.chunks.remove(&chunk);
+            }
+        }
+    }
+
+    /// Iterate over pixels inside an inclusive grid rectangle
+    ///
+    /// Only chunks overlapping the rectangle are visited, so empty regions
+    /// cost nothing. Iteration order is unspecified.
+    pub fn pixels_in_bounds(
+        &self,
+        min: GridCoord,
+        max: GridCoord,
+    ) -> impl Iterator<Item = (GridCoord, &Pixel)> {
+        let (cmin, cmax) = (min.chunk(), max.chunk());
+        let in_bounds =
+            move |c: &GridCoord| c.x >= min.x && c.x <= max.x && c.y >= min.y && c.y <= max.y;
+
+        // Walk the chunk range when it is small, otherwise filter the occupied chunks
+        let span = (cmax.0 - cmin.0 + 1).saturating_mul(cmax.1 - cmin.1 + 1);
+        let chunks: Vec<&HashSet<GridCoord>> = if min.x > max.x || min.y > max.y {
+            Vec::new()
+        } else if (span as usize) <= self.chunks.len() {
+            (cmin.1..=cmax.1)
+                .flat_map(|cy| (cmin.0..=cmax.0).map(move |cx| (cx, cy)))
+                .filter_map(|chunk| self.chunks.get(&chunk))
+                .collect()
+        } else {
+            self.chunks
+                .iter()
+                .filter(|((cx, cy), _)| {
+                    (cmin.0..=cmax.0).contains(cx) && (cmin.1..=cmax.1).contains(cy)
+                })
+                .map(|(_, cells)| cells)
+                .collect()
+        };
+
+        chunks
+            .into_iter()
+            .flatten()
+            .filter(move |c| in_bounds(c))
+            .map(|c| (*c, &self.pixels[c].0))
     }
 
     /// Clear all pixels
     pub fn clear(&mut self) {
         self.pixels.clear();
+        self.chunks.clear();
+        self.recency.clear();
         self.dirty = true;
     }
 
+    /// Limit the number of stored pixels, evicting the least recently set
+    ///
+    /// Gives a hard memory ceiling on shared canvases where remote clients
+    /// could otherwise set arbitrarily many distant cells.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+        self.evict_to_capacity();
+    }
+
+    /// Maximum number of stored pixels (None = unbounded)
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    fn evict_to_capacity(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+
+        let mut evicted = 0;
+        while self.pixels.len() > capacity {
+            let Some((_, coord)) = self.recency.pop_first() else {
+                break;
+            };
+            self.pixels.remove(&coord);
+            self.unindex(&coord);
+            evicted += 1;
+        }
+
+        if evicted > 0 {
+            log::debug!("Evicted {} pixels over capacity {}", evicted, capacity);
+            self.dirty = true;
+        }
+    }
+
     /// Convert world coordinates (lon, lat) to grid coordinates
     pub fn world_to_grid(&self, lon: f64, lat: f64) -> GridCoord {
         GridCoord {
-            x: (lon / self.cell_size).floor() as i64,
-            y: (lat / self.cell_size).floor() as i64,
+            x: ((lon - self.origin.0) / self.cell_size).floor() as i64,
+            y: ((lat - self.origin.1) / self.cell_size).floor() as i64,
         }
     }
 
     /// Convert grid coordinates to world coordinates (center of cell)
     pub fn grid_to_world(&self, coord: &GridCoord) -> (f64, f64) {
-        let lon = (coord.x as f64 + 0.5) * self.cell_size;
-        let lat = (coord.y as f64 + 0.5) * self.cell_size;
+        let lon = self.origin.0 + (coord.x as f64 + 0.5) * self.cell_size;
+        let lat = self.origin.1 + (coord.y as f64 + 0.5) * self.cell_size;
         (lon, lat)
     }
 
+    /// Shift the lattice so a cell corner lies on `(lon, lat)`, e.g. to line
+    /// art up with a building corner
+    ///
+    /// Only the remainder modulo the cell size matters. Pixels keep their
+    /// grid coordinates and move with the lattice.
+    pub fn set_origin(&mut self, lon: f64, lat: f64) {
+        if !lon.is_finite() || !lat.is_finite() {
+            log::warn!("Ignoring non-finite grid origin ({}, {})", lon, lat);
+            return;
+        }
+        if self.origin != (lon, lat) {
+            self.origin = (lon, lat);
+            self.dirty = true;
+        }
+    }
+
+    /// World position of the corner of cell (0, 0), (0, 0) by default
+    pub fn origin(&self) -> (f64, f64) {
+        self.origin
+    }
+
+    /// Set every cell of an inclusive rectangle to a color
+    ///
+    /// Rectangles of more than `MAX_FILL_CELLS` cells are left unfilled and
+    /// false is returned.
+    pub fn fill_region(&mut self, min: GridCoord, max: GridCoord, color: [f32; 4]) -> bool {
+        if !is_fillable(min, max) {
+            return false;
+        }
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.set_pixel(GridCoord::new(x, y), color);
+            }
+        }
+        true
+    }
+
+    /// Remove every pixel inside an inclusive rectangle
+    ///
+    /// Only the chunks overlapping the rectangle are visited, and the grid is
+    /// marked dirty once, if anything was removed. Returns the removed
+    /// pixels so the clear can be undone as one unit.
+    pub fn clear_bounds(&mut self, min: GridCoord, max: GridCoord) -> UndoUnit {
+        let removed: UndoUnit = self
+            .pixels_in_bounds(min, max)
+            .map(|(coord, pixel)| (coord, Some(pixel.color)))
+            .collect();
+        for (coord, _) in &removed {
+            if let Some((_, stamp)) = self.pixels.remove(coord) {
+                self.recency.remove(&stamp);
+                self.unindex(coord);
+            }
+        }
+        if !removed.is_empty() {
+            self.dirty = true;
+        }
+        removed
+    }
+
+    /// Copy the pixels inside an inclusive rectangle
+    ///
+    /// Coordinates are offsets from `min`, ready for `paste_region`.
+    pub fn copy_region(&self, min: GridCoord, max: GridCoord) -> Vec<(GridCoord, [f32; 4])> {
+        self.pixels_in_bounds(min, max)
+            .map(|(c, p)| (GridCoord::new(c.x - min.x, c.y - min.y), p.color))
+            .collect()
+    }
+
+    /// Serialize the pixels inside an inclusive rectangle (see `snapshot`)
+    ///
+    /// Colors are stored with 8 bits per channel.
+    pub fn serialize_region(&self, bounds: (GridCoord, GridCoord)) -> Vec<u8> {
+        let (min, max) = bounds;
+        snapshot::encode(min, max, self.pixels_in_bounds(min, max).map(|(c, p)| (c, p.color)))
+    }
+
+    /// Replace the snapshot's region with its contents, returning its bounds
+    ///
+    /// Pixels in the region that are not in the snapshot are removed. On
+    /// error the grid is left untouched.
+    pub fn apply_region(&mut self, bytes: &[u8]) -> Result<(GridCoord, GridCoord), SnapshotError> {
+        let snapshot = snapshot::decode(bytes)?;
+        self.clear_bounds(snapshot.min, snapshot.max);
+        for (coord, color) in snapshot.cells {
+            self.set_pixel(coord, color);
+        }
+        Ok((snapshot.min, snapshot.max))
+    }
+
+    /// Stamp copied cells with their offsets applied to `origin`
+    ///
+    /// Existing pixels are overwritten. Returns the previous contents of the
+    /// touched cells so the paste can be undone as one unit.
+    pub fn paste_region(
+        &mut self,
+        origin: GridCoord,
+        cells: &[(GridCoord, [f32; 4])],
+    ) -> UndoUnit {
+        let mut previous = Vec::with_capacity(cells.len());
+        for (offset, color) in cells {
+            let coord = GridCoord::new(origin.x + offset.x, origin.y + offset.y);
+            previous.push((coord, self.get_pixel(&coord).map(|p| p.color)));
+            self.set_pixel(coord, *color);
+        }
+        previous
+    }
+
+    /// Highlight a selected rectangle (inclusive min, max), or clear the highlight
+    pub fn set_selection(&mut self, selection: Option<(GridCoord, GridCoord)>) {
+        if self.selection != selection {
+            self.selection = selection;
+            self.dirty = true;
+        }
+    }
+
+    /// Currently highlighted selection
+    pub fn selection(&self) -> Option<(GridCoord, GridCoord)> {
+        self.selection
+    }
+
+    /// Highlight the cell under the cursor, or clear the highlight
+    pub fn set_hover(&mut self, hover: Option<GridCoord>) {
+        if self.hover != hover {
+            self.hover = hover;
+            self.dirty = true;
+        }
+    }
+
+    /// Currently highlighted cell
+    pub fn hover(&self) -> Option<GridCoord> {
+        self.hover
+    }
+
+    /// Draw pixels as squares or round dots
+    pub fn set_shape(&mut self, shape: PixelShape) {
+        if self.shape != shape {
+            self.shape = shape;
+            self.dirty = true;
+        }
+    }
+
+    pub fn shape(&self) -> PixelShape {
+        self.shape
+    }
+
+    /// Smooth the edges of square cells in the shader
+    ///
+    /// A cheaper alternative to multisampling the whole frame: only the grid
+    /// is smoothed. Round dots are always smooth.
+    pub fn set_edge_antialiasing(&mut self, enabled: bool) {
+        self.edge_antialiasing = enabled;
+    }
+
+    pub fn edge_antialiasing(&self) -> bool {
+        self.edge_antialiasing
+    }
+
+    /// Draw lines along the cell edges once cells are wide enough to tell
+    /// apart, or stop drawing them
+    pub fn set_outline(&mut self, outline: Option<GridOutline>) {
+        if let Some(outline) = outline
+            && !(outline.width.is_finite() && outline.width > 0.0)
+        {
+            log::warn!("Ignoring grid outline width {}", outline.width);
+            return;
+        }
+        if self.outline != outline {
+            self.outline = outline;
+            self.dirty = true;
+        }
+    }
+
+    /// Get the cell outline style, if drawn
+    pub fn outline(&self) -> Option<GridOutline> {
+        self.outline
+    }
+
+    /// Set how cell colors combine with the layers below (alpha blending by
+    /// default); the pipeline is rebuilt on the next update
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        if mode != self.blend_mode {
+            self.blend_mode = mode;
+            self.pipeline_stale = self.render_pipeline.is_some();
+        }
+    }
+
+    /// Get how cell colors combine with the layers below
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Add a guide line, if not already shown
+    pub fn add_guide(&mut self, guide: Guide) {
+        if !self.guides.contains(&guide) {
+            self.guides.push(guide);
+            self.dirty = true;
+        }
+    }
+
+    /// Remove a guide line, returning whether it was shown
+    pub fn remove_guide(&mut self, guide: Guide) -> bool {
+        let len = self.guides.len();
+        self.guides.retain(|g| *g != guide);
+        let removed = self.guides.len() != len;
+        self.dirty |= removed;
+        removed
+    }
+
+    /// Remove all guide lines
+    pub fn clear_guides(&mut self) {
+        if !self.guides.is_empty() {
+            self.guides.clear();
+            self.dirty = true;
+        }
+    }
+
+    /// Get the guide lines
+    pub fn guides(&self) -> &[Guide] {
+        &self.guides
+    }
+
+    /// Guide along the lattice line nearest to `(lon, lat)`
+    pub fn snap_guide(&self, lon: f64, lat: f64, vertical: bool) -> Guide {
+        if vertical {
+            Guide::Vertical(((lon - self.origin.0) / self.cell_size).round() as i64)
+        } else {
+            Guide::Horizontal(((lat - self.origin.1) / self.cell_size).round() as i64)
+        }
+    }
+
+    /// Uniforms for the current highlights and settings
+    fn uniforms(&self) -> GridUniforms {
+        GridUniforms {
+            highlight_alpha: self.highlight_alpha,
+            first_highlight: self.highlight_start,
+            edge_antialiasing: self.edge_antialiasing as u32,
+            _padding: 0,
+        }
+    }
+
+    /// Check if a selection or hover highlight is shown
+    pub fn has_highlight(&self) -> bool {
+        self.selection.is_some() || self.hover.is_some()
+    }
+
+    /// Scale the opacity of the selection and hover highlights, e.g. to pulse them
+    pub fn set_highlight_alpha(&mut self, alpha: f32) {
+        self.highlight_alpha = alpha.clamp(0.0, 1.0);
+    }
+
     /// Get number of pixels
     pub fn pixel_count(&self) -> usize {
         self.pixels.len()
     }
 
-    /// Update vertex buffer if dirty
+    /// Update vertex buffer if pixels or camera changed, and the uniforms
     pub fn update(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         camera: &super::camera::MapCamera,
     ) {
-        if !self.dirty && self.vertex_buffer.is_some() {
+        self.apply_queued();
+        if self.pipeline_stale
+            && let Some((texture_format, sample_count)) = self.target
+        {
+            self.recreate_pipeline(device, texture_format, sample_count);
+        }
+        if self.dirty || self.last_camera.as_ref() != Some(camera) {
+            self.rebuild_instances(device, queue, camera);
+        }
+
+        let uniforms = self.uniforms();
+        let Some(buffer) = &self.uniform_buffer else {
             return;
+        };
+        if self.written_uniforms != Some(uniforms) {
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&uniforms));
+            self.written_uniforms = Some(uniforms);
         }
+    }
 
-        let mut vertices = Vec::new();
+    /// Rebuild the instance buffer for `camera`
+    fn rebuild_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &super::camera::MapCamera,
+    ) {
+        let mut instances = Vec::new();
 
-        for (coord, pixel) in &self.pixels {
+        let block = self.block_cells(camera);
+        if block > 1 {
+            for (min, color) in self.merged_blocks(block, camera) {
+                let max = GridCoord::new(min.x + block - 1, min.y + block - 1);
+                let corners = self.rect_corners(min, max);
+                push_quad(&mut instances, corners, color, PixelShape::Square, camera);
+            }
+        }
+        let pixels = (block == 1).then_some(&self.pixels);
+        for (coord, (pixel, _)) in pixels.into_iter().flatten() {
             // Convert grid to world coordinates
             let (lon, lat) = self.grid_to_world(coord);
-
-            // Check if visible (rough culling)
-            let (center_lon, center_lat) = camera.center;
-            let view_range = 180.0 / 2.0_f64.powf(camera.zoom); // Approximate visible range
-
-            if (lon - center_lon).abs() > view_range * 2.0
-                || (lat - center_lat).abs() > view_range * 2.0
-            {
+            if !is_near_view(lon, lat, camera) {
                 continue;
             }
 
@@ -211,101 +917,947 @@ impl PixelGrid {
                 (lon - half_cell, lat + half_cell), // Top-left
             ];
 
-            // Convert corners to screen positions
-            let screen_corners: Vec<(f32, f32)> = corners
-                .iter()
-                .map(|(lo, la)| world_to_screen(*lo, *la, camera))
-                .collect();
-
-            // Create two triangles for the quad
-            let color = pixel.color;
+            push_quad(&mut instances, corners, pixel.color, self.shape, camera);
+        }
 
-            // Triangle 1: 0, 1, 2
-            vertices.push(GridVertex {
-                position: [screen_corners[0].0, screen_corners[0].1, 0.0],
-                color,
-            });
-            vertices.push(GridVertex {
-                position: [screen_corners[1].0, screen_corners[1].1, 0.0],
-                color,
-            });
-            vertices.push(GridVertex {
-                position: [screen_corners[2].0, screen_corners[2].1, 0.0],
-                color,
-            });
+        if let Some(outline) = self.outline {
+            self.push_outline(&mut instances, outline, camera);
+        }
+        self.push_guides(&mut instances, camera);
 
-            // Triangle 2: 0, 2, 3
-            vertices.push(GridVertex {
-                position: [screen_corners[0].0, screen_corners[0].1, 0.0],
-                color,
-            });
-            vertices.push(GridVertex {
-                position: [screen_corners[2].0, screen_corners[2].1, 0.0],
-                color,
-            });
-            vertices.push(GridVertex {
-                position: [screen_corners[3].0, screen_corners[3].1, 0.0],
-                color,
-            });
+        // Selection and hover highlights on top of the pixels
+        self.highlight_start = instances.len() as u32;
+        if let Some((min, max)) = self.selection {
+            let corners = self.rect_corners(min, max);
+            push_quad(&mut instances, corners, SELECTION_COLOR, PixelShape::Square, camera);
+        }
+        if let Some(cell) = self.hover {
+            let corners = self.rect_corners(cell, cell);
+            push_quad(&mut instances, corners, HOVER_COLOR, PixelShape::Square, camera);
         }
 
-        self.vertex_count = vertices.len() as u32;
+        self.instance_count = instances.len() as u32;
 
-        if !vertices.is_empty() {
-            self.vertex_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Grid Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            }));
+        if !instances.is_empty() {
+            let slot = self.instance_buffer.map_or(0, |slot| (slot + 1) % INSTANCE_BUFFER_RING);
+            let needed = instances.len() as u64;
+            let buffer = match &self.instance_buffers[slot] {
+                Some(buffer) if buffer.size() >= needed * GridInstance::SIZE => buffer,
+                _ => {
+                    // Sized for the largest recent rebuild so the ring settles
+                    let size = self
+                        .instance_buffers
+                        .iter()
+                        .flatten()
+                        .map(|buffer| buffer.size())
+                        .fold(buffer_size(needed), u64::max);
+                    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Grid Instance Buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    });
+                    self.buffers_created += 1;
+                    self.instance_buffers[slot].insert(buffer)
+                }
+            };
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&instances));
+            self.instance_buffer = Some(slot);
         } else {
-            self.vertex_buffer = None;
+            self.instance_buffer = None;
         }
 
+        self.last_camera = Some(*camera);
         self.dirty = false;
     }
 
+    /// Push the lattice lines crossing the view
+    fn push_outline(
+        &self,
+        instances: &mut Vec<GridInstance>,
+        outline: GridOutline,
+        camera: &super::camera::MapCamera,
+    ) {
+        if self.cell_pixels(camera) < MIN_OUTLINE_CELL_PIXELS {
+            return;
+        }
+        let (min, max) = self.view_cells(camera);
+        if max.x - min.x > MAX_OUTLINE_LINES || max.y - min.y > MAX_OUTLINE_LINES {
+            return;
+        }
+
+        // Lines run along the edges of the cells from min to max
+        let [(west, south), _, (east, north), _] = self.rect_corners(min, max);
+        let (x0, y0) = self.origin;
+        for x in min.x..=max.x + 1 {
+            let lon = x0 + x as f64 * self.cell_size;
+            push_line(instances, (lon, south), (lon, north), outline, camera);
+        }
+        for y in min.y..=max.y + 1 {
+            let lat = y0 + y as f64 * self.cell_size;
+            push_line(instances, (west, lat), (east, lat), outline, camera);
+        }
+    }
+
+    /// Push the guide lines crossing the view, whatever the zoom
+    fn push_guides(&self, instances: &mut Vec<GridInstance>, camera: &super::camera::MapCamera) {
+        if self.guides.is_empty() {
+            return;
+        }
+        let (min, max) = self.view_cells(camera);
+        let [(west, south), _, (east, north), _] = self.rect_corners(min, max);
+        let (x0, y0) = self.origin;
+        for guide in &self.guides {
+            match *guide {
+                Guide::Vertical(x) if (min.x..=max.x + 1).contains(&x) => {
+                    let lon = x0 + x as f64 * self.cell_size;
+                    push_line(instances, (lon, south), (lon, north), GUIDE_STYLE, camera);
+                }
+                Guide::Horizontal(y) if (min.y..=max.y + 1).contains(&y) => {
+                    let lat = y0 + y as f64 * self.cell_size;
+                    push_line(instances, (west, lat), (east, lat), GUIDE_STYLE, camera);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Cell rectangle (inclusive min, max) covering the viewport
+    fn view_cells(&self, camera: &super::camera::MapCamera) -> (GridCoord, GridCoord) {
+        let (width, height) = (camera.viewport_width as f32, camera.viewport_height as f32);
+        let cells = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)].map(|(x, y)| {
+            let (lon, lat) = camera.screen_to_world(x, y);
+            self.world_to_grid(lon, lat)
+        });
+        let min = GridCoord::new(
+            cells.iter().map(|cell| cell.x).min().unwrap_or(0),
+            cells.iter().map(|cell| cell.y).min().unwrap_or(0),
+        );
+        let max = GridCoord::new(
+            cells.iter().map(|cell| cell.x).max().unwrap_or(0),
+            cells.iter().map(|cell| cell.y).max().unwrap_or(0),
+        );
+        (min, max)
+    }
+
+    /// Width of a cell on screen in pixels
+    pub fn cell_pixels(&self, camera: &super::camera::MapCamera) -> f64 {
+        self.cell_size / 360.0 * camera.tile_size * 2.0_f64.powf(camera.zoom)
+    }
+
+    /// Side length in cells of the blocks drawn at the camera's zoom
+    ///
+    /// 1 (each cell drawn) unless cells are narrower than `MIN_CELL_PIXELS`,
+    /// else the smallest power of two making blocks `MIN_BLOCK_PIXELS` wide.
+    pub fn block_cells(&self, camera: &super::camera::MapCamera) -> i64 {
+        let cell_pixels = self.cell_pixels(camera);
+        if cell_pixels >= MIN_CELL_PIXELS || !cell_pixels.is_normal() {
+            return 1;
+        }
+        let doublings = (MIN_BLOCK_PIXELS / cell_pixels).log2().ceil().min(62.0);
+        1 << doublings as u32
+    }
+
+    /// Cells near the view merged into blocks of `block` cells a side, as
+    /// the block's first cell and the average color of its pixels
+    fn merged_blocks(
+        &self,
+        block: i64,
+        camera: &super::camera::MapCamera,
+    ) -> Vec<(GridCoord, [f32; 4])> {
+        // Alpha-weighted color sums, alpha sum and pixel count per block
+        let mut sums: HashMap<GridCoord, ([f32; 4], u32)> = HashMap::new();
+        for (coord, (pixel, _)) in &self.pixels {
+            let (lon, lat) = self.grid_to_world(coord);
+            if !is_near_view(lon, lat, camera) {
+                continue;
+            }
+            let min = GridCoord::new(
+                coord.x.div_euclid(block) * block,
+                coord.y.div_euclid(block) * block,
+            );
+            let (sum, count) = sums.entry(min).or_insert(([0.0; 4], 0));
+            let [r, g, b, a] = pixel.color;
+            for (total, value) in sum.iter_mut().zip([r * a, g * a, b * a, a]) {
+                *total += value;
+            }
+            *count += 1;
+        }
+
+        sums.into_iter()
+            .map(|(min, ([r, g, b, a], count))| {
+                let color = if a > 0.0 {
+                    [r / a, g / a, b / a, a / count as f32]
+                } else {
+                    [0.0; 4]
+                };
+                (min, color)
+            })
+            .collect()
+    }
+
+    /// World corners (bottom-left, bottom-right, top-right, top-left) of an
+    /// inclusive cell rectangle
+    fn rect_corners(&self, min: GridCoord, max: GridCoord) -> [(f64, f64); 4] {
+        let (x0, y0) = self.origin;
+        let (west, south) = (
+            x0 + min.x as f64 * self.cell_size,
+            y0 + min.y as f64 * self.cell_size,
+        );
+        let (east, north) = (
+            x0 + (max.x + 1) as f64 * self.cell_size,
+            y0 + (max.y + 1) as f64 * self.cell_size,
+        );
+        [(west, south), (east, south), (east, north), (west, north)]
+    }
+
+    /// Instance buffers created so far (the ring only grows when a rebuild
+    /// outgrows it)
+    pub fn buffers_created(&self) -> u32 {
+        self.buffers_created
+    }
+
+    /// Vertices `render` draws
+    pub fn drawn_vertices(&self) -> u32 {
+        if self.render_pipeline.is_some() && self.instance_buffer.is_some() {
+            self.instance_count * UNIT_QUAD.len() as u32
+        } else {
+            0
+        }
+    }
+
     /// Render the grid overlay
     pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
-        if self.vertex_count == 0 {
+        if self.instance_count == 0 {
             return;
         }
 
-        if let Some(ref buffer) = self.vertex_buffer {
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, buffer.slice(..));
-            render_pass.draw(0..self.vertex_count, 0..1);
+        let instances = self
+            .instance_buffer
+            .and_then(|slot| self.instance_buffers[slot].as_ref());
+        if let (Some(pipeline), Some(quad), Some(uniforms), Some(instances)) = (
+            &self.render_pipeline,
+            &self.quad_buffer,
+            &self.uniform_bind_group,
+            instances,
+        ) {
+            let len = self.instance_count as u64 * GridInstance::SIZE;
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, uniforms, &[]);
+            render_pass.set_vertex_buffer(0, quad.slice(..));
+            render_pass.set_vertex_buffer(1, instances.slice(..len));
+            render_pass.draw(0..UNIT_QUAD.len() as u32, 0..self.instance_count);
         }
     }
 
+    /// Recreate the pipeline on a new device, dropping buffers from the old one
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        let uniform_layout = create_uniform_bind_group_layout(device);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grid Uniform Buffer"),
+            size: std::mem::size_of::<GridUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.uniform_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid Uniform Bind Group"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        }));
+        self.uniform_buffer = Some(uniform_buffer);
+        self.written_uniforms = None;
+
+        self.render_pipeline = Some(create_pipeline(
+            device,
+            texture_format,
+            sample_count,
+            &uniform_layout,
+            self.blend_mode,
+        ));
+        self.target = Some((texture_format, sample_count));
+        self.pipeline_stale = false;
+        self.quad_buffer = Some(create_quad_buffer(device));
+        self.instance_buffers = Default::default();
+        self.instance_buffer = None;
+        self.instance_count = 0;
+        self.dirty = true;
+    }
+
     /// Mark as dirty (forces rebuild on next update)
     pub fn mark_dirty(&mut self) {
         self.dirty = true;
     }
 }
 
+/// Check if an inclusive rectangle is at most `MAX_FILL_CELLS` cells,
+/// warning if not
+pub fn is_fillable(min: GridCoord, max: GridCoord) -> bool {
+    let width = min.x.abs_diff(max.x).saturating_add(1);
+    let height = min.y.abs_diff(max.y).saturating_add(1);
+    if width.saturating_mul(height) > MAX_FILL_CELLS {
+        log::warn!("Region too large to fill ({}×{} cells)", width, height);
+        return false;
+    }
+    true
+}
+
+/// Layout of the `GridUniforms` bind group
+fn create_uniform_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Grid Uniform Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+/// Create the grid render pipeline
+fn create_pipeline(
+    device: &wgpu::Device,
+    texture_format: wgpu::TextureFormat,
+    sample_count: u32,
+    uniform_layout: &wgpu::BindGroupLayout,
+    blend_mode: BlendMode,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(include_wgsl!("../shader/grid.wgsl"));
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Grid Pipeline Layout"),
+        bind_group_layouts: &[uniform_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Grid Render Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            buffers: &[unit_quad_desc(), GridInstance::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: texture_format,
+                blend: target_blend(blend_mode, texture_format, device.features()),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &super::renderer::vertex_color_constants(texture_format),
+                ..Default::default()
+            },
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: super::renderer::multisample_state(sample_count),
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn create_quad_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Grid Quad Buffer"),
+        contents: bytemuck::cast_slice(&UNIT_QUAD),
+        usage: wgpu::BufferUsages::VERTEX,
+    })
+}
+
+/// Bytes to allocate for `instances` cells, rounded up to limit reallocations
+fn buffer_size(instances: u64) -> u64 {
+    instances.max(MIN_BUFFER_INSTANCES).next_power_of_two() * GridInstance::SIZE
+}
+
+/// Rough culling of a world position against the camera's view
+fn is_near_view(lon: f64, lat: f64, camera: &super::camera::MapCamera) -> bool {
+    let (center_lon, center_lat) = camera.center;
+    let view_range = 180.0 / 2.0_f64.powf(camera.zoom); // Approximate visible range
+    (lon - center_lon).abs() <= view_range * 2.0 && (lat - center_lat).abs() <= view_range * 2.0
+}
+
+/// Push a world-space quad (bottom-left, bottom-right, top-right, top-left) as one instance
+fn push_quad(
+    instances: &mut Vec<GridInstance>,
+    corners: [(f64, f64); 4],
+    color: [f32; 4],
+    shape: PixelShape,
+    camera: &super::camera::MapCamera,
+) {
+    let [(ox, oy), (rx, ry), _, (ux, uy)] =
+        corners.map(|(lon, lat)| world_to_screen(lon, lat, camera));
+
+    instances.push(GridInstance {
+        origin: [ox, oy],
+        axis_x: [rx - ox, ry - oy],
+        axis_y: [ux - ox, uy - oy],
+        color,
+        shape: shape as u32,
+    });
+}
+
+/// Push a world-space line `outline.width` screen pixels wide as one instance
+fn push_line(
+    instances: &mut Vec<GridInstance>,
+    from: (f64, f64),
+    to: (f64, f64),
+    outline: GridOutline,
+    camera: &super::camera::MapCamera,
+) {
+    let (ax, ay) = camera.world_to_screen(from.0, from.1);
+    let (bx, by) = camera.world_to_screen(to.0, to.1);
+    let length = (bx - ax).hypot(by - ay);
+    if !length.is_normal() {
+        return;
+    }
+
+    // Offset perpendicular to the line by half the width on each side, in
+    // screen pixels so the width doesn't change with zoom or aspect ratio
+    let scale = outline.width / length;
+    let (nx, ny) = (-(by - ay) * scale, (bx - ax) * scale);
+    let to_ndc = |x: f32, y: f32| {
+        super::renderer::screen_to_ndc(x, y, camera.viewport_width, camera.viewport_height)
+    };
+    let (ox, oy) = to_ndc(ax - nx / 2.0, ay - ny / 2.0);
+    let (rx, ry) = to_ndc(bx - nx / 2.0, by - ny / 2.0);
+    let (ux, uy) = to_ndc(ax + nx / 2.0, ay + ny / 2.0);
+
+    instances.push(GridInstance {
+        origin: [ox, oy],
+        axis_x: [rx - ox, ry - oy],
+        axis_y: [ux - ox, uy - oy],
+        color: outline.color,
+        shape: PixelShape::Square as u32,
+    });
+}
+
+/// Pixel color of 8-bit sRGB channels (e.g. from a color picker or hex code)
+pub fn srgba_to_color(srgba: [u8; 4]) -> [f32; 4] {
+    srgba.map(|v| v as f32 / 255.0)
+}
+
+/// 8-bit sRGB channels of a pixel color
+pub fn color_to_srgba(color: [f32; 4]) -> [u8; 4] {
+    color.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Source-over compositing of straight-alpha RGBA colors
+fn composite_over(src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let src_a = src[3].clamp(0.0, 1.0);
+    let dst_a = dst[3].clamp(0.0, 1.0) * (1.0 - src_a);
+    let out_a = src_a + dst_a;
+    if out_a <= f32::EPSILON {
+        return [0.0; 4];
+    }
+
+    let mut out = [0.0, 0.0, 0.0, out_a];
+    for i in 0..3 {
+        out[i] = (src[i] * src_a + dst[i] * dst_a) / out_a;
+    }
+    out
+}
+
 /// Convert world coordinates to NDC screen position
 fn world_to_screen(lon: f64, lat: f64, camera: &super::camera::MapCamera) -> (f32, f32) {
-    use super::tile::lon_lat_to_tile_f64;
+    let (x, y) = camera.world_to_screen(lon, lat);
+    super::renderer::screen_to_ndc(x, y, camera.viewport_width, camera.viewport_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    #[test]
+    fn test_srgba_u8_round_trip() {
+        // #FF8000 as picked is stored without conversion (the shader
+        // linearizes it only for sRGB targets), so it converts back exactly
+        let picked = [0xff, 0x80, 0x00, 0xff];
+        let color = srgba_to_color(picked);
+        assert_eq!(color, [1.0, 128.0 / 255.0, 0.0, 1.0]);
+        assert_eq!(color_to_srgba(color), picked);
+
+        for v in 0..=255 {
+            assert_eq!(color_to_srgba(srgba_to_color([v; 4])), [v; 4]);
+        }
+    }
+
+    /// Draw one cell filling a 64x64 target and read back its center texel,
+    /// or `None` without a usable adapter
+    fn render_cell(format: wgpu::TextureFormat, color: [f32; 4]) -> Option<[u8; 4]> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+                .ok()?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).ok()?;
+
+        const SIZE: u32 = 64;
+        let mut grid = PixelGrid::new(&device, format, 1, 0.001);
+        let coord = GridCoord::new(10, 10);
+        grid.set_pixel(coord, color);
+        let (lon, lat) = grid.grid_to_world(&coord);
+        let camera = super::super::camera::MapCamera::new(lon, lat, 20.0, SIZE, SIZE);
+        grid.update(&device, &queue, &camera);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Readback Target"),
+            size: wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bytes_per_row = SIZE * 4; // 256, already row-aligned
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (bytes_per_row * SIZE) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Readback Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            grid.render(&mut pass);
+        }
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        queue.submit([encoder.finish()]);
 
-    let z = camera.tile_zoom();
-    let scale = camera.zoom_scale();
-    let tile_size = super::camera::TILE_SIZE * scale;
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        let data = slice.get_mapped_range();
+        let center = ((SIZE / 2 * bytes_per_row) + SIZE / 2 * 4) as usize;
+        Some(data[center..center + 4].try_into().unwrap())
+    }
 
-    // Get tile coordinates
-    let (tx, ty) = lon_lat_to_tile_f64(lon, lat, z);
-    let (cx, cy) = lon_lat_to_tile_f64(camera.center.0, camera.center.1, z);
+    #[test]
+    fn test_picked_color_reads_back_unchanged() {
+        // The picked bytes must come back from both sRGB and linear targets,
+        // not just survive the u8 -> f32 -> u8 conversion
+        let picked = [0xff, 0x80, 0x00, 0xff];
+        for format in [
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureFormat::Rgba8Unorm,
+        ] {
+            let Some(texel) = render_cell(format, srgba_to_color(picked)) else {
+                log::warn!("No GPU adapter, skipping readback");
+                return;
+            };
+            for (got, want) in texel.iter().zip(picked) {
+                assert!(got.abs_diff(want) <= 1, "{:?}: {:?}", format, texel);
+            }
+        }
+    }
 
-    // Relative position
-    let rel_x = tx - cx;
-    let rel_y = ty - cy;
+    #[test]
+    fn test_capacity_evicts_least_recently_set() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        grid.set_capacity(Some(3));
 
-    // Screen position (centered)
-    let screen_x = (camera.viewport_width as f64 / 2.0) + (rel_x * tile_size);
-    let screen_y = (camera.viewport_height as f64 / 2.0) + (rel_y * tile_size);
+        for x in 0..3 {
+            grid.set_pixel(GridCoord::new(x, 0), RED);
+        }
+        // Re-setting (0, 0) makes (1, 0) the oldest
+        grid.set_pixel(GridCoord::new(0, 0), RED);
+        grid.set_pixel(GridCoord::new(3, 0), RED);
 
-    // Convert to NDC
-    let ndc_x = (screen_x / camera.viewport_width as f64) as f32 * 2.0 - 1.0;
-    let ndc_y = 1.0 - (screen_y / camera.viewport_height as f64) as f32 * 2.0;
+        assert_eq!(grid.pixel_count(), 3);
+        assert!(grid.get_pixel(&GridCoord::new(0, 0)).is_some());
+        assert!(grid.get_pixel(&GridCoord::new(1, 0)).is_none());
+        assert!(grid.get_pixel(&GridCoord::new(3, 0)).is_some());
+    }
+
+    #[test]
+    fn test_lowering_capacity_evicts_immediately() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        for x in 0..100 {
+            grid.set_pixel(GridCoord::new(x, x), RED);
+        }
+
+        grid.set_capacity(Some(10));
+        assert_eq!(grid.pixel_count(), 10);
+        assert!(grid.get_pixel(&GridCoord::new(99, 99)).is_some());
+        assert!(grid.get_pixel(&GridCoord::new(89, 89)).is_none());
+
+        grid.remove_pixel(&GridCoord::new(99, 99));
+        grid.set_capacity(None);
+        assert_eq!(grid.pixel_count(), 9);
+    }
 
-    (ndc_x, ndc_y)
+    #[test]
+    fn test_blend_pixel_source_over() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        let coord = GridCoord::new(5, 5);
+
+        // Blending onto an empty cell keeps the source color
+        grid.blend_pixel(coord, [0.0, 0.0, 1.0, 0.5]);
+        assert_eq!(grid.get_pixel(&coord).unwrap().color, [0.0, 0.0, 1.0, 0.5]);
+
+        // Half-transparent red over half-transparent blue
+        grid.blend_pixel(coord, [1.0, 0.0, 0.0, 0.5]);
+        let color = grid.get_pixel(&coord).unwrap().color;
+        let expected = [2.0 / 3.0, 0.0, 1.0 / 3.0, 0.75];
+        for (c, e) in color.iter().zip(expected) {
+            assert!((c - e).abs() < 1e-6, "{:?}", color);
+        }
+
+        // Opaque source replaces, transparent source is a no-op
+        grid.blend_pixel(coord, RED);
+        assert_eq!(grid.get_pixel(&coord).unwrap().color, RED);
+        grid.blend_pixel(coord, [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(grid.get_pixel(&coord).unwrap().color, RED);
+    }
+
+    #[test]
+    fn test_fill_and_clear_bounds() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        grid.set_pixel(GridCoord::new(10, 10), RED);
+
+        assert!(grid.fill_region(GridCoord::new(-1, -1), GridCoord::new(2, 1), RED));
+        assert_eq!(grid.pixel_count(), 13);
+        // Too large to fill at once
+        assert!(!grid.fill_region(GridCoord::new(0, 0), GridCoord::new(256, 255), RED));
+        assert!(!grid.fill_region(GridCoord::new(i64::MIN, 0), GridCoord::new(i64::MAX, 0), RED));
+        assert_eq!(grid.pixel_count(), 13);
+
+        let cleared = grid.clear_bounds(GridCoord::new(0, -5), GridCoord::new(20, 20));
+        assert_eq!(cleared.len(), 10);
+        assert!(cleared.contains(&(GridCoord::new(10, 10), Some(RED))));
+        assert_eq!(grid.pixel_count(), 3);
+        assert!(grid.get_pixel(&GridCoord::new(-1, 0)).is_some());
+
+        // Clearing an empty area leaves nothing to rebuild
+        grid.dirty = false;
+        assert!(grid.clear_bounds(GridCoord::new(100, 100), GridCoord::new(200, 200)).is_empty());
+        assert!(!grid.dirty);
+    }
+
+    #[test]
+    fn test_pixels_in_bounds() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        let inside = [(0, 0), (-1, -1), (63, 64), (-200, 100)];
+        let outside = [(-201, 0), (0, 101), (1000, 1000), (-5000, -5000)];
+        for (x, y) in inside.iter().chain(&outside) {
+            grid.set_pixel(GridCoord::new(*x, *y), RED);
+        }
+
+        let mut found: Vec<(i64, i64)> = grid
+            .pixels_in_bounds(GridCoord::new(-200, -1), GridCoord::new(63, 100))
+            .map(|(c, _)| (c.x, c.y))
+            .collect();
+        found.sort();
+        let mut expected = inside.to_vec();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        // Huge rectangle takes the occupied-chunk path
+        let all = grid.pixels_in_bounds(
+            GridCoord::new(i64::MIN / 2, i64::MIN / 2),
+            GridCoord::new(i64::MAX / 2, i64::MAX / 2),
+        );
+        assert_eq!(all.count(), 8);
+
+        // Removed and evicted pixels leave the index
+        grid.remove_pixel(&GridCoord::new(0, 0));
+        grid.set_capacity(Some(1));
+        let single = GridCoord::new(-5000, -5000);
+        assert_eq!(grid.pixels_in_bounds(single, single).count(), 1);
+        assert_eq!(
+            grid.pixels_in_bounds(GridCoord::new(-1, -1), GridCoord::new(0, 0))
+                .count(),
+            0
+        );
+
+        // Inverted bounds are empty
+        assert_eq!(
+            grid.pixels_in_bounds(GridCoord::new(1, 1), GridCoord::new(0, 0))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_origin_shifts_lattice() {
+        let mut grid = PixelGrid::new_headless(0.001);
+        assert_eq!(grid.world_to_grid(0.0005, -0.0005), GridCoord::new(0, -1));
+
+        grid.set_origin(0.0004, 0.0002);
+        assert_eq!(grid.world_to_grid(0.0004, 0.0002), GridCoord::new(0, 0));
+        assert_eq!(grid.world_to_grid(0.0003, 0.0011), GridCoord::new(-1, 0));
+        let (lon, lat) = grid.grid_to_world(&GridCoord::new(2, -1));
+        assert!((lon - 0.0029).abs() < 1e-12 && (lat + 0.0003).abs() < 1e-12);
+        assert_eq!(grid.world_to_grid(lon, lat), GridCoord::new(2, -1));
+
+        let corners = grid.rect_corners(GridCoord::new(0, 0), GridCoord::new(0, 0));
+        assert!((corners[0].0 - 0.0004).abs() < 1e-12);
+        assert!((corners[2].1 - 0.0012).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sub_pixel_cells_merge_into_blocks() {
+        // 0.0001° cells reach a screen pixel between zoom 13 and 14
+        let mut grid = PixelGrid::new_headless(0.0001);
+        let mut camera = crate::map::camera::MapCamera::new(0.0, 0.0, 14.0, 800, 600);
+        assert!((grid.cell_pixels(&camera) - 1.165).abs() < 1e-3);
+        assert_eq!(grid.block_cells(&camera), 1);
+        camera.zoom = 13.0;
+        assert_eq!(grid.block_cells(&camera), 8);
+        camera.zoom = 10.0;
+        assert_eq!(grid.block_cells(&camera), 64);
+
+        // Half-transparent red and opaque blue, and a lone cell in the next block
+        grid.set_pixel(GridCoord::new(0, 0), [1.0, 0.0, 0.0, 0.5]);
+        grid.set_pixel(GridCoord::new(63, 63), [0.0, 0.0, 1.0, 1.0]);
+        grid.set_pixel(GridCoord::new(-1, 0), [0.0, 1.0, 0.0, 1.0]);
+        let mut blocks = grid.merged_blocks(64, &camera);
+        blocks.sort_by_key(|(min, _)| min.x);
+        assert_eq!(blocks[0], (GridCoord::new(-64, 0), [0.0, 1.0, 0.0, 1.0]));
+        let (min, [r, g, b, a]) = blocks[1];
+        assert_eq!(min, GridCoord::new(0, 0));
+        assert!((r - 1.0 / 3.0).abs() < 1e-6 && g == 0.0 && (b - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(a, 0.75);
+    }
+
+    #[test]
+    fn test_outline_lines_keep_their_width() {
+        // 0.001° cells are about 12 pixels wide at zoom 14
+        let grid = PixelGrid::new_headless(0.001);
+        let mut camera = crate::map::camera::MapCamera::new(0.0, 0.0, 14.0, 800, 600);
+        let outline = GridOutline {
+            width: 2.0,
+            ..GridOutline::default()
+        };
+        let (min, max) = grid.view_cells(&camera);
+        let mut instances = Vec::new();
+        grid.push_outline(&mut instances, outline, &camera);
+        let lines = (max.x - min.x + 2) + (max.y - min.y + 2);
+        assert_eq!(instances.len() as i64, lines);
+
+        // Across each line is 2 pixels, whatever the zoom and aspect ratio
+        for line in [instances[0], instances[instances.len() - 1]] {
+            let [x, y] = line.axis_y;
+            let pixels = (x * 400.0).hypot(y * 300.0);
+            assert!((pixels - 2.0).abs() < 1e-3, "{pixels}");
+        }
+
+        // Zoomed out the lines would hide the cells
+        camera.zoom = 12.0;
+        instances.clear();
+        grid.push_outline(&mut instances, outline, &camera);
+        assert!(instances.is_empty());
+    }
+
+    #[test]
+    fn test_guides_snap_and_track_the_view() {
+        let mut grid = PixelGrid::new_headless(0.001);
+        grid.set_origin(0.0004, 0.0);
+        assert_eq!(grid.snap_guide(0.0018, 5.0, true), Guide::Vertical(1));
+        assert_eq!(grid.snap_guide(0.0018, -0.0026, false), Guide::Horizontal(-3));
+
+        grid.add_guide(Guide::Vertical(1));
+        grid.add_guide(Guide::Vertical(1));
+        grid.add_guide(Guide::Horizontal(-3));
+        grid.add_guide(Guide::Horizontal(1_000_000));
+        assert_eq!(grid.guides().len(), 3);
+
+        // Guides off screen are skipped; the others span the whole view
+        let camera = crate::map::camera::MapCamera::new(0.0, 0.0, 14.0, 800, 600);
+        let mut instances = Vec::new();
+        grid.push_guides(&mut instances, &camera);
+        assert_eq!(instances.len(), 2);
+        let [x, y] = instances[0].axis_x;
+        assert!(y.abs() >= 2.0 && x.abs() < 1e-3, "{x} {y}");
+
+        assert!(grid.remove_guide(Guide::Vertical(1)));
+        assert!(!grid.remove_guide(Guide::Vertical(1)));
+        grid.clear_guides();
+        assert!(grid.guides().is_empty());
+    }
+
+    #[test]
+    fn test_ops_from_other_threads_apply_in_order() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        grid.set_pixel(GridCoord::new(5, 5), RED);
+
+        let senders: Vec<_> = (0..4)
+            .map(|row| {
+                let sender = grid.sender();
+                std::thread::spawn(move || {
+                    for x in 0..10 {
+                        assert!(sender.send(PixelOp::Set(GridCoord::new(x, row), RED)));
+                    }
+                    sender.send(PixelOp::Remove(GridCoord::new(0, row)));
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        let sender = grid.sender();
+        sender.send(PixelOp::ClearBounds(GridCoord::new(5, 0), GridCoord::new(5, 5)));
+
+        // Nothing changes until the grid applies the queue
+        assert_eq!(grid.pixel_count(), 1);
+        assert_eq!(grid.apply_queued(), 45);
+        assert_eq!(grid.pixel_count(), 32);
+        assert!(grid.get_pixel(&GridCoord::new(0, 2)).is_none());
+        assert!(grid.get_pixel(&GridCoord::new(5, 5)).is_none());
+
+        drop(grid);
+        assert!(!sender.send(PixelOp::Remove(GridCoord::new(1, 1))));
+    }
+
+    #[test]
+    fn test_full_queue_waits_for_the_grid() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        let total = OP_QUEUE_CAPACITY * 2 + 1;
+        let sender = grid.sender();
+        let flood = std::thread::spawn(move || {
+            for x in 0..total {
+                sender.send(PixelOp::Set(GridCoord::new(x as i64, 0), RED));
+            }
+        });
+
+        let mut applied = 0;
+        while applied < total {
+            let batch = grid.apply_queued();
+            assert!(batch <= OP_QUEUE_CAPACITY);
+            applied += batch;
+        }
+        flood.join().unwrap();
+        assert_eq!(grid.pixel_count(), total);
+    }
+
+    #[test]
+    fn test_buffer_size_rounds_up() {
+        let instance = GridInstance::SIZE;
+        assert_eq!(buffer_size(0), MIN_BUFFER_INSTANCES * instance);
+        assert_eq!(buffer_size(6), MIN_BUFFER_INSTANCES * instance);
+        assert_eq!(buffer_size(1025), 2048 * instance);
+        assert_eq!(buffer_size(4096), 4096 * instance);
+    }
+
+    #[test]
+    fn test_highlight_pulse() {
+        let period = Duration::from_secs_f32(HIGHLIGHT_PERIOD);
+        assert!((highlight_pulse(Duration::ZERO) - 1.0).abs() < 1e-6);
+        assert!((highlight_pulse(period / 2) - HIGHLIGHT_MIN_ALPHA).abs() < 1e-3);
+        assert!((highlight_pulse(period * 3) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_uniforms_match_shader() {
+        let module = naga::front::wgsl::parse_str(include_str!("../shader/grid.wgsl")).unwrap();
+        let (members, span) = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some("GridUniforms"))
+            .and_then(|(_, ty)| match &ty.inner {
+                naga::TypeInner::Struct { members, span } => Some((members.len(), *span as usize)),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(members, 3);
+        assert!(span <= std::mem::size_of::<GridUniforms>());
+
+        let mut grid = PixelGrid::new_headless(0.0001);
+        assert_eq!(grid.uniforms().edge_antialiasing, 0);
+        grid.set_edge_antialiasing(true);
+        assert_eq!(grid.uniforms().edge_antialiasing, 1);
+    }
+
+    #[test]
+    fn test_region_snapshot_round_trip() {
+        let mut grid = PixelGrid::new_headless(0.0001);
+        let (min, max) = (GridCoord::new(-500, -500), GridCoord::new(499, 499));
+
+        // A few thousand scattered cells in 8-bit colors, plus one outside
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..5000 {
+            let (x, y) = ((next() % 1000) as i64 - 500, (next() % 1000) as i64 - 500);
+            let color = [next() % 256, next() % 256, next() % 256, 255].map(|v| v as f32 / 255.0);
+            grid.set_pixel(GridCoord::new(x, y), color);
+        }
+        grid.set_pixel(GridCoord::new(600, 0), RED);
+
+        let bytes = grid.serialize_region((min, max));
+        // Well under a naive 16 bytes of coordinates plus 4 of color per cell
+        assert!(bytes.len() < grid.pixel_count() * 10);
+
+        let mut copy = PixelGrid::new_headless(0.0001);
+        copy.set_pixel(GridCoord::new(0, 0), RED);
+        assert_eq!(copy.apply_region(&bytes), Ok((min, max)));
+
+        assert_eq!(copy.pixel_count(), grid.pixel_count() - 1);
+        for (coord, pixel) in grid.pixels_in_bounds(min, max) {
+            assert_eq!(copy.get_pixel(&coord).map(|p| p.color), Some(pixel.color));
+        }
+    }
 }
@@ -0,0 +1,531 @@
+//! Density heatmap of weighted points
+//!
+//! Points are drawn as soft sprites into an offscreen density texture with
+//! additive blending, then the texture is colormapped over the map. The
+//! density is rebuilt whenever the points or the camera change.
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::include_wgsl;
+use wgpu::util::DeviceExt;
+
+use super::camera::MapCamera;
+use super::renderer::{screen_to_ndc, size_to_ndc};
+
+/// Density accumulation format (float so overlapping points don't saturate)
+const DENSITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Density texture resolution relative to the viewport (the result is
+/// blurry by design, so half resolution is indistinguishable and cheaper)
+const DENSITY_SCALE: u32 = 2;
+
+/// Default sprite radius in screen pixels
+pub const DEFAULT_RADIUS: f32 = 25.0;
+
+/// A weighted location contributing to the heatmap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeatmapPoint {
+    /// Position (longitude, latitude)
+    pub position: (f64, f64),
+    pub weight: f32,
+}
+
+/// Per-instance data for one sprite, in NDC
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct PointInstance {
+    center: [f32; 2],
+    radius: [f32; 2],
+    weight: f32,
+}
+
+impl PointInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Float32,
+    ];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PointInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Colormap settings (padded to 16 bytes for WebGL)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct HeatmapUniforms {
+    max_density: f32,
+    opacity: f32,
+    _padding: [f32; 2],
+}
+
+/// Pipelines and buffers, created once per device
+struct HeatmapGpu {
+    splat_pipeline: wgpu::RenderPipeline,
+    colormap_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    /// Density texture and the colormap bind group sampling it
+    density: Option<(wgpu::Texture, wgpu::BindGroup)>,
+}
+
+/// Heatmap layer
+pub struct HeatmapRenderer {
+    points: Vec<HeatmapPoint>,
+    /// Sprite radius in logical pixels
+    radius: f32,
+    /// Physical pixels per logical pixel
+    scale_factor: f32,
+    /// Density shown in the hottest color
+    max_density: f32,
+    opacity: f32,
+
+    /// GPU resources (None when headless)
+    gpu: Option<HeatmapGpu>,
+
+    /// Points visible at the last rebuild
+    instance_count: u32,
+    /// Density passes drawn so far, each with its own instance buffer
+    density_passes: u32,
+
+    /// Camera used for the last rebuild
+    last_camera: Option<MapCamera>,
+
+    /// Dirty flag for density rebuild
+    dirty: bool,
+}
+
+impl HeatmapRenderer {
+    /// Create a heatmap drawing into a `sample_count` target
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            gpu: Some(HeatmapGpu::new(device, texture_format, sample_count)),
+            ..Self::new_headless()
+        }
+    }
+
+    /// Create a heatmap without GPU resources
+    pub fn new_headless() -> Self {
+        Self {
+            points: Vec::new(),
+            radius: DEFAULT_RADIUS,
+            scale_factor: 1.0,
+            max_density: 1.0,
+            opacity: 0.8,
+            gpu: None,
+            instance_count: 0,
+            density_passes: 0,
+            last_camera: None,
+            dirty: false,
+        }
+    }
+
+    /// Recreate GPU resources on a new device
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.gpu = Some(HeatmapGpu::new(device, texture_format, sample_count));
+        self.instance_count = 0;
+        self.dirty = true;
+    }
+
+    /// Replace all points
+    pub fn set_points(&mut self, points: Vec<HeatmapPoint>) {
+        self.points = points;
+        self.dirty = true;
+    }
+
+    /// Add a point
+    pub fn add_point(&mut self, point: HeatmapPoint) {
+        self.points.push(point);
+        self.dirty = true;
+    }
+
+    /// Remove all points
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.dirty = true;
+    }
+
+    pub fn point_count(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Sprite radius in logical pixels
+    pub fn set_radius(&mut self, pixels: f32) {
+        self.radius = pixels.max(1.0);
+        self.dirty = true;
+    }
+
+    /// Set the display's physical pixels per logical pixel, by which the
+    /// sprite radius is multiplied (1 by default)
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        if scale_factor > 0.0 && scale_factor != self.scale_factor {
+            self.scale_factor = scale_factor;
+            self.dirty = true;
+        }
+    }
+
+    /// Accumulated weight shown in the hottest color
+    ///
+    /// A lone point of weight 1 reaches a density of 1 at its center.
+    pub fn set_max_density(&mut self, density: f32) {
+        self.max_density = density.max(f32::EPSILON);
+        self.dirty = true;
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self.dirty = true;
+    }
+
+    /// Sprites for the points near the viewport
+    fn instances(&self, camera: &MapCamera) -> Vec<PointInstance> {
+        let (width, height) = (camera.viewport_width, camera.viewport_height);
+        let r = self.radius * self.scale_factor;
+        let radius = size_to_ndc(r, width, height);
+        self.points
+            .iter()
+            .filter_map(|point| {
+                let (x, y) = camera.world_to_screen(point.position.0, point.position.1);
+                let near = x > -r && y > -r && x < width as f32 + r && y < height as f32 + r;
+                near.then(|| PointInstance {
+                    center: screen_to_ndc(x, y, width, height).into(),
+                    radius: radius.into(),
+                    weight: point.weight,
+                })
+            })
+            .collect()
+    }
+
+    /// Redraw the density texture if points or camera changed
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, camera: &MapCamera) {
+        if !self.dirty && self.last_camera.as_ref() == Some(camera) {
+            return;
+        }
+        self.last_camera = Some(*camera);
+        self.dirty = false;
+
+        let instances = self.instances(camera);
+        self.instance_count = instances.len() as u32;
+        let Some(gpu) = &mut self.gpu else {
+            return;
+        };
+        if instances.is_empty() {
+            return;
+        }
+
+        let uniforms = HeatmapUniforms {
+            max_density: self.max_density,
+            opacity: self.opacity,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&gpu.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        let size = (
+            (camera.viewport_width / DENSITY_SCALE).max(1),
+            (camera.viewport_height / DENSITY_SCALE).max(1),
+        );
+        let density = gpu.density_texture(device, size);
+        let view = density.create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.density_passes += 1;
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Heatmap Instance Buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Heatmap Density Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Heatmap Density Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&gpu.splat_pipeline);
+            pass.set_vertex_buffer(0, instance_buffer.slice(..));
+            pass.draw(0..4, 0..self.instance_count);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Density passes drawn so far; each creates an instance buffer
+    pub fn density_passes(&self) -> u32 {
+        self.density_passes
+    }
+
+    /// Check if `render` draws anything
+    pub fn is_drawn(&self) -> bool {
+        self.instance_count > 0
+            && matches!(&self.gpu, Some(HeatmapGpu { density: Some(_), .. }))
+    }
+
+    /// Draw the colormapped density
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.instance_count == 0 {
+            return;
+        }
+
+        if let Some(HeatmapGpu {
+            colormap_pipeline,
+            density: Some((_, bind_group)),
+            ..
+        }) = &self.gpu
+        {
+            render_pass.set_pipeline(colormap_pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+impl HeatmapGpu {
+    fn new(device: &wgpu::Device, texture_format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let shader = device.create_shader_module(include_wgsl!("../shader/heatmap.wgsl"));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Heatmap Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let splat_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Heatmap Splat Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let splat_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Heatmap Splat Pipeline"),
+            layout: Some(&splat_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_splat"),
+                buffers: &[PointInstance::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_splat"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: DENSITY_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: additive,
+                        alpha: additive,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let colormap_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Heatmap Colormap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let colormap_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Heatmap Colormap Pipeline"),
+            layout: Some(&colormap_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_colormap"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_colormap"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &super::renderer::vertex_color_constants(texture_format),
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: super::renderer::multisample_state(sample_count),
+            multiview: None,
+            cache: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Heatmap Density Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Heatmap Uniform Buffer"),
+            size: std::mem::size_of::<HeatmapUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            splat_pipeline,
+            colormap_pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+            density: None,
+        }
+    }
+
+    /// Density texture of `size`, recreated (with its bind group) on resize
+    fn density_texture(
+        &mut self,
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+    ) -> wgpu::Texture {
+        if let Some((texture, _)) = &self.density
+            && (texture.width(), texture.height()) == (width, height)
+        {
+            return texture.clone();
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Heatmap Density Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DENSITY_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Heatmap Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        self.density = Some((texture.clone(), bind_group));
+        texture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instances_skip_far_points() {
+        let camera = MapCamera::new(0.0, 0.0, 10.0, 800, 600);
+        let mut heatmap = HeatmapRenderer::new_headless();
+        heatmap.set_points(vec![
+            HeatmapPoint {
+                position: camera.screen_to_world(400.0, 300.0),
+                weight: 2.0,
+            },
+            // Just off screen, but its sprite reaches in
+            HeatmapPoint {
+                position: camera.screen_to_world(-10.0, 300.0),
+                weight: 1.0,
+            },
+            HeatmapPoint {
+                position: (90.0, 45.0),
+                weight: 1.0,
+            },
+        ]);
+
+        let instances = heatmap.instances(&camera);
+        assert_eq!(instances.len(), 2);
+        assert!(instances[0].center[0].abs() < 1e-4 && instances[0].center[1].abs() < 1e-4);
+        assert_eq!(instances[0].weight, 2.0);
+        let radius = [2.0 * DEFAULT_RADIUS / 800.0, 2.0 * DEFAULT_RADIUS / 600.0];
+        assert_eq!(instances[0].radius, radius);
+
+        // Sprites keep their size on a high-DPI display
+        heatmap.set_scale_factor(2.0);
+        let instances = heatmap.instances(&camera);
+        assert_eq!(instances[0].radius, radius.map(|r| r * 2.0));
+    }
+}
@@ -0,0 +1,86 @@
+//! Undo history for pixel edits
+
+use std::collections::VecDeque;
+
+use super::grid::GridCoord;
+
+/// Default number of edits kept for undo
+pub const DEFAULT_UNDO_LIMIT: usize = 100;
+
+/// Previous contents of the cells touched by one edit (None = empty cell)
+pub type UndoUnit = Vec<(GridCoord, Option<[f32; 4]>)>;
+
+/// Bounded stack of undo units, oldest dropped first
+#[derive(Debug)]
+pub struct UndoStack {
+    units: VecDeque<UndoUnit>,
+    limit: usize,
+}
+
+impl UndoStack {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            units: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Record an edit (empty units are ignored)
+    pub fn push(&mut self, unit: UndoUnit) {
+        if unit.is_empty() || self.limit == 0 {
+            return;
+        }
+        if self.units.len() == self.limit {
+            self.units.pop_front();
+        }
+        self.units.push_back(unit);
+    }
+
+    /// Take the most recent edit
+    pub fn pop(&mut self) -> Option<UndoUnit> {
+        self.units.pop_back()
+    }
+
+    /// Number of edits that can be undone
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Forget all edits
+    pub fn clear(&mut self) {
+        self.units.clear();
+    }
+}
+
+impl Default for UndoStack {
+    fn default() -> Self {
+        Self::new(DEFAULT_UNDO_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(x: i64) -> UndoUnit {
+        vec![(GridCoord::new(x, 0), None)]
+    }
+
+    #[test]
+    fn test_limit_drops_oldest() {
+        let mut stack = UndoStack::new(2);
+        stack.push(unit(1));
+        stack.push(Vec::new());
+        stack.push(unit(2));
+        stack.push(unit(3));
+
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.pop(), Some(unit(3)));
+        assert_eq!(stack.pop(), Some(unit(2)));
+        assert_eq!(stack.pop(), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Draw order of the map's layers
+
+/// Something drawn by `MapSystem::render`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OverlayLayer {
+    /// Base map tiles
+    Tiles,
+    /// Point density heatmap
+    Heatmap,
+    /// Placed pixels, selection and hover highlight
+    PixelGrid,
+    /// Polygon fills and outlines
+    Polygons,
+    /// Polylines
+    Polylines,
+    /// Point markers
+    Markers,
+}
+
+impl OverlayLayer {
+    /// Every layer, in default draw order
+    pub const ALL: [OverlayLayer; 6] = [
+        OverlayLayer::Tiles,
+        OverlayLayer::Heatmap,
+        OverlayLayer::PixelGrid,
+        OverlayLayer::Polygons,
+        OverlayLayer::Polylines,
+        OverlayLayer::Markers,
+    ];
+
+    /// Sort key used until changed with `LayerStack::set_z`
+    pub fn default_z(self) -> i32 {
+        match self {
+            OverlayLayer::Tiles => 0,
+            OverlayLayer::Heatmap => 5,
+            OverlayLayer::PixelGrid => 10,
+            OverlayLayer::Polygons => 20,
+            OverlayLayer::Polylines => 30,
+            OverlayLayer::Markers => 40,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Sort key per layer; higher keys are drawn later (on top)
+///
+/// Layers with equal keys keep their default order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayerStack {
+    z: [i32; OverlayLayer::ALL.len()],
+}
+
+impl Default for LayerStack {
+    fn default() -> Self {
+        Self {
+            z: OverlayLayer::ALL.map(OverlayLayer::default_z),
+        }
+    }
+}
+
+impl LayerStack {
+    pub fn z(&self, layer: OverlayLayer) -> i32 {
+        self.z[layer.index()]
+    }
+
+    pub fn set_z(&mut self, layer: OverlayLayer, z: i32) {
+        self.z[layer.index()] = z;
+    }
+
+    /// Layers from bottom to top
+    pub fn ordered(&self) -> [OverlayLayer; OverlayLayer::ALL.len()] {
+        let mut layers = OverlayLayer::ALL;
+        layers.sort_by_key(|layer| self.z(*layer));
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordered_by_z() {
+        let mut stack = LayerStack::default();
+        assert_eq!(stack.ordered(), OverlayLayer::ALL);
+
+        // Grid above markers, polylines over everything
+        stack.set_z(OverlayLayer::PixelGrid, 45);
+        stack.set_z(OverlayLayer::Polylines, 100);
+        assert_eq!(
+            stack.ordered(),
+            [
+                OverlayLayer::Tiles,
+                OverlayLayer::Heatmap,
+                OverlayLayer::Polygons,
+                OverlayLayer::Markers,
+                OverlayLayer::PixelGrid,
+                OverlayLayer::Polylines,
+            ]
+        );
+
+        // Ties keep the default order
+        stack.set_z(OverlayLayer::Tiles, 40);
+        assert_eq!(stack.ordered()[2..4], [OverlayLayer::Tiles, OverlayLayer::Markers]);
+    }
+}
@@ -1,69 +1,294 @@
 //! Asynchronous tile loader with platform-specific implementations
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
+use web_time::Instant;
+
+use super::decode::TileDecodeError;
+use super::source::{OfflineTiles, TileSource};
+use super::throttle::{Throttle, parse_retry_after};
 use super::tile::TileId;
 
+/// How long a request stays in flight for deduplication after it was made
+///
+/// A tile cancelled and wanted again within this time (e.g. when zooming
+/// back and forth) waits for the original download instead of starting another.
+const REQUEST_COOLDOWN: Duration = Duration::from_secs(2);
+
+/// Default limit of requests waiting for a result
+pub const DEFAULT_MAX_PENDING: usize = 256;
+
+/// User-Agent used when the embedder doesn't provide one
+pub const DEFAULT_USER_AGENT: &str = "CPlace/0.1 (https://github.com/antegral/cplace)";
+
+/// User-Agent prefixes of HTTP libraries and browsers, which OSM blocks
+const GENERIC_USER_AGENTS: [&str; 6] = ["mozilla/", "reqwest", "curl/", "python", "wget/", "rust"];
+
+/// Why a User-Agent is likely to be rejected by the OSM tile servers
+///
+/// The tile usage policy asks for an identifying User-Agent with contact
+/// details; requests without one fail with HTTP 403.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserAgentProblem {
+    Empty,
+    /// Looks like a library or browser default
+    Generic,
+    /// Has no URL or e-mail address to reach the operator
+    NoContact,
+}
+
+impl fmt::Display for UserAgentProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserAgentProblem::Empty => write!(f, "User-Agent is empty"),
+            UserAgentProblem::Generic => write!(f, "User-Agent looks like a library default"),
+            UserAgentProblem::NoContact => {
+                write!(f, "User-Agent has no contact URL or e-mail address")
+            }
+        }
+    }
+}
+
+/// Check a User-Agent against the OSM tile usage policy
+///
+/// Other tile servers have their own rules, so only sources on
+/// openstreetmap.org are checked.
+pub fn check_user_agent(user_agent: &str, source: &TileSource) -> Option<UserAgentProblem> {
+    if !source.url_template.contains("openstreetmap.org") {
+        return None;
+    }
+
+    let user_agent = user_agent.trim();
+    let lower = user_agent.to_lowercase();
+    if user_agent.is_empty() {
+        Some(UserAgentProblem::Empty)
+    } else if GENERIC_USER_AGENTS.iter().any(|prefix| lower.starts_with(prefix)) {
+        Some(UserAgentProblem::Generic)
+    } else if !lower.contains("http") && !lower.contains('@') {
+        Some(UserAgentProblem::NoContact)
+    } else {
+        None
+    }
+}
+
 /// Result of a tile load operation
 #[derive(Debug)]
 pub enum TileLoadResult {
-    Success(TileId, Vec<u8>),
+    /// The tile file, with the time from starting the request to the response
+    Success(TileId, Vec<u8>, Duration),
     Failed(TileId, String),
 }
 
+impl TileLoadResult {
+    /// Tile this result is for
+    pub fn tile_id(&self) -> TileId {
+        match self {
+            TileLoadResult::Success(id, ..) | TileLoadResult::Failed(id, _) => *id,
+        }
+    }
+}
+
+/// Number of recent loads the load time statistics cover
+const LOAD_TIME_SAMPLES: usize = 256;
+
+/// Load times of the most recent tiles
+#[derive(Debug, Default)]
+pub struct LoadTimes {
+    samples: VecDeque<Duration>,
+}
+
+impl LoadTimes {
+    pub fn record(&mut self, elapsed: Duration) {
+        if self.samples.len() == LOAD_TIME_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Statistics of the recorded load times, or None before the first load
+    pub fn stats(&self) -> Option<LoadTimeStats> {
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let count = sorted.len();
+        // Nearest rank
+        let p95 = *sorted.get((count * 95).div_ceil(100).checked_sub(1)?)?;
+        Some(LoadTimeStats {
+            count,
+            min: sorted[0],
+            avg: sorted.iter().sum::<Duration>() / count as u32,
+            max: sorted[count - 1],
+            p95,
+        })
+    }
+}
+
+/// Load time statistics for debugging/UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadTimeStats {
+    /// Number of loads covered
+    pub count: usize,
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    /// 95th percentile
+    pub p95: Duration,
+}
+
+/// Span following a tile from its request through fetch, decode and upload
+///
+/// Each stage logs with `log` while inside it, so a tracing subscriber that
+/// also collects `log` records (e.g. through `tracing_log::LogTracer`)
+/// shows them as one timeline per tile.
+pub fn tile_span(tile_id: &TileId) -> tracing::Span {
+    tracing::debug_span!("tile", z = tile_id.z, x = tile_id.x, y = tile_id.y)
+}
+
 /// Tile loading request
 #[derive(Debug, Clone)]
 struct TileRequest {
     tile_id: TileId,
     url: String,
+    /// Loader epoch when the request was made
+    epoch: u64,
+    /// Entered by the worker while fetching
+    span: tracing::Span,
 }
 
+/// A request whose result is still wanted
+#[derive(Debug, Clone)]
+struct Pending {
+    /// Loader epoch when the request was made
+    epoch: u64,
+    /// When the tile was last requested; the least recently wanted request
+    /// is dropped first
+    wanted: Instant,
+    /// Handed back with the result
+    span: tracing::Span,
+}
+
+/// Finished request with the epoch it was made in
+type EpochResult = (u64, TileLoadResult);
+
+/// Cancelled requests (tile and epoch) the worker skips unless it has
+/// already started them
+#[cfg(not(target_arch = "wasm32"))]
+type DroppedRequests = Arc<std::sync::Mutex<HashSet<(TileId, u64)>>>;
+
 // Platform-specific channel types
 #[cfg(not(target_arch = "wasm32"))]
-type ResultReceiver = std::sync::mpsc::Receiver<TileLoadResult>;
+type ResultReceiver = std::sync::mpsc::Receiver<EpochResult>;
 #[cfg(not(target_arch = "wasm32"))]
 type RequestSender = std::sync::mpsc::Sender<TileRequest>;
 
 #[cfg(target_arch = "wasm32")]
-use std::sync::{Arc, Mutex};
-use log::debug;
+use std::sync::Mutex;
 
 #[cfg(target_arch = "wasm32")]
-type ResultReceiver = Arc<Mutex<Vec<TileLoadResult>>>;
-#[cfg(target_arch = "wasm32")]
-type RequestSender = (); // Not used in WASM
+type ResultReceiver = Arc<Mutex<Vec<EpochResult>>>;
+
+/// Result of a request that needs no download: a tile found offline, or a
+/// miss when the source has no server to fall back to
+fn offline_result(offline: Option<&OfflineTiles>, request: &TileRequest) -> Option<TileLoadResult> {
+    let started = Instant::now();
+    if let Some(bytes) = offline.and_then(|tiles| tiles.read(&request.tile_id)) {
+        Some(TileLoadResult::Success(request.tile_id, bytes, started.elapsed()))
+    } else if request.url.is_empty() {
+        Some(TileLoadResult::Failed(request.tile_id, "Not in offline tiles".into()))
+    } else {
+        None
+    }
+}
 
 /// Tile loader with async HTTP fetching
 pub struct TileLoader {
     result_rx: ResultReceiver,
     #[cfg(not(target_arch = "wasm32"))]
     request_tx: RequestSender,
-    pending: HashSet<TileId>,
+    /// Tiles waiting for a result
+    pending: HashMap<TileId, Pending>,
+    /// Most requests waiting at once
+    max_pending: usize,
+    #[cfg(not(target_arch = "wasm32"))]
+    dropped: DroppedRequests,
+    /// Aborts the fetch of each request (tile and epoch) still running
+    #[cfg(target_arch = "wasm32")]
+    aborts: HashMap<(TileId, u64), web_sys::AbortController>,
+    /// Requests without a result yet, cancelled or not, with when and in
+    /// which epoch they were made
+    recent: HashMap<TileId, (Instant, u64)>,
+    /// Advanced on major view changes; results from older epochs are only
+    /// accepted for tiles still pending from that epoch
+    epoch: u64,
     user_agent: String,
+    source: TileSource,
+    /// Set when the server answers 429 Too Many Requests
+    throttle: Arc<Throttle>,
     #[cfg(not(target_arch = "wasm32"))]
     _worker_handle: Option<std::thread::JoinHandle<()>>,
 }
 
 impl TileLoader {
-    /// Create a new tile loader
+    /// Create a new tile loader for OpenStreetMap tiles
     pub fn new(user_agent: &str) -> Self {
+        Self::with_source(user_agent, TileSource::osm())
+    }
+
+    /// Create a tile loader for another tile server
+    ///
+    /// A User-Agent the OSM tile servers would reject is logged as a warning
+    /// (see `check_user_agent`).
+    pub fn with_source(user_agent: &str, source: TileSource) -> Self {
+        if let Some(problem) = check_user_agent(user_agent, &source) {
+            log::warn!(
+                "{} ({:?}); tile.openstreetmap.org will likely refuse tiles with HTTP 403. \
+                 Set an identifying User-Agent with a contact URL or e-mail address.",
+                problem,
+                user_agent
+            );
+        }
+
         #[cfg(not(target_arch = "wasm32"))]
         {
             let (request_tx, request_rx) = std::sync::mpsc::channel::<TileRequest>();
-            let (result_tx, result_rx) = std::sync::mpsc::channel::<TileLoadResult>();
+            let (result_tx, result_rx) = std::sync::mpsc::channel::<EpochResult>();
 
+            let throttle = Arc::new(Throttle::default());
+            let dropped = DroppedRequests::default();
             let _worker_handle = {
                 let user_agent = user_agent.to_string();
+                let throttle = throttle.clone();
+                let dropped = dropped.clone();
+                let source = source.clone();
                 Some(std::thread::spawn(move || {
-                    Self::worker_thread(request_rx, result_tx, user_agent);
+                    Self::worker_thread(
+                        request_rx,
+                        result_tx,
+                        user_agent,
+                        throttle,
+                        dropped,
+                        source,
+                    );
                 }))
             };
 
             Self {
                 result_rx,
                 request_tx,
-                pending: HashSet::new(),
+                pending: HashMap::new(),
+                max_pending: DEFAULT_MAX_PENDING,
+                dropped,
+                recent: HashMap::new(),
+                epoch: 0,
                 user_agent: user_agent.to_string(),
+                source,
+                throttle,
                 _worker_handle,
             }
         }
@@ -74,73 +299,209 @@ impl TileLoader {
 
             Self {
                 result_rx,
-                pending: HashSet::new(),
+                pending: HashMap::new(),
+                max_pending: DEFAULT_MAX_PENDING,
+                aborts: HashMap::new(),
+                recent: HashMap::new(),
+                epoch: 0,
                 user_agent: user_agent.to_string(),
+                source,
+                throttle: Arc::new(Throttle::default()),
             }
         }
     }
 
     /// Request a tile to be loaded
+    ///
+    /// A cancelled request made less than `REQUEST_COOLDOWN` ago is resumed
+    /// instead of downloading the tile again.
     pub fn request(&mut self, tile_id: TileId) {
-        if self.pending.contains(&tile_id) {
-            return; // Already loading
+        self.request_at(tile_id, Instant::now());
+    }
+
+    /// Request a tile to be loaded, or mark its pending request as wanted
+    /// at `now`
+    ///
+    /// With `max_pending` requests waiting, the least recently wanted one is
+    /// dropped to make room, unless it was wanted at `now` too: requesting
+    /// the tiles of a frame in priority order with the frame's time then
+    /// keeps the most important ones.
+    pub fn request_at(&mut self, tile_id: TileId, now: Instant) {
+        if let Some(pending) = self.pending.get_mut(&tile_id) {
+            pending.wanted = now; // Already loading
+            return;
+        }
+        let in_flight = self
+            .recent
+            .get(&tile_id)
+            .filter(|(at, _)| now.saturating_duration_since(*at) < REQUEST_COOLDOWN)
+            .map(|&(_, epoch)| epoch);
+        if let Some(epoch) = in_flight
+            && self.undrop_request(tile_id, epoch)
+        {
+            // Its result completes the request
+            if self.make_room(now) {
+                let span = tile_span(&tile_id);
+                span.in_scope(|| log::debug!("Resuming cancelled request for {:?}", tile_id));
+                self.pending.insert(tile_id, Pending { epoch, wanted: now, span });
+            } else {
+                self.drop_request(tile_id, epoch);
+            }
+            return;
+        }
+        if self.is_throttled() || !self.make_room(now) {
+            return; // Requested again on a later update
         }
 
-        let url = tile_id.to_osm_url();
-        // debug!("Requesting tile {}", url);
-        let request = TileRequest { tile_id, url };
+        let url = self.source.tile_url(&tile_id);
+        let epoch = self.epoch;
+        let span = tile_span(&tile_id);
+        span.in_scope(|| {
+            let url = self.source.redacted_tile_url(&tile_id);
+            log::debug!("Requesting tile {} (epoch {})", url, epoch);
+        });
+        let pending = Pending {
+            epoch,
+            wanted: now,
+            span: span.clone(),
+        };
+        let request = TileRequest {
+            tile_id,
+            url,
+            epoch,
+            span,
+        };
 
         #[cfg(not(target_arch = "wasm32"))]
         {
             if self.request_tx.send(request).is_ok() {
-                self.pending.insert(tile_id);
+                self.pending.insert(tile_id, pending);
+                self.recent.insert(tile_id, (now, epoch));
             }
         }
 
         #[cfg(target_arch = "wasm32")]
         {
-            self.pending.insert(tile_id);
-            self.spawn_wasm_fetch(request);
+            self.pending.insert(tile_id, pending);
+            self.recent.insert(tile_id, (now, epoch));
+            match offline_result(self.source.offline.as_ref(), &request) {
+                Some(result) => self.result_rx.lock().unwrap().push((epoch, result)),
+                None => self.spawn_wasm_fetch(request),
+            }
         }
     }
 
-    /// Poll for completed tile loads
-    pub fn poll(&mut self) -> Option<TileLoadResult> {
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            match self.result_rx.try_recv() {
-                Ok(result) => {
-                    match &result {
-                        TileLoadResult::Success(id, _) | TileLoadResult::Failed(id, _) => {
-                            self.pending.remove(id);
-                        }
-                    }
-                    Some(result)
+    /// Poll for completed tile loads, each with the span of its request
+    /// (see `tile_span`)
+    ///
+    /// Results for cancelled requests are skipped, including late results
+    /// of a request that was cancelled and then made again in a newer epoch.
+    pub fn poll(&mut self) -> Option<(TileLoadResult, tracing::Span)> {
+        while let Some((epoch, result)) = self.next_result() {
+            let id = result.tile_id();
+            if self.recent.get(&id).is_some_and(|&(_, made)| made == epoch) {
+                self.recent.remove(&id);
+            }
+            // Started before it was dropped
+            self.undrop_request(id, epoch);
+            #[cfg(target_arch = "wasm32")]
+            self.aborts.remove(&(id, epoch));
+            if let Some(pending) = self.pending.remove(&id) {
+                if pending.epoch == epoch {
+                    return Some((result, pending.span));
                 }
-                Err(std::sync::mpsc::TryRecvError::Empty) => None,
-                Err(std::sync::mpsc::TryRecvError::Disconnected) => None,
+                self.pending.insert(id, pending);
             }
+            log::debug!("Dropping stale result for {:?} (epoch {})", id, epoch);
         }
+        None
+    }
+
+    /// Drop the least recently wanted request if `max_pending` are waiting,
+    /// returning whether there is room for another
+    fn make_room(&mut self, now: Instant) -> bool {
+        if self.pending.len() < self.max_pending {
+            return true;
+        }
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, pending)| pending.wanted)
+            .filter(|(_, pending)| pending.wanted < now)
+            .map(|(&id, _)| id);
+        let Some(pending) = oldest.and_then(|id| self.pending.remove_entry(&id)) else {
+            return false;
+        };
+        let (id, Pending { epoch, span, .. }) = pending;
+        span.in_scope(|| log::debug!("Dropping least recently wanted request {:?}", id));
+        self.drop_request(id, epoch);
+        true
+    }
 
+    /// Tell the worker not to download a cancelled request it hasn't started
+    ///
+    /// Web fetches start right away, so there the fetch is aborted instead,
+    /// freeing its connection.
+    fn drop_request(&mut self, tile_id: TileId, epoch: u64) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(mut dropped) = self.dropped.lock() {
+            dropped.insert((tile_id, epoch));
+        }
         #[cfg(target_arch = "wasm32")]
+        if let Some(controller) = self.aborts.remove(&(tile_id, epoch)) {
+            controller.abort();
+        }
+    }
+
+    /// Take back a dropped request, returning false if the worker already
+    /// skipped it (so its result never arrives)
+    ///
+    /// Aborted web fetches never deliver a result, so there this is false.
+    fn undrop_request(&mut self, tile_id: TileId, epoch: u64) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
         {
-            let mut results = self.result_rx.lock().unwrap();
-            if let Some(result) = results.pop() {
-                match &result {
-                    TileLoadResult::Success(id, _) | TileLoadResult::Failed(id, _) => {
-                        self.pending.remove(id);
-                    }
-                }
-                Some(result)
-            } else {
-                None
-            }
+            self.dropped
+                .lock()
+                .is_ok_and(|mut dropped| dropped.remove(&(tile_id, epoch)))
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = (tile_id, epoch);
+            false
+        }
+    }
+
+    /// Take the next finished request, stale or not
+    fn next_result(&mut self) -> Option<EpochResult> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.result_rx.try_recv().ok()
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.result_rx.lock().unwrap().pop()
         }
     }
 
     /// Check if a tile is currently being loaded
     pub fn is_loading(&self, tile_id: &TileId) -> bool {
-        self.pending.contains(tile_id)
+        self.pending.contains_key(tile_id)
+    }
+
+    /// Tile server requests go to
+    pub fn source(&self) -> &TileSource {
+        &self.source
+    }
+
+    /// User-Agent sent with tile requests
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    /// Check if the server rate limited us and requests are paused
+    pub fn is_throttled(&self) -> bool {
+        self.throttle.remaining(web_time::Instant::now()).is_some()
     }
 
     /// Get number of pending requests
@@ -148,34 +509,130 @@ impl TileLoader {
         self.pending.len()
     }
 
-    /// Cancel all pending requests (tiles will still complete but be ignored)
+    /// Most requests waiting for a result at once
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    /// Limit the requests waiting for a result (at least 1)
+    pub fn set_max_pending(&mut self, max: usize) {
+        self.max_pending = max.max(1);
+    }
+
+    /// Cancel all pending requests
+    ///
+    /// Queued downloads are skipped; in-flight ones still complete but are
+    /// ignored, except on the web, where they are aborted.
     pub fn clear_pending(&mut self) {
-        self.pending.clear();
+        self.cancel_except(&HashSet::new());
+    }
+
+    /// Cancel pending requests for tiles not in `keep`, returning how many
+    ///
+    /// Queued downloads are skipped. In-flight downloads can't be aborted
+    /// natively, so their results are dropped by `poll`; web fetches are
+    /// aborted.
+    pub fn cancel_except(&mut self, keep: &HashSet<TileId>) -> usize {
+        let cancelled: Vec<(TileId, u64)> = self
+            .pending
+            .iter()
+            .filter(|(id, _)| !keep.contains(id))
+            .map(|(&id, pending)| (id, pending.epoch))
+            .collect();
+        for &(id, epoch) in &cancelled {
+            self.pending.remove(&id);
+            self.drop_request(id, epoch);
+        }
+        cancelled.len()
+    }
+
+    /// Start a new epoch (call on major view changes)
+    ///
+    /// Requests made from now on are told apart from earlier ones, so a
+    /// cancelled request's result can't complete its replacement.
+    pub fn advance_epoch(&mut self) {
+        self.epoch += 1;
+
+        // Requests whose result never arrived no longer hold back new ones
+        let now = Instant::now();
+        self.recent
+            .retain(|_, (at, _)| now.saturating_duration_since(*at) < REQUEST_COOLDOWN);
+    }
+
+    /// Forget in-flight requests, so every tile is downloaded again when
+    /// requested (call after invalidating cached tiles)
+    pub fn forget_requests(&mut self) {
+        self.clear_pending();
+        self.recent.clear();
+    }
+
+    /// Current request epoch
+    pub fn epoch(&self) -> u64 {
+        self.epoch
     }
 
     // Native implementation
     #[cfg(not(target_arch = "wasm32"))]
     fn worker_thread(
         request_rx: std::sync::mpsc::Receiver<TileRequest>,
-        result_tx: std::sync::mpsc::Sender<TileLoadResult>,
+        result_tx: std::sync::mpsc::Sender<EpochResult>,
         user_agent: String,
+        throttle: Arc<Throttle>,
+        dropped: DroppedRequests,
+        source: TileSource,
     ) {
         let client = reqwest::blocking::Client::builder()
             .user_agent(&user_agent)
+            .default_headers(header_map(&source.headers))
             .build()
             .expect("Failed to create HTTP client");
 
         while let Ok(request) = request_rx.recv() {
+            let _entered = request.span.enter();
+            if let Some(result) = offline_result(source.offline.as_ref(), &request) {
+                let found = matches!(result, TileLoadResult::Success(..));
+                log::debug!("Read {:?} offline (found: {})", request.tile_id, found);
+                if result_tx.send((request.epoch, result)).is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            // Hold queued requests until the rate limit pause is over
+            if let Some(left) = throttle.remaining(web_time::Instant::now()) {
+                std::thread::sleep(left);
+            }
+
+            // Cancelled while queued
+            let key = (request.tile_id, request.epoch);
+            if dropped.lock().is_ok_and(|mut dropped| dropped.remove(&key)) {
+                log::debug!("Skipping cancelled request {:?}", request.tile_id);
+                continue;
+            }
+
+            let started = Instant::now();
             let result = match client.get(&request.url).send() {
                 Ok(response) => {
-                    if response.status().is_success() {
+                    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        let retry_after = response
+                            .headers()
+                            .get(reqwest::header::RETRY_AFTER)
+                            .and_then(|v| v.to_str().ok());
+                        let pause = parse_retry_after(retry_after);
+                        log::warn!("Tile server rate limited us, pausing for {:?}", pause);
+                        throttle.pause(web_time::Instant::now(), pause);
+                        TileLoadResult::Failed(request.tile_id, "HTTP 429 (rate limited)".into())
+                    } else if response.status().is_success() {
                         match response.bytes() {
-                            Ok(bytes) => {
-                                TileLoadResult::Success(request.tile_id, bytes.to_vec())
-                            }
-                            Err(e) => {
-                                TileLoadResult::Failed(request.tile_id, e.to_string())
-                            }
+                            Ok(bytes) => TileLoadResult::Success(
+                                request.tile_id,
+                                bytes.to_vec(),
+                                started.elapsed(),
+                            ),
+                            Err(e) => TileLoadResult::Failed(
+                                request.tile_id,
+                                e.without_url().to_string(),
+                            ),
                         }
                     } else {
                         TileLoadResult::Failed(
@@ -184,10 +641,17 @@ impl TileLoader {
                         )
                     }
                 }
-                Err(e) => TileLoadResult::Failed(request.tile_id, e.to_string()),
+                // The URL may hold an API key
+                Err(e) => TileLoadResult::Failed(request.tile_id, e.without_url().to_string()),
             };
+            match &result {
+                TileLoadResult::Success(id, bytes, elapsed) => {
+                    log::debug!("Fetched {:?} ({} bytes in {:?})", id, bytes.len(), elapsed);
+                }
+                TileLoadResult::Failed(id, err) => log::debug!("Fetch of {:?} failed: {}", id, err),
+            }
 
-            if result_tx.send(result).is_err() {
+            if result_tx.send((request.epoch, result)).is_err() {
                 break; // Receiver dropped, exit thread
             }
         }
@@ -195,7 +659,8 @@ impl TileLoader {
 
     // WASM implementation using web-sys fetch API
     #[cfg(target_arch = "wasm32")]
-    fn spawn_wasm_fetch(&self, request: TileRequest) {
+    fn spawn_wasm_fetch(&mut self, request: TileRequest) {
+        use tracing::Instrument;
         use wasm_bindgen::prelude::*;
         use wasm_bindgen::JsCast;
         use wasm_bindgen_futures::JsFuture;
@@ -203,13 +668,31 @@ impl TileLoader {
 
         let result_buffer = self.result_rx.clone();
         let user_agent = self.user_agent.clone();
+        let headers = self.source.headers.clone();
+        let throttle = self.throttle.clone();
+
+        // Without a controller the fetch just can't be aborted
+        let signal = match web_sys::AbortController::new() {
+            Ok(controller) => {
+                let signal = controller.signal();
+                self.aborts.insert((request.tile_id, request.epoch), controller);
+                Some(signal)
+            }
+            Err(e) => {
+                log::warn!("Failed to create AbortController: {:?}", e);
+                None
+            }
+        };
 
+        let span = request.span.clone();
         wasm_bindgen_futures::spawn_local(async move {
+            let started = Instant::now();
             let result = async {
                 // Create request with proper headers
                 let mut opts = RequestInit::new();
                 opts.method("GET");
                 opts.mode(RequestMode::Cors);
+                opts.set_signal(signal.as_ref());
 
                 let web_request = Request::new_with_str_and_init(&request.url, &opts)
                     .map_err(|e| format!("Failed to create request: {:?}", e))?;
@@ -219,6 +702,13 @@ impl TileLoader {
                     .headers()
                     .set("User-Agent", &user_agent)
                     .map_err(|e| format!("Failed to set User-Agent: {:?}", e))?;
+                for (name, value) in &headers {
+                    // The error would echo the value, which may be a secret
+                    web_request
+                        .headers()
+                        .set(name, value)
+                        .map_err(|_| format!("Failed to set header {}", name))?;
+                }
 
                 // Fetch the tile
                 let window = web_sys::window().ok_or("No window object")?;
@@ -230,6 +720,14 @@ impl TileLoader {
                     .dyn_into()
                     .map_err(|_| "Response is not a Response object")?;
 
+                if resp.status() == 429 {
+                    let retry_after = resp.headers().get("Retry-After").ok().flatten();
+                    let pause = parse_retry_after(retry_after.as_deref());
+                    log::warn!("Tile server rate limited us, pausing for {:?}", pause);
+                    throttle.pause(web_time::Instant::now(), pause);
+                    return Err("HTTP 429 (rate limited)".to_string());
+                }
+
                 if !resp.ok() {
                     return Err(format!("HTTP {}", resp.status()));
                 }
@@ -250,32 +748,317 @@ impl TileLoader {
             }
             .await;
 
+            // Cancelled on purpose; the loader expects no result
+            if signal.as_ref().is_some_and(|signal| signal.aborted()) {
+                log::debug!("Fetch of {:?} aborted", request.tile_id);
+                return;
+            }
+
             // Store result in shared buffer
             let tile_result = match result {
-                Ok(bytes) => TileLoadResult::Success(request.tile_id, bytes),
-                Err(err) => TileLoadResult::Failed(request.tile_id, err),
+                Ok(bytes) => {
+                    let elapsed = started.elapsed();
+                    log::debug!(
+                        "Fetched {:?} ({} bytes in {:?})",
+                        request.tile_id,
+                        bytes.len(),
+                        elapsed
+                    );
+                    TileLoadResult::Success(request.tile_id, bytes, elapsed)
+                }
+                Err(err) => {
+                    log::debug!("Fetch of {:?} failed: {}", request.tile_id, err);
+                    TileLoadResult::Failed(request.tile_id, err)
+                }
             };
 
             if let Ok(mut results) = result_buffer.lock() {
-                results.push(tile_result);
+                results.push((request.epoch, tile_result));
             }
-        });
+        }
+        .instrument(span));
     }
 }
 
 impl Default for TileLoader {
     fn default() -> Self {
-        Self::new("CPlace/0.1 (https://github.com/antegral/cplace)")
+        Self::new(DEFAULT_USER_AGENT)
+    }
+}
+
+/// Headers of a tile source as sent by reqwest, skipping invalid ones
+#[cfg(not(target_arch = "wasm32"))]
+fn header_map(headers: &[(String, String)]) -> reqwest::header::HeaderMap {
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    let mut map = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        match (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            (Ok(name), Ok(mut value)) => {
+                value.set_sensitive(true);
+                map.append(name, value);
+            }
+            // Only the name is logged; the value may be a secret
+            _ => log::warn!("Ignoring invalid tile request header {:?}", name),
+        }
     }
+    map
 }
 
-/// Create GPU texture from image bytes
-pub fn decode_tile_image(data: &[u8]) -> Result<image::RgbaImage, image::ImageError> {
+/// Decode a tile file to RGBA pixels
+///
+/// Images wider or taller than `max_dimension` are rejected from their
+/// header, before any pixels are allocated.
+pub fn decode_tile_image(
+    data: &[u8],
+    max_dimension: u32,
+) -> Result<image::RgbaImage, TileDecodeError> {
+    let (width, height) = image::ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .map_err(image::ImageError::IoError)?
+        .into_dimensions()?;
+    if width > max_dimension || height > max_dimension {
+        return Err(TileDecodeError::TooLarge {
+            width,
+            height,
+            max: max_dimension,
+        });
+    }
     let img = image::load_from_memory(data)?;
     Ok(img.to_rgba8())
 }
 
-/// Calculate memory size for a tile texture
-pub fn tile_memory_size(width: u32, height: u32) -> usize {
-    (width * height * 4) as usize // RGBA8 = 4 bytes per pixel
+/// Estimate GPU memory of an RGBA8 tile texture with `mip_levels` levels
+///
+/// Each level halves the size (down to 1×1) and its rows are padded to
+/// `COPY_BYTES_PER_ROW_ALIGNMENT`, as drivers commonly lay them out. This
+/// errs high for small textures, which keeps the cache budget conservative.
+pub fn tile_memory_size(width: u32, height: u32, mip_levels: u32) -> usize {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+    (0..mip_levels.max(1))
+        .map(|level| {
+            let w = (width >> level).max(1) as usize;
+            let h = (height >> level).max(1) as usize;
+            (w * 4).next_multiple_of(align) * h // RGBA8 = 4 bytes per pixel
+        })
+        .sum()
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Loader without a worker; results are fed through the returned sender
+    fn offline_loader() -> (TileLoader, mpsc::Sender<EpochResult>, mpsc::Receiver<TileRequest>) {
+        let (request_tx, request_rx) = mpsc::channel();
+        let (result_tx, result_rx) = mpsc::channel();
+        let loader = TileLoader {
+            result_rx,
+            request_tx,
+            pending: HashMap::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+            dropped: DroppedRequests::default(),
+            recent: HashMap::new(),
+            epoch: 0,
+            user_agent: String::new(),
+            source: TileSource::osm(),
+            throttle: Arc::default(),
+            _worker_handle: None,
+        };
+        (loader, result_tx, request_rx)
+    }
+
+    #[test]
+    fn test_stale_result_does_not_complete_rerequest() {
+        let (mut loader, results, requests) = offline_loader();
+        let tile = TileId::new(1, 2, 3);
+
+        let now = Instant::now();
+        loader.request_at(tile, now);
+        loader.cancel_except(&HashSet::new());
+        loader.advance_epoch();
+        loader.request_at(tile, now + REQUEST_COOLDOWN);
+        assert_eq!(requests.try_iter().map(|r| r.epoch).collect::<Vec<_>>(), [0, 1]);
+
+        // The cancelled request lands late and is dropped
+        results.send((0, TileLoadResult::Failed(tile, "late".into()))).unwrap();
+        assert!(loader.poll().is_none());
+        assert!(loader.is_loading(&tile));
+
+        results.send((1, TileLoadResult::Success(tile, Vec::new(), Duration::ZERO))).unwrap();
+        assert!(matches!(loader.poll(), Some((TileLoadResult::Success(..), _))));
+        assert!(!loader.is_loading(&tile));
+    }
+
+    #[test]
+    fn test_rerequest_within_cooldown_resumes_download() {
+        let (mut loader, results, requests) = offline_loader();
+        let tile = TileId::new(1, 2, 3);
+
+        let now = Instant::now();
+        loader.request_at(tile, now);
+        loader.cancel_except(&HashSet::new());
+        loader.advance_epoch();
+        loader.request_at(tile, now + Duration::from_millis(500));
+        assert_eq!(requests.try_iter().count(), 1);
+        assert!(loader.is_loading(&tile));
+
+        // The original download completes the request
+        results.send((0, TileLoadResult::Success(tile, Vec::new(), Duration::ZERO))).unwrap();
+        assert!(matches!(loader.poll(), Some((TileLoadResult::Success(..), _))));
+
+        // Once its result arrived, the tile is downloaded again
+        loader.request_at(tile, now + Duration::from_millis(600));
+        assert_eq!(requests.try_iter().count(), 1);
+
+        // As it is after forgetting requests
+        loader.forget_requests();
+        loader.request_at(tile, now + Duration::from_millis(700));
+        assert_eq!(requests.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_max_pending_drops_least_recently_wanted() {
+        let (mut loader, _results, requests) = offline_loader();
+        loader.set_max_pending(2);
+        let (a, b, c) = (TileId::new(0, 0, 2), TileId::new(1, 0, 2), TileId::new(2, 0, 2));
+
+        let now = Instant::now();
+        loader.request_at(a, now);
+        loader.request_at(b, now);
+        // Everything pending is wanted as much
+        loader.request_at(c, now);
+        assert!(!loader.is_loading(&c));
+
+        let later = now + Duration::from_millis(100);
+        loader.request_at(a, later);
+        loader.request_at(c, later);
+        assert!(loader.is_loading(&a) && loader.is_loading(&c));
+        assert!(!loader.is_loading(&b));
+        assert_eq!(requests.try_iter().count(), 3);
+
+        // The worker skips b, so wanting it again downloads it again
+        assert!(loader.dropped.lock().unwrap().remove(&(b, 0)));
+        loader.request_at(b, later + Duration::from_millis(100));
+        assert_eq!(requests.try_iter().count(), 1);
+        assert_eq!(loader.pending_count(), 2);
+    }
+
+    #[test]
+    fn test_kept_requests_survive_new_epoch() {
+        let (mut loader, results, _requests) = offline_loader();
+        let tile = TileId::new(1, 2, 3);
+
+        loader.request(tile);
+        loader.cancel_except(&HashSet::from([tile]));
+        loader.advance_epoch();
+
+        results.send((0, TileLoadResult::Success(tile, Vec::new(), Duration::ZERO))).unwrap();
+        assert!(loader.poll().is_some());
+        assert_eq!(loader.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_tile_memory_size_pads_rows_and_counts_mips() {
+        assert_eq!(tile_memory_size(256, 256, 1), 256 * 256 * 4);
+        // 100 px rows (400 bytes) are padded to 512 bytes
+        assert_eq!(tile_memory_size(100, 100, 1), 512 * 100);
+
+        // 256, 128, ..., 1: rows below 64 px still take 256 bytes
+        let full_chain = tile_memory_size(256, 256, 9);
+        let unpadded: usize = (0..9).map(|l| (256usize >> l).pow(2) * 4).sum();
+        assert!(full_chain > unpadded);
+        assert_eq!(
+            full_chain,
+            256 * 1024 + 128 * 512 + 64 * 256 + (32 + 16 + 8 + 4 + 2 + 1) * 256
+        );
+    }
+
+    #[test]
+    fn test_load_time_stats() {
+        let mut times = LoadTimes::default();
+        assert_eq!(times.stats(), None);
+
+        // Only the most recent loads count
+        times.record(Duration::from_secs(60));
+        for ms in 1..=LOAD_TIME_SAMPLES as u64 {
+            times.record(Duration::from_millis(ms));
+        }
+        let stats = times.stats().unwrap();
+        assert_eq!(stats.count, LOAD_TIME_SAMPLES);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.max, Duration::from_millis(256));
+        assert_eq!(stats.avg, Duration::from_micros(128_500));
+        assert_eq!(stats.p95, Duration::from_millis(244));
+    }
+
+    #[test]
+    fn test_header_map_skips_invalid_headers() {
+        let headers = [
+            ("Authorization".to_string(), "Bearer token".to_string()),
+            ("X-API-Key".to_string(), "key".to_string()),
+            ("Bad Name".to_string(), "value".to_string()),
+            ("X-Bad-Value".to_string(), "line\nbreak".to_string()),
+        ];
+        let map = header_map(&headers);
+        assert_eq!(map.len(), 2);
+        assert!(map["authorization"].is_sensitive());
+        assert_eq!(map["x-api-key"], "key");
+    }
+
+    #[test]
+    fn test_check_user_agent() {
+        let osm = TileSource::osm();
+        assert_eq!(check_user_agent(DEFAULT_USER_AGENT, &osm), None);
+        assert_eq!(check_user_agent("MyApp/1.0 (ops@example.com)", &osm), None);
+        assert_eq!(check_user_agent("  ", &osm), Some(UserAgentProblem::Empty));
+        assert_eq!(
+            check_user_agent("reqwest/0.12 (https://x.y)", &osm),
+            Some(UserAgentProblem::Generic)
+        );
+        assert_eq!(check_user_agent("MyApp/1.0", &osm), Some(UserAgentProblem::NoContact));
+
+        // Other servers have their own policies
+        let custom = TileSource::new("https://tiles.example.com/{z}/{x}/{y}.png");
+        assert_eq!(check_user_agent("", &custom), None);
+    }
+
+    #[test]
+    fn test_offline_source_loads_without_network() {
+        let bundled = TileId::new(1, 2, 3);
+        let missing = TileId::new(0, 0, 3);
+        let tiles = HashMap::from([(bundled, b"png".to_vec())]);
+        let source = TileSource::offline(OfflineTiles::Embedded(Arc::new(tiles)));
+        let mut loader = TileLoader::with_source(DEFAULT_USER_AGENT, source);
+
+        loader.request(bundled);
+        loader.request(missing);
+        let mut results = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while results.len() < 2 && Instant::now() < deadline {
+            match loader.poll() {
+                Some((result, _)) => results.push(result),
+                None => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+
+        assert!(matches!(&results[0], TileLoadResult::Success(id, bytes, _)
+            if *id == bundled && bytes == b"png"));
+        assert!(matches!(&results[1], TileLoadResult::Failed(id, _) if *id == missing));
+        assert_eq!(loader.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_throttled_loader_defers_requests() {
+        let (mut loader, _results, requests) = offline_loader();
+        loader
+            .throttle
+            .pause(web_time::Instant::now(), std::time::Duration::from_secs(60));
+        assert!(loader.is_throttled());
+
+        loader.request(TileId::new(1, 2, 3));
+        assert_eq!(loader.pending_count(), 0);
+        assert!(requests.try_recv().is_err());
+    }
 }
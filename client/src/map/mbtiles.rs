@@ -0,0 +1,229 @@
+//! Tiles from MBTiles files, SQLite databases of map tiles
+//!
+//! See <https://github.com/mapbox/mbtiles-spec>. Rows are stored in TMS
+//! order, counted from the south, so they are flipped on lookup.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+
+use super::tile::TileId;
+
+const TILE_QUERY: &str =
+    "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3";
+
+/// Tile image formats the map can draw
+const SUPPORTED_FORMATS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// MBTiles open error
+#[derive(Debug)]
+pub enum MbTilesError {
+    /// Not an SQLite file, or missing the MBTiles tables
+    Sqlite(rusqlite::Error),
+    /// Tiles are in a format the map can't draw (e.g. `pbf` vector tiles)
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for MbTilesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MbTilesError::Sqlite(e) => write!(f, "invalid MBTiles file: {}", e),
+            MbTilesError::UnsupportedFormat(format) => {
+                write!(f, "unsupported MBTiles tile format {:?}", format)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MbTilesError {}
+
+impl From<rusqlite::Error> for MbTilesError {
+    fn from(e: rusqlite::Error) -> Self {
+        MbTilesError::Sqlite(e)
+    }
+}
+
+/// Entries of the `metadata` table the map uses
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MbTilesMetadata {
+    pub name: Option<String>,
+    /// Tile image format (`png`, `jpg`, ...)
+    pub format: Option<String>,
+    pub min_zoom: Option<u8>,
+    pub max_zoom: Option<u8>,
+    /// Credit for the tiles, with any HTML markup removed
+    pub attribution: Option<String>,
+}
+
+/// A read-only MBTiles file
+pub struct MbTiles {
+    path: PathBuf,
+    /// Locked since connections can't be shared between threads
+    connection: Mutex<Connection>,
+    metadata: MbTilesMetadata,
+}
+
+impl MbTiles {
+    /// Open an MBTiles file, reading its metadata
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MbTilesError> {
+        let path = path.as_ref();
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        let metadata = read_metadata(&connection)?;
+        if let Some(format) = &metadata.format
+            && !SUPPORTED_FORMATS.contains(&format.to_lowercase().as_str())
+        {
+            return Err(MbTilesError::UnsupportedFormat(format.clone()));
+        }
+        // Fails on files without a tiles table
+        connection.prepare_cached(TILE_QUERY)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            connection: Mutex::new(connection),
+            metadata,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn metadata(&self) -> &MbTilesMetadata {
+        &self.metadata
+    }
+
+    /// Image file of a tile, or None if the file doesn't have it
+    pub fn read(&self, tile_id: &TileId) -> Option<Vec<u8>> {
+        let connection = self.connection.lock().ok()?;
+        let result = connection.prepare_cached(TILE_QUERY).and_then(|mut query| {
+            query
+                .query_row(params![tile_id.z, tile_id.x, tms_row(tile_id)], |row| {
+                    row.get(0)
+                })
+                .optional()
+        });
+        result.unwrap_or_else(|e| {
+            log::warn!(
+                "Failed to read tile {:?} from {}: {}",
+                tile_id,
+                self.path.display(),
+                e
+            );
+            None
+        })
+    }
+}
+
+impl PartialEq for MbTiles {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for MbTiles {}
+
+impl fmt::Debug for MbTiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MbTiles").field("path", &self.path).finish()
+    }
+}
+
+/// Row of a tile in the TMS scheme (y counted from the south)
+fn tms_row(tile_id: &TileId) -> u32 {
+    tile_id.max_tile_coord().saturating_sub(tile_id.y + 1)
+}
+
+fn read_metadata(connection: &Connection) -> Result<MbTilesMetadata, rusqlite::Error> {
+    let mut metadata = MbTilesMetadata::default();
+    let mut query = connection.prepare("SELECT name, value FROM metadata")?;
+    let rows = query.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+    for row in rows {
+        let (name, value): (String, String) = row?;
+        match name.as_str() {
+            "name" => metadata.name = Some(value),
+            "format" => metadata.format = Some(value),
+            "minzoom" => metadata.min_zoom = value.trim().parse().ok(),
+            "maxzoom" => metadata.max_zoom = value.trim().parse().ok(),
+            "attribution" => metadata.attribution = Some(strip_tags(&value)),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Remove HTML tags, which attributions often contain for links
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_flipped_rows_and_metadata() {
+        let path = std::env::temp_dir().join(format!("cplace-{}.mbtiles", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let db = Connection::open(&path).unwrap();
+            db.execute_batch(
+                "CREATE TABLE metadata (name TEXT, value TEXT);
+                 CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER,
+                                     tile_row INTEGER, tile_data BLOB);
+                 INSERT INTO metadata VALUES ('format', 'png'), ('minzoom', '2'),
+                     ('maxzoom', '14'),
+                     ('attribution', '<a href=\"https://example.com\">© Example</a>');
+                 INSERT INTO tiles VALUES (3, 1, 5, x'706e67');",
+            )
+            .unwrap();
+        }
+
+        let file = MbTiles::open(&path).unwrap();
+        let metadata = file.metadata();
+        assert_eq!((metadata.min_zoom, metadata.max_zoom), (Some(2), Some(14)));
+        assert_eq!(metadata.attribution.as_deref(), Some("© Example"));
+
+        // TMS row 5 of 8 is XYZ row 2
+        assert_eq!(file.read(&TileId::new(1, 2, 3)), Some(b"png".to_vec()));
+        assert_eq!(file.read(&TileId::new(1, 5, 3)), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_inverted_metadata_zooms_are_ordered() {
+        let path = std::env::temp_dir().join(format!(
+            "cplace-inverted-{}.mbtiles",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        {
+            let db = Connection::open(&path).unwrap();
+            db.execute_batch(
+                "CREATE TABLE metadata (name TEXT, value TEXT);
+                 CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER,
+                                     tile_row INTEGER, tile_data BLOB);
+                 INSERT INTO metadata VALUES ('minzoom', '14'), ('maxzoom', '2');",
+            )
+            .unwrap();
+        }
+
+        let source = crate::map::source::TileSource::mbtiles(&path).unwrap();
+        assert_eq!((source.min_zoom, source.max_zoom), (2, 14));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
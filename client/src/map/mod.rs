@@ -2,129 +2,868 @@
 
 pub mod cache;
 pub mod camera;
+pub mod command;
+pub mod config;
+pub mod decode;
+pub mod events;
+pub mod geojson;
 pub mod grid;
+pub mod heatmap;
+pub mod history;
+pub mod layers;
 pub mod loader;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mbtiles;
+pub mod overlay;
+pub mod polygon;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod region;
 pub mod renderer;
+pub mod snapshot;
+pub mod source;
+pub mod stats;
+pub mod throttle;
 pub mod tile;
+pub mod worldfile;
+pub mod zoom;
 
-use cache::TileCache;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use cache::{TileBytes, TileCache};
 use camera::MapCamera;
-use grid::PixelGrid;
-use loader::{TileLoadResult, TileLoader};
-use renderer::{screen_to_ndc, size_to_ndc, TileRenderer};
+use command::{CommandQueue, MapCommand};
+use decode::{DecodePool, DecodedTile};
+pub use config::MapSystemConfig;
+use events::{EventSink, MapEvent};
+use geojson::GeoJsonError;
+use grid::{GridCoord, PixelGrid};
+use heatmap::HeatmapRenderer;
+use history::{UndoStack, UndoUnit};
+use layers::{LayerStack, OverlayLayer};
+use loader::{LoadTimeStats, LoadTimes, TileLoadResult, TileLoader, tile_span};
+use overlay::OverlayRenderer;
+#[cfg(not(target_arch = "wasm32"))]
+use region::{RegionDownload, RegionProgress};
+use renderer::{fit_to_aspect, screen_to_ndc, TileFilter, TileQuad, TileRenderer};
+use source::TileSource;
+use stats::{FrameStats, FrameStatsCollector};
 use tile::TileId;
+use web_time::Instant;
+use zoom::{SmoothZoom, ZoomSettle};
+
+use crate::net::{PixelSync, SyncEvent};
+
+/// Most tiles a single `prefetch_bounds` call queues
+pub const MAX_PREFETCH_TILES: usize = 10_000;
+
+/// Most region tiles handed out per update
+#[cfg(not(target_arch = "wasm32"))]
+const REGION_TILES_PER_UPDATE: usize = 256;
 
 /// Integrated map system
 pub struct MapSystem {
     pub camera: MapCamera,
     tile_cache: TileCache,
+    /// Downloaded tile files, to re-upload evicted tiles without the network
+    byte_cache: TileCache<TileBytes>,
     tile_loader: TileLoader,
-    tile_renderer: TileRenderer,
+    /// Recent tile download times, to tell slow servers from a slow client
+    load_times: LoadTimes,
+    /// Decodes downloaded tiles before they are uploaded
+    decoder: DecodePool,
+    /// Tiles to download into the file cache, whether visible or not
+    prefetch: Vec<TileId>,
+    /// Region being saved to disk for offline use
+    #[cfg(not(target_arch = "wasm32"))]
+    region: Option<RegionDownload>,
+    /// Tiles whose files can't be decoded, not requested again until
+    /// `reload_tiles`
+    broken_tiles: HashSet<TileId>,
+    /// Tile renderer (None when headless)
+    tile_renderer: Option<TileRenderer>,
     pub pixel_grid: PixelGrid,
+    pub overlays: OverlayRenderer,
+    pub heatmap: HeatmapRenderer,
+
+    /// Collaborative pixel sync (None when offline)
+    sync: Option<PixelSync>,
+
+    /// Undo history of local pixel edits
+    history: UndoStack,
+
+    /// Tile load notifications for an embedding application
+    events: EventSink,
+
+    /// Draw order of tiles, pixel grid and vector overlays
+    layers: LayerStack,
+
+    /// Opacity applied to all tiles (e.g. to dim the base map)
+    tile_opacity: f32,
+
+    /// Sampling of tile textures
+    tile_filter: TileFilter,
+
+    /// Wheel zoom animating toward its target
+    smooth_zoom: SmoothZoom,
+
+    /// Settling on a whole zoom level after zooming stops (None = off)
+    zoom_settle: Option<ZoomSettle>,
+
+    /// Scripted camera movements still to play
+    commands: CommandQueue,
+
+    /// Draw call and allocation counts (None unless turned on)
+    frame_stats: Option<FrameStatsCollector>,
+
+    /// Start of the highlight pulse animation
+    created_at: Instant,
+
+    /// A tile of unexpected size was reported (warned about only once)
+    tile_size_mismatch: bool,
+
+    /// View at the last cancellation of stale tile requests
+    request_view: MapCamera,
+
+    /// MSAA samples of the render target, to rebuild pipelines after device loss
+    sample_count: u32,
+
+    /// Tile vertex buffers created with a new tile renderer
+    tile_vertex_slots: usize,
 
     /// Tiles to render this frame (calculated in update)
-    /// id, (x, y), (width, height)
-    render_tiles: Vec<(TileId, (f32, f32), (f32, f32))>,
+    /// id, NDC corners, opacity (see `TileQuad`)
+    render_tiles: Vec<TileQuad>,
 }
 
 impl MapSystem {
-    /// Create a new map system
+    /// Create a new map system with default settings
     pub fn new(
         device: &wgpu::Device,
         texture_format: wgpu::TextureFormat,
         viewport_width: u32,
         viewport_height: u32,
     ) -> Self {
-        // Default camera: Seoul at zoom 12
-        let camera = MapCamera::new(126.9780, 37.5665, 12.0, viewport_width, viewport_height);
+        let config = MapSystemConfig::default().viewport(viewport_width, viewport_height);
+        Self::from_config(device, texture_format, config)
+    }
 
-        let tile_cache = TileCache::default();
-        let tile_loader = TileLoader::default();
-        let tile_renderer = TileRenderer::new(device, texture_format);
+    /// Create a map system with custom settings
+    pub fn from_config(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        config: MapSystemConfig,
+    ) -> Self {
+        let samples = config.msaa_samples;
+        let max_dimension = config
+            .max_tile_dimension
+            .min(device.limits().max_texture_dimension_2d);
+        let mut pixel_grid = PixelGrid::new(device, texture_format, samples, config.cell_size);
+        pixel_grid.set_origin(config.grid_origin.0, config.grid_origin.1);
+        pixel_grid.set_capacity(config.max_pixels);
+        let mut tile_renderer = TileRenderer::new(device, texture_format, samples);
+        tile_renderer.reserve_vertex_slots(device, config.tile_vertex_slots);
+        let mut map = Self {
+            tile_renderer: Some(tile_renderer),
+            pixel_grid,
+            overlays: OverlayRenderer::new(device, texture_format, samples),
+            heatmap: HeatmapRenderer::new(device, texture_format, samples),
+            ..Self::headless_from_config(config)
+        };
+        map.decoder.set_max_dimension(max_dimension);
+        map
+    }
 
-        // Pixel grid with ~10m cell size at equator
-        let pixel_grid = PixelGrid::new(device, texture_format, 0.0001);
+    /// Rebuild GPU resources on a new device (e.g. after device loss)
+    ///
+    /// Cached tiles belonged to the old device and are dropped; they are
+    /// decoded again from the byte cache (or reloaded) on the next updates.
+    /// Pixels, overlays and the camera are kept.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+    ) {
+        let samples = self.sample_count;
+        let mut tile_renderer = TileRenderer::new(device, texture_format, samples);
+        tile_renderer.reserve_vertex_slots(device, self.tile_vertex_slots);
+        self.tile_renderer = Some(tile_renderer);
+        self.tile_cache.clear();
+        self.render_tiles.clear();
+        self.pixel_grid
+            .recreate_pipeline(device, texture_format, samples);
+        self.overlays
+            .recreate_pipeline(device, texture_format, samples);
+        self.heatmap
+            .recreate_pipeline(device, texture_format, samples);
+    }
+
+    /// Create a map system without GPU resources
+    ///
+    /// Camera, cache, loader and pixel storage behave as usual, which allows
+    /// testing visibility and cache logic without a device. Loaded tiles are
+    /// never uploaded and `render` draws nothing. The tile source has no URL
+    /// and no offline tiles, so requested tiles fail without network access.
+    pub fn new_headless(viewport_width: u32, viewport_height: u32) -> Self {
+        Self::headless_from_config(
+            MapSystemConfig::default()
+                .viewport(viewport_width, viewport_height)
+                .tile_source(TileSource::new("")),
+        )
+    }
+
+    /// Create a map system without GPU resources, with custom settings
+    pub fn headless_from_config(config: MapSystemConfig) -> Self {
+        let config = config.validated();
+
+        let (lon, lat) = config.center;
+        let (width, height) = config.viewport;
+        let mut camera = MapCamera::new(lon, lat, config.zoom, width, height);
+        camera.set_prefetch_buffer(config.prefetch_buffer);
+        let (min_zoom, max_zoom) = config.tile_source.zoom_range();
+        camera.set_zoom_range(min_zoom, max_zoom);
+        camera.set_tile_size(config.tile_source.tile_size);
+
+        let mut pixel_grid = PixelGrid::new_headless(config.cell_size);
+        pixel_grid.set_origin(config.grid_origin.0, config.grid_origin.1);
+        pixel_grid.set_capacity(config.max_pixels);
+
+        let mut tile_loader = TileLoader::with_source(&config.user_agent, config.tile_source);
+        tile_loader.set_max_pending(config.max_pending_tiles);
+
+        let mut decoder = DecodePool::new(config.max_parallel_decodes);
+        decoder.set_max_dimension(config.max_tile_dimension);
 
         Self {
             camera,
-            tile_cache,
+            request_view: camera,
+            tile_cache: TileCache::with_policy(
+                config.max_tiles,
+                config.max_memory,
+                config.eviction_policy,
+            ),
+            byte_cache: TileCache::with_policy(
+                config.max_byte_tiles,
+                config.max_bytes,
+                config.eviction_policy,
+            ),
             tile_loader,
-            tile_renderer,
+            load_times: LoadTimes::default(),
+            decoder,
+            prefetch: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            region: None,
+            broken_tiles: HashSet::new(),
+            tile_renderer: None,
             pixel_grid,
+            overlays: OverlayRenderer::new_headless(),
+            heatmap: HeatmapRenderer::new_headless(),
+            sync: None,
+            history: UndoStack::default(),
+            events: EventSink::default(),
+            layers: LayerStack::default(),
+            tile_opacity: 1.0,
+            tile_filter: TileFilter::default(),
+            smooth_zoom: SmoothZoom::default(),
+            zoom_settle: config.settle_delay.map(ZoomSettle::new),
+            commands: CommandQueue::default(),
+            frame_stats: None,
+            created_at: Instant::now(),
+            tile_size_mismatch: false,
+            sample_count: config.msaa_samples,
+            tile_vertex_slots: config.tile_vertex_slots,
             render_tiles: Vec::new(),
         }
     }
 
-    /// Update the map system (call each frame)
-    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        // 1. Get visible tiles
+    /// Update the map system (call each frame with the frame's time)
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, now: Instant) {
+        if let Some(stats) = &mut self.frame_stats {
+            stats.begin_frame();
+        }
+
+        // 0-4. Move the camera, load tiles and apply remote pixels
+        let (visible, blend) = self.load_tiles(now);
+
+        // 5. Upload decoded tiles, and build the render list with screen
+        // positions
+        for decoded in self.decoder.finished() {
+            self.upload_tile(device, queue, decoded);
+        }
+        self.render_tiles.clear();
+
+        let layers = std::iter::once((&visible, 1.0))
+            .chain(blend.as_ref().map(|(tiles, opacity)| (tiles, *opacity)));
+        for (tiles, opacity) in layers {
+            for tile_id in tiles {
+                // Only add to render list if cached; counts as the tile's
+                // one use this update for LRU/LFU eviction
+                if let Some(cached) = self.tile_cache.get(tile_id) {
+                    // Fit the image's aspect, then convert corners to NDC
+                    let corners = fit_to_aspect(self.camera.tile_corners(tile_id), cached.size);
+                    let corners = corners.map(|(x, y)| {
+                        screen_to_ndc(x, y, self.camera.viewport_width, self.camera.viewport_height)
+                    });
+
+                    self.render_tiles.push((*tile_id, corners, opacity));
+                }
+            }
+        }
+
+        // Buffers created by the renderers before updating
+        let buffers_created = self.buffers_created();
+        let density_passes = self.heatmap.density_passes();
+
+        if let Some(tile_renderer) = &mut self.tile_renderer {
+            tile_renderer.set_tile_opacity(queue, self.tile_opacity);
+            tile_renderer.set_filter(self.tile_filter);
+            tile_renderer.prepare(device, queue, &self.render_tiles);
+        }
+
+        // 6. Update pixel grid, pulsing its highlights
+        if self.pixel_grid.has_highlight() {
+            let elapsed = now.saturating_duration_since(self.created_at);
+            self.pixel_grid.set_highlight_alpha(grid::highlight_pulse(elapsed));
+        }
+        self.pixel_grid.update(device, queue, &self.camera);
+
+        // 7. Update vector overlays and the heatmap density
+        self.overlays.update(device, &self.camera);
+        self.heatmap.update(device, queue, &self.camera);
+
+        if let Some(stats) = &self.frame_stats {
+            let created = self.buffers_created();
+            let passes = self.heatmap.density_passes() - density_passes;
+            stats.add(|stats| {
+                stats.buffers_allocated += created - buffers_created;
+                stats.draw_calls += passes;
+            });
+        }
+    }
+
+    /// The steps of [`update`](Self::update) that need no GPU: play camera
+    /// commands, request visible tiles, poll the loader into the decoder
+    /// and file cache, and apply remote pixels
+    pub fn update_loads(&mut self, now: Instant) {
+        self.load_tiles(now);
+    }
+
+    /// [`update_loads`](Self::update_loads), returning the visible tiles and
+    /// the cross-faded level with its opacity
+    fn load_tiles(&mut self, now: Instant) -> (Vec<TileId>, Option<(Vec<TileId>, f32)>) {
+        // 0. Play queued camera commands, which take over from a wheel zoom,
+        // and advance an animated zoom
+        if self.commands.is_active() {
+            self.smooth_zoom.cancel();
+            self.commands.step(&mut self.camera, now);
+        }
+        if let Some((delta, (x, y))) = self.smooth_zoom.step(self.camera.zoom, now) {
+            self.camera.zoom_at(delta, x, y);
+        }
+        if let Some(settle) = &mut self.zoom_settle
+            && let Some(level) = settle.check(self.camera.zoom, now)
+            && !self.commands.is_active()
+            && !self.smooth_zoom.is_active()
+        {
+            let center = (
+                self.camera.viewport_width as f32 / 2.0,
+                self.camera.viewport_height as f32 / 2.0,
+            );
+            self.smooth_zoom.add(
+                self.camera.zoom,
+                level - self.camera.zoom,
+                center,
+                self.camera.min_zoom as f64,
+                self.camera.max_zoom as f64,
+            );
+        }
+
+        // 1. Get visible tiles, plus the next level while cross-fading into it
         let visible = self.camera.visible_tiles();
+        let blend = self.camera.blend_level().map(|(z, opacity)| {
+            let tiles = self
+                .camera
+                .visible_tiles_at_level(z, self.camera.prefetch_buffer as i32);
+            (tiles, opacity)
+        });
 
-        // 2. Request loading for tiles not in cache
-        for tile_id in &visible {
-            if !self.tile_cache.contains(tile_id) && !self.tile_loader.is_loading(tile_id) {
-                self.tile_loader.request(*tile_id);
+        // 2. Request loading for tiles not in cache (current level first),
+        // dropping requests for a view we've left
+        let blend_tiles = blend.iter().flat_map(|(tiles, _)| tiles);
+        if self.camera.is_far_from(&self.request_view) {
+            let wanted = visible
+                .iter()
+                .chain(blend_tiles.clone())
+                .chain(&self.prefetch);
+            #[cfg(not(target_arch = "wasm32"))]
+            let wanted = wanted.chain(self.region.iter().flat_map(RegionDownload::in_flight));
+            let wanted: HashSet<TileId> = wanted.copied().collect();
+            let cancelled = self.tile_loader.cancel_except(&wanted);
+            self.tile_loader.advance_epoch();
+            if cancelled > 0 {
+                log::debug!("Cancelled {} stale tile requests", cancelled);
             }
+            self.request_view = self.camera;
         }
+        for tile_id in visible.iter().chain(blend_tiles) {
+            if self.tile_cache.contains(tile_id)
+                || self.decoder.is_decoding(tile_id)
+                || self.broken_tiles.contains(tile_id)
+            {
+                continue;
+            }
+            // Evicted from the GPU but still downloaded: decode again
+            if self.tile_renderer.is_some()
+                && let Some(bytes) = self.byte_cache.get(tile_id)
+            {
+                let _entered = tile_span(tile_id).entered();
+                log::debug!("Decoding cached file of {:?} again", tile_id);
+                self.decoder.submit(*tile_id, bytes.0.clone());
+                continue;
+            }
+            // Also keeps pending requests from being dropped for newer ones
+            self.tile_loader.request_at(*tile_id, now);
+        }
+        // Then prefetched tiles, without crowding out the visible ones
+        self.prefetch.retain(|tile_id| {
+            !self.byte_cache.contains(tile_id) && !self.broken_tiles.contains(tile_id)
+        });
+        for tile_id in &self.prefetch {
+            if self.tile_loader.is_throttled()
+                || self.tile_loader.pending_count() >= self.tile_loader.max_pending()
+            {
+                break;
+            }
+            if !self.tile_loader.is_loading(tile_id) {
+                self.tile_loader.request_at(*tile_id, now);
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        self.request_region_tiles(now);
 
-        // 3. Process completed loads
-        while let Some(result) = self.tile_loader.poll() {
+        // 3. Hand completed loads to the decoder
+        while let Some((result, span)) = self.tile_loader.poll() {
+            let _entered = span.enter();
             match result {
-                TileLoadResult::Success(id, data) => {
-                    match self.tile_renderer.create_cached_tile(device, queue, &data) {
-                        Ok(cached) => {
-                            log::debug!("Loaded tile {:?}", id);
-                            self.tile_cache.insert(id, cached);
-                        }
-                        Err(e) => {
-                            log::warn!("Failed to decode tile {:?}: {}", id, e);
-                        }
+                TileLoadResult::Success(id, data, elapsed) => {
+                    self.load_times.record(elapsed);
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if let Some(region) = &mut self.region {
+                        region.save(&id, &data);
+                    }
+                    // Prefetched tiles are decoded once they are visible
+                    if let Some(index) = self.prefetch.iter().position(|t| *t == id) {
+                        self.prefetch.swap_remove(index);
+                        self.byte_cache.insert(id, TileBytes(data));
+                    } else if self.tile_renderer.is_some() {
+                        // Without a GPU there is nothing to decode for
+                        self.decoder.submit(id, data);
                     }
                 }
                 TileLoadResult::Failed(id, err) => {
+                    self.prefetch.retain(|t| *t != id);
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if let Some(region) = &mut self.region {
+                        region.fail(&id);
+                    }
                     log::warn!("Failed to load tile {:?}: {}", id, err);
+                    self.events.emit(|| MapEvent::TileFailed(id, err));
                 }
             }
         }
 
-        // 4. Build render list with screen positions
-        self.render_tiles.clear();
-        let tile_size = self.camera.tile_screen_size();
-
-        for tile_id in &visible {
-            // Only add to render list if cached
-            if self.tile_cache.contains(tile_id) {
-                let (x, y) = self.camera.tile_to_screen(tile_id);
-
-                // Convert to NDC
-                let (ndc_x, ndc_y) =
-                    screen_to_ndc(x, y, self.camera.viewport_width, self.camera.viewport_height);
-                let (ndc_w, ndc_h) = size_to_ndc(
-                    tile_size,
-                    self.camera.viewport_width,
-                    self.camera.viewport_height,
+        // 4. Apply remote pixel operations
+        self.poll_sync();
+
+        (visible, blend)
+    }
+
+    /// GPU buffers created by the tile renderer, grid, overlays and heatmap
+    fn buffers_created(&self) -> u32 {
+        let tiles = self.tile_renderer.as_ref().map_or(0, TileRenderer::buffers_created);
+        tiles
+            + self.pixel_grid.buffers_created()
+            + self.overlays.buffers_created()
+            + self.heatmap.density_passes()
+    }
+
+    /// Upload a decoded tile to the GPU cache, keeping its file for
+    /// re-uploads
+    fn upload_tile(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, decoded: DecodedTile) {
+        let Some(tile_renderer) = &self.tile_renderer else {
+            return;
+        };
+        let id = decoded.tile_id;
+        let _entered = decoded.span.enter();
+        let cached = decoded
+            .image
+            .and_then(|image| tile_renderer.create_cached_tile_from_image(device, queue, &image));
+        match cached {
+            Ok(cached) => {
+                log::debug!("Uploaded tile {:?} ({} bytes)", id, cached.memory_size);
+                let size = (cached.texture.width(), cached.texture.height());
+                let expected = self.camera.tile_size as u32;
+                if size != (expected, expected) && !self.tile_size_mismatch {
+                    log::warn!(
+                        "Tile {:?} is {}x{} pixels but the source is set up for {}px tiles; \
+                         set TileSource::with_tile_size to match",
+                        id,
+                        size.0,
+                        size.1,
+                        expected
+                    );
+                    self.tile_size_mismatch = true;
+                }
+                self.tile_cache.insert(id, cached);
+                self.byte_cache.insert(id, TileBytes(decoded.data));
+                log::debug!("Cached tile {:?} ({} tiles)", id, self.tile_cache.len());
+                if let Some(stats) = &self.frame_stats {
+                    stats.add(|stats| stats.tiles_uploaded += 1);
+                }
+                self.events.emit(|| MapEvent::TileLoaded(id));
+            }
+            Err(e) => {
+                if e.is_retryable() {
+                    log::warn!("Failed to decode tile {:?}, retrying: {}", id, e);
+                } else {
+                    log::warn!("Failed to decode tile {:?}, giving up: {}", id, e);
+                    self.broken_tiles.insert(id);
+                }
+                self.events.emit(|| MapEvent::TileFailed(id, e.to_string()));
+            }
+        }
+    }
+
+    /// Render the map
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        for layer in self.layers.ordered() {
+            match layer {
+                OverlayLayer::Tiles => {
+                    if let Some(tile_renderer) = &self.tile_renderer {
+                        tile_renderer.render(render_pass, &self.render_tiles, &self.tile_cache);
+                    }
+                }
+                OverlayLayer::Heatmap => self.heatmap.render(render_pass),
+                OverlayLayer::PixelGrid => self.pixel_grid.render(render_pass),
+                _ => self.overlays.render_layer(render_pass, layer),
+            }
+        }
+
+        if let Some(stats) = &self.frame_stats {
+            stats.add(|stats| self.count_render(stats));
+        }
+    }
+
+    /// Count what `render` drew
+    fn count_render(&self, stats: &mut FrameStats) {
+        if self.tile_renderer.is_some() {
+            // One draw call per tile; vertex buffers are counted in update
+            let tiles = self.rendered_tiles().len() as u32;
+            stats.tiles += tiles;
+            stats.draw_calls += tiles;
+        }
+        if self.heatmap.is_drawn() {
+            stats.draw_calls += 1;
+        }
+        let grid_vertices = self.pixel_grid.drawn_vertices();
+        stats.grid_vertices += grid_vertices;
+        stats.draw_calls += (grid_vertices > 0) as u32;
+        for layer in [OverlayLayer::Polygons, OverlayLayer::Polylines, OverlayLayer::Markers] {
+            let vertices = self.overlays.drawn_vertices(layer);
+            stats.overlay_vertices += vertices;
+            stats.draw_calls += (vertices > 0) as u32;
+        }
+    }
+
+    /// Count draw calls and allocations per frame (off by default)
+    ///
+    /// Counting is cheap but not free, so it is meant for diagnostics.
+    pub fn set_frame_stats(&mut self, enabled: bool) {
+        if enabled != self.frame_stats.is_some() {
+            self.frame_stats = enabled.then(FrameStatsCollector::default);
+        }
+    }
+
+    /// Counts of the last frame (an `update` and the `render` after it), if
+    /// turned on with `set_frame_stats`
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.frame_stats.as_ref().and_then(FrameStatsCollector::last)
+    }
+
+    /// Set the display's physical pixels per logical pixel
+    ///
+    /// The map itself is drawn at the viewport's physical resolution, but
+    /// marker sizes, line widths and heatmap sprites are given in logical
+    /// pixels and are scaled by this.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.overlays.set_scale_factor(scale_factor);
+        self.heatmap.set_scale_factor(scale_factor);
+    }
+
+    /// Fade all tiles (0 = invisible, 1 = opaque), e.g. to dim the base map
+    /// under bright overlays
+    pub fn set_tile_opacity(&mut self, opacity: f32) {
+        self.tile_opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    pub fn tile_opacity(&self) -> f32 {
+        self.tile_opacity
+    }
+
+    /// Sample tiles smoothly or crisply (see `TileFilter` for the trade-off)
+    pub fn set_tile_filter(&mut self, filter: TileFilter) {
+        self.tile_filter = filter;
+    }
+
+    pub fn tile_filter(&self) -> TileFilter {
+        self.tile_filter
+    }
+
+    /// Credit for the tile source, to be shown over the map
+    pub fn attribution(&self) -> Option<&str> {
+        self.tile_loader.source().attribution.as_deref()
+    }
+
+    /// Change a layer's sort key; higher keys are drawn on top
+    ///
+    /// Defaults are `OverlayLayer::default_z` (tiles, heatmap, pixel grid,
+    /// polygons, polylines, markers from bottom to top).
+    pub fn set_layer_z(&mut self, layer: OverlayLayer, z: i32) {
+        self.layers.set_z(layer, z);
+    }
+
+    /// Layers in draw order, bottom first
+    pub fn layer_order(&self) -> [OverlayLayer; OverlayLayer::ALL.len()] {
+        self.layers.ordered()
+    }
+
+    /// Receive tile load events, replacing any previous subscriber
+    ///
+    /// Events are sent during `update`; drain the receiver whenever
+    /// convenient. Dropping it unsubscribes.
+    pub fn subscribe_events(&mut self) -> std::sync::mpsc::Receiver<MapEvent> {
+        self.events.subscribe()
+    }
+
+    /// Connect to a pixel sync server and request the visible canvas region
+    pub fn connect_sync(&mut self, url: &str) {
+        let mut sync = PixelSync::connect(url);
+        let (min, max) = self.visible_grid_bounds();
+        sync.request_region(min, max);
+        self.sync = Some(sync);
+    }
+
+    /// Drop the pixel sync connection
+    pub fn disconnect_sync(&mut self) {
+        self.sync = None;
+    }
+
+    /// Check if the pixel sync connection is open
+    pub fn is_sync_connected(&self) -> bool {
+        self.sync.as_ref().is_some_and(PixelSync::is_connected)
+    }
+
+    /// Set a pixel locally and broadcast it to the sync server
+    pub fn set_pixel(&mut self, coord: GridCoord, color: [f32; 4]) {
+        let unit = self.apply_cells([(coord, Some(color))]);
+        self.history.push(unit);
+    }
+
+    /// Set the pixel covering a world position (longitude, latitude)
+    ///
+    /// Same as `set_pixel` on the cell from `PixelGrid::world_to_grid`.
+    pub fn set_pixel_at(&mut self, lon: f64, lat: f64, color: [f32; 4]) {
+        let coord = self.pixel_grid.world_to_grid(lon, lat);
+        self.set_pixel(coord, color);
+    }
+
+    /// Erase the pixel covering a world position, returning false if the
+    /// cell was empty
+    pub fn clear_pixel_at(&mut self, lon: f64, lat: f64) -> bool {
+        let coord = self.pixel_grid.world_to_grid(lon, lat);
+        if self.pixel_grid.get_pixel(&coord).is_none() {
+            return false;
+        }
+        let unit = self.apply_cells([(coord, None)]);
+        self.history.push(unit);
+        true
+    }
+
+    /// Color of the pixel covering a world position, if set
+    pub fn get_pixel_at(&self, lon: f64, lat: f64) -> Option<[f32; 4]> {
+        let coord = self.pixel_grid.world_to_grid(lon, lat);
+        self.pixel_grid.get_pixel(&coord).map(|pixel| pixel.color)
+    }
+
+    /// Fill an inclusive grid rectangle locally and broadcast it
+    ///
+    /// Like `PixelGrid::fill_region`, refuses rectangles of more than
+    /// `grid::MAX_FILL_CELLS` cells and returns false.
+    pub fn fill_region(&mut self, min: GridCoord, max: GridCoord, color: [f32; 4]) -> bool {
+        if !grid::is_fillable(min, max) {
+            return false;
+        }
+        let cells = (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| (GridCoord::new(x, y), Some(color))));
+        let unit = self.apply_cells(cells);
+        self.history.push(unit);
+        true
+    }
+
+    /// Erase an inclusive grid rectangle locally and broadcast it
+    pub fn clear_bounds(&mut self, min: GridCoord, max: GridCoord) {
+        let unit = self.pixel_grid.clear_bounds(min, max);
+        if let Some(sync) = &mut self.sync {
+            for (coord, _) in &unit {
+                sync.send_pixel(*coord, [0.0; 4]);
+            }
+        }
+        self.history.push(unit);
+    }
+
+    /// Stamp copied cells (see `PixelGrid::copy_region`) at `origin` as one undo unit
+    pub fn paste_region(&mut self, origin: GridCoord, cells: &[(GridCoord, [f32; 4])]) {
+        let unit = self.pixel_grid.paste_region(origin, cells);
+        if let Some(sync) = &mut self.sync {
+            for (offset, color) in cells {
+                sync.send_pixel(
+                    GridCoord::new(origin.x + offset.x, origin.y + offset.y),
+                    *color,
                 );
+            }
+        }
+        self.history.push(unit);
+    }
+
+    /// Revert the most recent local edit, returning false if there is none
+    pub fn undo(&mut self) -> bool {
+        let Some(unit) = self.history.pop() else {
+            return false;
+        };
+        // Reverse order so a cell touched twice ends at its oldest state
+        self.apply_cells(unit.into_iter().rev());
+        true
+    }
+
+    /// Number of local edits that can be undone
+    pub fn undo_count(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Apply cell changes (None erases), broadcast them, and return the
+    /// previous contents
+    ///
+    /// Erasures are sent as fully transparent pixels.
+    fn apply_cells(
+        &mut self,
+        cells: impl IntoIterator<Item = (GridCoord, Option<[f32; 4]>)>,
+    ) -> UndoUnit {
+        let mut previous = Vec::new();
+        for (coord, color) in cells {
+            previous.push((coord, self.pixel_grid.get_pixel(&coord).map(|p| p.color)));
+            match color {
+                Some(color) => self.pixel_grid.set_pixel(coord, color),
+                None => {
+                    self.pixel_grid.remove_pixel(&coord);
+                }
+            }
+            if let Some(sync) = &mut self.sync {
+                sync.send_pixel(coord, color.unwrap_or([0.0; 4]));
+            }
+        }
+        previous
+    }
 
-                self.render_tiles.push((*tile_id, (ndc_x, ndc_y), (ndc_w, ndc_h)));
+    /// Apply pending remote pixel operations to the grid
+    fn poll_sync(&mut self) {
+        let Some(sync) = &mut self.sync else {
+            return;
+        };
+        while let Some(event) = sync.poll() {
+            match event {
+                SyncEvent::Connected => log::info!("Pixel sync connected to {}", sync.url()),
+                SyncEvent::Pixel(coord, color) if color[3] <= 0.0 => {
+                    self.pixel_grid.remove_pixel(&coord);
+                }
+                SyncEvent::Pixel(coord, color) => self.pixel_grid.set_pixel(coord, color),
+                SyncEvent::Disconnected(reason) => {
+                    log::warn!("Pixel sync disconnected: {}", reason);
+                }
             }
         }
+    }
 
-        // 5. Update pixel grid
-        self.pixel_grid.update(device, &self.camera);
+    /// Inclusive grid rectangle covering the viewport
+    pub fn visible_grid_bounds(&self) -> (GridCoord, GridCoord) {
+        let (w, h) = (
+            self.camera.viewport_width as f32,
+            self.camera.viewport_height as f32,
+        );
+        // All four corners, since a rotated view's extremes can be any of them
+        let corners = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)].map(|(x, y)| {
+            let (lon, lat) = self.camera.screen_to_world(x, y);
+            self.pixel_grid.world_to_grid(lon, lat)
+        });
+        corners.iter().skip(1).fold((corners[0], corners[0]), |(min, max), c| {
+            (
+                GridCoord::new(min.x.min(c.x), min.y.min(c.y)),
+                GridCoord::new(max.x.max(c.x), max.y.max(c.y)),
+            )
+        })
     }
 
-    /// Render the map
-    pub fn render<'a>(
-        &'a self,
-        render_pass: &mut wgpu::RenderPass<'a>,
-        device: &wgpu::Device,
-    ) {
-        // Render tiles
-        self.tile_renderer
-            .render(render_pass, device, &self.render_tiles, &self.tile_cache);
+    /// Load GeoJSON features into the vector overlays
+    pub fn load_geojson(&mut self, input: &str) -> Result<(), GeoJsonError> {
+        let parsed = geojson::parse(input)?;
+        log::debug!(
+            "Loaded GeoJSON: {} markers, {} polylines, {} polygons",
+            parsed.markers.len(),
+            parsed.polylines.len(),
+            parsed.polygons.len()
+        );
+
+        for marker in parsed.markers {
+            self.overlays.add_marker(marker);
+        }
+        for polyline in parsed.polylines {
+            self.overlays.add_polyline(polyline);
+        }
+        for polygon in parsed.polygons {
+            self.overlays.add_polygon(polygon);
+        }
+        Ok(())
+    }
+
+    /// Tiles covering the viewport (plus preload buffer) for the current camera
+    pub fn visible_tiles(&self) -> Vec<TileId> {
+        self.camera.visible_tiles()
+    }
+
+    /// Tiles drawn by `render`: those visible (and of a level being faded
+    /// into) that were cached at the last `update`, in draw order
+    ///
+    /// Unlike `visible_tiles` this leaves out tiles still loading.
+    pub fn rendered_tiles(&self) -> Vec<TileId> {
+        self.render_tiles
+            .iter()
+            .map(|(id, _, _)| *id)
+            .filter(|id| self.tile_cache.contains(id))
+            .collect()
+    }
 
-        // Render pixel grid overlay
-        self.pixel_grid.render(render_pass);
+    /// Set map rotation in radians (clockwise, 0 = north up)
+    pub fn set_rotation(&mut self, radians: f32) {
+        self.camera.set_rotation(radians);
+    }
+
+    /// Get current map rotation in radians
+    pub fn rotation(&self) -> f32 {
+        self.camera.rotation
+    }
+
+    /// Set how many tile rings to preload around the viewport (see `MapCamera::set_prefetch_buffer`)
+    pub fn set_prefetch_buffer(&mut self, rings: u32) {
+        self.camera.set_prefetch_buffer(rings);
     }
 
     /// Handle viewport resize
@@ -142,6 +881,74 @@ impl MapSystem {
         self.camera.zoom_at(delta, screen_x, screen_y);
     }
 
+    /// Zoom at screen position, easing there over the next updates
+    ///
+    /// Repeated calls accumulate, so coarse wheel notches and fine trackpad
+    /// deltas both zoom at a steady pace. Keep calling `update` while
+    /// `is_zooming` is true.
+    pub fn zoom_smoothly_at(&mut self, delta: f64, screen_x: f32, screen_y: f32) {
+        let (min, max) = (self.camera.min_zoom as f64, self.camera.max_zoom as f64);
+        self.smooth_zoom
+            .add(self.camera.zoom, delta, (screen_x, screen_y), min, max);
+    }
+
+    /// Zoom at screen position to the next whole level in the direction of
+    /// `delta`, easing there like `zoom_smoothly_at`
+    pub fn zoom_level_smoothly_at(&mut self, delta: f64, screen_x: f32, screen_y: f32) {
+        let (min, max) = (self.camera.min_zoom as f64, self.camera.max_zoom as f64);
+        self.smooth_zoom
+            .add_level(self.camera.zoom, delta, (screen_x, screen_y), min, max);
+    }
+
+    /// Ease to the nearest whole zoom level `delay` after zooming stops, or
+    /// never with None (the default)
+    ///
+    /// Tiles are only drawn at their native size on whole levels; elsewhere
+    /// they are slightly scaled and blurry. Keep calling `update` until
+    /// `settle_due_at`.
+    pub fn set_zoom_settle(&mut self, delay: Option<Duration>) {
+        if self.zoom_settle.map(|settle| settle.delay()) != delay {
+            self.zoom_settle = delay.map(ZoomSettle::new);
+        }
+    }
+
+    pub fn zoom_settle(&self) -> Option<Duration> {
+        self.zoom_settle.map(|settle| settle.delay())
+    }
+
+    /// When the zoom will settle on a whole level, if it is waiting to
+    pub fn settle_due_at(&self) -> Option<Instant> {
+        self.zoom_settle.and_then(|settle| settle.due_at())
+    }
+
+    /// Check if an animated zoom is still in progress
+    pub fn is_zooming(&self) -> bool {
+        self.smooth_zoom.is_active()
+    }
+
+    /// Queue a camera movement, played after those queued before
+    ///
+    /// Commands advance in `update` (or `update_loads`); keep calling it
+    /// while `is_playing_commands` is true.
+    pub fn queue_command(&mut self, command: MapCommand) {
+        self.commands.push(command);
+    }
+
+    /// Stop the running command and drop the queued ones
+    pub fn clear_commands(&mut self) {
+        self.commands.clear();
+    }
+
+    /// Check if queued camera commands are still playing
+    pub fn is_playing_commands(&self) -> bool {
+        self.commands.is_active()
+    }
+
+    /// Check if a pulsing selection or hover highlight needs further updates
+    pub fn is_highlighting(&self) -> bool {
+        self.pixel_grid.has_highlight()
+    }
+
     /// Zoom centered
     pub fn zoom(&mut self, delta: f64) {
         self.camera.zoom_by(delta);
@@ -152,14 +959,260 @@ impl MapSystem {
         self.camera.screen_to_world(screen_x, screen_y)
     }
 
+    /// Grid cell under a screen position
+    pub fn screen_to_grid(&self, screen_x: f32, screen_y: f32) -> GridCoord {
+        let (lon, lat) = self.camera.screen_to_world(screen_x, screen_y);
+        self.pixel_grid.world_to_grid(lon, lat)
+    }
+
     /// Get cache statistics
     pub fn cache_stats(&self) -> cache::CacheStats {
         self.tile_cache.stats()
     }
 
-    /// Get pending tile count
+    /// Latency of the recent tile loads, or None before the first load
+    pub fn load_time_stats(&self) -> Option<LoadTimeStats> {
+        self.load_times.stats()
+    }
+
+    /// Keep the tiles of a view (e.g. the home view) cached once loaded, so
+    /// going back to it is instant
+    ///
+    /// The view has the viewport's size. Returns the number of tiles pinned,
+    /// fewer than covering the view when the cache can't spare them.
+    pub fn pin_view(&mut self, lon: f64, lat: f64, zoom: f64) -> usize {
+        let mut view = self.camera;
+        view.center = (lon, lat);
+        view.zoom = view.clamp_zoom(zoom);
+        view.visible_tiles_with_buffer(0)
+            .into_iter()
+            .take_while(|tile_id| self.pin_tile(*tile_id))
+            .count()
+    }
+
+    /// Keep a tile cached once loaded; false if too many tiles are pinned
+    pub fn pin_tile(&mut self, tile_id: TileId) -> bool {
+        if !self.tile_cache.pin(tile_id) {
+            return false;
+        }
+        // The file too, in case the texture is lost with the device
+        if !self.byte_cache.pin(tile_id) {
+            self.tile_cache.unpin(&tile_id);
+            return false;
+        }
+        true
+    }
+
+    pub fn unpin_tile(&mut self, tile_id: &TileId) {
+        self.tile_cache.unpin(tile_id);
+        self.byte_cache.unpin(tile_id);
+    }
+
+    /// Let all tiles be evicted again
+    pub fn unpin_all(&mut self) {
+        self.tile_cache.unpin_all();
+        self.byte_cache.unpin_all();
+    }
+
+    /// Download the tiles covering a box at `zoom`, visible or not, so the
+    /// map can later show them without the network
+    ///
+    /// `min` is the south-west corner and `max` the north-east one. Tiles
+    /// are requested as the pending request limit and rate limiting allow,
+    /// after the visible ones, and kept in the tile file cache, which must
+    /// be large enough to hold them. Returns the number of tiles queued;
+    /// none if the box has more than `MAX_PREFETCH_TILES`. `is_idle` is
+    /// false until all are downloaded.
+    pub fn prefetch_bounds(&mut self, min: (f64, f64), max: (f64, f64), zoom: u8) -> usize {
+        let (min_zoom, max_zoom) = self.tile_loader.source().zoom_range();
+        let zoom = zoom.clamp(min_zoom, max_zoom);
+        if tile::tile_count_in_bounds(min, max, zoom) > MAX_PREFETCH_TILES as u64 {
+            log::warn!(
+                "Not prefetching more than {} tiles at zoom {}",
+                MAX_PREFETCH_TILES,
+                zoom
+            );
+            return 0;
+        }
+
+        let queued: HashSet<TileId> = self.prefetch.iter().copied().collect();
+        let before = self.prefetch.len();
+        let tiles = tile::tiles_in_bounds(min, max, zoom);
+        self.prefetch.extend(tiles.filter(|tile_id| {
+            !queued.contains(tile_id)
+                && !self.byte_cache.contains(tile_id)
+                && !self.broken_tiles.contains(tile_id)
+        }));
+        self.prefetch.len() - before
+    }
+
+    /// Number of tiles `download_region` would save for a box and zoom
+    /// range (within the source's zoom levels), to confirm large downloads
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn region_tile_count(
+        &self,
+        min: (f64, f64),
+        max: (f64, f64),
+        min_zoom: u8,
+        max_zoom: u8,
+    ) -> u64 {
+        region::region_tile_count(min, max, self.region_zooms(min_zoom, max_zoom))
+    }
+
+    /// Save the tiles covering a box at `min_zoom` to `max_zoom` under
+    /// `dir`, for use with `OfflineTiles::Directory` (native only)
+    ///
+    /// `min` is the south-west corner and `max` the north-east one. Tiles
+    /// are downloaded in the background of `update`, after the visible and
+    /// prefetched ones and as rate limiting allows; tiles already in `dir`
+    /// are skipped. Replaces a download in progress. Returns the number of
+    /// tiles in the region (see `region_tile_count`); follow it with
+    /// `region_progress`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn download_region(
+        &mut self,
+        min: (f64, f64),
+        max: (f64, f64),
+        min_zoom: u8,
+        max_zoom: u8,
+        dir: impl Into<std::path::PathBuf>,
+    ) -> std::io::Result<u64> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let zooms = self.region_zooms(min_zoom, max_zoom);
+        let region = RegionDownload::new(min, max, zooms, dir);
+        let total = region.progress().total;
+        log::info!("Saving {} tiles to {}", total, region.dir().display());
+        self.region = Some(region);
+        Ok(total)
+    }
+
+    /// Check if a region is still being saved
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn is_downloading_region(&self) -> bool {
+        self.region_progress()
+            .is_some_and(|progress| !progress.is_finished())
+    }
+
+    /// Progress of the last `download_region`, finished or not
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn region_progress(&self) -> Option<RegionProgress> {
+        self.region.as_ref().map(RegionDownload::progress)
+    }
+
+    /// Stop saving a region; tiles saved so far stay on disk
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn cancel_region_download(&mut self) {
+        self.region = None;
+    }
+
+    /// Zoom levels of a region, within the source's
+    #[cfg(not(target_arch = "wasm32"))]
+    fn region_zooms(&self, min_zoom: u8, max_zoom: u8) -> std::ops::RangeInclusive<u8> {
+        let (source_min, source_max) = self.tile_loader.source().zoom_range();
+        let clamp = |zoom: u8| zoom.clamp(source_min, source_max);
+        clamp(min_zoom)..=clamp(max_zoom)
+    }
+
+    /// Request the next tiles of the region being saved, as room allows
+    ///
+    /// Tiles already downloaded are saved from the file cache instead, and
+    /// requests dropped to make room for visible tiles are made again.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn request_region_tiles(&mut self, now: Instant) {
+        let Some(region) = &mut self.region else {
+            return;
+        };
+        let has_room = |loader: &TileLoader| {
+            !loader.is_throttled() && loader.pending_count() < loader.max_pending()
+        };
+        let dropped: Vec<TileId> = region
+            .in_flight()
+            .filter(|tile_id| !self.tile_loader.is_loading(tile_id))
+            .copied()
+            .collect();
+        for tile_id in dropped {
+            if !has_room(&self.tile_loader) {
+                return;
+            }
+            self.tile_loader.request_at(tile_id, now);
+        }
+        // Bounds the files checked per update when resuming a large region
+        let mut budget = REGION_TILES_PER_UPDATE;
+        while budget > 0
+            && has_room(&self.tile_loader)
+            && let Some(tile_id) = region.next_tile()
+        {
+            budget -= 1;
+            if region.skip_if_saved(&tile_id) {
+                continue;
+            }
+            match self.byte_cache.get(&tile_id) {
+                Some(bytes) => {
+                    region.save(&tile_id, &bytes.0);
+                }
+                None => self.tile_loader.request_at(tile_id, now),
+            }
+        }
+    }
+
+    /// Number of tiles queued by `prefetch_bounds` and not downloaded yet
+    pub fn prefetch_remaining(&self) -> usize {
+        self.prefetch.len()
+    }
+
+    /// Drop all cached tiles and download the visible ones again
+    ///
+    /// For when the tile server's content changed. Results of requests made
+    /// before are discarded.
+    pub fn reload_tiles(&mut self) {
+        self.tile_cache.clear();
+        self.byte_cache.clear();
+        self.render_tiles.clear();
+        self.tile_loader.forget_requests();
+        self.tile_loader.advance_epoch();
+        self.decoder.discard_pending();
+        self.broken_tiles.clear();
+    }
+
+    /// Get the number of tiles being downloaded or decoded
     pub fn pending_tiles(&self) -> usize {
-        self.tile_loader.pending_count()
+        self.tile_loader.pending_count() + self.decoder.pending_count()
+    }
+
+    /// Check if tile requests are paused because the server rate limited us
+    pub fn is_throttled(&self) -> bool {
+        self.tile_loader.is_throttled()
+    }
+
+    /// Fraction of the on-screen tiles (without prefetch rings) that are cached
+    ///
+    /// Broken tiles are left out, since they won't load until `reload_tiles`.
+    pub fn visible_load_progress(&self) -> f32 {
+        let visible: Vec<TileId> = self
+            .camera
+            .visible_tiles_with_buffer(0)
+            .into_iter()
+            .filter(|tile_id| !self.broken_tiles.contains(tile_id))
+            .collect();
+        if visible.is_empty() {
+            return 1.0;
+        }
+        let cached = visible
+            .iter()
+            .filter(|tile_id| self.tile_cache.contains(tile_id))
+            .count();
+        cached as f32 / visible.len() as f32
+    }
+
+    /// Check if the viewport is fully loaded: nothing pending or left to
+    /// prefetch, and every on-screen tile cached or broken
+    ///
+    /// Useful to hide a loading indicator or to time screenshots.
+    pub fn is_idle(&self) -> bool {
+        self.pending_tiles() == 0
+            && self.prefetch.is_empty()
+            && self.visible_load_progress() >= 1.0
     }
 
     /// Get current zoom level
@@ -173,15 +1226,260 @@ impl MapSystem {
     }
 
     /// Set center position
+    ///
+    /// Non-finite coordinates are ignored with a warning, since NaN would
+    /// spread through the projection and blank the map.
     pub fn set_center(&mut self, lon: f64, lat: f64) {
+        if !lon.is_finite() || !lat.is_finite() {
+            log::warn!("Ignoring non-finite map center ({}, {})", lon, lat);
+            return;
+        }
         self.camera.center = (
             tile::normalize_longitude(lon),
             tile::clamp_latitude(lat),
         );
     }
 
-    /// Set zoom level
+    /// Set zoom level (non-finite levels are ignored with a warning)
     pub fn set_zoom(&mut self, zoom: f64) {
-        self.camera.zoom = zoom.clamp(0.0, 19.0);
+        if !zoom.is_finite() {
+            log::warn!("Ignoring non-finite zoom level {}", zoom);
+            return;
+        }
+        self.smooth_zoom.cancel();
+        self.camera.zoom = self.camera.clamp_zoom(zoom);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headless_visible_tiles() {
+        let mut map = MapSystem::new_headless(800, 600);
+        map.set_center(126.9780, 37.5665);
+        map.set_zoom(10.0);
+
+        let visible = map.visible_tiles();
+        assert!(visible.contains(&TileId::new(873, 396, 10)));
+        assert!(visible.iter().all(|t| t.z == 10));
+
+        // Every viewport corner is covered
+        for (x, y) in [(0.0, 0.0), (799.0, 0.0), (0.0, 599.0), (799.0, 599.0)] {
+            let (lon, lat) = map.screen_to_world(x, y);
+            let (tx, ty) = tile::lon_lat_to_tile(lon, lat, 10);
+            assert!(visible.contains(&TileId::new(tx, ty, 10)));
+        }
+    }
+
+    #[test]
+    fn test_visible_grid_bounds_contain_center() {
+        let mut map = MapSystem::new_headless(800, 600);
+        map.set_center(126.9780, 37.5665);
+        map.set_zoom(16.0);
+
+        let (min, max) = map.visible_grid_bounds();
+        let center = map.pixel_grid.world_to_grid(126.9780, 37.5665);
+        assert!(min.x < center.x && center.x < max.x);
+        assert!(min.y < center.y && center.y < max.y);
+    }
+
+    #[test]
+    fn test_visible_grid_bounds_cover_rotated_corners() {
+        let mut map = MapSystem::new_headless(800, 600);
+        map.set_center(126.9780, 37.5665);
+        map.set_zoom(16.0);
+        map.set_rotation(std::f32::consts::FRAC_PI_4);
+
+        let (min, max) = map.visible_grid_bounds();
+        for (x, y) in [(0.0, 0.0), (800.0, 0.0), (0.0, 600.0), (800.0, 600.0)] {
+            let corner = map.screen_to_grid(x, y);
+            assert!(min.x <= corner.x && corner.x <= max.x);
+            assert!(min.y <= corner.y && corner.y <= max.y);
+        }
+    }
+
+    #[test]
+    fn test_paste_is_one_undo_unit() {
+        let mut map = MapSystem::new_headless(800, 600);
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let blue = [0.0, 0.0, 1.0, 1.0];
+
+        map.fill_region(GridCoord::new(0, 0), GridCoord::new(1, 1), red);
+        map.set_pixel(GridCoord::new(3, 0), blue);
+        let copied = map
+            .pixel_grid
+            .copy_region(GridCoord::new(0, 0), GridCoord::new(1, 1));
+
+        // Overlaps the fill at (1, *) and the blue pixel at (3, 0)
+        map.paste_region(GridCoord::new(1, 0), &copied);
+        map.paste_region(GridCoord::new(2, 0), &copied);
+        assert_eq!(map.pixel_grid.pixel_count(), 8);
+        assert_eq!(map.undo_count(), 4);
+
+        assert!(map.undo());
+        assert!(map.undo());
+        assert_eq!(map.pixel_grid.pixel_count(), 5);
+        assert_eq!(map.pixel_grid.get_pixel(&GridCoord::new(3, 0)).unwrap().color, blue);
+
+        assert!(map.undo());
+        assert!(map.undo());
+        assert_eq!(map.pixel_grid.pixel_count(), 0);
+        assert!(!map.undo());
+    }
+
+    #[test]
+    fn test_clear_bounds_is_one_undo_unit() {
+        let mut map = MapSystem::new_headless(800, 600);
+        let red = [1.0, 0.0, 0.0, 1.0];
+        assert!(map.fill_region(GridCoord::new(0, 0), GridCoord::new(3, 3), red));
+        map.set_pixel(GridCoord::new(10, 10), red);
+
+        map.clear_bounds(GridCoord::new(-1, -1), GridCoord::new(2, 2));
+        assert_eq!(map.pixel_grid.pixel_count(), 8);
+        assert_eq!(map.undo_count(), 3);
+
+        assert!(map.undo());
+        assert_eq!(map.pixel_grid.pixel_count(), 17);
+        assert_eq!(map.pixel_grid.get_pixel(&GridCoord::new(1, 1)).unwrap().color, red);
+
+        // Nothing to clear adds no undo step
+        map.clear_bounds(GridCoord::new(50, 50), GridCoord::new(60, 60));
+        assert_eq!(map.undo_count(), 2);
+    }
+
+    #[test]
+    fn test_zoom_limited_by_tile_source() {
+        let source = TileSource::new("https://example.com/{z}/{x}/{y}.png")
+            .with_attribution("© Example")
+            .with_zoom_range(2, 16);
+        let mut map = MapSystem::headless_from_config(
+            MapSystemConfig::default().tile_source(source).zoom(18.0),
+        );
+        assert_eq!(map.zoom_level(), 16.0);
+        assert_eq!(map.attribution(), Some("© Example"));
+
+        map.set_zoom(0.0);
+        assert_eq!(map.zoom_level(), 2.0);
+        map.zoom_at(20.0, 400.0, 300.0);
+        assert_eq!(map.zoom_level(), 16.0);
+    }
+
+    #[test]
+    fn test_non_finite_view_is_ignored() {
+        let mut map = MapSystem::new_headless(800, 600);
+        map.set_center(126.978, 37.5665);
+        map.set_zoom(10.0);
+        let center = map.center();
+
+        map.set_center(f64::NAN, 37.0);
+        map.set_center(126.0, f64::INFINITY);
+        map.set_zoom(f64::NAN);
+        assert_eq!(map.center(), center);
+        assert_eq!(map.zoom_level(), 10.0);
+    }
+
+    #[test]
+    fn test_pixel_at_world_position() {
+        let mut map = MapSystem::new_headless(800, 600);
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let (lon, lat) = (126.97805, 37.56655);
+
+        map.set_pixel_at(lon, lat, red);
+        assert_eq!(map.get_pixel_at(lon, lat), Some(red));
+        let coord = map.pixel_grid.world_to_grid(lon, lat);
+        assert_eq!(map.pixel_grid.get_pixel(&coord).map(|p| p.color), Some(red));
+
+        assert!(map.clear_pixel_at(lon, lat));
+        assert!(!map.clear_pixel_at(lon, lat));
+        assert_eq!(map.get_pixel_at(lon, lat), None);
+        assert_eq!(map.undo_count(), 2);
+    }
+
+    #[test]
+    fn test_pin_view_leaves_room_in_cache() {
+        let mut map =
+            MapSystem::headless_from_config(MapSystemConfig::default().cache_size(16, usize::MAX));
+        let (lon, lat) = map.center();
+        let tiles = map.camera.visible_tiles_with_buffer(0);
+        assert!(tiles.len() > 8);
+
+        assert_eq!(map.pin_view(lon, lat, map.zoom_level()), 8);
+        map.unpin_all();
+        assert!(map.pin_tile(tiles[0]));
+    }
+
+    #[test]
+    fn test_prefetch_bounds_queues_each_tile_once() {
+        let mut map = MapSystem::new_headless(800, 600);
+        let (min, max) = ((126.95, 37.3), (127.3, 37.6));
+        assert_eq!(map.prefetch_bounds(min, max, 10), 4);
+        assert_eq!(map.prefetch_bounds(min, max, 10), 0);
+        assert_eq!(map.prefetch_remaining(), 4);
+
+        // The whole world at zoom 7 is too much
+        assert_eq!(map.prefetch_bounds((-180.0, -85.0), (180.0, 85.0), 7), 0);
+        assert_eq!(map.prefetch_remaining(), 4);
+    }
+
+    #[test]
+    fn test_headless_update_plays_commands() {
+        let mut map = MapSystem::new_headless(800, 600);
+        map.queue_command(MapCommand::ZoomTo {
+            zoom: 5.0,
+            duration: Duration::from_millis(100),
+        });
+        let start = Instant::now();
+        map.update_loads(start);
+        assert!(map.is_playing_commands());
+
+        map.update_loads(start + Duration::from_millis(200));
+        assert!(!map.is_playing_commands());
+        assert_eq!(map.zoom_level(), 5.0);
+    }
+
+    #[test]
+    fn test_pixel_capacity_from_config() {
+        let map = MapSystem::new_headless(800, 600);
+        assert_eq!(map.pixel_grid.capacity(), Some(grid::DEFAULT_MAX_PIXELS));
+
+        let mut map =
+            MapSystem::headless_from_config(MapSystemConfig::default().max_pixels(Some(2)));
+        for x in 0..3 {
+            map.set_pixel(GridCoord::new(x, 0), [1.0, 0.0, 0.0, 1.0]);
+        }
+        assert_eq!(map.pixel_grid.pixel_count(), 2);
+    }
+
+    #[test]
+    fn test_headless_never_idle() {
+        // Tiles are never uploaded without a device, so the view stays incomplete
+        let map = MapSystem::new_headless(800, 600);
+        assert_eq!(map.visible_load_progress(), 0.0);
+        assert!(!map.is_idle());
+    }
+
+    #[test]
+    fn test_broken_tiles_count_as_settled() {
+        let mut map = MapSystem::new_headless(800, 600);
+        let visible = map.camera.visible_tiles_with_buffer(0);
+        map.broken_tiles.insert(visible[0]);
+        assert_eq!(map.visible_load_progress(), 0.0);
+
+        // Nothing left that could still load
+        map.broken_tiles.extend(visible);
+        assert_eq!(map.visible_load_progress(), 1.0);
+        assert!(map.is_idle());
+    }
+
+    #[test]
+    fn test_headless_visible_tiles_at_world_view() {
+        let mut map = MapSystem::new_headless(256, 256);
+        map.set_center(0.0, 0.0);
+        map.set_zoom(0.0);
+
+        let visible = map.visible_tiles();
+        assert!(visible.iter().all(|t| *t == TileId::new(0, 0, 0)));
     }
 }
@@ -0,0 +1,490 @@
+//! Vector overlays (markers, polylines, polygons) drawn on top of the map
+
+use std::f32::consts::TAU;
+use std::ops::Range;
+
+use wgpu::include_wgsl;
+use wgpu::util::DeviceExt;
+
+use super::camera::MapCamera;
+use super::grid::GridVertex;
+use super::layers::OverlayLayer;
+use super::polygon::PolygonFill;
+use super::renderer::{BlendMode, screen_to_ndc, target_blend};
+
+/// Number of segments used to approximate a marker circle
+const MARKER_SEGMENTS: usize = 16;
+
+/// Point marker drawn as a filled circle
+#[derive(Clone, Debug)]
+pub struct Marker {
+    /// Position (longitude, latitude)
+    pub position: (f64, f64),
+    pub color: [f32; 4],
+    /// Diameter in logical pixels (see `OverlayRenderer::set_scale_factor`)
+    pub size: f32,
+}
+
+/// Line through a sequence of points
+#[derive(Clone, Debug)]
+pub struct Polyline {
+    /// Points (longitude, latitude)
+    pub points: Vec<(f64, f64)>,
+    pub color: [f32; 4],
+    /// Line width in logical pixels
+    pub width: f32,
+}
+
+/// Polygon with optional holes
+///
+/// Rings are stored open (the closing point is not repeated).
+#[derive(Clone, Debug)]
+pub struct Polygon {
+    /// Outer ring (longitude, latitude)
+    pub exterior: Vec<(f64, f64)>,
+    /// Inner rings cut out of the exterior
+    pub holes: Vec<Vec<(f64, f64)>>,
+    pub fill_color: [f32; 4],
+    pub stroke_color: [f32; 4],
+    /// Outline width in logical pixels
+    pub stroke_width: f32,
+}
+
+/// Overlay renderer for vector features
+pub struct OverlayRenderer {
+    markers: Vec<Marker>,
+    polylines: Vec<Polyline>,
+    polygons: Vec<Polygon>,
+    /// Triangulated polygon interiors
+    fills: Vec<PolygonFill>,
+
+    /// Render pipeline (None when headless)
+    render_pipeline: Option<wgpu::RenderPipeline>,
+    /// Format and sample count the pipeline draws into
+    target: Option<(wgpu::TextureFormat, u32)>,
+    /// How overlay colors combine with the layers below
+    blend_mode: BlendMode,
+    /// The pipeline was built for another blend mode
+    pipeline_stale: bool,
+
+    /// Cached vertex buffer (rebuilt when features or camera change)
+    vertex_buffer: Option<wgpu::Buffer>,
+    vertex_count: u32,
+    /// Vertex buffers created so far, for frame stats
+    buffers_created: u32,
+    /// Vertices of the polygon, polyline and marker layers in the buffer
+    layer_ranges: [Range<u32>; 3],
+
+    /// Camera used for the last rebuild
+    last_camera: Option<MapCamera>,
+
+    /// Physical pixels per logical pixel of marker and line sizes
+    scale_factor: f32,
+
+    /// Dirty flag for buffer rebuild
+    dirty: bool,
+}
+
+impl OverlayRenderer {
+    /// Create a new overlay renderer drawing into a `sample_count` target
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let mut overlays = Self::new_headless();
+        overlays.recreate_pipeline(device, texture_format, sample_count);
+        overlays
+    }
+
+    /// Create an overlay store without GPU resources
+    pub fn new_headless() -> Self {
+        Self {
+            markers: Vec::new(),
+            polylines: Vec::new(),
+            polygons: Vec::new(),
+            fills: Vec::new(),
+            render_pipeline: None,
+            target: None,
+            blend_mode: BlendMode::default(),
+            pipeline_stale: false,
+            vertex_buffer: None,
+            vertex_count: 0,
+            buffers_created: 0,
+            layer_ranges: Default::default(),
+            last_camera: None,
+            scale_factor: 1.0,
+            dirty: false,
+        }
+    }
+
+    /// Set the display's physical pixels per logical pixel, by which marker
+    /// sizes and line widths are multiplied (1 by default)
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        if scale_factor > 0.0 && scale_factor != self.scale_factor {
+            self.scale_factor = scale_factor;
+            self.dirty = true;
+        }
+    }
+
+    /// Set how overlay colors combine with the layers below (alpha blending
+    /// by default); the pipeline is rebuilt on the next update
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        if mode != self.blend_mode {
+            self.blend_mode = mode;
+            self.pipeline_stale = self.render_pipeline.is_some();
+        }
+    }
+
+    /// Get how overlay colors combine with the layers below
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Recreate the pipeline on a new device, dropping buffers from the old one
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.render_pipeline = Some(create_pipeline(
+            device,
+            texture_format,
+            sample_count,
+            self.blend_mode,
+        ));
+        self.target = Some((texture_format, sample_count));
+        self.pipeline_stale = false;
+        self.vertex_buffer = None;
+        self.vertex_count = 0;
+        self.dirty = true;
+    }
+
+    /// Add a point marker
+    pub fn add_marker(&mut self, marker: Marker) {
+        self.markers.push(marker);
+        self.dirty = true;
+    }
+
+    /// Add a polyline
+    pub fn add_polyline(&mut self, polyline: Polyline) {
+        self.polylines.push(polyline);
+        self.dirty = true;
+    }
+
+    /// Add a polygon
+    ///
+    /// Polygons that cannot be triangulated (degenerate or self-intersecting)
+    /// are drawn as outlines only.
+    pub fn add_polygon(&mut self, polygon: Polygon) {
+        if polygon.fill_color[3] > 0.0 {
+            match PolygonFill::new(&polygon) {
+                Ok(fill) => self.fills.push(fill),
+                Err(e) => log::warn!("Skipping polygon fill: {}", e),
+            }
+        }
+        self.polygons.push(polygon);
+        self.dirty = true;
+    }
+
+    /// Remove all overlay features
+    pub fn clear(&mut self) {
+        self.markers.clear();
+        self.polylines.clear();
+        self.polygons.clear();
+        self.fills.clear();
+        self.dirty = true;
+    }
+
+    /// Get number of overlay features
+    pub fn feature_count(&self) -> usize {
+        self.markers.len() + self.polylines.len() + self.polygons.len()
+    }
+
+    /// Update vertex buffer if features or camera changed
+    pub fn update(&mut self, device: &wgpu::Device, camera: &MapCamera) {
+        if self.pipeline_stale
+            && let Some((texture_format, sample_count)) = self.target
+        {
+            self.recreate_pipeline(device, texture_format, sample_count);
+        }
+        if !self.dirty && self.last_camera.as_ref() == Some(camera) {
+            return;
+        }
+
+        let mut builder = VertexBuilder::new(camera, self.scale_factor);
+        let mut layer_ranges: [Range<u32>; 3] = Default::default();
+
+        for fill in &self.fills {
+            builder.push_fill(fill);
+        }
+
+        for polygon in &self.polygons {
+            builder.push_ring(
+                &polygon.exterior,
+                polygon.stroke_width,
+                polygon.stroke_color,
+            );
+            for hole in &polygon.holes {
+                builder.push_ring(hole, polygon.stroke_width, polygon.stroke_color);
+            }
+        }
+
+        layer_ranges[0] = 0..builder.len();
+
+        for polyline in &self.polylines {
+            builder.push_line(&polyline.points, polyline.width, polyline.color);
+        }
+        layer_ranges[1] = layer_ranges[0].end..builder.len();
+
+        for marker in &self.markers {
+            builder.push_marker(marker);
+        }
+        layer_ranges[2] = layer_ranges[1].end..builder.len();
+        self.layer_ranges = layer_ranges;
+
+        let vertices = builder.vertices;
+        self.vertex_count = vertices.len() as u32;
+
+        if !vertices.is_empty() {
+            self.buffers_created += 1;
+            self.vertex_buffer = Some(device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Overlay Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+            ));
+        } else {
+            self.vertex_buffer = None;
+        }
+
+        self.last_camera = Some(*camera);
+        self.dirty = false;
+    }
+
+    /// Render all overlays
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.draw(render_pass, 0..self.vertex_count);
+    }
+
+    /// Render the features of one layer (other layers draw nothing)
+    pub fn render_layer<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, layer: OverlayLayer) {
+        self.draw(render_pass, self.layer_vertices(layer));
+    }
+
+    /// Vertex buffers created so far
+    pub fn buffers_created(&self) -> u32 {
+        self.buffers_created
+    }
+
+    /// Vertices `render_layer` draws for `layer`
+    pub fn drawn_vertices(&self, layer: OverlayLayer) -> u32 {
+        if self.render_pipeline.is_some() && self.vertex_buffer.is_some() {
+            self.layer_vertices(layer).len() as u32
+        } else {
+            0
+        }
+    }
+
+    /// Vertices of a layer in the buffer (none for non-overlay layers)
+    fn layer_vertices(&self, layer: OverlayLayer) -> Range<u32> {
+        match layer {
+            OverlayLayer::Polygons => self.layer_ranges[0].clone(),
+            OverlayLayer::Polylines => self.layer_ranges[1].clone(),
+            OverlayLayer::Markers => self.layer_ranges[2].clone(),
+            OverlayLayer::Tiles | OverlayLayer::Heatmap | OverlayLayer::PixelGrid => 0..0,
+        }
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertices: Range<u32>) {
+        if vertices.is_empty() {
+            return;
+        }
+
+        if let (Some(pipeline), Some(buffer)) = (&self.render_pipeline, &self.vertex_buffer) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw(vertices, 0..1);
+        }
+    }
+}
+
+/// Create the overlay render pipeline
+fn create_pipeline(
+    device: &wgpu::Device,
+    texture_format: wgpu::TextureFormat,
+    sample_count: u32,
+    blend_mode: BlendMode,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(include_wgsl!("../shader/overlay.wgsl"));
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Overlay Pipeline Layout"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Overlay Render Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            buffers: &[GridVertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: texture_format,
+                blend: target_blend(blend_mode, texture_format, device.features()),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &super::renderer::vertex_color_constants(texture_format),
+                ..Default::default()
+            },
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: super::renderer::multisample_state(sample_count),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Builds overlay triangles in screen space and emits them in NDC
+struct VertexBuilder<'a> {
+    camera: &'a MapCamera,
+    /// Physical pixels per logical pixel of widths and sizes
+    scale: f32,
+    vertices: Vec<GridVertex>,
+}
+
+impl<'a> VertexBuilder<'a> {
+    fn len(&self) -> u32 {
+        self.vertices.len() as u32
+    }
+
+    fn new(camera: &'a MapCamera, scale: f32) -> Self {
+        Self {
+            camera,
+            scale,
+            vertices: Vec::new(),
+        }
+    }
+
+    fn push_vertex(&mut self, (x, y): (f32, f32), color: [f32; 4]) {
+        let (ndc_x, ndc_y) = screen_to_ndc(
+            x,
+            y,
+            self.camera.viewport_width,
+            self.camera.viewport_height,
+        );
+        self.vertices.push(GridVertex {
+            position: [ndc_x, ndc_y, 0.0],
+            color,
+        });
+    }
+
+    fn push_triangle(&mut self, a: (f32, f32), b: (f32, f32), c: (f32, f32), color: [f32; 4]) {
+        self.push_vertex(a, color);
+        self.push_vertex(b, color);
+        self.push_vertex(c, color);
+    }
+
+    fn push_fill(&mut self, fill: &PolygonFill) {
+        let screen: Vec<(f32, f32)> = fill
+            .points
+            .iter()
+            .map(|(lon, lat)| self.camera.world_to_screen(*lon, *lat))
+            .collect();
+
+        for triangle in fill.indices.chunks_exact(3) {
+            self.push_triangle(
+                screen[triangle[0]],
+                screen[triangle[1]],
+                screen[triangle[2]],
+                fill.color,
+            );
+        }
+    }
+
+    /// Thick line segment as a quad
+    fn push_segment(&mut self, a: (f32, f32), b: (f32, f32), width: f32, color: [f32; 4]) {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len < f32::EPSILON {
+            return;
+        }
+
+        // Perpendicular offset of half the line width
+        let half = width * self.scale / 2.0;
+        let (nx, ny) = (-dy / len * half, dx / len * half);
+        let p0 = (a.0 + nx, a.1 + ny);
+        let p1 = (b.0 + nx, b.1 + ny);
+        let p2 = (b.0 - nx, b.1 - ny);
+        let p3 = (a.0 - nx, a.1 - ny);
+
+        self.push_triangle(p0, p1, p2, color);
+        self.push_triangle(p0, p2, p3, color);
+    }
+
+    fn push_line(&mut self, points: &[(f64, f64)], width: f32, color: [f32; 4]) {
+        let screen: Vec<(f32, f32)> = points
+            .iter()
+            .map(|(lon, lat)| self.camera.world_to_screen(*lon, *lat))
+            .collect();
+
+        for pair in screen.windows(2) {
+            self.push_segment(pair[0], pair[1], width, color);
+        }
+    }
+
+    /// Closed ring outline
+    fn push_ring(&mut self, ring: &[(f64, f64)], width: f32, color: [f32; 4]) {
+        if ring.len() < 2 || width <= 0.0 {
+            return;
+        }
+        self.push_line(ring, width, color);
+        let first = self.camera.world_to_screen(ring[0].0, ring[0].1);
+        let last = ring[ring.len() - 1];
+        let last = self.camera.world_to_screen(last.0, last.1);
+        self.push_segment(last, first, width, color);
+    }
+
+    fn push_marker(&mut self, marker: &Marker) {
+        let center = self
+            .camera
+            .world_to_screen(marker.position.0, marker.position.1);
+        let radius = marker.size * self.scale / 2.0;
+
+        // Skip markers entirely outside the viewport
+        if center.0 + radius < 0.0
+            || center.1 + radius < 0.0
+            || center.0 - radius > self.camera.viewport_width as f32
+            || center.1 - radius > self.camera.viewport_height as f32
+        {
+            return;
+        }
+
+        for i in 0..MARKER_SEGMENTS {
+            let a0 = i as f32 / MARKER_SEGMENTS as f32 * TAU;
+            let a1 = (i + 1) as f32 / MARKER_SEGMENTS as f32 * TAU;
+            let p0 = (center.0 + radius * a0.cos(), center.1 + radius * a0.sin());
+            let p1 = (center.0 + radius * a1.cos(), center.1 + radius * a1.sin());
+            self.push_triangle(center, p0, p1, marker.color);
+        }
+    }
+}
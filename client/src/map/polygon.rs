@@ -0,0 +1,175 @@
+//! Polygon triangulation for filled overlays
+//!
+//! Rings are triangulated once in Web Mercator space (so straight edges stay
+//! straight on screen) and the resulting vertices are projected per frame.
+
+use std::fmt;
+
+use super::overlay::Polygon;
+use super::tile::lon_lat_to_tile_f64;
+
+/// Maximum relative difference between the polygon area and the summed
+/// triangle area before the triangulation is considered garbage
+const MAX_AREA_DEVIATION: f64 = 1e-3;
+
+/// Reason a polygon could not be filled
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriangulationError {
+    /// Polygon encloses no area (collinear or repeated points)
+    Degenerate,
+    /// Triangulator failed outright
+    Failed,
+    /// Triangles do not cover the polygon area, usually from self-intersections
+    AreaMismatch { expected: f64, actual: f64 },
+}
+
+impl fmt::Display for TriangulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriangulationError::Degenerate => write!(f, "polygon has no area"),
+            TriangulationError::Failed => write!(f, "triangulation failed"),
+            TriangulationError::AreaMismatch { expected, actual } => write!(
+                f,
+                "triangles cover {:.3e} instead of {:.3e} (self-intersecting polygon?)",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TriangulationError {}
+
+/// Triangulated polygon ready for per-frame projection
+#[derive(Clone, Debug)]
+pub struct PolygonFill {
+    /// Vertices (longitude, latitude), exterior ring followed by holes
+    pub points: Vec<(f64, f64)>,
+    /// Triangle list indices into `points`
+    pub indices: Vec<usize>,
+    pub color: [f32; 4],
+}
+
+impl PolygonFill {
+    /// Triangulate a polygon, rejecting results that do not match its area
+    pub fn new(polygon: &Polygon) -> Result<Self, TriangulationError> {
+        let mut points = polygon.exterior.clone();
+        let mut hole_indices = Vec::with_capacity(polygon.holes.len());
+        for hole in &polygon.holes {
+            hole_indices.push(points.len());
+            points.extend_from_slice(hole);
+        }
+
+        // Triangulate in zoom-0 Mercator tile space
+        let projected: Vec<(f64, f64)> = points
+            .iter()
+            .map(|(lon, lat)| lon_lat_to_tile_f64(*lon, *lat, 0))
+            .collect();
+        let flat: Vec<f64> = projected.iter().flat_map(|(x, y)| [*x, *y]).collect();
+
+        // Exterior area minus hole areas
+        let ring_bounds = |i: usize| {
+            let start = if i == 0 { 0 } else { hole_indices[i - 1] };
+            let end = hole_indices.get(i).copied().unwrap_or(points.len());
+            start..end
+        };
+        let expected = ring_area(&projected[ring_bounds(0)])
+            - (1..=hole_indices.len())
+                .map(|i| ring_area(&projected[ring_bounds(i)]))
+                .sum::<f64>();
+        if expected <= f64::EPSILON {
+            return Err(TriangulationError::Degenerate);
+        }
+
+        let indices =
+            earcutr::earcut(&flat, &hole_indices, 2).map_err(|_| TriangulationError::Failed)?;
+
+        let actual: f64 = indices
+            .chunks_exact(3)
+            .map(|t| triangle_area(projected[t[0]], projected[t[1]], projected[t[2]]))
+            .sum();
+        if ((actual - expected) / expected).abs() > MAX_AREA_DEVIATION {
+            return Err(TriangulationError::AreaMismatch { expected, actual });
+        }
+
+        Ok(Self {
+            points,
+            indices,
+            color: polygon.fill_color,
+        })
+    }
+
+    /// Number of triangles
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+/// Unsigned area of a ring (shoelace formula)
+fn ring_area(ring: &[(f64, f64)]) -> f64 {
+    let mut sum = 0.0;
+    for i in 0..ring.len() {
+        let (x0, y0) = ring[i];
+        let (x1, y1) = ring[(i + 1) % ring.len()];
+        sum += x0 * y1 - x1 * y0;
+    }
+    sum.abs() / 2.0
+}
+
+fn triangle_area(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    ((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)).abs() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(exterior: &[(f64, f64)], holes: &[&[(f64, f64)]]) -> Polygon {
+        Polygon {
+            exterior: exterior.to_vec(),
+            holes: holes.iter().map(|h| h.to_vec()).collect(),
+            fill_color: [1.0, 0.0, 0.0, 0.5],
+            stroke_color: [0.0, 0.0, 0.0, 1.0],
+            stroke_width: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_simple_polygon_either_winding() {
+        let ccw = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let cw = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)];
+        assert_eq!(
+            PolygonFill::new(&polygon(&ccw, &[]))
+                .unwrap()
+                .triangle_count(),
+            2
+        );
+        assert_eq!(
+            PolygonFill::new(&polygon(&cw, &[]))
+                .unwrap()
+                .triangle_count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_polygon_with_hole() {
+        let outer = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let hole = [(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0)];
+        let fill = PolygonFill::new(&polygon(&outer, &[&hole])).unwrap();
+        assert_eq!(fill.points.len(), 8);
+        assert_eq!(fill.triangle_count(), 8);
+    }
+
+    #[test]
+    fn test_invalid_polygons_fail() {
+        let collinear = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)];
+        assert_eq!(
+            PolygonFill::new(&polygon(&collinear, &[])).unwrap_err(),
+            TriangulationError::Degenerate
+        );
+
+        // Self-intersecting "bowtie"
+        let bowtie = [(0.0, 0.0), (2.0, 2.0), (2.0, 0.0), (0.0, 2.0)];
+        assert!(PolygonFill::new(&polygon(&bowtie, &[])).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Saving the tiles of a region to disk for offline use
+//!
+//! Tiles are written as `{z}/{x}/{y}.png` under a directory, the layout
+//! `OfflineTiles::Directory` reads, so the region can later be shown
+//! without a network. Tiles already saved are skipped, which makes an
+//! interrupted download resume where it stopped.
+
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use super::source::tile_file_path;
+use super::tile::{TileId, lon_lat_to_tile, tile_count_in_bounds};
+
+/// Number of tiles covering a box across a range of zoom levels
+///
+/// Each level has about four times the tiles of the one before, so the
+/// deepest level dominates. Multiply by a typical tile size (~20 KB for
+/// OpenStreetMap) for the download size.
+pub fn region_tile_count(min: (f64, f64), max: (f64, f64), zooms: RangeInclusive<u8>) -> u64 {
+    zooms.map(|zoom| tile_count_in_bounds(min, max, zoom)).sum()
+}
+
+/// Tile number `index` of `tiles_in_bounds`, without walking the ones
+/// before it
+fn tile_in_bounds_at(min: (f64, f64), max: (f64, f64), zoom: u8, index: u64) -> Option<TileId> {
+    if index >= tile_count_in_bounds(min, max, zoom) {
+        return None;
+    }
+    let (west, north) = lon_lat_to_tile(min.0, max.1, zoom);
+    let (_, south) = lon_lat_to_tile(max.0, min.1, zoom);
+    let rows = (south - north + 1) as u64;
+    let x = (west as u64 + index / rows) % (1 << zoom);
+    let y = north as u64 + index % rows;
+    Some(TileId::new(x as u32, y as u32, zoom))
+}
+
+/// Tiles saved and failed so far out of the region's total
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegionProgress {
+    pub total: u64,
+    /// Downloaded now or already on disk
+    pub saved: u64,
+    /// Failed to download or write; downloading the region again retries them
+    pub failed: u64,
+}
+
+impl RegionProgress {
+    pub fn is_finished(&self) -> bool {
+        self.saved + self.failed >= self.total
+    }
+
+    /// Share of tiles done, from 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        (self.saved + self.failed) as f32 / self.total as f32
+    }
+}
+
+/// A region being saved, handing out its tiles level by level
+#[derive(Debug)]
+pub struct RegionDownload {
+    min: (f64, f64),
+    max: (f64, f64),
+    zooms: RangeInclusive<u8>,
+    /// Level being handed out, and the index of its next tile
+    zoom: u8,
+    index: u64,
+    dir: PathBuf,
+    progress: RegionProgress,
+    /// Handed out and neither saved nor failed yet
+    in_flight: HashSet<TileId>,
+}
+
+impl RegionDownload {
+    /// Save the tiles covering `min` (south-west) to `max` (north-east) at
+    /// `zooms` under `dir`
+    pub fn new(
+        min: (f64, f64),
+        max: (f64, f64),
+        zooms: RangeInclusive<u8>,
+        dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            min,
+            max,
+            zoom: *zooms.start(),
+            index: 0,
+            progress: RegionProgress {
+                total: region_tile_count(min, max, zooms.clone()),
+                ..Default::default()
+            },
+            zooms,
+            dir: dir.into(),
+            in_flight: HashSet::new(),
+        }
+    }
+
+    /// Hand out the next tile, or None once all were
+    pub fn next_tile(&mut self) -> Option<TileId> {
+        while self.zooms.contains(&self.zoom) {
+            let Some(tile_id) = tile_in_bounds_at(self.min, self.max, self.zoom, self.index) else {
+                self.zoom += 1;
+                self.index = 0;
+                continue;
+            };
+            self.index += 1;
+            self.in_flight.insert(tile_id);
+            return Some(tile_id);
+        }
+        None
+    }
+
+    /// Count a handed out tile as saved if it is already on disk
+    pub fn skip_if_saved(&mut self, tile_id: &TileId) -> bool {
+        let saved = self.in_flight.contains(tile_id) && tile_file_path(&self.dir, tile_id).exists();
+        if saved {
+            self.in_flight.remove(tile_id);
+            self.progress.saved += 1;
+        }
+        saved
+    }
+
+    /// Tiles handed out and not saved or failed yet
+    pub fn in_flight(&self) -> impl Iterator<Item = &TileId> {
+        self.in_flight.iter()
+    }
+
+    pub fn is_in_flight(&self, tile_id: &TileId) -> bool {
+        self.in_flight.contains(tile_id)
+    }
+
+    /// Write a downloaded tile, returning false if it isn't in flight
+    pub fn save(&mut self, tile_id: &TileId, data: &[u8]) -> bool {
+        if !self.in_flight.remove(tile_id) {
+            return false;
+        }
+        let path = tile_file_path(&self.dir, tile_id);
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, data));
+        match written {
+            Ok(()) => self.progress.saved += 1,
+            Err(e) => {
+                log::warn!("Failed to save tile to {}: {}", path.display(), e);
+                self.progress.failed += 1;
+            }
+        }
+        true
+    }
+
+    /// Give up on a tile, returning false if it isn't in flight
+    pub fn fail(&mut self, tile_id: &TileId) -> bool {
+        let failed = self.in_flight.remove(tile_id);
+        self.progress.failed += failed as u64;
+        failed
+    }
+
+    pub fn progress(&self) -> RegionProgress {
+        self.progress
+    }
+
+    /// Directory the tiles are saved under
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::tile::tiles_in_bounds;
+
+    #[test]
+    fn test_tile_at_index_matches_enumeration() {
+        for (min, max, zoom) in [
+            ((126.95, 37.3), (127.3, 37.6), 12),
+            ((170.0, -20.0), (-170.0, -10.0), 6),
+            ((-180.0, -90.0), (180.0, 90.0), 2),
+        ] {
+            let listed: Vec<TileId> = tiles_in_bounds(min, max, zoom).collect();
+            let indexed: Vec<TileId> = (0..)
+                .map_while(|index| tile_in_bounds_at(min, max, zoom, index))
+                .collect();
+            assert_eq!(indexed, listed);
+        }
+        assert_eq!(region_tile_count((-180.0, -90.0), (180.0, 90.0), 0..=2), 21);
+    }
+
+    #[test]
+    fn test_download_skips_saved_tiles() {
+        let dir = std::env::temp_dir().join(format!("cplace-region-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (min, max) = ((-180.0, -90.0), (180.0, 90.0));
+
+        let mut region = RegionDownload::new(min, max, 0..=1, &dir);
+        assert_eq!(region.progress().total, 5);
+        let first = region.next_tile().unwrap();
+        assert_eq!(first, TileId::new(0, 0, 0));
+        assert!(region.save(&first, b"tile"));
+        assert!(!region.save(&first, b"tile"));
+        let second = region.next_tile().unwrap();
+        assert!(region.fail(&second));
+        assert_eq!(std::fs::read(dir.join("0/0/0.png")).unwrap(), b"tile");
+
+        // Starting over finds the saved tile on disk
+        let mut again = RegionDownload::new(min, max, 0..=1, &dir);
+        let mut tiles = Vec::new();
+        while let Some(tile_id) = again.next_tile() {
+            if !again.skip_if_saved(&tile_id) {
+                tiles.push(tile_id);
+            }
+        }
+        assert_eq!(tiles.len(), 4);
+        let progress = again.progress();
+        assert_eq!((progress.saved, progress.failed), (1, 0));
+        assert!(!progress.is_finished());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,25 +1,28 @@
 //! wgpu tile renderer with texture management
 
 use bytemuck::{Pod, Zeroable};
-use log::debug;
 use wgpu::include_wgsl;
 use wgpu::util::DeviceExt;
 
 use super::cache::{CachedTile, TileCache};
+use super::decode::TileDecodeError;
+use super::loader::tile_memory_size;
 use super::tile::TileId;
 
 /// Vertex for tile rendering
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
 pub struct TileVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
+    pub opacity: f32,
 }
 
 impl TileVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
         0 => Float32x3,
         1 => Float32x2,
+        2 => Float32,
     ];
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -31,50 +34,260 @@ impl TileVertex {
     }
 }
 
+/// Uniforms shared by all tiles (padded to 16 bytes for WebGL)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct TileUniforms {
+    opacity: f32,
+    _padding: [f32; 3],
+}
+
+impl TileUniforms {
+    fn new(opacity: f32) -> Self {
+        Self {
+            opacity,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// How tile textures are sampled between texels
+///
+/// Linear filtering is smooth at fractional zoom, but next to a tile's
+/// border it blends with the clamped edge texel instead of the neighboring
+/// tile, which can show as a faint seam. Nearest keeps texels crisp and
+/// seamless, which suits pixel-art sources and integer zoom levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileFilter {
+    #[default]
+    Linear,
+    Nearest,
+}
+
+impl TileFilter {
+    fn filter_mode(self) -> wgpu::FilterMode {
+        match self {
+            TileFilter::Linear => wgpu::FilterMode::Linear,
+            TileFilter::Nearest => wgpu::FilterMode::Nearest,
+        }
+    }
+}
+
+/// Tile draw entry: (tile_id, NDC corners, opacity)
+///
+/// Corners are ordered top-left, top-right, bottom-right, bottom-left of the
+/// tile image (see `MapCamera::tile_corners`).
+pub type TileQuad = (TileId, [(f32, f32); 4], f32);
+
+/// Texture format for tile images drawn to a target of `surface_format`
+///
+/// Tiles are sRGB images. On an sRGB target they are decoded to linear when
+/// sampled and re-encoded on write; on a non-sRGB target they must be
+/// sampled raw, or they come out too dark.
+pub fn tile_texture_format(surface_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    if surface_format.is_srgb() {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    }
+}
+
+/// Override constants for the vertex-color shaders (grid, overlay)
+///
+/// Their colors are sRGB-encoded, so they are linearized in the fragment
+/// shader when the target re-encodes to sRGB.
+pub(crate) fn vertex_color_constants(
+    surface_format: wgpu::TextureFormat,
+) -> [(&'static str, f64); 1] {
+    [("srgb_target", if surface_format.is_srgb() { 1.0 } else { 0.0 })]
+}
+
+/// Multisample state for map pipelines drawing into a `sample_count` target
+pub(crate) fn multisample_state(sample_count: u32) -> wgpu::MultisampleState {
+    wgpu::MultisampleState {
+        count: sample_count.max(1),
+        ..Default::default()
+    }
+}
+
+/// How an overlay's colors combine with what is drawn below it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Paint over, showing the background through translucent colors
+    #[default]
+    Alpha,
+    /// Add the colors, weighted by their alpha, brightening the background
+    Additive,
+    /// Multiply the background by the colors, darkening it
+    ///
+    /// Exact for opaque colors; translucent ones darken a little less.
+    Multiply,
+}
+
+impl BlendMode {
+    fn blend_state(self) -> wgpu::BlendState {
+        let color = match self {
+            BlendMode::Alpha => return wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            BlendMode::Multiply => wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Dst,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        wgpu::BlendState {
+            color,
+            alpha: wgpu::BlendComponent::OVER,
+        }
+    }
+}
+
+/// Blend state of `mode` for a `format` target, or None (drawing opaque)
+/// when the format can't blend on a device with `features`
+pub(crate) fn target_blend(
+    mode: BlendMode,
+    format: wgpu::TextureFormat,
+    features: wgpu::Features,
+) -> Option<wgpu::BlendState> {
+    let flags = format.guaranteed_format_features(features).flags;
+    if !flags.contains(wgpu::TextureFormatFeatureFlags::BLENDABLE) {
+        log::warn!("{:?} targets can't blend, drawing {:?} overlays opaque", format, mode);
+        return None;
+    }
+    Some(mode.blend_state())
+}
+
 /// Tile indices for a quad (2 triangles)
 const TILE_INDICES: [u16; 6] = [0, 1, 2, 0, 2, 3];
 
+/// Tile vertex buffers created up front, enough for a full-screen view
+pub const DEFAULT_TILE_VERTEX_SLOTS: usize = 32;
+
+/// What a vertex slot's buffer needs for the tile drawn from it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SlotUpdate {
+    /// More tiles than slots: create a buffer
+    Create,
+    /// The tile moved or another tile took the slot
+    Write,
+    /// Same vertices as last frame
+    Keep,
+}
+
 /// Tile renderer
 pub struct TileRenderer {
     render_pipeline: wgpu::RenderPipeline,
     bind_group_layout: wgpu::BindGroupLayout,
-    sampler: wgpu::Sampler,
+    /// Holds the `TileUniforms`
+    uniform_buffer: wgpu::Buffer,
+    /// Uniforms with the sampler of each `TileFilter`
+    uniform_bind_groups: [wgpu::BindGroup; 2],
+    /// Opacity last written to the uniform buffer
+    tile_opacity: f32,
+    filter: TileFilter,
     index_buffer: wgpu::Buffer,
+    /// One quad's vertex buffer per tile drawn, reused across frames by the
+    /// tile's position in the render list
+    vertex_slots: Vec<wgpu::Buffer>,
+    /// Vertices last written to each slot
+    slot_vertices: Vec<[TileVertex; 4]>,
+    /// Vertex buffers created so far, for `FrameStats`
+    buffers_created: u32,
+    /// Tile texture format matching the target's color space
+    tile_format: wgpu::TextureFormat,
 }
 
 impl TileRenderer {
-    /// Create a new tile renderer
-    pub fn new(device: &wgpu::Device, texture_format: wgpu::TextureFormat) -> Self {
+    /// Create a new tile renderer drawing into a `sample_count` target
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
         // Load shader
         let shader = device.create_shader_module(include_wgsl!("../shader/tile.wgsl"));
 
-        // Bind group layout for texture + sampler
+        // Bind group layout for the tile texture
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Tile Bind Group Layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
                 },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
+                count: None,
+            }],
+        });
+
+        // Bind group layout for the shared uniforms and sampler
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Tile Uniform Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tile Uniform Buffer"),
+            contents: bytemuck::bytes_of(&TileUniforms::new(1.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Shared samplers, switched by binding another uniform bind group
+        let uniform_bind_groups = [TileFilter::Linear, TileFilter::Nearest].map(|filter| {
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Tile Sampler"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter.filter_mode(),
+                min_filter: filter.filter_mode(),
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Tile Uniform Bind Group"),
+                layout: &uniform_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            })
         });
 
         // Pipeline layout
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Tile Pipeline Layout"),
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &uniform_bind_group_layout],
             push_constant_ranges: &[],
         });
 
@@ -108,23 +321,11 @@ impl TileRenderer {
                 conservative: false,
             },
             depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
+            multisample: multisample_state(sample_count),
             multiview: None,
             cache: None,
         });
 
-        // Shared sampler
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Tile Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
         // Index buffer (shared for all tiles)
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Tile Index Buffer"),
@@ -135,21 +336,83 @@ impl TileRenderer {
         Self {
             render_pipeline,
             bind_group_layout,
-            sampler,
+            uniform_buffer,
+            uniform_bind_groups,
+            tile_opacity: 1.0,
+            filter: TileFilter::default(),
             index_buffer,
+            vertex_slots: Vec::new(),
+            slot_vertices: Vec::new(),
+            buffers_created: 0,
+            tile_format: tile_texture_format(texture_format),
         }
     }
 
+    /// Create vertex buffers for at least `slots` tiles ahead of drawing
+    ///
+    /// More are created as needed; this only avoids allocating during the
+    /// first frames.
+    pub fn reserve_vertex_slots(&mut self, device: &wgpu::Device, slots: usize) {
+        while self.vertex_slots.len() < slots {
+            let vertices = [TileVertex::zeroed(); 4];
+            self.vertex_slots.push(create_vertex_slot(device, &vertices));
+            self.slot_vertices.push(vertices);
+        }
+    }
+
+    /// Write the vertices of `tiles` to the vertex slots for `render`
+    ///
+    /// Slots are rewritten only when their tile moved, and created only
+    /// when more tiles are drawn than ever before.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, tiles: &[TileQuad]) {
+        for (slot, (_, corners, opacity)) in tiles.iter().enumerate() {
+            let vertices = create_tile_quad(corners, *opacity);
+            match update_slot(&mut self.slot_vertices, slot, vertices) {
+                SlotUpdate::Create => {
+                    self.vertex_slots.push(create_vertex_slot(device, &vertices));
+                    self.buffers_created += 1;
+                }
+                SlotUpdate::Write => {
+                    let contents = bytemuck::cast_slice(&vertices);
+                    queue.write_buffer(&self.vertex_slots[slot], 0, contents);
+                }
+                SlotUpdate::Keep => {}
+            }
+        }
+    }
+
+    /// Number of tile vertex buffers created so far (for profiling)
+    pub fn buffers_created(&self) -> u32 {
+        self.buffers_created
+    }
+
     /// Create a cached tile from image data
     pub fn create_cached_tile(
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         image_data: &[u8],
-    ) -> Result<CachedTile, image::ImageError> {
-        let img = image::load_from_memory(image_data)?;
-        let rgba = img.to_rgba8();
+    ) -> Result<CachedTile, TileDecodeError> {
+        let max = device.limits().max_texture_dimension_2d;
+        let rgba = super::loader::decode_tile_image(image_data, max)?;
+        self.create_cached_tile_from_image(device, queue, &rgba)
+    }
+
+    /// Create a cached tile from a decoded image
+    ///
+    /// Fails for images larger than the device's textures can be, which
+    /// would otherwise be a validation error.
+    pub fn create_cached_tile_from_image(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &image::RgbaImage,
+    ) -> Result<CachedTile, TileDecodeError> {
         let (width, height) = rgba.dimensions();
+        let max = device.limits().max_texture_dimension_2d;
+        if width > max || height > max {
+            return Err(TileDecodeError::TooLarge { width, height, max });
+        }
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Map Tile Texture"),
@@ -161,7 +424,7 @@ impl TileRenderer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: self.tile_format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -173,7 +436,7 @@ impl TileRenderer {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &rgba,
+            rgba,
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * width),
@@ -191,51 +454,62 @@ impl TileRenderer {
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Tile Bind Group"),
             layout: &self.bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.sampler),
-                },
-            ],
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            }],
         });
 
-        let memory_size = (width * height * 4) as usize;
+        let memory_size = tile_memory_size(width, height, texture.mip_level_count());
 
         Ok(CachedTile {
             texture,
             texture_view,
             bind_group,
+            size: (width, height),
             memory_size,
             created_at: web_time::Instant::now(),
         })
     }
 
+    /// Set the opacity applied to every tile (written only when it changes)
+    pub fn set_tile_opacity(&mut self, queue: &wgpu::Queue, opacity: f32) {
+        if opacity != self.tile_opacity {
+            queue.write_buffer(
+                &self.uniform_buffer,
+                0,
+                bytemuck::bytes_of(&TileUniforms::new(opacity)),
+            );
+            self.tile_opacity = opacity;
+        }
+    }
+
+    /// Set how tile textures are filtered
+    pub fn set_filter(&mut self, filter: TileFilter) {
+        self.filter = filter;
+    }
+
+    pub fn filter(&self) -> TileFilter {
+        self.filter
+    }
+
     /// Render visible tiles
+    ///
+    /// `tiles` must be those last passed to `prepare`, which wrote their
+    /// vertices.
     pub fn render<'a>(
         &'a self,
         render_pass: &mut wgpu::RenderPass<'a>,
-        device: &wgpu::Device,
-        tiles: &[(TileId, (f32, f32), (f32, f32))], // (tile_id, screen_pos, (width, height))
+        tiles: &[TileQuad],
         cache: &'a TileCache,
     ) {
         render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, &self.uniform_bind_groups[self.filter as usize], &[]);
         render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
-        for (tile_id, (x, y), (width, height)) in tiles {
+        // The update that built `tiles` already counted their use
+        for ((tile_id, _, _), vertex_buffer) in tiles.iter().zip(&self.vertex_slots) {
             if let Some(cached) = cache.peek(tile_id) {
-                // Create vertex buffer for this tile
-                let vertices = create_tile_quad(*x, *y, *width, *height);
-                // debug!("size is:{}, {}",*width, *height);
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Tile Vertex Buffer"),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-
                 render_pass.set_bind_group(0, &cached.bind_group, &[]);
                 render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
                 render_pass.draw_indexed(0..6, 0, 0..1);
@@ -244,38 +518,195 @@ impl TileRenderer {
     }
 }
 
-/// Create quad vertices for a tile at given screen position
-fn create_tile_quad(x: f32, y: f32, width: f32, height: f32) -> [TileVertex; 4] {
-    [
-        TileVertex {
-            position: [x, y, 0.0],
-            tex_coords: [0.0, 0.0],
-        },
-        TileVertex {
-            position: [x + width, y, 0.0],
-            tex_coords: [1.0, 0.0],
-        },
-        TileVertex {
-            position: [x + width, y + height, 0.0],
-            tex_coords: [1.0, 1.0],
-        },
-        TileVertex {
-            position: [x, y + height, 0.0],
-            tex_coords: [0.0, 1.0],
-        },
-    ]
+/// Vertex buffer for one tile quad, rewritten when reused
+fn create_vertex_slot(device: &wgpu::Device, vertices: &[TileVertex; 4]) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Tile Vertex Buffer"),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// Record `vertices` as the contents of vertex slot `slot` (at most one
+/// past the last) and tell what its buffer needs
+fn update_slot(
+    written: &mut Vec<[TileVertex; 4]>,
+    slot: usize,
+    vertices: [TileVertex; 4],
+) -> SlotUpdate {
+    match written.get_mut(slot) {
+        None => {
+            written.push(vertices);
+            SlotUpdate::Create
+        }
+        Some(previous) if *previous == vertices => SlotUpdate::Keep,
+        Some(previous) => {
+            *previous = vertices;
+            SlotUpdate::Write
+        }
+    }
+}
+
+/// Create quad vertices for a tile from its NDC corners
+fn create_tile_quad(corners: &[(f32, f32); 4], opacity: f32) -> [TileVertex; 4] {
+    const TEX_COORDS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    let mut vertices = [TileVertex {
+        position: [0.0; 3],
+        tex_coords: [0.0; 2],
+        opacity,
+    }; 4];
+    for ((vertex, (x, y)), tex_coords) in vertices.iter_mut().zip(corners).zip(TEX_COORDS) {
+        vertex.position = [*x, *y, 0.0];
+        vertex.tex_coords = tex_coords;
+    }
+    vertices
+}
+
+/// Shrink a tile's screen quad to an image of `size` pixels, keeping the
+/// image's aspect ratio
+///
+/// The image spans the quad along its longer side and is centered along
+/// the other; square images fill the quad. Works for rotated quads too.
+pub fn fit_to_aspect(corners: [(f32, f32); 4], (width, height): (u32, u32)) -> [(f32, f32); 4] {
+    if width == height || width == 0 || height == 0 {
+        return corners;
+    }
+    let longer = width.max(height) as f32;
+    let (span_u, span_v) = (width as f32 / longer, height as f32 / longer);
+    let (u0, v0) = ((1.0 - span_u) / 2.0, (1.0 - span_v) / 2.0);
+
+    // Edges from the top-left corner along the tile's x and y axes
+    let origin = corners[0];
+    let edge_u = (corners[1].0 - origin.0, corners[1].1 - origin.1);
+    let edge_v = (corners[3].0 - origin.0, corners[3].1 - origin.1);
+    let at = |u: f32, v: f32| {
+        (
+            origin.0 + edge_u.0 * u + edge_v.0 * v,
+            origin.1 + edge_u.1 * u + edge_v.1 * v,
+        )
+    };
+    let (u1, v1) = (u0 + span_u, v0 + span_v);
+    [at(u0, v0), at(u1, v0), at(u1, v1), at(u0, v1)]
 }
 
 /// Convert screen coordinates to NDC
+///
+/// A zero viewport dimension is treated as 1 pixel.
 pub fn screen_to_ndc(x: f32, y: f32, viewport_width: u32, viewport_height: u32) -> (f32, f32) {
-    let ndc_x = (x / viewport_width as f32) * 2.0 - 1.0;
-    let ndc_y = 1.0 - (y / viewport_height as f32) * 2.0;
+    let ndc_x = (x / viewport_width.max(1) as f32) * 2.0 - 1.0;
+    let ndc_y = 1.0 - (y / viewport_height.max(1) as f32) * 2.0;
     (ndc_x, ndc_y)
 }
 
 /// Convert screen size to NDC size
 pub fn size_to_ndc(size: f32, viewport_width: u32, viewport_height: u32) -> (f32, f32) {
-    let ndc_w = (size / viewport_width as f32) * 2.0;
-    let ndc_h = (size / viewport_height as f32) * 2.0;
+    let ndc_w = (size / viewport_width.max(1) as f32) * 2.0;
+    let ndc_h = (size / viewport_height.max(1) as f32) * 2.0;
     (ndc_w, ndc_h)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shaders_validate() {
+        let shaders = [
+            ("tile.wgsl", include_str!("../shader/tile.wgsl")),
+            ("grid.wgsl", include_str!("../shader/grid.wgsl")),
+            ("overlay.wgsl", include_str!("../shader/overlay.wgsl")),
+            ("heatmap.wgsl", include_str!("../shader/heatmap.wgsl")),
+        ];
+
+        for (name, source) in shaders {
+            let module = naga::front::wgsl::parse_str(source)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e.emit_to_string(source)));
+            naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::all(),
+            )
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+        }
+    }
+
+    #[test]
+    fn test_blend_needs_blendable_target() {
+        let none = wgpu::Features::empty();
+        let surface = wgpu::TextureFormat::Bgra8UnormSrgb;
+        for mode in [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply] {
+            assert_eq!(target_blend(mode, surface, none), Some(mode.blend_state()));
+        }
+        let additive = target_blend(BlendMode::Additive, surface, none).unwrap();
+        assert_eq!(additive.color.dst_factor, wgpu::BlendFactor::One);
+
+        // Integer targets never blend, and 32-bit floats not everywhere
+        assert_eq!(target_blend(BlendMode::Alpha, wgpu::TextureFormat::R32Uint, none), None);
+        let float = wgpu::TextureFormat::Rgba32Float;
+        assert_eq!(target_blend(BlendMode::Additive, float, none), None);
+    }
+
+    #[test]
+    fn test_non_square_tiles_keep_their_aspect() {
+        let mut data = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(512, 256))
+            .write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+        let size = crate::map::loader::decode_tile_image(&data, 512)
+            .unwrap()
+            .dimensions();
+
+        let square = [(100.0, 100.0), (356.0, 100.0), (356.0, 356.0), (100.0, 356.0)];
+        assert_eq!(
+            fit_to_aspect(square, size),
+            [(100.0, 164.0), (356.0, 164.0), (356.0, 292.0), (100.0, 292.0)]
+        );
+        assert_eq!(fit_to_aspect(square, (256, 256)), square);
+
+        // Rotated a quarter turn clockwise, the narrow side follows the tile
+        let rotated = [(356.0, 100.0), (356.0, 356.0), (100.0, 356.0), (100.0, 100.0)];
+        assert_eq!(
+            fit_to_aspect(rotated, size),
+            [(292.0, 100.0), (292.0, 356.0), (164.0, 356.0), (164.0, 100.0)]
+        );
+    }
+
+    #[test]
+    fn test_vertex_slots_are_reused() {
+        let quad = |x: f32| {
+            let corners = [(x, 0.0), (x + 0.5, 0.0), (x + 0.5, 0.5), (x, 0.5)];
+            create_tile_quad(&corners, 1.0)
+        };
+        let mut written = Vec::new();
+        let frame = |written: &mut Vec<_>, xs: &[f32]| -> Vec<SlotUpdate> {
+            xs.iter()
+                .enumerate()
+                .map(|(slot, x)| update_slot(written, slot, quad(*x)))
+                .collect()
+        };
+
+        use SlotUpdate::*;
+        assert_eq!(frame(&mut written, &[0.0, 0.5]), [Create, Create]);
+        assert_eq!(frame(&mut written, &[0.0, 0.5]), [Keep, Keep]);
+        // Panning rewrites the slots, and only a larger view creates more
+        assert_eq!(frame(&mut written, &[0.1, 0.6, -0.4]), [Write, Write, Create]);
+        assert_eq!(frame(&mut written, &[-0.4]), [Write]);
+        assert_eq!(written.len(), 3);
+    }
+
+    #[test]
+    fn test_tile_format_matches_target_color_space() {
+        use wgpu::TextureFormat;
+
+        assert_eq!(
+            tile_texture_format(TextureFormat::Bgra8Unorm),
+            TextureFormat::Rgba8Unorm
+        );
+        assert_eq!(
+            tile_texture_format(TextureFormat::Bgra8UnormSrgb),
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(vertex_color_constants(TextureFormat::Rgba8Unorm)[0].1, 0.0);
+        assert_eq!(vertex_color_constants(TextureFormat::Rgba8UnormSrgb)[0].1, 1.0);
+    }
+}
@@ -0,0 +1,256 @@
+//! Compact binary snapshots of a pixel grid region
+//!
+//! Layout (integers little-endian, `varint` = unsigned LEB128):
+//!
+//! ```text
+//! u8      version (1)
+//! i64 x4  min.x, min.y, max.x, max.y (inclusive bounds)
+//! runs    until the end of the data:
+//!   varint  empty cells skipped since the previous run
+//!   varint  run length - 1
+//!   u8 x4   RGBA color of every cell in the run
+//! ```
+//!
+//! Cells are ordered row by row (y, then x) within the bounds, so empty
+//! space costs a single varint and rows of one color a single run. Colors
+//! are quantized to 8 bits per channel.
+
+use std::fmt;
+
+use super::grid::{GridCoord, color_to_srgba, srgba_to_color};
+
+/// Current snapshot format version
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Most cells a snapshot may decode to (a 2048×2048 region), so a hostile
+/// payload can't run a single huge run out of memory
+pub const MAX_SNAPSHOT_CELLS: u64 = 1 << 22;
+
+/// Why a snapshot could not be read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Written by a newer (or unknown) format version
+    UnsupportedVersion(u8),
+    /// Data ended in the middle of a value
+    Truncated,
+    /// Bounds are inverted or runs extend past them
+    OutOfBounds,
+    /// More than `MAX_SNAPSHOT_CELLS` cells are set
+    TooLarge,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {}", v)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot data is truncated"),
+            SnapshotError::OutOfBounds => write!(f, "snapshot cells lie outside its bounds"),
+            SnapshotError::TooLarge => {
+                write!(f, "snapshot sets more than {} cells", MAX_SNAPSHOT_CELLS)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Decoded snapshot: bounds and the set cells inside them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub min: GridCoord,
+    pub max: GridCoord,
+    pub cells: Vec<(GridCoord, [f32; 4])>,
+}
+
+/// Encode the cells inside `min..=max` (cells outside are ignored)
+///
+/// With bounds spanning nearly the whole coordinate range, cells whose
+/// index doesn't fit in 64 bits are left out too.
+pub fn encode(
+    min: GridCoord,
+    max: GridCoord,
+    cells: impl IntoIterator<Item = (GridCoord, [f32; 4])>,
+) -> Vec<u8> {
+    let mut out = vec![SNAPSHOT_VERSION];
+    for v in [min.x, min.y, max.x, max.y] {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let width = max.x.abs_diff(min.x).saturating_add(1);
+    let mut cells: Vec<(u64, [u8; 4])> = cells
+        .into_iter()
+        .filter(|(c, _)| c.x >= min.x && c.x <= max.x && c.y >= min.y && c.y <= max.y)
+        .filter_map(|(c, color)| {
+            let column = c.x.abs_diff(min.x);
+            if column >= width {
+                return None;
+            }
+            let index = c.y.abs_diff(min.y).checked_mul(width)?.checked_add(column)?;
+            Some((index, color_to_srgba(color)))
+        })
+        .collect();
+    cells.sort_unstable_by_key(|(index, _)| *index);
+
+    // Index of the cell after the previous run
+    let mut next = 0;
+    let mut i = 0;
+    while i < cells.len() {
+        let (start, color) = cells[i];
+        let mut len = 1;
+        while i + len < cells.len() && cells[i + len] == (start + len as u64, color) {
+            len += 1;
+        }
+        write_varint(&mut out, start - next);
+        write_varint(&mut out, len as u64 - 1);
+        out.extend_from_slice(&color);
+        next = start + len as u64;
+        i += len;
+    }
+    out
+}
+
+/// Decode a snapshot written by `encode`
+pub fn decode(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let version = reader.take(1)?[0];
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let mut bounds = [0i64; 4];
+    for v in &mut bounds {
+        *v = i64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+    }
+    let [min_x, min_y, max_x, max_y] = bounds;
+    if min_x > max_x || min_y > max_y {
+        return Err(SnapshotError::OutOfBounds);
+    }
+    let width = max_x.abs_diff(min_x).saturating_add(1);
+    let area = width.saturating_mul(max_y.abs_diff(min_y).saturating_add(1));
+
+    let mut cells = Vec::new();
+    let mut next: u64 = 0;
+    while !reader.is_empty() {
+        let start = next
+            .checked_add(reader.varint()?)
+            .ok_or(SnapshotError::OutOfBounds)?;
+        let len = reader.varint()?.saturating_add(1);
+        let color = reader.take(4)?;
+        let end = start.checked_add(len).ok_or(SnapshotError::OutOfBounds)?;
+        if end > area {
+            return Err(SnapshotError::OutOfBounds);
+        }
+        if cells.len() as u64 + len > MAX_SNAPSHOT_CELLS {
+            return Err(SnapshotError::TooLarge);
+        }
+
+        let color = srgba_to_color([color[0], color[1], color[2], color[3]]);
+        for index in start..end {
+            let coord = GridCoord::new(
+                min_x + (index % width) as i64,
+                min_y + (index / width) as i64,
+            );
+            cells.push((coord, color));
+        }
+        next = end;
+    }
+
+    Ok(Snapshot {
+        min: GridCoord::new(min_x, min_y),
+        max: GridCoord::new(max_x, max_y),
+        cells,
+    })
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        let slice = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or(SnapshotError::Truncated)?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut v: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(SnapshotError::OutOfBounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_and_gaps() {
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let (min, max) = (GridCoord::new(-2, -2), GridCoord::new(7, 7));
+        let cells: Vec<_> = (0..5).map(|x| (GridCoord::new(x, 3), red)).collect();
+
+        let bytes = encode(min, max, cells.clone());
+        // Header plus one run: skip, length, color
+        assert_eq!(bytes.len(), 1 + 32 + 1 + 1 + 4);
+
+        let snapshot = decode(&bytes).unwrap();
+        assert_eq!((snapshot.min, snapshot.max), (min, max));
+        assert_eq!(snapshot.cells, cells);
+    }
+
+    #[test]
+    fn test_rejects_bad_data() {
+        let bytes = encode(GridCoord::new(0, 0), GridCoord::new(3, 3), []);
+        assert_eq!(decode(&[2]), Err(SnapshotError::UnsupportedVersion(2)));
+        assert_eq!(decode(&bytes[..10]), Err(SnapshotError::Truncated));
+
+        // A run past the 4×4 area
+        let mut overflowing = bytes.clone();
+        overflowing.extend_from_slice(&[15, 1, 0, 0, 0, 255]);
+        assert_eq!(decode(&overflowing), Err(SnapshotError::OutOfBounds));
+
+        // One run of 2^40 cells inside huge bounds
+        let (min, max) = (GridCoord::new(i64::MIN, 0), GridCoord::new(i64::MAX, 1 << 30));
+        let mut huge = encode(min, max, []);
+        huge.push(0);
+        write_varint(&mut huge, (1 << 40) - 1);
+        huge.extend_from_slice(&[0, 0, 0, 255]);
+        assert_eq!(decode(&huge), Err(SnapshotError::TooLarge));
+    }
+
+    #[test]
+    fn test_extreme_bounds_encode() {
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let (min, max) = (GridCoord::new(i64::MIN, i64::MIN), GridCoord::new(i64::MAX, i64::MAX));
+        let cells = [(GridCoord::new(i64::MIN + 3, i64::MIN), red), (GridCoord::new(0, 0), red)];
+
+        // The second cell's index doesn't fit in 64 bits and is left out
+        let snapshot = decode(&encode(min, max, cells)).unwrap();
+        assert_eq!(snapshot.cells, cells[..1]);
+    }
+}
@@ -0,0 +1,328 @@
+//! Tile server definitions
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::camera::{MAX_ZOOM, TILE_SIZE};
+#[cfg(not(target_arch = "wasm32"))]
+use super::mbtiles::{MbTiles, MbTilesError};
+use super::tile::TileId;
+
+/// OpenStreetMap's standard tile layer
+pub const OSM_URL_TEMPLATE: &str = "https://tile.openstreetmap.org/{z}/{x}/{y}.png";
+
+/// Attribution required by the OpenStreetMap tile usage policy
+pub const OSM_ATTRIBUTION: &str = "© OpenStreetMap contributors";
+
+/// Stand-in for secrets in logs
+const REDACTED: &str = "<redacted>";
+
+/// Tile files available without a network connection
+#[derive(Clone, PartialEq, Eq)]
+pub enum OfflineTiles {
+    /// A sideloaded directory laid out as `{z}/{x}/{y}.png` (native only)
+    Directory(PathBuf),
+    /// Tile files bundled with the application, e.g. with `include_bytes!`
+    Embedded(Arc<HashMap<TileId, Vec<u8>>>),
+    /// An MBTiles file (native only)
+    #[cfg(not(target_arch = "wasm32"))]
+    MbTiles(Arc<MbTiles>),
+}
+
+impl OfflineTiles {
+    /// File contents of a tile, or None if the bundle doesn't have it
+    pub fn read(&self, tile_id: &TileId) -> Option<Vec<u8>> {
+        match self {
+            OfflineTiles::Directory(root) => std::fs::read(tile_file_path(root, tile_id)).ok(),
+            OfflineTiles::Embedded(tiles) => tiles.get(tile_id).cloned(),
+            #[cfg(not(target_arch = "wasm32"))]
+            OfflineTiles::MbTiles(file) => file.read(tile_id),
+        }
+    }
+}
+
+/// Path of a tile in a directory laid out as `{z}/{x}/{y}.png`
+///
+/// JPEG tiles keep the `.png` name too; decoding goes by the contents.
+pub fn tile_file_path(root: &Path, tile_id: &TileId) -> PathBuf {
+    root.join(tile_id.z.to_string())
+        .join(tile_id.x.to_string())
+        .join(format!("{}.png", tile_id.y))
+}
+
+impl fmt::Debug for OfflineTiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OfflineTiles::Directory(root) => f.debug_tuple("Directory").field(root).finish(),
+            // Tile bytes would flood the output
+            OfflineTiles::Embedded(tiles) => write!(f, "Embedded({} tiles)", tiles.len()),
+            #[cfg(not(target_arch = "wasm32"))]
+            OfflineTiles::MbTiles(file) => f.debug_tuple("MbTiles").field(&file.path()).finish(),
+        }
+    }
+}
+
+/// A raster tile server addressed by a URL template
+///
+/// `{z}`, `{x}` and `{y}` in the template are replaced with the tile's
+/// zoom level and column/row, and `{apikey}` with `api_key`. Tiles in
+/// `offline` are used without asking the server; with an empty template,
+/// tiles missing there fail to load.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TileSource {
+    pub url_template: String,
+    /// Extra HTTP headers sent with every tile request (e.g. `Authorization`)
+    ///
+    /// Their values are secrets and are left out of `Debug` output.
+    pub headers: Vec<(String, String)>,
+    /// Key filled into `{apikey}` in the template, kept out of logs
+    pub api_key: Option<String>,
+    /// Tiles read locally before falling back to the server
+    pub offline: Option<OfflineTiles>,
+    /// Credit shown over the map, as the provider's terms require
+    pub attribution: Option<String>,
+    /// Lowest zoom level the server has tiles for
+    pub min_zoom: u8,
+    /// Highest zoom level the server has tiles for
+    pub max_zoom: u8,
+    /// Width and height of the tile images in pixels
+    pub tile_size: u32,
+}
+
+impl TileSource {
+    pub fn new(url_template: &str) -> Self {
+        Self {
+            url_template: url_template.to_string(),
+            headers: Vec::new(),
+            api_key: None,
+            offline: None,
+            attribution: None,
+            min_zoom: 0,
+            max_zoom: MAX_ZOOM,
+            tile_size: TILE_SIZE as u32,
+        }
+    }
+
+    /// OpenStreetMap's standard tile layer
+    pub fn osm() -> Self {
+        Self::new(OSM_URL_TEMPLATE).with_attribution(OSM_ATTRIBUTION)
+    }
+
+    /// Tiles from `tiles` only, without network access
+    pub fn offline(tiles: OfflineTiles) -> Self {
+        Self::new("").with_offline(tiles)
+    }
+
+    /// Tiles from an MBTiles file, with its zoom range and attribution
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn mbtiles(path: impl AsRef<std::path::Path>) -> Result<Self, MbTilesError> {
+        let file = MbTiles::open(path)?;
+        let metadata = file.metadata().clone();
+        let (min_zoom, max_zoom) = (metadata.min_zoom.unwrap_or(0), metadata.max_zoom);
+        let mut source = Self::offline(OfflineTiles::MbTiles(Arc::new(file)))
+            .with_zoom_range(min_zoom, max_zoom.unwrap_or(MAX_ZOOM));
+        source.attribution = metadata.attribution;
+        Ok(source)
+    }
+
+    /// Read tiles from `tiles` first, downloading only those it lacks
+    pub fn with_offline(mut self, tiles: OfflineTiles) -> Self {
+        self.offline = Some(tiles);
+        self
+    }
+
+    /// Check if tiles missing offline are downloaded from a server
+    pub fn has_network(&self) -> bool {
+        !self.url_template.is_empty()
+    }
+
+    /// Offline file of a tile, if this source has one
+    pub fn offline_tile(&self, tile_id: &TileId) -> Option<Vec<u8>> {
+        self.offline.as_ref()?.read(tile_id)
+    }
+
+    /// Send an HTTP header with every tile request
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Key for servers taking it in the URL, e.g. `?key={apikey}`
+    pub fn with_api_key(mut self, key: &str) -> Self {
+        self.api_key = Some(key.to_string());
+        self
+    }
+
+    pub fn with_attribution(mut self, attribution: &str) -> Self {
+        self.attribution = Some(attribution.to_string());
+        self
+    }
+
+    /// Zoom levels the server has tiles for; the map won't zoom past them
+    ///
+    /// An inverted range (e.g. from a file's metadata) is swapped.
+    pub fn with_zoom_range(mut self, min_zoom: u8, max_zoom: u8) -> Self {
+        if min_zoom > max_zoom {
+            log::warn!(
+                "Zoom range {}-{} is inverted, using {}-{}",
+                min_zoom,
+                max_zoom,
+                max_zoom,
+                min_zoom
+            );
+        }
+        self.min_zoom = min_zoom;
+        self.max_zoom = max_zoom;
+        (self.min_zoom, self.max_zoom) = self.zoom_range();
+        self
+    }
+
+    /// `min_zoom` and `max_zoom` in order, safe to clamp to even if the
+    /// fields were set inverted
+    pub fn zoom_range(&self) -> (u8, u8) {
+        (
+            self.min_zoom.min(self.max_zoom),
+            self.min_zoom.max(self.max_zoom),
+        )
+    }
+
+    /// Size of the served tile images (256 unless set, 512 for many
+    /// vector-rendered sources)
+    ///
+    /// Zero is ignored, as it would divide the projection by zero.
+    pub fn with_tile_size(mut self, pixels: u32) -> Self {
+        if pixels == 0 {
+            log::warn!("Ignoring tile size of 0 pixels");
+            return self;
+        }
+        self.tile_size = pixels;
+        self
+    }
+
+    /// URL of a tile on this server
+    pub fn tile_url(&self, tile_id: &TileId) -> String {
+        self.fill_template(tile_id, self.api_key.as_deref().unwrap_or(""))
+    }
+
+    /// URL of a tile with the API key masked, for logging
+    pub fn redacted_tile_url(&self, tile_id: &TileId) -> String {
+        self.fill_template(tile_id, REDACTED)
+    }
+
+    fn fill_template(&self, tile_id: &TileId, api_key: &str) -> String {
+        self.url_template
+            .replace("{z}", &tile_id.z.to_string())
+            .replace("{x}", &tile_id.x.to_string())
+            .replace("{y}", &tile_id.y.to_string())
+            .replace("{apikey}", api_key)
+    }
+}
+
+impl fmt::Debug for TileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Header values are often credentials
+        let headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, _)| (name.as_str(), REDACTED))
+            .collect();
+        f.debug_struct("TileSource")
+            .field("url_template", &self.url_template)
+            .field("headers", &headers)
+            .field("api_key", &self.api_key.as_ref().map(|_| REDACTED))
+            .field("offline", &self.offline)
+            .field("attribution", &self.attribution)
+            .field("min_zoom", &self.min_zoom)
+            .field("max_zoom", &self.max_zoom)
+            .field("tile_size", &self.tile_size)
+            .finish()
+    }
+}
+
+impl Default for TileSource {
+    fn default() -> Self {
+        Self::osm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_url() {
+        let tile = TileId::new(873, 396, 10);
+        assert_eq!(TileSource::osm().tile_url(&tile), tile.to_osm_url());
+
+        let custom = TileSource::new("https://example.com/tiles/{z}/{y}/{x}@2x.webp");
+        assert_eq!(
+            custom.tile_url(&tile),
+            "https://example.com/tiles/10/396/873@2x.webp"
+        );
+    }
+
+    #[test]
+    fn test_inverted_zoom_range_is_swapped() {
+        let source = TileSource::osm().with_zoom_range(16, 2);
+        assert_eq!((source.min_zoom, source.max_zoom), (2, 16));
+
+        let mut source = TileSource::osm();
+        (source.min_zoom, source.max_zoom) = (10, 4);
+        assert_eq!(source.zoom_range(), (4, 10));
+    }
+
+    #[test]
+    fn test_zero_tile_size_is_ignored() {
+        assert_eq!(TileSource::osm().with_tile_size(512).tile_size, 512);
+        assert_eq!(TileSource::osm().with_tile_size(0).tile_size, 256);
+    }
+
+    #[test]
+    fn test_header_values_are_redacted() {
+        let source = TileSource::new("https://tiles.example.com/{z}/{x}/{y}.png")
+            .with_header("Authorization", "Bearer secret-token")
+            .with_header("X-API-Key", "secret-key");
+        assert_eq!(source.headers.len(), 2);
+
+        let debug = format!("{:?}", source);
+        assert!(debug.contains("Authorization") && debug.contains("X-API-Key"));
+        assert!(!debug.contains("secret"));
+    }
+
+    #[test]
+    fn test_api_key_is_filled_in_and_masked() {
+        let tile = TileId::new(3, 5, 4);
+        let source = TileSource::new("https://tiles.example.com/{z}/{x}/{y}.png?key={apikey}")
+            .with_api_key("secret-key");
+        assert_eq!(
+            source.tile_url(&tile),
+            "https://tiles.example.com/4/3/5.png?key=secret-key"
+        );
+
+        let logged = source.redacted_tile_url(&tile);
+        assert_eq!(logged, "https://tiles.example.com/4/3/5.png?key=<redacted>");
+        assert!(!format!("{:?}", source).contains("secret"));
+    }
+
+    #[test]
+    fn test_offline_tiles() {
+        let tile = TileId::new(873, 396, 10);
+        let root = std::env::temp_dir().join(format!("cplace-tiles-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("10/873")).unwrap();
+        std::fs::write(root.join("10/873/396.png"), b"png").unwrap();
+
+        let directory = TileSource::offline(OfflineTiles::Directory(root.clone()));
+        assert!(!directory.has_network());
+        assert_eq!(directory.offline_tile(&tile), Some(b"png".to_vec()));
+        assert_eq!(directory.offline_tile(&TileId::new(0, 0, 0)), None);
+        std::fs::remove_dir_all(root).unwrap();
+
+        let bundle = HashMap::from([(tile, b"embedded".to_vec())]);
+        let embedded = TileSource::osm().with_offline(OfflineTiles::Embedded(Arc::new(bundle)));
+        assert!(embedded.has_network());
+        assert_eq!(embedded.offline_tile(&tile), Some(b"embedded".to_vec()));
+        assert_eq!(format!("{:?}", embedded.offline.unwrap()), "Embedded(1 tiles)");
+    }
+}
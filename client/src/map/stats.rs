@@ -0,0 +1,77 @@
+//! Per-frame counts of draw calls and GPU allocations, for performance work
+
+use std::cell::Cell;
+
+/// What one frame drew and allocated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Draw calls, including the heatmap's density pass
+    pub draw_calls: u32,
+    /// Tiles drawn, including those of a level being faded into
+    pub tiles: u32,
+    /// Pixel grid vertices (cells or blocks times the quad's vertices)
+    pub grid_vertices: u32,
+    /// Vector overlay vertices
+    pub overlay_vertices: u32,
+    /// GPU buffers created while updating and rendering
+    pub buffers_allocated: u32,
+    /// Tile images uploaded to the GPU
+    pub tiles_uploaded: u32,
+}
+
+/// Collects `FrameStats` over an update and the render that follows it
+#[derive(Debug, Default)]
+pub struct FrameStatsCollector {
+    /// Frame being counted; a `Cell` since rendering borrows the map
+    /// immutably
+    current: Cell<FrameStats>,
+    /// Last finished frame
+    last: Option<FrameStats>,
+}
+
+impl FrameStatsCollector {
+    /// Finish the frame counted so far and start a new one
+    pub fn begin_frame(&mut self) {
+        self.last = Some(self.current.take());
+    }
+
+    /// Count into the current frame
+    pub fn add(&self, count: impl FnOnce(&mut FrameStats)) {
+        let mut stats = self.current.get();
+        count(&mut stats);
+        self.current.set(stats);
+    }
+
+    /// Counts of the last finished frame (None before the first)
+    pub fn last(&self) -> Option<FrameStats> {
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_cover_update_and_render() {
+        let mut collector = FrameStatsCollector::default();
+        collector.begin_frame();
+        collector.add(|stats| stats.buffers_allocated += 1);
+        collector.add(|stats| {
+            stats.draw_calls += 3;
+            stats.tiles += 2;
+        });
+        assert_eq!(collector.last(), Some(FrameStats::default()));
+
+        collector.begin_frame();
+        let last = collector.last().unwrap();
+        assert_eq!(
+            (last.draw_calls, last.tiles, last.buffers_allocated),
+            (3, 2, 1)
+        );
+
+        // Counting starts over each frame
+        collector.begin_frame();
+        assert_eq!(collector.last(), Some(FrameStats::default()));
+    }
+}
@@ -0,0 +1,78 @@
+//! Global backoff after the tile server rate limits us (HTTP 429)
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Pause when the server sends no usable `Retry-After`
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Longest pause honored, in case a server asks for something absurd
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Shared between the loader and its fetch workers
+#[derive(Debug, Default)]
+pub struct Throttle {
+    until: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// Pause requests for `duration` from `now` (never shortens a pause)
+    pub fn pause(&self, now: Instant, duration: Duration) {
+        let until = now + duration.min(MAX_RETRY_AFTER);
+        let mut current = self.until.lock().unwrap();
+        if current.is_none_or(|current| current < until) {
+            *current = Some(until);
+        }
+    }
+
+    /// Time left until requests may resume, None if not throttled
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.until
+            .lock()
+            .unwrap()
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|left| !left.is_zero())
+    }
+}
+
+/// Pause requested by a `Retry-After` header
+///
+/// Only the delay-seconds form is understood; HTTP dates and missing or
+/// malformed values fall back to `DEFAULT_RETRY_AFTER`.
+pub fn parse_retry_after(value: Option<&str>) -> Duration {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(Some(" 120 ")), Duration::from_secs(120));
+        assert_eq!(parse_retry_after(Some("Wed, 21 Oct 2015 07:28:00 GMT")), DEFAULT_RETRY_AFTER);
+        assert_eq!(parse_retry_after(None), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    fn test_throttle_pause() {
+        let throttle = Throttle::default();
+        let now = Instant::now();
+        assert_eq!(throttle.remaining(now), None);
+
+        throttle.pause(now, Duration::from_secs(10));
+        assert_eq!(throttle.remaining(now), Some(Duration::from_secs(10)));
+        // A shorter pause doesn't cut the current one short
+        throttle.pause(now, Duration::from_secs(1));
+        assert_eq!(throttle.remaining(now), Some(Duration::from_secs(10)));
+
+        assert_eq!(throttle.remaining(now + Duration::from_secs(10)), None);
+
+        throttle.pause(now, Duration::from_secs(86400));
+        assert_eq!(throttle.remaining(now), Some(MAX_RETRY_AFTER));
+    }
+}
@@ -44,19 +44,26 @@ impl TileId {
 }
 
 /// Convert longitude/latitude to tile coordinates at given zoom
+///
+/// Longitudes past ±180 wrap around the world, latitude is clamped to the
+/// Mercator limits, and both indices are clamped to `0..2^zoom` before the
+/// integer cast, so lon = +180 and the poles map to the last/first tile
+/// instead of overflowing.
 pub fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u8) -> (u32, u32) {
-    let n = (1_u64 << zoom) as f64; // 2^zoom
-
-    // X coordinate (longitude)
-    let x = ((lon + 180.0) / 360.0 * n).floor() as u32;
-
-    // Y coordinate (latitude) - Mercator projection
-    let lat_rad = lat.to_radians();
-    let y = ((1.0 - lat_rad.tan().asinh() / PI) / 2.0 * n).floor() as u32;
+    // Keeps +180 on the last tile, where normalizing would move it to -180
+    let lon = if (-180.0..=180.0).contains(&lon) {
+        lon
+    } else {
+        normalize_longitude(lon)
+    };
+    let (x, y) = lon_lat_to_tile_f64(lon, clamp_latitude(lat), zoom);
 
     // Clamp to valid range
-    let max_tile = (1_u32 << zoom) - 1;
-    (x.min(max_tile), y.min(max_tile))
+    let max_tile = ((1_u64 << zoom) - 1) as f64;
+    (
+        x.floor().clamp(0.0, max_tile) as u32,
+        y.floor().clamp(0.0, max_tile) as u32,
+    )
 }
 
 /// Convert longitude/latitude to fractional tile coordinates (for sub-tile positioning)
@@ -81,6 +88,63 @@ pub fn tile_to_lon_lat(x: u32, y: u32, zoom: u8) -> (f64, f64) {
     (lon, lat_rad.to_degrees())
 }
 
+/// Convert fractional tile coordinates to longitude/latitude (inverse of `lon_lat_to_tile_f64`)
+pub fn tile_to_lon_lat_f64(x: f64, y: f64, zoom: u8) -> (f64, f64) {
+    let n = (1_u64 << zoom) as f64;
+
+    let lon = x / n * 360.0 - 180.0;
+    let lat_rad = (PI * (1.0 - 2.0 * y / n)).sinh().atan();
+
+    (lon, lat_rad.to_degrees())
+}
+
+/// Tiles at `zoom` covering a longitude/latitude box, column by column
+///
+/// `min` is the south-west corner and `max` the north-east one; a west
+/// edge east of the east edge crosses the antimeridian.
+pub fn tiles_in_bounds(min: (f64, f64), max: (f64, f64), zoom: u8) -> impl Iterator<Item = TileId> {
+    let (west, north) = lon_lat_to_tile(min.0, max.1, zoom);
+    let (east, south) = lon_lat_to_tile(max.0, min.1, zoom);
+    let (columns, wrapped) = if min.0 <= max.0 {
+        (west..=east, None)
+    } else {
+        (west..=(1 << zoom) - 1, Some(0..=east))
+    };
+    let columns = columns.chain(wrapped.into_iter().flatten());
+    columns.flat_map(move |x| (north..=south).map(move |y| TileId::new(x, y, zoom)))
+}
+
+/// Tiles at `zoom` covering the box from (`min_lon`, `min_lat`) to
+/// (`max_lon`, `max_lat`), in the order of `tiles_in_bounds`: columns from
+/// the west edge eastward, each north to south
+///
+/// A `min_lon` east of `max_lon` crosses the antimeridian. Check the size
+/// with `tile_count_in_bounds` first for large boxes or deep zooms.
+pub fn tiles_for_bounds(
+    min_lon: f64,
+    min_lat: f64,
+    max_lon: f64,
+    max_lat: f64,
+    zoom: u8,
+) -> Vec<TileId> {
+    tiles_in_bounds((min_lon, min_lat), (max_lon, max_lat), zoom).collect()
+}
+
+/// Number of tiles `tiles_in_bounds` yields, without listing them
+///
+/// For estimating downloads: multiply by a typical tile size for bytes.
+pub fn tile_count_in_bounds(min: (f64, f64), max: (f64, f64), zoom: u8) -> u64 {
+    let (west, north) = lon_lat_to_tile(min.0, max.1, zoom);
+    let (east, south) = lon_lat_to_tile(max.0, min.1, zoom);
+    let columns = if min.0 <= max.0 {
+        east as u64 + 1 - west as u64
+    } else {
+        (1_u64 << zoom) - west as u64 + east as u64 + 1
+    };
+    let rows = (south as u64 + 1).saturating_sub(north as u64);
+    columns * rows
+}
+
 /// Wrap X coordinate for infinite horizontal scrolling
 pub fn wrap_tile_x(x: i32, zoom: u8) -> u32 {
     let max_tiles = 1_i32 << zoom;
@@ -93,16 +157,11 @@ pub fn is_valid_tile_y(y: i32, zoom: u8) -> bool {
     y >= 0 && y < max_tiles
 }
 
-/// Normalize longitude to [-180, 180]
+/// Normalize longitude to [-180, 180)
+///
+/// Constant time for any input; +180 maps to the equivalent -180.
 pub fn normalize_longitude(lon: f64) -> f64 {
-    let mut l = lon;
-    while l < -180.0 {
-        l += 360.0;
-    }
-    while l > 180.0 {
-        l -= 360.0;
-    }
-    l
+    (lon + 180.0).rem_euclid(360.0) - 180.0
 }
 
 /// Clamp latitude to valid Mercator range
@@ -137,8 +196,85 @@ mod tests {
     fn test_lon_lat_to_tile() {
         // Seoul (approx 126.9780, 37.5665)
         let (x, y) = lon_lat_to_tile(126.9780, 37.5665, 10);
-        assert_eq!(x, 872);
-        assert_eq!(y, 395);
+        assert_eq!(x, 873);
+        assert_eq!(y, 396);
+    }
+
+    #[test]
+    fn test_lon_lat_to_tile_reference_values() {
+        assert_eq!(lon_lat_to_tile(-0.1278, 51.5074, 10), (511, 340)); // London
+        assert_eq!(lon_lat_to_tile(139.6917, 35.6895, 12), (3637, 1612)); // Tokyo
+        assert_eq!(lon_lat_to_tile(-74.006, 40.7128, 15), (9647, 12320)); // New York
+        assert_eq!(lon_lat_to_tile(0.0, 0.0, 1), (1, 1));
+        assert_eq!(lon_lat_to_tile(-0.001, 0.001, 1), (0, 0));
+    }
+
+    #[test]
+    fn test_lon_lat_to_tile_boundaries() {
+        // Zoom 0 has a single tile regardless of input
+        let corners = [(-180.0, 85.0511), (180.0, -85.0511), (180.0, 90.0), (-180.0, -90.0)];
+        for &(lon, lat) in &corners {
+            assert_eq!(lon_lat_to_tile(lon, lat, 0), (0, 0));
+        }
+
+        // Zoom 1: corners of the world
+        assert_eq!(lon_lat_to_tile(-180.0, 85.05112878, 1), (0, 0));
+        assert_eq!(lon_lat_to_tile(180.0, 85.05112878, 1), (1, 0));
+        assert_eq!(lon_lat_to_tile(-180.0, -85.05112878, 1), (0, 1));
+        assert_eq!(lon_lat_to_tile(180.0, -85.05112878, 1), (1, 1));
+
+        // Beyond the Mercator limits clamps instead of overflowing
+        assert_eq!(lon_lat_to_tile(180.0, -90.0, 10), (1023, 1023));
+        assert_eq!(lon_lat_to_tile(-180.0, 90.0, 10), (0, 0));
+
+        // Past the antimeridian wraps: 200° is -160°, in the first of 16 columns
+        assert_eq!(lon_lat_to_tile(200.0, 0.0, 4), (0, 8));
+        assert_eq!(lon_lat_to_tile(-200.0, 0.0, 4), (15, 8));
+        assert_eq!(lon_lat_to_tile(540.0, 0.0, 4), (0, 8));
+    }
+
+    #[test]
+    fn test_tile_to_lon_lat_round_trip() {
+        for &(x, y, z) in &[(0, 0, 0), (1, 1, 1), (873, 396, 10), (1023, 1023, 10)] {
+            let (lon, lat) = tile_to_lon_lat(x, y, z);
+            // Nudge into the tile interior to avoid edge ambiguity
+            assert_eq!(lon_lat_to_tile(lon + 1e-9, lat - 1e-9, z), (x, y));
+        }
+    }
+
+    #[test]
+    fn test_tiles_in_bounds() {
+        // Seoul's tile at zoom 10 and its neighbors to the south-east
+        let tiles: Vec<TileId> = tiles_in_bounds((126.95, 37.3), (127.3, 37.6), 10).collect();
+        assert_eq!(
+            tiles,
+            [(873, 396), (873, 397), (874, 396), (874, 397)].map(|(x, y)| TileId::new(x, y, 10))
+        );
+
+        // Across the antimeridian
+        let columns: Vec<u32> = tiles_in_bounds((179.0, 1.0), (-179.0, 2.0), 2)
+            .map(|tile| tile.x)
+            .collect();
+        assert_eq!(columns, [3, 0]);
+        assert_eq!(tiles_in_bounds((-180.0, -90.0), (180.0, 90.0), 3).count(), 64);
+    }
+
+    #[test]
+    fn test_tiles_for_bounds() {
+        // Paris at zoom 12, west to east, north to south within a column
+        let tiles = tiles_for_bounds(2.25, 48.8, 2.42, 48.9, 12);
+        assert_eq!(tiles.len() as u64, tile_count_in_bounds((2.25, 48.8), (2.42, 48.9), 12));
+        assert_eq!(tiles.first(), Some(&TileId::new(2073, 1408, 12)));
+        assert!(tiles.windows(2).all(|pair| (pair[0].x, pair[0].y) < (pair[1].x, pair[1].y)));
+
+        // Across the antimeridian: the far east columns, then the far west
+        let (min, max) = ((170.0, -20.0), (-170.0, -10.0));
+        let tiles = tiles_for_bounds(min.0, min.1, max.0, max.1, 5);
+        assert_eq!(tiles.len() as u64, tile_count_in_bounds(min, max, 5));
+        let columns: Vec<u32> = tiles.iter().map(|tile| tile.x).collect();
+        assert_eq!(columns.first(), Some(&31));
+        assert_eq!(columns.last(), Some(&0));
+        assert_eq!(tile_count_in_bounds((-180.0, -90.0), (180.0, 90.0), 12), 1 << 24);
     }
 
     #[test]
@@ -154,4 +290,22 @@ mod tests {
         assert!((normalize_longitude(190.0) - (-170.0)).abs() < 0.001);
         assert!((normalize_longitude(-190.0) - 170.0).abs() < 0.001);
     }
+
+    #[test]
+    fn test_normalize_longitude_large_values() {
+        assert_eq!(normalize_longitude(720.0), 0.0);
+        assert_eq!(normalize_longitude(-540.0), -180.0);
+        assert!((-180.0..180.0).contains(&normalize_longitude(1e15)));
+        assert!((normalize_longitude(-3600.0 + 45.5) - 45.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_normalize_longitude_boundaries() {
+        assert_eq!(normalize_longitude(180.0), -180.0);
+        assert_eq!(normalize_longitude(-180.0), -180.0);
+        assert_eq!(normalize_longitude(0.0), 0.0);
+        assert!((normalize_longitude(179.999) - 179.999).abs() < 1e-9);
+        assert!((normalize_longitude(180.001) - (-179.999)).abs() < 1e-9);
+        assert!((normalize_longitude(-180.001) - 179.999).abs() < 1e-9);
+    }
 }
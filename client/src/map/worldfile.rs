@@ -0,0 +1,154 @@
+//! World files georeferencing exported views
+//!
+//! A world file is six lines of plain text: the affine transform from an
+//! image's pixel (column, row) to map coordinates, in the order
+//!
+//! ```text
+//! x size of a pixel    (A)
+//! y skew               (D)
+//! x skew               (B)
+//! y size of a pixel    (E, negative for north up)
+//! x of the top-left pixel's center (C)
+//! y of the top-left pixel's center (F)
+//! ```
+//!
+//! Coordinates are Web Mercator (EPSG:3857) meters, the map's own
+//! projection, so the transform is exact at any zoom and rotation. GIS
+//! software reads it next to an image of the same name, e.g. `view.pgw`
+//! for `view.png`; the CRS has to be set to EPSG:3857 on import.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::camera::MapCamera;
+use super::tile::lon_lat_to_tile_f64;
+
+/// Width of the Web Mercator world in meters (the equator's length on the
+/// WGS 84 sphere of radius 6378137 m)
+pub const MERCATOR_WORLD_METERS: f64 = 2.0 * std::f64::consts::PI * 6378137.0;
+
+/// Pixel to Web Mercator transform of an image of the map
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldFile {
+    pub x_scale: f64,
+    pub y_skew: f64,
+    pub x_skew: f64,
+    pub y_scale: f64,
+    /// Top-left pixel's center
+    pub origin: (f64, f64),
+}
+
+impl WorldFile {
+    /// Transform of an image of `camera`'s viewport, one pixel per screen
+    /// pixel
+    pub fn for_view(camera: &MapCamera) -> Self {
+        let meters = MERCATOR_WORLD_METERS / (camera.tile_size * 2.0_f64.powf(camera.zoom));
+        let (x, y) = lon_lat_to_tile_f64(camera.center.0, camera.center.1, 0);
+        let center = (
+            (x - 0.5) * MERCATOR_WORLD_METERS,
+            (0.5 - y) * MERCATOR_WORLD_METERS,
+        );
+
+        // Screen offsets are rotated clockwise from north up
+        let (sin, cos) = (camera.rotation as f64).sin_cos();
+        let (x_scale, x_skew) = (meters * cos, meters * sin);
+        let (y_skew, y_scale) = (meters * sin, -meters * cos);
+
+        let dx = 0.5 - camera.viewport_width as f64 / 2.0;
+        let dy = 0.5 - camera.viewport_height as f64 / 2.0;
+        Self {
+            x_scale,
+            y_skew,
+            x_skew,
+            y_scale,
+            origin: (
+                center.0 + x_scale * dx + x_skew * dy,
+                center.1 + y_skew * dx + y_scale * dy,
+            ),
+        }
+    }
+
+    /// Web Mercator position of a pixel's center (column, row)
+    pub fn pixel_to_mercator(&self, column: f64, row: f64) -> (f64, f64) {
+        (
+            self.origin.0 + self.x_scale * column + self.x_skew * row,
+            self.origin.1 + self.y_skew * column + self.y_scale * row,
+        )
+    }
+
+    /// Where the world file of `image` goes: `.png` becomes `.pgw`, `.jpg`
+    /// `.jgw`, and other extensions get a `w` appended
+    pub fn path_for(image: &Path) -> PathBuf {
+        let extension = image.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let mut chars = extension.chars();
+        let world = match (chars.next(), chars.last()) {
+            (Some(first), Some(last)) if extension.len() == 3 => format!("{}{}w", first, last),
+            _ => format!("{}w", extension),
+        };
+        image.with_extension(world)
+    }
+}
+
+impl fmt::Display for WorldFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = [
+            self.x_scale,
+            self.y_skew,
+            self.x_skew,
+            self.y_scale,
+            self.origin.0,
+            self.origin.1,
+        ];
+        for value in lines {
+            writeln!(f, "{:.10}", value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_world_file_matches_the_camera() {
+        // One 256 pixel tile showing the whole world
+        let camera = MapCamera::new(0.0, 0.0, 0.0, 256, 256);
+        let world = WorldFile::for_view(&camera);
+        let (left, top) = world.pixel_to_mercator(-0.5, -0.5);
+        assert!((left + MERCATOR_WORLD_METERS / 2.0).abs() < 1e-6);
+        assert!((top - MERCATOR_WORLD_METERS / 2.0).abs() < 1e-6);
+        assert_eq!(world.to_string().lines().count(), 6);
+
+        // Pixels agree with the camera's own projection when rotated
+        let mut camera = MapCamera::new(126.978, 37.5665, 13.3, 800, 600);
+        camera.set_rotation(0.6);
+        let world = WorldFile::for_view(&camera);
+        for (x, y) in [(0.0, 0.0), (800.0, 0.0), (123.0, 456.0)] {
+            let (lon, lat) = camera.screen_to_world(x as f32 + 0.5, y as f32 + 0.5);
+            let (tx, ty) = lon_lat_to_tile_f64(lon, lat, 0);
+            let expected = (
+                (tx - 0.5) * MERCATOR_WORLD_METERS,
+                (0.5 - ty) * MERCATOR_WORLD_METERS,
+            );
+            let (mx, my) = world.pixel_to_mercator(x, y);
+            assert!((mx - expected.0).abs() < 0.01 && (my - expected.1).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_world_file_paths() {
+        assert_eq!(
+            WorldFile::path_for(Path::new("out/view.png")),
+            Path::new("out/view.pgw")
+        );
+        assert_eq!(
+            WorldFile::path_for(Path::new("view.jpg")),
+            Path::new("view.jgw")
+        );
+        assert_eq!(
+            WorldFile::path_for(Path::new("view.tiff")),
+            Path::new("view.tiffw")
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Animated zoom: wheel steps are accumulated into a target the camera eases
+//! toward over a few frames
+
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Time for the zoom to cover ~63% of the remaining distance
+const EASE_TIME: f64 = 0.08;
+
+/// Fastest zoom change, in levels per second
+const MAX_ZOOM_RATE: f64 = 12.0;
+
+/// Remaining distance below which the target is snapped to
+const SNAP_DISTANCE: f64 = 1e-3;
+
+/// Frame time assumed for the first step and capped to after a stall
+const MAX_STEP: Duration = Duration::from_millis(50);
+
+/// Default time after the last zoom change before settling on a whole level
+pub const DEFAULT_SETTLE_DELAY: Duration = Duration::from_millis(300);
+
+/// Zoom level the camera is moving toward, anchored at a screen position
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SmoothZoom {
+    target: Option<f64>,
+    /// Screen position that stays fixed while zooming
+    anchor: (f32, f32),
+    last_step: Option<Instant>,
+}
+
+impl SmoothZoom {
+    /// Add `delta` levels to the target, starting from `zoom` if idle
+    pub fn add(&mut self, zoom: f64, delta: f64, anchor: (f32, f32), min: f64, max: f64) {
+        let target = self.target.unwrap_or(zoom) + delta;
+        self.target = Some(target.clamp(min, max));
+        self.anchor = anchor;
+    }
+
+    /// Move the target to the next whole level in the direction of `delta`
+    ///
+    /// Tiles are drawn at their native size there, so they are sharpest.
+    pub fn add_level(&mut self, zoom: f64, delta: f64, anchor: (f32, f32), min: f64, max: f64) {
+        let from = self.target.unwrap_or(zoom);
+        // A target a hair below a level counts as reaching it
+        let level = if delta > 0.0 {
+            (from + SNAP_DISTANCE).floor() + 1.0
+        } else if delta < 0.0 {
+            (from - SNAP_DISTANCE).ceil() - 1.0
+        } else {
+            return;
+        };
+        self.target = Some(level.clamp(min, max));
+        self.anchor = anchor;
+    }
+
+    /// Stop animating, keeping the current zoom
+    pub fn cancel(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Check if the zoom is still moving toward the target
+    pub fn is_active(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Zoom change to apply at `now` and the anchor to apply it at
+    pub fn step(&mut self, zoom: f64, now: Instant) -> Option<(f64, (f32, f32))> {
+        let target = self.target?;
+        let dt = self
+            .last_step
+            .map_or(MAX_STEP, |last| now.saturating_duration_since(last).min(MAX_STEP))
+            .as_secs_f64();
+        self.last_step = Some(now);
+
+        let remaining = target - zoom;
+        if remaining.abs() < SNAP_DISTANCE {
+            let anchor = self.anchor;
+            self.cancel();
+            return Some((remaining, anchor)).filter(|(delta, _)| *delta != 0.0);
+        }
+
+        let eased = remaining * (1.0 - (-dt / EASE_TIME).exp());
+        let max_delta = MAX_ZOOM_RATE * dt;
+        Some((eased.clamp(-max_delta, max_delta), self.anchor))
+    }
+}
+
+/// Waits for zooming to stop, then picks the nearest whole level, where
+/// tiles are drawn at their native size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoomSettle {
+    delay: Duration,
+    /// Zoom seen at the last check
+    zoom: Option<f64>,
+    /// When the zoom last changed
+    changed_at: Option<Instant>,
+}
+
+impl ZoomSettle {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            zoom: None,
+            changed_at: None,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Level to settle on at `now`, if the zoom has rested for the delay
+    /// away from a whole level
+    pub fn check(&mut self, zoom: f64, now: Instant) -> Option<f64> {
+        if self.zoom != Some(zoom) {
+            self.zoom = Some(zoom);
+            self.changed_at = Some(now);
+        }
+        let changed_at = self.changed_at?;
+        if now.saturating_duration_since(changed_at) < self.delay {
+            return None;
+        }
+        self.changed_at = None;
+        let level = zoom.round();
+        Some(level).filter(|level| (level - zoom).abs() >= SNAP_DISTANCE)
+    }
+
+    /// When the zoom will have rested long enough to settle
+    pub fn due_at(&self) -> Option<Instant> {
+        Some(self.changed_at? + self.delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converges_to_accumulated_target() {
+        let mut smooth = SmoothZoom::default();
+        let now = Instant::now();
+        assert_eq!(smooth.step(10.0, now), None);
+
+        smooth.add(10.0, 0.5, (100.0, 50.0), 0.0, 19.0);
+        smooth.add(10.0, 0.5, (100.0, 50.0), 0.0, 19.0);
+
+        let mut zoom = 10.0;
+        for frame in 0..120 {
+            let at = now + Duration::from_millis(16 * frame);
+            if let Some((delta, anchor)) = smooth.step(zoom, at) {
+                // Capped per-frame rate
+                assert!(delta.abs() <= MAX_ZOOM_RATE * MAX_STEP.as_secs_f64() + 1e-9);
+                assert_eq!(anchor, (100.0, 50.0));
+                zoom += delta;
+            }
+        }
+        assert!(!smooth.is_active());
+        assert!((zoom - 11.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_level_steps_land_on_whole_levels() {
+        let mut smooth = SmoothZoom::default();
+        smooth.add_level(10.3, 0.5, (0.0, 0.0), 0.0, 19.0);
+        assert_eq!(smooth.target, Some(11.0));
+        smooth.add_level(10.6, 0.1, (0.0, 0.0), 0.0, 19.0);
+        assert_eq!(smooth.target, Some(12.0));
+        smooth.add_level(11.2, -2.0, (0.0, 0.0), 0.0, 19.0);
+        assert_eq!(smooth.target, Some(11.0));
+
+        smooth.cancel();
+        smooth.add_level(10.9999, -0.5, (0.0, 0.0), 0.0, 19.0);
+        assert_eq!(smooth.target, Some(10.0));
+        smooth.add_level(18.5, 1.0, (0.0, 0.0), 0.0, 10.0);
+        assert_eq!(smooth.target, Some(10.0));
+    }
+
+    #[test]
+    fn test_settles_after_zooming_stops() {
+        let mut settle = ZoomSettle::new(Duration::from_millis(300));
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        assert_eq!(settle.check(10.4, start), None);
+        assert_eq!(settle.check(10.6, ms(200)), None);
+        assert_eq!(settle.due_at(), Some(ms(500)));
+        assert_eq!(settle.check(10.6, ms(400)), None);
+        assert_eq!(settle.check(10.6, ms(500)), Some(11.0));
+        // Only once per rest
+        assert_eq!(settle.check(10.6, ms(600)), None);
+        assert_eq!(settle.due_at(), None);
+
+        // Resting on a whole level needs nothing
+        settle.check(12.0, ms(700));
+        assert_eq!(settle.check(12.0, ms(1000)), None);
+    }
+
+    #[test]
+    fn test_target_is_clamped() {
+        let mut smooth = SmoothZoom::default();
+        smooth.add(18.5, 3.0, (0.0, 0.0), 0.0, 19.0);
+        assert_eq!(smooth.target, Some(19.0));
+    }
+}
@@ -0,0 +1,535 @@
+//! WebSocket sync for collaborative pixel placement
+//!
+//! Pixel operations are exchanged as JSON text frames:
+//!
+//! - `{"type": "set", "x": 12, "y": -3, "color": [1.0, 0.0, 0.0, 1.0]}` in
+//!   both directions; a fully transparent color erases the cell
+//! - `{"type": "region", "min": [x, y], "max": [x, y]}` asks the server for
+//!   every pixel set inside an inclusive grid rectangle, which it answers
+//!   with `set` messages
+
+use serde_json::{Value, json};
+
+use crate::map::grid::GridCoord;
+
+/// Message exchanged with the sync server
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyncMessage {
+    /// Pixel set at a grid cell
+    Set { coord: GridCoord, color: [f32; 4] },
+    /// Request for the current canvas state inside a grid rectangle
+    Region { min: GridCoord, max: GridCoord },
+}
+
+impl SyncMessage {
+    /// Encode as a JSON text frame
+    pub fn to_json(&self) -> String {
+        let value = match self {
+            SyncMessage::Set { coord, color } => json!({
+                "type": "set",
+                "x": coord.x,
+                "y": coord.y,
+                "color": color,
+            }),
+            SyncMessage::Region { min, max } => json!({
+                "type": "region",
+                "min": [min.x, min.y],
+                "max": [max.x, max.y],
+            }),
+        };
+        value.to_string()
+    }
+
+    /// Decode a JSON text frame
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+        match value.get("type").and_then(Value::as_str) {
+            Some("set") => {
+                let coord = GridCoord::new(int_field(&value, "x")?, int_field(&value, "y")?);
+                let color = value
+                    .get("color")
+                    .and_then(Value::as_array)
+                    .filter(|c| c.len() == 4)
+                    .ok_or("\"color\" must be an array of 4 numbers")?;
+                // Channels outside 0..=1 (including ones too large for f32)
+                // would reach the GPU and the undo history as they are
+                let mut rgba = [0.0; 4];
+                for (out, c) in rgba.iter_mut().zip(color) {
+                    *out = c
+                        .as_f64()
+                        .map(|c| c as f32)
+                        .filter(|c| (0.0..=1.0).contains(c))
+                        .ok_or("\"color\" channels must be numbers from 0 to 1")?;
+                }
+                Ok(SyncMessage::Set { coord, color: rgba })
+            }
+            Some("region") => Ok(SyncMessage::Region {
+                min: coord_field(&value, "min")?,
+                max: coord_field(&value, "max")?,
+            }),
+            Some(other) => Err(format!("Unknown message type \"{}\"", other)),
+            None => Err("Missing message type".to_string()),
+        }
+    }
+}
+
+fn int_field(value: &Value, key: &str) -> Result<i64, String> {
+    value
+        .get(key)
+        .and_then(Value::as_i64)
+        .ok_or_else(|| format!("\"{}\" must be an integer", key))
+}
+
+fn coord_field(value: &Value, key: &str) -> Result<GridCoord, String> {
+    match value.get(key).and_then(Value::as_array).map(Vec::as_slice) {
+        Some([x, y]) => match (x.as_i64(), y.as_i64()) {
+            (Some(x), Some(y)) => Ok(GridCoord::new(x, y)),
+            _ => Err(format!("\"{}\" must be [x, y] integers", key)),
+        },
+        _ => Err(format!("\"{}\" must be [x, y] integers", key)),
+    }
+}
+
+/// Event reported by the sync connection
+#[derive(Debug)]
+pub enum SyncEvent {
+    /// Connection established (queued messages are being sent)
+    Connected,
+    /// Pixel set by another client (or replayed canvas state)
+    Pixel(GridCoord, [f32; 4]),
+    /// Connection closed or failed; no further events follow
+    Disconnected(String),
+}
+
+// Platform-specific channel types, mirroring the tile loader
+#[cfg(not(target_arch = "wasm32"))]
+type EventReceiver = std::sync::mpsc::Receiver<SyncEvent>;
+#[cfg(not(target_arch = "wasm32"))]
+type MessageSender = std::sync::mpsc::Sender<SyncMessage>;
+
+#[cfg(target_arch = "wasm32")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(target_arch = "wasm32")]
+type EventReceiver = Arc<Mutex<std::collections::VecDeque<SyncEvent>>>;
+/// Browser socket plus the callbacks that must live as long as it does
+#[cfg(target_arch = "wasm32")]
+type WasmSocket = (
+    web_sys::WebSocket,
+    Vec<wasm_bindgen::closure::Closure<dyn FnMut(wasm_bindgen::JsValue)>>,
+);
+
+/// How long the native worker blocks on a read before flushing outgoing ops
+#[cfg(not(target_arch = "wasm32"))]
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// WebSocket client syncing pixel operations with a server
+///
+/// Messages sent before the connection opens are queued and delivered once
+/// it does.
+pub struct PixelSync {
+    event_rx: EventReceiver,
+    #[cfg(not(target_arch = "wasm32"))]
+    message_tx: MessageSender,
+    #[cfg(not(target_arch = "wasm32"))]
+    _worker_handle: Option<std::thread::JoinHandle<()>>,
+    /// Browser socket (None if it could not be created)
+    #[cfg(target_arch = "wasm32")]
+    socket: Option<web_sys::WebSocket>,
+    #[cfg(target_arch = "wasm32")]
+    outbox: Arc<Mutex<Vec<String>>>,
+    #[cfg(target_arch = "wasm32")]
+    _callbacks: Vec<wasm_bindgen::closure::Closure<dyn FnMut(wasm_bindgen::JsValue)>>,
+    connected: bool,
+    url: String,
+}
+
+impl PixelSync {
+    /// Start connecting to a sync server (e.g. `ws://localhost:8080`)
+    ///
+    /// Connection errors are reported as `SyncEvent::Disconnected` by `poll`.
+    pub fn connect(url: &str) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (message_tx, message_rx) = std::sync::mpsc::channel::<SyncMessage>();
+            let (event_tx, event_rx) = std::sync::mpsc::channel::<SyncEvent>();
+
+            let _worker_handle = {
+                let url = url.to_string();
+                Some(std::thread::spawn(move || {
+                    let reason = Self::worker_thread(&url, message_rx, &event_tx)
+                        .err()
+                        .unwrap_or_else(|| "Connection closed".to_string());
+                    let _ = event_tx.send(SyncEvent::Disconnected(reason));
+                }))
+            };
+
+            Self {
+                event_rx,
+                message_tx,
+                _worker_handle,
+                connected: false,
+                url: url.to_string(),
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let event_rx = Arc::new(Mutex::new(std::collections::VecDeque::new()));
+            let outbox = Arc::new(Mutex::new(Vec::new()));
+
+            let (socket, _callbacks) = match Self::open_wasm_socket(url, &event_rx, &outbox) {
+                Ok((socket, callbacks)) => (Some(socket), callbacks),
+                Err(err) => {
+                    event_rx
+                        .lock()
+                        .unwrap()
+                        .push_back(SyncEvent::Disconnected(err));
+                    (None, Vec::new())
+                }
+            };
+
+            Self {
+                event_rx,
+                socket,
+                outbox,
+                _callbacks,
+                connected: false,
+                url: url.to_string(),
+            }
+        }
+    }
+
+    /// Send a locally placed pixel
+    pub fn send_pixel(&mut self, coord: GridCoord, color: [f32; 4]) {
+        self.send(SyncMessage::Set { coord, color });
+    }
+
+    /// Ask the server for every pixel inside an inclusive grid rectangle
+    pub fn request_region(&mut self, min: GridCoord, max: GridCoord) {
+        self.send(SyncMessage::Region { min, max });
+    }
+
+    fn send(&mut self, message: SyncMessage) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // Fails only after the worker exited, which is reported via poll
+            let _ = self.message_tx.send(message);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let Some(socket) = &self.socket else {
+                return;
+            };
+            let text = message.to_json();
+            match socket.ready_state() {
+                web_sys::WebSocket::OPEN => {
+                    if let Err(e) = socket.send_with_str(&text) {
+                        log::warn!("Failed to send sync message: {:?}", e);
+                    }
+                }
+                web_sys::WebSocket::CONNECTING => self.outbox.lock().unwrap().push(text),
+                _ => {}
+            }
+        }
+    }
+
+    /// Poll for connection events and remote pixel operations
+    pub fn poll(&mut self) -> Option<SyncEvent> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let event = self.event_rx.try_recv().ok();
+
+        #[cfg(target_arch = "wasm32")]
+        let event = self.event_rx.lock().unwrap().pop_front();
+
+        match &event {
+            Some(SyncEvent::Connected) => self.connected = true,
+            Some(SyncEvent::Disconnected(_)) => self.connected = false,
+            _ => {}
+        }
+        event
+    }
+
+    /// Whether the connection is currently open
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Server URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Native implementation
+    #[cfg(not(target_arch = "wasm32"))]
+    fn worker_thread(
+        url: &str,
+        message_rx: std::sync::mpsc::Receiver<SyncMessage>,
+        event_tx: &std::sync::mpsc::Sender<SyncEvent>,
+    ) -> Result<(), String> {
+        use std::io::ErrorKind;
+        use std::sync::mpsc::TryRecvError;
+        use tungstenite::stream::MaybeTlsStream;
+        use tungstenite::{Error, Message};
+
+        let (mut socket, _) = tungstenite::connect(url).map_err(|e| e.to_string())?;
+
+        // Short read timeout on the TCP stream so outgoing ops are flushed
+        // while idle; without one a quiet server would block them forever
+        let stream = match socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream,
+            MaybeTlsStream::NativeTls(stream) => stream.get_mut(),
+            _ => return Err("Unsupported WebSocket stream".to_string()),
+        };
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+
+        if event_tx.send(SyncEvent::Connected).is_err() {
+            return Ok(()); // Receiver dropped
+        }
+
+        loop {
+            // 1. Send queued local ops
+            loop {
+                match message_rx.try_recv() {
+                    Ok(message) => socket
+                        .send(Message::text(message.to_json()))
+                        .map_err(|e| e.to_string())?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        // PixelSync dropped, close politely
+                        let _ = socket.close(None);
+                        return Ok(());
+                    }
+                }
+            }
+
+            // 2. Receive remote ops
+            match socket.read() {
+                Ok(Message::Text(text)) => match SyncMessage::from_json(&text) {
+                    Ok(SyncMessage::Set { coord, color }) => {
+                        if event_tx.send(SyncEvent::Pixel(coord, color)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Ok(SyncMessage::Region { .. }) => {}
+                    Err(e) => log::warn!("Ignoring malformed sync message: {}", e),
+                },
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(Error::ConnectionClosed) => return Ok(()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    // WASM implementation using the browser WebSocket API
+    #[cfg(target_arch = "wasm32")]
+    fn open_wasm_socket(
+        url: &str,
+        event_rx: &EventReceiver,
+        outbox: &Arc<Mutex<Vec<String>>>,
+    ) -> Result<WasmSocket, String> {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen::prelude::*;
+        use web_sys::{CloseEvent, MessageEvent, WebSocket};
+
+        let socket =
+            WebSocket::new(url).map_err(|e| format!("Failed to open WebSocket: {:?}", e))?;
+
+        let onopen = {
+            let socket = socket.clone();
+            let events = event_rx.clone();
+            let outbox = outbox.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |_: JsValue| {
+                for text in outbox.lock().unwrap().drain(..) {
+                    if let Err(e) = socket.send_with_str(&text) {
+                        log::warn!("Failed to send sync message: {:?}", e);
+                    }
+                }
+                events.lock().unwrap().push_back(SyncEvent::Connected);
+            })
+        };
+
+        let onmessage = {
+            let events = event_rx.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+                let Some(text) = event
+                    .dyn_into::<MessageEvent>()
+                    .ok()
+                    .and_then(|e| e.data().as_string())
+                else {
+                    return; // Binary frames are not part of the protocol
+                };
+                match SyncMessage::from_json(&text) {
+                    Ok(SyncMessage::Set { coord, color }) => {
+                        events
+                            .lock()
+                            .unwrap()
+                            .push_back(SyncEvent::Pixel(coord, color));
+                    }
+                    Ok(SyncMessage::Region { .. }) => {}
+                    Err(e) => log::warn!("Ignoring malformed sync message: {}", e),
+                }
+            })
+        };
+
+        let onclose = {
+            let events = event_rx.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+                let reason = event
+                    .dyn_into::<CloseEvent>()
+                    .map(|e| format!("Connection closed ({})", e.code()))
+                    .unwrap_or_else(|_| "Connection closed".to_string());
+                events
+                    .lock()
+                    .unwrap()
+                    .push_back(SyncEvent::Disconnected(reason));
+            })
+        };
+
+        socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+
+        Ok((socket, vec![onopen, onmessage, onclose]))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for PixelSync {
+    fn drop(&mut self) {
+        // Detach callbacks before they are freed
+        if let Some(socket) = &self.socket {
+            socket.set_onopen(None);
+            socket.set_onmessage(None);
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let messages = [
+            SyncMessage::Set {
+                coord: GridCoord::new(-12, 34),
+                color: [1.0, 0.5, 0.0, 1.0],
+            },
+            SyncMessage::Region {
+                min: GridCoord::new(-100, -50),
+                max: GridCoord::new(100, 50),
+            },
+        ];
+        for message in messages {
+            assert_eq!(SyncMessage::from_json(&message.to_json()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_malformed_messages_rejected() {
+        for text in [
+            "not json",
+            r#"{"x": 1, "y": 2}"#,
+            r#"{"type": "erase", "x": 1, "y": 2}"#,
+            r#"{"type": "set", "x": 1.5, "y": 2, "color": [0, 0, 0, 1]}"#,
+            r#"{"type": "set", "x": 1, "y": 2, "color": [0, 0, 0]}"#,
+            r#"{"type": "set", "x": 1, "y": 2, "color": [2, 0, 0, 1]}"#,
+            r#"{"type": "set", "x": 1, "y": 2, "color": [0, -0.5, 0, 1]}"#,
+            r#"{"type": "set", "x": 1, "y": 2, "color": [0, 0, 1e300, 1]}"#,
+            r#"{"type": "region", "min": [0], "max": [1, 1]}"#,
+        ] {
+            assert!(SyncMessage::from_json(text).is_err(), "{}", text);
+        }
+    }
+
+    /// Wait for the next event, failing after a few seconds
+    fn next_event(sync: &mut PixelSync) -> SyncEvent {
+        let start = std::time::Instant::now();
+        loop {
+            if let Some(event) = sync.poll() {
+                return event;
+            }
+            assert!(start.elapsed().as_secs() < 10, "no event received");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_sync_with_local_server() {
+        use tungstenite::Message;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        // Minimal server: records two messages, then replays one pixel
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            let mut received = Vec::new();
+            while received.len() < 2 {
+                if let Message::Text(text) = socket.read().unwrap() {
+                    received.push(SyncMessage::from_json(&text).unwrap());
+                }
+            }
+            let reply = SyncMessage::Set {
+                coord: GridCoord::new(7, -7),
+                color: [0.0, 0.0, 1.0, 1.0],
+            };
+            socket.send(Message::text(reply.to_json())).unwrap();
+            received
+        });
+
+        // Queued before the connection opens
+        let mut sync = PixelSync::connect(&url);
+        sync.request_region(GridCoord::new(-1, -1), GridCoord::new(1, 1));
+        sync.send_pixel(GridCoord::new(0, 0), [1.0, 0.0, 0.0, 1.0]);
+
+        assert!(matches!(next_event(&mut sync), SyncEvent::Connected));
+        assert!(sync.is_connected());
+        match next_event(&mut sync) {
+            SyncEvent::Pixel(coord, color) => {
+                assert_eq!(coord, GridCoord::new(7, -7));
+                assert_eq!(color, [0.0, 0.0, 1.0, 1.0]);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let received = server.join().unwrap();
+        assert_eq!(
+            received,
+            vec![
+                SyncMessage::Region {
+                    min: GridCoord::new(-1, -1),
+                    max: GridCoord::new(1, 1),
+                },
+                SyncMessage::Set {
+                    coord: GridCoord::new(0, 0),
+                    color: [1.0, 0.0, 0.0, 1.0],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connection_failure_is_reported() {
+        // Nothing listens on port 1
+        let mut sync = PixelSync::connect("ws://127.0.0.1:1");
+        sync.send_pixel(GridCoord::new(0, 0), [0.0, 0.0, 0.0, 1.0]);
+
+        match next_event(&mut sync) {
+            SyncEvent::Disconnected(_) => {}
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(!sync.is_connected());
+    }
+}
@@ -0,0 +1,56 @@
+//! Web Mercator (EPSG:3857) coordinate conversions
+//!
+//! Public facade over the map's projection math so it can be reused
+//! without the renderer. Tile coordinates follow the OSM slippy-map scheme;
+//! pixel coordinates are global, measured from the top-left corner of the
+//! world at the given zoom.
+//!
+//! ```
+//! use client::projection::{lon_lat_to_tile, pixel_to_world, world_to_pixel};
+//!
+//! // Seoul at zoom 10
+//! assert_eq!(lon_lat_to_tile(126.9780, 37.5665, 10), (873, 396));
+//!
+//! let (x, y) = world_to_pixel(126.9780, 37.5665, 10.0);
+//! let (lon, lat) = pixel_to_world(x, y, 10.0);
+//! assert!((lon - 126.9780).abs() < 1e-9 && (lat - 37.5665).abs() < 1e-9);
+//! ```
+
+pub use crate::map::camera::TILE_SIZE;
+pub use crate::map::tile::{
+    TileId, clamp_latitude, lon_lat_to_tile, lon_lat_to_tile_f64, normalize_longitude,
+    tile_to_lon_lat, tile_to_lon_lat_f64,
+};
+
+/// Convert longitude/latitude to global pixel coordinates at a (fractional) zoom
+///
+/// ```
+/// use client::projection::{world_to_pixel, TILE_SIZE};
+///
+/// // The null island sits in the middle of the world
+/// let (x, y) = world_to_pixel(0.0, 0.0, 2.0);
+/// assert_eq!((x, y), (2.0 * TILE_SIZE, 2.0 * TILE_SIZE));
+/// ```
+pub fn world_to_pixel(lon: f64, lat: f64, zoom: f64) -> (f64, f64) {
+    let world_size = TILE_SIZE * 2.0_f64.powf(zoom);
+    let (x, y) = lon_lat_to_tile_f64(lon, clamp_latitude(lat), 0);
+    (x * world_size, y * world_size)
+}
+
+/// Convert global pixel coordinates at a (fractional) zoom to longitude/latitude
+///
+/// ```
+/// use client::projection::{pixel_to_world, TILE_SIZE};
+///
+/// // Top-left corner of the world
+/// let (lon, lat) = pixel_to_world(0.0, 0.0, 5.5);
+/// assert_eq!(lon, -180.0);
+/// assert!((lat - 85.0511287798).abs() < 1e-9);
+///
+/// let (lon, _) = pixel_to_world(TILE_SIZE, 0.0, 0.0);
+/// assert_eq!(lon, 180.0);
+/// ```
+pub fn pixel_to_world(x: f64, y: f64, zoom: f64) -> (f64, f64) {
+    let world_size = TILE_SIZE * 2.0_f64.powf(zoom);
+    tile_to_lon_lat_f64(x / world_size, y / world_size, 0)
+}
@@ -1,25 +1,89 @@
 // Grid overlay shader for pixel drawing
 
+// Set when the target re-encodes to sRGB; colors are sRGB and must be linearized
+override srgb_target: bool = false;
+
 struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) color: vec4<f32>,
+    // Corner of the unit quad, (0, 0) to (1, 1)
+    @location(0) corner: vec2<f32>,
+}
+
+// One cell: a parallelogram in NDC spanned from its bottom-left corner
+struct InstanceInput {
+    @location(1) origin: vec2<f32>,
+    @location(2) axis_x: vec2<f32>,
+    @location(3) axis_y: vec2<f32>,
+    @location(4) color: vec4<f32>,
+    // 0 = square, 1 = circle
+    @location(5) shape: u32,
+}
+
+struct GridUniforms {
+    // Alpha multiplier of the selection and hover highlights
+    highlight_alpha: f32,
+    // Instances from this index on are highlights
+    first_highlight: u32,
+    // Nonzero to smooth the edges of square cells
+    edge_antialiasing: u32,
 }
 
+@group(0) @binding(0) var<uniform> uniforms: GridUniforms;
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec4<f32>,
+    // Position inside the cell, (0, 0) to (1, 1)
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) shape: u32,
 }
 
 @vertex
-fn vs_main(in: VertexInput) -> VertexOutput {
+fn vs_main(
+    in: VertexInput,
+    cell: InstanceInput,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
     var out: VertexOutput;
-    out.clip_position = vec4<f32>(in.position, 1.0);
-    out.clip_position.y *= -1.0;
-    out.color = in.color;
+    let position = cell.origin + in.corner.x * cell.axis_x + in.corner.y * cell.axis_y;
+    out.clip_position = vec4<f32>(position, 0.0, 1.0);
+    out.color = cell.color;
+    if instance >= uniforms.first_highlight {
+        out.color.a *= uniforms.highlight_alpha;
+    }
+    out.uv = in.corner;
+    out.shape = cell.shape;
     return out;
 }
 
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
+    // Distance from the cell center, 1 at the middle of its edges
+    let distance = length(in.uv * 2.0 - 1.0);
+    let edge = fwidth(distance);
+
+    var color = in.color;
+    if in.shape == 1u {
+        // Antialiased circle; fragments fully outside are dropped
+        let coverage = 1.0 - smoothstep(1.0 - edge, 1.0, distance);
+        if coverage <= 0.0 {
+            discard;
+        }
+        color.a *= coverage;
+    } else if uniforms.edge_antialiasing != 0u {
+        // Fade out over the last screen pixel towards each edge
+        let to_edge = min(in.uv, 1.0 - in.uv);
+        let coverage = smoothstep(vec2<f32>(0.0), fwidth(in.uv), to_edge);
+        color.a *= coverage.x * coverage.y;
+    }
+
+    if srgb_target {
+        return vec4<f32>(srgb_to_linear(color.rgb), color.a);
+    }
+    return color;
 }
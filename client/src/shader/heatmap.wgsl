@@ -0,0 +1,100 @@
+// Heatmap shader: weighted points are splatted additively into a density
+// texture, which is then colormapped over the map
+
+// Set when the target re-encodes to sRGB; ramp colors are sRGB and must be linearized
+override srgb_target: bool = false;
+
+// One point: a soft sprite of `radius` (NDC) around `center`
+struct PointInput {
+    @location(0) center: vec2<f32>,
+    @location(1) radius: vec2<f32>,
+    @location(2) weight: f32,
+}
+
+struct SplatOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position inside the sprite, -1 to 1 on both axes
+    @location(0) local: vec2<f32>,
+    @location(1) weight: f32,
+}
+
+// Sprites are 4-vertex triangle strips
+@vertex
+fn vs_splat(@builtin(vertex_index) index: u32, point: PointInput) -> SplatOutput {
+    var out: SplatOutput;
+    let corner = vec2<f32>(f32(index & 1u), f32(index >> 1u)) * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(point.center + corner * point.radius, 0.0, 1.0);
+    out.local = corner;
+    out.weight = point.weight;
+    return out;
+}
+
+@fragment
+fn fs_splat(in: SplatOutput) -> @location(0) vec4<f32> {
+    // Smooth falloff reaching zero at the sprite's edge
+    let falloff = max(1.0 - dot(in.local, in.local), 0.0);
+    return vec4<f32>(in.weight * falloff * falloff, 0.0, 0.0, 0.0);
+}
+
+struct HeatmapUniforms {
+    // Density shown in the hottest color
+    max_density: f32,
+    opacity: f32,
+}
+
+@group(0) @binding(0) var t_density: texture_2d<f32>;
+@group(0) @binding(1) var s_density: sampler;
+@group(0) @binding(2) var<uniform> uniforms: HeatmapUniforms;
+
+struct ColormapOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Fullscreen triangle
+@vertex
+fn vs_colormap(@builtin(vertex_index) index: u32) -> ColormapOutput {
+    var out: ColormapOutput;
+    let position = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(position, 0.0, 1.0);
+    out.uv = vec2<f32>(position.x * 0.5 + 0.5, 0.5 - position.y * 0.5);
+    return out;
+}
+
+// Blue to red through cyan, lime and yellow
+fn ramp(t: f32) -> vec3<f32> {
+    let blue = vec3<f32>(0.0, 0.0, 1.0);
+    let cyan = vec3<f32>(0.0, 1.0, 1.0);
+    let lime = vec3<f32>(0.0, 1.0, 0.0);
+    let yellow = vec3<f32>(1.0, 1.0, 0.0);
+    let red = vec3<f32>(1.0, 0.0, 0.0);
+    if t < 0.25 {
+        return mix(blue, cyan, t / 0.25);
+    }
+    if t < 0.5 {
+        return mix(cyan, lime, (t - 0.25) / 0.25);
+    }
+    if t < 0.75 {
+        return mix(lime, yellow, (t - 0.5) / 0.25);
+    }
+    return mix(yellow, red, (t - 0.75) / 0.25);
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fs_colormap(in: ColormapOutput) -> @location(0) vec4<f32> {
+    let density = textureSample(t_density, s_density, in.uv).r;
+    let t = clamp(density / uniforms.max_density, 0.0, 1.0);
+    // Sparse areas fade out instead of showing as solid blue
+    let alpha = smoothstep(0.0, 0.2, t) * uniforms.opacity;
+    var color = ramp(t);
+    if srgb_target {
+        color = srgb_to_linear(color);
+    }
+    return vec4<f32>(color, alpha);
+}
@@ -3,11 +3,13 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) opacity: f32,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) opacity: f32,
 }
 
 @vertex
@@ -15,14 +17,24 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     out.clip_position = vec4<f32>(in.position, 1.0);
     out.tex_coords = in.tex_coords;
-    out.tex_coords.y = 1.0 - out.tex_coords.y;
+    out.opacity = in.opacity;
     return out;
 }
 
 @group(0) @binding(0) var t_tile: texture_2d<f32>;
-@group(0) @binding(1) var s_tile: sampler;
+
+// Settings shared by all tiles
+struct TileUniforms {
+    // Global tile opacity, multiplied with the per-tile opacity
+    opacity: f32,
+}
+
+@group(1) @binding(0) var<uniform> uniforms: TileUniforms;
+// Linear or nearest, as chosen on the renderer
+@group(1) @binding(1) var s_tile: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_tile, s_tile, in.tex_coords);
+    let color = textureSample(t_tile, s_tile, in.tex_coords);
+    return vec4<f32>(color.rgb, color.a * in.opacity * uniforms.opacity);
 }
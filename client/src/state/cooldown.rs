@@ -0,0 +1,97 @@
+//! Cooldown between pixel placements
+
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Default time between placements
+pub const DEFAULT_PLACEMENT_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Throttles pixel placement to one every `duration`
+#[derive(Clone, Copy, Debug)]
+pub struct PlacementCooldown {
+    duration: Duration,
+    last_placement: Option<Instant>,
+}
+
+impl PlacementCooldown {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            last_placement: None,
+        }
+    }
+
+    /// Time left until the next placement is allowed
+    pub fn remaining(&self, now: Instant) -> Duration {
+        match self.last_placement {
+            Some(last) => self.duration.saturating_sub(now.saturating_duration_since(last)),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Check if a pixel can be placed now
+    pub fn is_ready(&self, now: Instant) -> bool {
+        self.remaining(now).is_zero()
+    }
+
+    /// Start the cooldown if ready, returning whether placement is allowed
+    pub fn try_place(&mut self, now: Instant) -> bool {
+        if !self.is_ready(now) {
+            return false;
+        }
+        self.last_placement = Some(now);
+        true
+    }
+
+    /// Cooldown length
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Change the cooldown length (applies to a running cooldown too)
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+}
+
+impl Default for PlacementCooldown {
+    fn default() -> Self {
+        Self::new(DEFAULT_PLACEMENT_COOLDOWN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown_blocks_until_elapsed() {
+        let mut cooldown = PlacementCooldown::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(cooldown.try_place(start));
+        assert!(!cooldown.try_place(start + Duration::from_secs(3)));
+        assert_eq!(
+            cooldown.remaining(start + Duration::from_secs(3)),
+            Duration::from_secs(7)
+        );
+
+        assert!(cooldown.try_place(start + Duration::from_secs(10)));
+        assert!(!cooldown.is_ready(start + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_shortening_duration_applies_immediately() {
+        let mut cooldown = PlacementCooldown::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(cooldown.try_place(start));
+
+        cooldown.set_duration(Duration::from_secs(1));
+        assert!(cooldown.is_ready(start + Duration::from_secs(2)));
+
+        cooldown.set_duration(Duration::ZERO);
+        assert!(cooldown.try_place(start + Duration::from_secs(2)));
+        assert!(cooldown.try_place(start + Duration::from_secs(2)));
+    }
+}
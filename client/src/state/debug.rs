@@ -0,0 +1,29 @@
+//! Debug overlays drawn with egui on top of the map
+
+use egui::{Align2, Color32, FontId, Painter, Pos2, Shape, Stroke, vec2};
+
+use crate::map::camera::MapCamera;
+
+/// Color of tile outlines and labels
+const TILE_BOUNDS_COLOR: Color32 = Color32::from_rgb(255, 0, 255);
+
+/// Outline each on-screen tile and label it with its z/x/y
+///
+/// The camera works in physical pixels; `pixels_per_point` converts them to
+/// egui points.
+pub fn draw_tile_bounds(painter: &Painter, camera: &MapCamera, pixels_per_point: f32) {
+    let to_pos = |(x, y): (f32, f32)| Pos2::new(x / pixels_per_point, y / pixels_per_point);
+    let stroke = Stroke::new(1.0, TILE_BOUNDS_COLOR);
+
+    for tile in camera.visible_tiles_with_buffer(0) {
+        let corners = camera.tile_corners(&tile).map(to_pos);
+        painter.add(Shape::closed_line(corners.to_vec(), stroke));
+        painter.text(
+            to_pos(camera.tile_to_screen(&tile)) + vec2(4.0, 4.0),
+            Align2::LEFT_TOP,
+            format!("{}/{}/{}", tile.z, tile.x, tile.y),
+            FontId::monospace(12.0),
+            TILE_BOUNDS_COLOR,
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Coordinates typed or pasted into the "Go to" window
+//!
+//! Accepts two numbers in decimal degrees (`37.5665, 126.9780`) or degrees,
+//! minutes and seconds (`37°33'59"N 126°58'41"E`), separated by a comma or
+//! a space. Latitude comes first unless the window is set to longitude
+//! first; N/S/E/W letters override the order.
+
+use std::fmt;
+
+/// Latitude or longitude, for errors and hemisphere letters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    Lat,
+    Lon,
+}
+
+impl Axis {
+    fn name(self) -> &'static str {
+        match self {
+            Axis::Lat => "latitude",
+            Axis::Lon => "longitude",
+        }
+    }
+
+    fn limit(self) -> f64 {
+        match self {
+            Axis::Lat => 90.0,
+            Axis::Lon => 180.0,
+        }
+    }
+}
+
+/// Text that isn't a pair of coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoordinateError {
+    /// Not exactly two coordinates
+    NotAPair,
+    /// A part that isn't a number or DMS angle
+    NotANumber(String),
+    /// Both coordinates marked as the same axis (e.g. two N/S letters)
+    SameAxis(Axis),
+    OutOfRange {
+        axis: Axis,
+        value: f64,
+    },
+}
+
+impl fmt::Display for CoordinateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoordinateError::NotAPair => write!(f, "enter a latitude and a longitude"),
+            CoordinateError::NotANumber(part) => write!(f, "{:?} is not a coordinate", part),
+            CoordinateError::SameAxis(axis) => write!(f, "both coordinates are a {}", axis.name()),
+            CoordinateError::OutOfRange { axis, value } => write!(
+                f,
+                "{} must be between -{limit} and {limit}, got {}",
+                axis.name(),
+                value,
+                limit = axis.limit()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CoordinateError {}
+
+/// Parse a pair of coordinates to (lon, lat)
+pub fn parse_coordinates(text: &str, lon_first: bool) -> Result<(f64, f64), CoordinateError> {
+    let parts: Vec<&str> = if text.contains(',') {
+        text.split(',').map(str::trim).collect()
+    } else {
+        text.split_whitespace().collect()
+    };
+    let [first, second] = parts[..] else {
+        return Err(CoordinateError::NotAPair);
+    };
+    let (first, first_axis) = parse_angle(first)?;
+    let (second, second_axis) = parse_angle(second)?;
+
+    let swapped = match (first_axis, second_axis) {
+        (Some(a), Some(b)) if a == b => return Err(CoordinateError::SameAxis(a)),
+        (Some(axis), _) => axis == Axis::Lon,
+        (None, Some(axis)) => axis == Axis::Lat,
+        (None, None) => lon_first,
+    };
+    let (lon, lat) = if swapped {
+        (first, second)
+    } else {
+        (second, first)
+    };
+
+    for (axis, value) in [(Axis::Lat, lat), (Axis::Lon, lon)] {
+        // Also rejects NaN
+        if !(-axis.limit()..=axis.limit()).contains(&value) {
+            return Err(CoordinateError::OutOfRange { axis, value });
+        }
+    }
+    Ok((lon, lat))
+}
+
+/// Parse decimal degrees or DMS, with an optional hemisphere letter before
+/// or after
+fn parse_angle(part: &str) -> Result<(f64, Option<Axis>), CoordinateError> {
+    let not_a_number = || CoordinateError::NotANumber(part.to_string());
+
+    let mut text = part.trim();
+    let mut hemisphere = None;
+    for (letter, axis, sign) in [
+        ('N', Axis::Lat, 1.0),
+        ('S', Axis::Lat, -1.0),
+        ('E', Axis::Lon, 1.0),
+        ('W', Axis::Lon, -1.0),
+    ] {
+        let stripped = text
+            .strip_suffix([letter, letter.to_ascii_lowercase()])
+            .or_else(|| text.strip_prefix([letter, letter.to_ascii_lowercase()]));
+        if let Some(rest) = stripped {
+            text = rest.trim();
+            hemisphere = Some((axis, sign));
+            break;
+        }
+    }
+
+    let mut fields = text
+        .split(['°', '\'', '"', '′', '″'])
+        .map(str::trim)
+        .filter(|field| !field.is_empty());
+    let degrees: f64 = fields
+        .next()
+        .and_then(|d| d.parse().ok())
+        .ok_or_else(not_a_number)?;
+    let mut value = degrees.abs();
+    for scale in [60.0, 3600.0] {
+        let Some(field) = fields.next() else { break };
+        let amount: f64 = field.parse().map_err(|_| not_a_number())?;
+        if !(0.0..60.0).contains(&amount) {
+            return Err(not_a_number());
+        }
+        value += amount / scale;
+    }
+    if fields.next().is_some() {
+        return Err(not_a_number());
+    }
+
+    let negative = text.starts_with('-');
+    match hemisphere {
+        // "-37 S" is ambiguous
+        Some(_) if negative => Err(not_a_number()),
+        Some((axis, sign)) => Ok((sign * value, Some(axis))),
+        None if negative => Ok((-value, None)),
+        None => Ok((value, None)),
+    }
+}
+
+/// Contents of the "Go to" window
+#[derive(Clone, Debug, Default)]
+pub struct CoordinateInput {
+    pub text: String,
+    /// Read the first number as the longitude
+    pub lon_first: bool,
+    /// Why the last submitted text was rejected
+    pub error: Option<CoordinateError>,
+}
+
+impl CoordinateInput {
+    /// Parse the text, keeping the error to show next to it
+    pub fn submit(&mut self) -> Option<(f64, f64)> {
+        let parsed = parse_coordinates(&self.text, self.lon_first);
+        self.error = parsed.as_ref().err().cloned();
+        parsed.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-4 && (actual.1 - expected.1).abs() < 1e-4,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_decimal_and_dms_pairs() {
+        let seoul = (126.978, 37.5665);
+        assert_near(
+            parse_coordinates("37.5665, 126.9780", false).unwrap(),
+            seoul,
+        );
+        assert_near(parse_coordinates("126.9780 37.5665", true).unwrap(), seoul);
+        assert_near(
+            parse_coordinates("37°33'59.4\"N 126°58'40.8\"E", false).unwrap(),
+            seoul,
+        );
+        // Hemisphere letters win over the order
+        assert_near(
+            parse_coordinates("E 126.978, N 37.5665", false).unwrap(),
+            seoul,
+        );
+        assert_near(
+            parse_coordinates("33° 52′ S, 151° 12′ E", true).unwrap(),
+            (151.2, -33.8667),
+        );
+        assert_near(
+            parse_coordinates("-33.8667,151.2", false).unwrap(),
+            (151.2, -33.8667),
+        );
+    }
+
+    #[test]
+    fn test_bad_coordinates_are_rejected() {
+        assert_eq!(
+            parse_coordinates("37.5", false),
+            Err(CoordinateError::NotAPair)
+        );
+        assert_eq!(
+            parse_coordinates("1, 2, 3", false),
+            Err(CoordinateError::NotAPair)
+        );
+        assert!(matches!(
+            parse_coordinates("north, 126", false),
+            Err(CoordinateError::NotANumber(_))
+        ));
+        assert!(matches!(
+            parse_coordinates("37°75'N, 126E", false),
+            Err(CoordinateError::NotANumber(_))
+        ));
+        assert_eq!(
+            parse_coordinates("37N, 38S", false),
+            Err(CoordinateError::SameAxis(Axis::Lat))
+        );
+        let error = parse_coordinates("126.978, 37.5665", false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "latitude must be between -90 and 90, got 126.978"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Tunable mouse and trackpad sensitivity
+
+use serde::{Deserialize, Serialize};
+use winit::event::MouseScrollDelta;
+
+/// What a left click on the map does (dragging pans in both)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PointerMode {
+    /// Clicks place pixels, aimed with a crosshair cursor
+    #[default]
+    Draw,
+    /// Clicks do nothing, for browsing without placing pixels by accident
+    Pan,
+}
+
+/// How raw pointer and wheel deltas map to panning and zooming
+///
+/// Saved settings missing a field load it from the defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputSettings {
+    /// Pan distance per pixel of drag (1 = the map follows the cursor)
+    pub pan_speed: f32,
+    /// Zoom levels per wheel notch (mouse wheels)
+    pub line_zoom_speed: f64,
+    /// Zoom levels per scrolled pixel (trackpads)
+    pub pixel_zoom_speed: f64,
+    /// Scrolling up zooms out instead of in
+    pub invert_scroll: bool,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            pan_speed: 1.0,
+            line_zoom_speed: 0.5,
+            pixel_zoom_speed: 0.01,
+            invert_scroll: false,
+        }
+    }
+}
+
+impl InputSettings {
+    /// Pan for a drag of (`dx`, `dy`) pixels
+    pub fn pan_delta(&self, dx: f32, dy: f32) -> (f32, f32) {
+        (dx * self.pan_speed, dy * self.pan_speed)
+    }
+
+    /// Zoom change for a wheel or trackpad scroll
+    pub fn zoom_delta(&self, delta: &MouseScrollDelta) -> f64 {
+        let zoom = match delta {
+            MouseScrollDelta::LineDelta(_, y) => *y as f64 * self.line_zoom_speed,
+            MouseScrollDelta::PixelDelta(pos) => pos.y * self.pixel_zoom_speed,
+        };
+        if self.invert_scroll { -zoom } else { zoom }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::PhysicalPosition;
+
+    #[test]
+    fn test_zoom_delta() {
+        let mut settings = InputSettings::default();
+        let notch = MouseScrollDelta::LineDelta(0.0, 2.0);
+        let pixels = MouseScrollDelta::PixelDelta(PhysicalPosition::new(0.0, 50.0));
+        assert_eq!(settings.zoom_delta(&notch), 1.0);
+        assert_eq!(settings.zoom_delta(&pixels), 0.5);
+
+        settings.invert_scroll = true;
+        settings.pixel_zoom_speed = 0.02;
+        assert_eq!(settings.zoom_delta(&notch), -1.0);
+        assert_eq!(settings.zoom_delta(&pixels), -1.0);
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let settings = InputSettings {
+            pan_speed: 2.0,
+            invert_scroll: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(serde_json::from_str::<InputSettings>(&json).unwrap(), settings);
+
+        let partial: InputSettings = serde_json::from_str(r#"{"pan_speed": 0.5}"#).unwrap();
+        assert_eq!(partial.pan_speed, 0.5);
+        assert_eq!(partial.line_zoom_speed, InputSettings::default().line_zoom_speed);
+    }
+}
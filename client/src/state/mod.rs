@@ -1,11 +1,28 @@
+mod cooldown;
+mod debug;
+mod goto;
+mod input;
+mod pacing;
+mod readback;
+mod recovery;
+mod rulers;
+mod selection;
+mod surface;
+mod title;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use egui::{Context, FullOutput, TopBottomPanel};
+use egui::{Context, FullOutput, TopBottomPanel, ViewportId};
 use egui_wgpu::{Renderer, RendererOptions, ScreenDescriptor};
 use wgpu::{
     Backends, ExperimentalFeatures, Features, Instance, InstanceDescriptor, MemoryHints,
-    SurfaceError, TextureFormat, Trace,
+    SurfaceError, Trace,
 };
+use web_time::Instant;
 use winit::window::Window;
 
 #[cfg(target_arch = "wasm32")]
@@ -13,16 +30,102 @@ use wasm_bindgen::prelude::*;
 
 use winit::dpi::PhysicalSize;
 use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+use crate::geocoder::{Address, Geocoder};
+use crate::launch::LaunchView;
+use crate::map::loader::DEFAULT_USER_AGENT;
+use crate::map::command::MapCommand;
+use crate::map::{MapSystem, MapSystemConfig};
+use crate::map::grid::{self, GridCoord, Guide, PixelShape, color_to_srgba, srgba_to_color};
+use crate::map::renderer::TileFilter;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::map::worldfile::WorldFile;
+use crate::map::zoom::DEFAULT_SETTLE_DELAY;
+use cooldown::PlacementCooldown;
+use goto::CoordinateInput;
+use input::{InputSettings, PointerMode};
+use pacing::FramePacer;
+use readback::FrameReadback;
+use recovery::{AcquireBackoff, Recovery};
+use rulers::{Ruler, Rulers, draw_rulers};
+use selection::Selection;
+use title::LocationTitle;
+
+/// Maximum cursor travel (pixels) for a press/release to count as a click
+const CLICK_DISTANCE: f32 = 4.0;
+
+/// Map rotation per pixel of horizontal right-drag (radians)
+const ROTATE_SPEED: f32 = 0.005;
+
+/// How often to redraw while tiles are downloading or decoding, to show
+/// them as they arrive
+const TILE_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-use crate::map::MapSystem;
+/// How often to redraw while idle with a sync connection, to show remote edits
+const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often to redraw while a selection or hover highlight pulses
+const HIGHLIGHT_FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// How often to redraw while tile requests are paused by rate limiting
+const THROTTLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to redraw while waiting for an address lookup
+const GEOCODER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Background where no tile has loaded
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.8,
+    g: 0.85,
+    b: 0.9,
+    a: 1.0,
+};
+
+/// How long the map takes to fly to coordinates from the "Go to" window
+const GOTO_FLIGHT: Duration = Duration::from_millis(1500);
 
 // This will store the state of our game
 pub struct State {
     pub window: Arc<Window>,
+    instance: Instance,
+    /// Kept to request a new device after device loss (native only)
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    adapter: wgpu::Adapter,
     pub surface: wgpu::Surface<'static>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    /// GPU adapter details, shown in the diagnostics window
+    pub adapter_info: wgpu::AdapterInfo,
+    show_diagnostics: bool,
+    /// Outline and label the visible tiles (toggled with F3)
+    show_tile_bounds: bool,
+    /// Present modes supported by the surface
+    present_modes: Vec<wgpu::PresentMode>,
+    /// MSAA samples per pixel (1 = no antialiasing)
+    msaa_samples: u32,
+    /// Multisampled color target resolved into the frame (None without MSAA)
+    msaa_view: Option<wgpu::TextureView>,
+    /// Usages the surface textures support
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    surface_usages: wgpu::TextureUsages,
+    /// Copy of the last frame for `read_pixel` (None unless enabled)
+    readback: Option<FrameReadback>,
+    /// Color under the last click, shown in the diagnostics window
+    #[cfg(not(target_arch = "wasm32"))]
+    clicked_color: Option<[u8; 4]>,
+    /// Export the view after the next frame (set by the toolbar)
+    #[cfg(not(target_arch = "wasm32"))]
+    export_requested: bool,
+    /// Set by the device-lost callback, handled at the next render
+    device_lost: Arc<AtomicBool>,
+    /// Spaces out retries when frames cannot be acquired
+    acquire_backoff: AcquireBackoff,
+    /// Decides when to draw; the app idles between requested frames
+    frame_pacer: FramePacer,
+    /// Delay after which egui wants to be repainted (e.g. for animations)
+    egui_repaint_delay: Duration,
     pub is_surface_configured: bool,
     resize_request: Option<PhysicalSize<u32>>,
     ui_renderer: Renderer,
@@ -37,12 +140,60 @@ pub struct State {
     mouse_pressed: bool,
     last_mouse_pos: Option<(f32, f32)>,
     current_mouse_pos: (f32, f32),
+    /// Cursor is over the window (the hovered cell is highlighted)
+    cursor_inside: bool,
+    /// Where the left button went down (for click detection)
+    press_pos: Option<(f32, f32)>,
+    /// Right button held (rotating)
+    rotate_pressed: bool,
+    /// Where the right button went down (a click looks up the address)
+    rotate_press_pos: Option<(f32, f32)>,
+    modifiers: ModifiersState,
+    /// Pan and wheel sensitivity
+    input_settings: InputSettings,
+    /// Whether clicks place pixels
+    pointer_mode: PointerMode,
+
+    // Pixel placement
+    placement_cooldown: PlacementCooldown,
+    selected_color: [f32; 4],
+
+    // Region editing (Shift + drag selects)
+    selection: Option<Selection>,
+    selecting: bool,
+    /// Copied cells as offsets from the copied selection's min corner
+    clipboard: Vec<(GridCoord, [f32; 4])>,
+
+    // Guide lines (dragged out of the rulers)
+    rulers: Rulers,
+    /// Ruler a guide is being dragged from, and the guide added so far
+    guide_drag: Option<(Ruler, Option<Guide>)>,
+
+    // Address lookup (right click)
+    geocoder: Geocoder,
+    /// Point (lon, lat) whose address is shown
+    address_point: Option<(f64, f64)>,
+
+    // Coordinate entry ("Go to" window)
+    show_goto: bool,
+    goto: CoordinateInput,
+
+    /// Map location in the window title (None unless turned on)
+    location_title: Option<LocationTitle>,
 }
 
 impl State {
     // We don't need this to be async right now,
     // but we will in the next tutorial
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
+    ///
+    /// `msaa_samples` is validated against the adapter and falls back to 1
+    /// (no antialiasing) if unsupported. The map starts at `launch_view`
+    /// where given.
+    pub async fn new(
+        window: Arc<Window>,
+        msaa_samples: u32,
+        launch_view: LaunchView,
+    ) -> anyhow::Result<Self> {
         let instance = Instance::new(&InstanceDescriptor {
             backends: Backends::all(),
             ..Default::default()
@@ -58,78 +209,80 @@ impl State {
             })
             .await?;
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: Some("Main Device"),
-                required_features: Features::empty(),
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                experimental_features: ExperimentalFeatures::disabled(),
-                memory_hints: MemoryHints::Performance,
-                trace: Trace::Off,
-            })
-            .await?;
+        let adapter_info = adapter.get_info();
+        log::info!(
+            "Using {} ({:?}, {:?}), driver: {} {}",
+            adapter_info.name,
+            adapter_info.backend,
+            adapter_info.device_type,
+            adapter_info.driver,
+            adapter_info.driver_info
+        );
+
+        let (device, queue) = adapter.request_device(&device_descriptor()).await?;
+        let device_lost = Arc::new(AtomicBool::new(false));
+        watch_device_lost(&device, &device_lost);
 
         let cap: wgpu::SurfaceCapabilities = surface.get_capabilities(&adapter);
 
-        let texture_format = cap
-            .formats
-            .iter()
-            .find(|format| {
-                **format == TextureFormat::Rgba8Unorm || **format == TextureFormat::Bgra8Unorm
-            })
-            .copied()
-            .unwrap_or(cap.formats[0]);
+        let texture_format = surface::choose_surface_format(&cap)
+            .ok_or_else(|| anyhow::anyhow!("Surface is incompatible with the adapter"))?;
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: texture_format,
             width: window.inner_size().width,
             height: window.inner_size().height,
-            present_mode: cap.present_modes[0],
+            present_mode: surface::choose_present_mode(&cap.present_modes, &surface::VSYNC_MODES),
             alpha_mode: cap.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
 
-        let ui_renderer = Renderer::new(
-            &device,
-            texture_format,
-            RendererOptions {
-                msaa_samples: 0,
-                depth_stencil_format: None,
-                dithering: false,
-                predictable_texture_filtering: false,
-            },
-        );
-        let egui_ctx = Context::default();
-
-        let egui_state = egui_winit::State::new(
-            egui_ctx.clone(),
-            egui_ctx.viewport_id(),
-            window.as_ref(),
-            egui_ctx.native_pixels_per_point(),
-            window.theme(),
-            None,
+        let msaa_samples = surface::choose_sample_count(
+            msaa_samples,
+            adapter.get_texture_format_features(texture_format).flags,
         );
 
+        let (ui_renderer, egui_ctx, egui_state) =
+            create_ui(&window, &device, texture_format, msaa_samples);
+
         // Create map system
-        let map_system = MapSystem::new(
-            &device,
-            texture_format,
-            window.inner_size().width,
-            window.inner_size().height,
+        let mut frame_pacer = FramePacer::new(None);
+        frame_pacer.request_frame(Instant::now());
+
+        let map_config = launch_view.apply(
+            MapSystemConfig::default()
+                .viewport(window.inner_size().width, window.inner_size().height)
+                .msaa_samples(msaa_samples),
         );
+        let mut map_system = MapSystem::from_config(&device, texture_format, map_config);
+        map_system.set_scale_factor(window.scale_factor() as f32);
 
         Ok(Self {
             window,
+            instance,
+            adapter,
             surface,
             device,
             queue,
             config,
+            adapter_info,
+            show_diagnostics: false,
+            show_tile_bounds: false,
+            present_modes: cap.present_modes.clone(),
+            msaa_samples,
+            msaa_view: None,
+            surface_usages: cap.usages,
+            readback: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            clicked_color: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            export_requested: false,
+            device_lost,
+            acquire_backoff: AcquireBackoff::default(),
+            frame_pacer,
+            egui_repaint_delay: Duration::MAX,
             is_surface_configured: false,
             resize_request: None,
             ui_renderer,
@@ -140,6 +293,25 @@ impl State {
             mouse_pressed: false,
             last_mouse_pos: None,
             current_mouse_pos: (0.0, 0.0),
+            cursor_inside: false,
+            press_pos: None,
+            rotate_pressed: false,
+            rotate_press_pos: None,
+            modifiers: ModifiersState::empty(),
+            input_settings: InputSettings::default(),
+            pointer_mode: PointerMode::default(),
+            placement_cooldown: PlacementCooldown::default(),
+            selected_color: [1.0, 0.0, 0.0, 1.0],
+            selection: None,
+            selecting: false,
+            clipboard: Vec::new(),
+            rulers: Rulers::default(),
+            guide_drag: None,
+            geocoder: Geocoder::new(DEFAULT_USER_AGENT),
+            address_point: None,
+            show_goto: false,
+            goto: CoordinateInput::default(),
+            location_title: None,
         })
     }
 
@@ -158,13 +330,19 @@ impl State {
         self.config.width = width;
         self.config.height = height;
         self.surface.configure(&self.device, &self.config);
+        self.msaa_view = create_msaa_view(&self.device, &self.config, self.msaa_samples);
         self.map_system.resize(width, height);
     }
 
     pub fn handle_input(&mut self, event: &WindowEvent) -> bool {
+        // Any input may change what's on screen
+        if !matches!(event, WindowEvent::RedrawRequested) {
+            self.frame_pacer.request_frame(Instant::now());
+        }
+
         let response = self
             .egui_state
-            .on_window_event(self.window.as_ref(), &event);
+            .on_window_event(self.window.as_ref(), event);
         self.draw_egui = response.repaint;
 
         // If egui consumed it, don't process map input
@@ -174,34 +352,153 @@ impl State {
 
         // Handle map-specific input
         match event {
-            WindowEvent::MouseInput { state, button, .. } => {
-                if *button == MouseButton::Left {
-                    self.mouse_pressed = *state == ElementState::Pressed;
-                    if !self.mouse_pressed {
-                        self.last_mouse_pos = None;
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            // egui picks up the new pixels per point by itself
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.map_system.set_scale_factor(*scale_factor as f32);
+            }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed
+                    && event.logical_key == Key::Named(NamedKey::Escape)
+                    && self.selection.is_some() =>
+            {
+                // Consumed so the app doesn't also quit on Escape
+                self.set_selection(None);
+                return true;
+            }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed
+                    && !event.repeat
+                    && event.logical_key == Key::Named(NamedKey::F3) =>
+            {
+                self.show_tile_bounds = !self.show_tile_bounds;
+            }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed
+                    && (self.modifiers.control_key() || self.modifiers.super_key()) =>
+            {
+                // Ctrl+C copies the selection, Ctrl+V pastes at the cursor, Ctrl+Z undoes
+                if let Key::Character(c) = &event.logical_key {
+                    match c.to_lowercase().as_str() {
+                        "c" => self.copy_selection(),
+                        "v" => {
+                            let (x, y) = self.current_mouse_pos;
+                            let origin = self.map_system.screen_to_grid(x, y);
+                            self.paste_at(origin);
+                        }
+                        "z" => {
+                            self.map_system.undo();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. }
+                if *button == MouseButton::Left
+                    && (self.guide_drag.is_some()
+                        || (*state == ElementState::Pressed
+                            && self.ruler_under_cursor().is_some())) =>
+            {
+                // Dragging out of a ruler places a guide; it stays where released
+                self.guide_drag = match state {
+                    ElementState::Pressed => self.ruler_under_cursor().map(|ruler| (ruler, None)),
+                    ElementState::Released => None,
+                };
+            }
+            WindowEvent::MouseInput { state, button, .. }
+                if *button == MouseButton::Left
+                    && (self.selecting || self.modifiers.shift_key()) =>
+            {
+                // Shift + drag selects a rectangle instead of panning
+                self.selecting = *state == ElementState::Pressed;
+                if self.selecting {
+                    let (x, y) = self.current_mouse_pos;
+                    let cell = self.map_system.screen_to_grid(x, y);
+                    self.set_selection(Some(Selection::new(cell)));
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } if *button == MouseButton::Left => {
+                self.mouse_pressed = *state == ElementState::Pressed;
+                if self.mouse_pressed {
+                    self.press_pos = Some(self.current_mouse_pos);
+                } else {
+                    self.last_mouse_pos = None;
+
+                    // Click without dragging places a pixel
+                    let (x, y) = self.current_mouse_pos;
+                    if let Some((px, py)) = self.press_pos.take()
+                        && (x - px).hypot(y - py) <= CLICK_DISTANCE
+                    {
+                        // Read back on click only, as each read waits for the GPU
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if self.show_diagnostics {
+                            self.clicked_color = self.read_pixel(x as u32, y as u32);
+                        }
+                        if self.pointer_mode == PointerMode::Draw {
+                            self.place_pixel(x, y);
+                        }
                     }
                 }
             }
+            WindowEvent::MouseInput { state, button, .. } if *button == MouseButton::Right => {
+                self.rotate_pressed = *state == ElementState::Pressed;
+                if self.rotate_pressed {
+                    self.rotate_press_pos = Some(self.current_mouse_pos);
+                } else {
+                    // Click without dragging shows the address there
+                    let (x, y) = self.current_mouse_pos;
+                    if let Some((px, py)) = self.rotate_press_pos.take()
+                        && (x - px).hypot(y - py) <= CLICK_DISTANCE
+                    {
+                        self.address_point = Some(self.map_system.screen_to_world(x, y));
+                    }
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_inside = false;
+            }
             WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_inside = true;
                 let (x, y) = (position.x as f32, position.y as f32);
+                if self.rotate_pressed {
+                    let dx = x - self.current_mouse_pos.0;
+                    let rotation = self.map_system.rotation();
+                    self.map_system.set_rotation(rotation + dx * ROTATE_SPEED);
+                }
                 self.current_mouse_pos = (x, y);
 
+                if let Some((ruler, added)) = self.guide_drag {
+                    self.drag_guide(ruler, added, x, y);
+                }
+
+                if self.selecting
+                    && let Some(mut selection) = self.selection
+                {
+                    selection.corner = self.map_system.screen_to_grid(x, y);
+                    self.set_selection(Some(selection));
+                }
+
                 if self.mouse_pressed {
                     if let Some((last_x, last_y)) = self.last_mouse_pos {
-                        let dx = x - last_x;
-                        let dy = y - last_y;
+                        let (dx, dy) = self.input_settings.pan_delta(x - last_x, y - last_y);
                         self.map_system.pan(dx, dy);
                     }
                     self.last_mouse_pos = Some((x, y));
                 }
             }
             WindowEvent::MouseWheel { delta, .. } => {
-                let zoom_delta = match delta {
-                    MouseScrollDelta::LineDelta(_, y) => *y as f64 * 0.5,
-                    MouseScrollDelta::PixelDelta(pos) => pos.y as f64 * 0.01,
-                };
+                let zoom_delta = self.input_settings.zoom_delta(delta);
                 let (mx, my) = self.current_mouse_pos;
-                self.map_system.zoom_at(zoom_delta, mx, my);
+                // Ctrl + wheel jumps between whole levels, where tiles are
+                // sharpest; trackpads (and pinches) keep zooming freely
+                if self.modifiers.control_key() && matches!(delta, MouseScrollDelta::LineDelta(..))
+                {
+                    self.map_system.zoom_level_smoothly_at(zoom_delta, mx, my);
+                } else {
+                    self.map_system.zoom_smoothly_at(zoom_delta, mx, my);
+                }
             }
             _ => {}
         }
@@ -209,26 +506,145 @@ impl State {
         response.consumed
     }
 
+    /// Ruler under the cursor
+    fn ruler_under_cursor(&self) -> Option<Ruler> {
+        let (x, y) = self.current_mouse_pos;
+        self.rulers.at(x, y)
+    }
+
+    /// Move the guide dragged from `ruler` to the lattice line nearest the
+    /// cursor, replacing the one the drag `added`
+    ///
+    /// Back over its ruler the guide is taken away again.
+    fn drag_guide(&mut self, ruler: Ruler, added: Option<Guide>, x: f32, y: f32) {
+        let (lon, lat) = self.map_system.screen_to_world(x, y);
+        let grid = &mut self.map_system.pixel_grid;
+        if let Some(guide) = added {
+            grid.remove_guide(guide);
+        }
+        let guide = grid.snap_guide(lon, lat, ruler == Ruler::Left);
+        // A guide already there isn't the drag's to take away
+        let added = (self.rulers.at(x, y) != Some(ruler) && !grid.guides().contains(&guide))
+            .then(|| {
+                grid.add_guide(guide);
+                guide
+            });
+        self.guide_drag = Some((ruler, added));
+    }
+
+    /// Place the selected color at a screen position if the cooldown allows
+    fn place_pixel(&mut self, screen_x: f32, screen_y: f32) {
+        if !self.placement_cooldown.try_place(Instant::now()) {
+            return;
+        }
+        let coord = self.map_system.screen_to_grid(screen_x, screen_y);
+        self.map_system.set_pixel(coord, self.selected_color);
+    }
+
+    /// Replace the selection and its highlight
+    fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection;
+        self.map_system
+            .pixel_grid
+            .set_selection(selection.map(|s| s.bounds()));
+    }
+
+    /// Fill the selection with the active color if the cooldown allows
+    ///
+    /// Bulk edits take one placement from the cooldown, like a single pixel.
+    fn fill_selection(&mut self) {
+        if let Some(selection) = self.selection {
+            // Too large a selection is refused with a warning, without
+            // using up the cooldown
+            let (min, max) = selection.bounds();
+            if grid::is_fillable(min, max) && self.placement_cooldown.try_place(Instant::now()) {
+                self.map_system.fill_region(min, max, self.selected_color);
+            }
+        }
+    }
+
+    /// Erase every pixel in the selection if the cooldown allows
+    fn clear_selection(&mut self) {
+        if let Some(selection) = self.selection
+            && self.placement_cooldown.try_place(Instant::now())
+        {
+            let (min, max) = selection.bounds();
+            self.map_system.clear_bounds(min, max);
+        }
+    }
+
+    /// Copy the selection's pixels to the clipboard
+    fn copy_selection(&mut self) {
+        if let Some(selection) = self.selection {
+            let (min, max) = selection.bounds();
+            self.clipboard = self.map_system.pixel_grid.copy_region(min, max);
+        }
+    }
+
+    /// Paste the clipboard with its min corner at `origin` if the cooldown
+    /// allows
+    fn paste_at(&mut self, origin: GridCoord) {
+        if !self.clipboard.is_empty() && self.placement_cooldown.try_place(Instant::now()) {
+            self.map_system.paste_region(origin, &self.clipboard);
+        }
+    }
+
     pub fn update(&mut self) {
+        // Recomputed every frame since the map can move under a still cursor
+        let over_map = self.cursor_inside && !self.egui_ctx.is_pointer_over_area();
+        let drawing = over_map && self.pointer_mode == PointerMode::Draw;
+        let hover = drawing.then(|| {
+            let (x, y) = self.current_mouse_pos;
+            self.map_system.screen_to_grid(x, y)
+        });
+        self.map_system.pixel_grid.set_hover(hover);
+
         // Update map system
-        self.map_system.update(&self.device, &self.queue);
+        let now = Instant::now();
+        self.map_system.update(&self.device, &self.queue, now);
+        self.geocoder.poll(now);
+
+        if let Some(title) = &mut self.location_title
+            && let Some(text) =
+                title.update(self.map_system.center(), self.map_system.zoom_level(), now)
+        {
+            self.window.set_title(&text);
+        }
+    }
+
+    /// Show the map location in the window title, or restore the title
+    ///
+    /// Off by default, for embedders that set the title themselves.
+    pub fn set_location_in_title(&mut self, enabled: bool) {
+        if enabled == self.location_title.is_some() {
+            return;
+        }
+        match self.location_title.take() {
+            Some(title) => self.window.set_title(title.base()),
+            None => self.location_title = Some(LocationTitle::new(&self.window.title())),
+        }
     }
 
     fn draw_egui(&mut self) -> FullOutput {
         let input = self.egui_state.take_egui_input(self.window.as_ref());
         let context = self.egui_ctx.clone();
-        let output = context.run(input, |ctx| {
+        context.run(input, |ctx| {
             self.egui(ctx);
-        });
-        output
+        })
     }
 
     fn egui(&mut self, ctx: &Context) {
         // Update egui
         let map_center = self.map_system.center();
         let map_zoom = self.map_system.zoom_level();
+        let rotation = self.map_system.rotation();
         let cache_stats = self.map_system.cache_stats();
         let pending = self.map_system.pending_tiles();
+        let loaded = self.map_system.is_idle();
+        let progress = self.map_system.visible_load_progress();
+        let remaining = self
+            .placement_cooldown
+            .remaining(Instant::now());
 
         TopBottomPanel::top("menu").show(ctx, |ui| {
             ui.horizontal(|ui| {
@@ -236,6 +652,13 @@ impl State {
                     "Zoom: {:.1} | Center: ({:.4}, {:.4})",
                     map_zoom, map_center.0, map_center.1
                 ));
+                if rotation != 0.0 {
+                    ui.separator();
+                    ui.label(format!("Bearing: {:.0}°", rotation.to_degrees()));
+                    if ui.button("Reset north").clicked() {
+                        self.map_system.set_rotation(0.0);
+                    }
+                }
                 ui.separator();
                 ui.label(format!(
                     "Cache: {}/{} ({:.0}%)",
@@ -243,17 +666,600 @@ impl State {
                     cache_stats.max_tiles,
                     cache_stats.tile_usage_percent()
                 ));
-                if pending > 0 {
+                if self.map_system.is_throttled() {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::ORANGE, "Rate limited, slowing down");
+                } else if !loaded {
                     ui.separator();
-                    ui.label(format!("Loading: {}", pending));
+                    ui.spinner();
+                    ui.label(format!("Loading: {} ({:.0}%)", pending, progress * 100.0));
+                }
+                ui.separator();
+                let mut tile_opacity = self.map_system.tile_opacity();
+                ui.label("Map");
+                if ui
+                    .add(egui::Slider::new(&mut tile_opacity, 0.0..=1.0).show_value(false))
+                    .changed()
+                {
+                    self.map_system.set_tile_opacity(tile_opacity);
                 }
+                let mut crisp = self.map_system.tile_filter() == TileFilter::Nearest;
+                if ui.checkbox(&mut crisp, "Crisp").changed() {
+                    let filter = if crisp { TileFilter::Nearest } else { TileFilter::Linear };
+                    self.map_system.set_tile_filter(filter);
+                }
+                ui.separator();
+                let mut vsync = self.config.present_mode == wgpu::PresentMode::Fifo;
+                if ui.checkbox(&mut vsync, "VSync").changed() {
+                    self.set_vsync(vsync);
+                }
+                let mut max_fps = self.frame_pacer.max_fps().unwrap_or(0);
+                ui.add(
+                    egui::DragValue::new(&mut max_fps)
+                        .range(0..=240)
+                        .prefix("Max FPS: ")
+                        .custom_formatter(|fps, _| {
+                            if fps == 0.0 {
+                                "unlimited".to_owned()
+                            } else {
+                                format!("{}", fps)
+                            }
+                        }),
+                );
+                self.frame_pacer.set_max_fps(Some(max_fps));
+                ui.menu_button("Input", |ui| {
+                    let settings = &mut self.input_settings;
+                    ui.add(
+                        egui::Slider::new(&mut settings.pan_speed, 0.25..=4.0)
+                            .logarithmic(true)
+                            .text("Pan speed"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.line_zoom_speed, 0.05..=2.0)
+                            .logarithmic(true)
+                            .text("Wheel zoom step"),
+                    )
+                    .on_hover_text("Levels per notch; hold Ctrl for whole levels");
+                    ui.add(
+                        egui::Slider::new(&mut settings.pixel_zoom_speed, 0.001..=0.1)
+                            .logarithmic(true)
+                            .text("Trackpad zoom speed"),
+                    );
+                    ui.checkbox(&mut settings.invert_scroll, "Invert scroll");
+                });
+                ui.toggle_value(&mut self.show_diagnostics, "Diagnostics");
+                if ui
+                    .button("What's here?")
+                    .on_hover_text("Address of the map center (or right-click a point)")
+                    .clicked()
+                {
+                    self.address_point = Some(map_center);
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui
+                    .button("Export")
+                    .on_hover_text("Save the map as a PNG with a world file for GIS")
+                    .clicked()
+                {
+                    self.export_requested = true;
+                }
+                ui.toggle_value(&mut self.show_goto, "Go to")
+                    .on_hover_text("Fly to typed or pasted coordinates");
+                ui.separator();
+                ui.selectable_value(&mut self.pointer_mode, PointerMode::Draw, "Draw")
+                    .on_hover_text("Click to place pixels");
+                ui.selectable_value(&mut self.pointer_mode, PointerMode::Pan, "Pan")
+                    .on_hover_text("Clicks only move the map");
+                // Pixel colors are sRGB, as the picker shows them
+                let mut srgba = color_to_srgba(self.selected_color);
+                if ui.color_edit_button_srgba_unmultiplied(&mut srgba).changed() {
+                    self.selected_color = srgba_to_color(srgba);
+                }
+                let mut round = self.map_system.pixel_grid.shape() == PixelShape::Circle;
+                if ui.checkbox(&mut round, "Dots").changed() {
+                    let shape = if round { PixelShape::Circle } else { PixelShape::Square };
+                    self.map_system.pixel_grid.set_shape(shape);
+                }
+                let mut smooth = self.map_system.pixel_grid.edge_antialiasing();
+                if ui
+                    .checkbox(&mut smooth, "Smooth")
+                    .on_hover_text("Antialias cell edges without multisampling")
+                    .changed()
+                {
+                    self.map_system.pixel_grid.set_edge_antialiasing(smooth);
+                }
+                ui.menu_button("Grid", |ui| {
+                    let grid = &mut self.map_system.pixel_grid;
+                    let mut shown = grid.outline().is_some();
+                    let mut outline = grid.outline().unwrap_or_default();
+                    ui.checkbox(&mut shown, "Outline cells")
+                        .on_hover_text("Drawn once cells are a few pixels wide");
+                    ui.add_enabled_ui(shown, |ui| {
+                        let mut srgba = color_to_srgba(outline.color);
+                        if ui.color_edit_button_srgba_unmultiplied(&mut srgba).changed() {
+                            outline.color = srgba_to_color(srgba);
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut outline.width)
+                                .range(0.5..=8.0)
+                                .speed(0.1)
+                                .suffix(" px"),
+                        );
+                    });
+                    grid.set_outline(shown.then_some(outline));
+                    ui.separator();
+                    let has_guides = !grid.guides().is_empty();
+                    if ui
+                        .add_enabled(has_guides, egui::Button::new("Clear guides"))
+                        .on_hover_text("Drag from the rulers at the map's edges to add guides")
+                        .clicked()
+                    {
+                        grid.clear_guides();
+                    }
+                });
+                if remaining.is_zero() {
+                    ui.label("Ready to place");
+                } else {
+                    let seconds = remaining.as_secs_f64().ceil() as u64;
+                    ui.label(format!("Next pixel in {}s", seconds));
+                    // Repaint when the displayed seconds tick over
+                    let tick = remaining.saturating_sub(Duration::from_secs(seconds - 1));
+                    ctx.request_repaint_after(tick);
+                }
+                let mut cooldown_secs = self.placement_cooldown.duration().as_secs();
+                ui.add(
+                    egui::DragValue::new(&mut cooldown_secs)
+                        .range(0..=600)
+                        .prefix("Cooldown: ")
+                        .suffix("s"),
+                );
+                self.placement_cooldown
+                    .set_duration(Duration::from_secs(cooldown_secs));
             });
+
+            if let Some(selection) = self.selection {
+                ui.horizontal(|ui| {
+                    let (w, h) = selection.size();
+                    ui.label(format!("Selection: {}×{}", w, h));
+                    if ui.button("Fill").clicked() {
+                        self.fill_selection();
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.clear_selection();
+                    }
+                    if ui.button("Copy").clicked() {
+                        self.copy_selection();
+                    }
+                    let can_paste = !self.clipboard.is_empty();
+                    if ui.add_enabled(can_paste, egui::Button::new("Paste")).clicked() {
+                        self.paste_at(selection.bounds().0);
+                    }
+                    if ui.button("Deselect").clicked() {
+                        self.set_selection(None);
+                    }
+                });
+            }
         });
+
+        if let Some((lon, lat)) = self.address_point {
+            let address = self.geocoder.reverse(lon, lat, Instant::now());
+            let mut open = true;
+            egui::Window::new("Address")
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!("{:.5}, {:.5}", lat, lon));
+                    match address {
+                        Address::Pending => {
+                            ui.spinner();
+                        }
+                        Address::Found(label) => {
+                            ui.label(label);
+                        }
+                        Address::NotFound => {
+                            ui.label("No address here");
+                        }
+                        Address::Failed(e) => {
+                            let message = format!("Lookup failed: {}", e);
+                            ui.colored_label(egui::Color32::ORANGE, message);
+                        }
+                    }
+                    ui.small("Nominatim, © OpenStreetMap contributors");
+                });
+            if !open {
+                self.address_point = None;
+            }
+        }
+
+        let mut destination = None;
+        egui::Window::new("Go to")
+            .open(&mut self.show_goto)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let goto = &mut self.goto;
+                let hint = if goto.lon_first { "126.978, 37.5665" } else { "37.5665, 126.978" };
+                let field = ui.add(egui::TextEdit::singleline(&mut goto.text).hint_text(hint));
+                let entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut goto.lon_first, "Longitude first")
+                        .on_hover_text("N/S/E/W letters override the order");
+                    if ui.button("Go").clicked() || entered {
+                        destination = goto.submit();
+                    }
+                });
+                if let Some(e) = &goto.error {
+                    ui.colored_label(egui::Color32::ORANGE, e.to_string());
+                }
+            });
+        if let Some(center) = destination {
+            self.map_system.clear_commands();
+            self.map_system.queue_command(MapCommand::FlyTo {
+                center,
+                zoom: self.map_system.zoom_level(),
+                duration: GOTO_FLIGHT,
+            });
+        }
+
+        if let Some(attribution) = self.map_system.attribution() {
+            egui::Area::new(egui::Id::new("attribution"))
+                .anchor(egui::Align2::RIGHT_BOTTOM, [-4.0, -4.0])
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.small(attribution);
+                    });
+                });
+        }
+
+        // Rulers fill the edges of what the panels leave of the window
+        let map_area = ctx.available_rect();
+        self.rulers = Rulers::new(map_area, ctx.pixels_per_point());
+        draw_rulers(&ctx.layer_painter(egui::LayerId::background()), map_area);
+
+        // Aim pixels with a crosshair; egui applies it to the window
+        let ruler = self.guide_drag.map(|(ruler, _)| ruler).or(self.ruler_under_cursor());
+        if self.cursor_inside && !ctx.is_pointer_over_area() {
+            match ruler {
+                Some(Ruler::Left) => ctx.set_cursor_icon(egui::CursorIcon::ResizeColumn),
+                Some(Ruler::Top) => ctx.set_cursor_icon(egui::CursorIcon::ResizeRow),
+                None if self.pointer_mode == PointerMode::Draw => {
+                    ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+                }
+                None => {}
+            }
+        }
+
+        if self.show_tile_bounds {
+            let painter = ctx.layer_painter(egui::LayerId::background());
+            debug::draw_tile_bounds(&painter, &self.map_system.camera, ctx.pixels_per_point());
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut readback = self.readback.is_some();
+        #[cfg(not(target_arch = "wasm32"))]
+        let clicked_color = self.clicked_color;
+
+        let mut location_in_title = self.location_title.is_some();
+        let mut settle_zoom = self.map_system.zoom_settle().is_some();
+        let info = &self.adapter_info;
+        let load_times = self.map_system.load_time_stats();
+        let frame_stats = self.map_system.frame_stats();
+        let mut count_frames = frame_stats.is_some();
+        egui::Window::new("Diagnostics")
+            .open(&mut self.show_diagnostics)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("adapter_info").show(ui, |ui| {
+                    ui.label("Backend");
+                    ui.label(format!("{:?}", info.backend));
+                    ui.end_row();
+                    ui.label("Adapter");
+                    ui.label(&info.name);
+                    ui.end_row();
+                    ui.label("Device type");
+                    ui.label(format!("{:?}", info.device_type));
+                    ui.end_row();
+                    ui.label("Driver");
+                    ui.label(format!("{} {}", info.driver, info.driver_info));
+                    ui.end_row();
+                    ui.label("Vendor / device");
+                    ui.label(format!("{:#06x} / {:#06x}", info.vendor, info.device));
+                    ui.end_row();
+                    ui.label("Surface format");
+                    ui.label(format!("{:?}", self.config.format));
+                    ui.end_row();
+                    ui.label("Antialiasing");
+                    ui.label(format!("{}x MSAA", self.msaa_samples));
+                    ui.end_row();
+                    ui.checkbox(&mut self.show_tile_bounds, "Tile bounds (F3)");
+                    ui.checkbox(&mut location_in_title, "Location in title");
+                    ui.end_row();
+                    ui.checkbox(&mut settle_zoom, "Settle on whole zoom levels")
+                        .on_hover_text("Sharpen tiles shortly after zooming stops");
+                    ui.end_row();
+                    ui.label("Present mode");
+                    ui.label(format!(
+                        "{:?} (supported: {:?})",
+                        self.config.present_mode, self.present_modes
+                    ));
+                    ui.end_row();
+                    ui.label("Tile load time");
+                    match load_times {
+                        Some(stats) => {
+                            let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+                            ui.label(format!(
+                                "{:.0} / {:.0} / {:.0} / {:.0} ms (min / avg / p95 / max of {})",
+                                ms(stats.min),
+                                ms(stats.avg),
+                                ms(stats.p95),
+                                ms(stats.max),
+                                stats.count
+                            ))
+                        }
+                        None => ui.label("No tiles loaded yet"),
+                    };
+                    ui.end_row();
+                    ui.checkbox(&mut count_frames, "Frame stats");
+                    if let Some(stats) = frame_stats {
+                        ui.label(format!(
+                            "{} draws, {} tiles, {} grid / {} overlay vertices, \
+                             {} buffers, {} uploads",
+                            stats.draw_calls,
+                            stats.tiles,
+                            stats.grid_vertices,
+                            stats.overlay_vertices,
+                            stats.buffers_allocated,
+                            stats.tiles_uploaded
+                        ));
+                    }
+                    ui.end_row();
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        ui.checkbox(&mut readback, "Read back frames");
+                        match clicked_color {
+                            Some([r, g, b, a]) => {
+                                let hex = format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a);
+                                ui.label(format!("At last click: {}", hex));
+                            }
+                            None if readback => {
+                                ui.label("Click the map to read a pixel");
+                            }
+                            None => {}
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+
+        self.set_location_in_title(location_in_title);
+        self.map_system.set_frame_stats(count_frames);
+        self.map_system
+            .set_zoom_settle(settle_zoom.then_some(DEFAULT_SETTLE_DELAY));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if readback != self.readback.is_some() {
+            if readback {
+                self.enable_readback();
+            } else {
+                self.readback = None;
+                self.clicked_color = None;
+            }
+        }
+    }
+
+    /// Switch between vsync (FIFO) and low-latency presentation
+    ///
+    /// Unsupported choices fall back to FIFO.
+    pub fn set_vsync(&mut self, vsync: bool) {
+        let preferred: &[wgpu::PresentMode] = if vsync {
+            &surface::VSYNC_MODES
+        } else {
+            &surface::LOW_LATENCY_MODES
+        };
+        let mode = surface::choose_present_mode(&self.present_modes, preferred);
+        if mode != self.config.present_mode {
+            log::info!("Present mode: {:?}", mode);
+            self.config.present_mode = mode;
+            if self.is_surface_configured {
+                self.surface.configure(&self.device, &self.config);
+            }
+        }
+    }
+
+    /// Reconfigure or recreate the surface after a failed frame acquisition
+    fn recover_surface(&mut self, error: SurfaceError) {
+        let recovery = self
+            .acquire_backoff
+            .record_failure(Instant::now());
+        log::warn!(
+            "Failed to acquire frame ({:?}, attempt {}), {:?}",
+            error,
+            self.acquire_backoff.failures(),
+            recovery
+        );
+
+        if recovery == Recovery::Recreate {
+            match self.instance.create_surface(self.window.clone()) {
+                Ok(surface) => self.surface = surface,
+                Err(e) => {
+                    log::error!("Failed to recreate surface: {}", e);
+                    return;
+                }
+            }
+        }
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    /// Get the next frame, reconfiguring right away once if the surface is
+    /// outdated or lost (e.g. after a resize)
+    ///
+    /// Only a second failure goes through `recover_surface` and its backoff.
+    fn acquire_frame(&mut self) -> Result<wgpu::SurfaceTexture, SurfaceError> {
+        match self.surface.get_current_texture() {
+            Err(SurfaceError::Outdated | SurfaceError::Lost) => {
+                self.surface.configure(&self.device, &self.config);
+                self.surface.get_current_texture()
+            }
+            result => result,
+        }
+    }
+
+    /// Request a new device after the old one was lost and rebuild GPU resources
+    ///
+    /// Map state (camera, pixels, overlays) is kept; cached tiles are dropped
+    /// and reloaded.
+    fn recover_device(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let now = Instant::now();
+            if !self.acquire_backoff.ready(now) {
+                return;
+            }
+
+            let (device, queue) =
+                match pollster::block_on(self.adapter.request_device(&device_descriptor())) {
+                    Ok(pair) => pair,
+                    Err(e) => {
+                        self.acquire_backoff.record_failure(now);
+                        log::error!("Failed to recreate lost device: {}", e);
+                        return;
+                    }
+                };
+            log::info!("Recreated lost device");
+
+            self.device_lost.store(false, Ordering::Release);
+            watch_device_lost(&device, &self.device_lost);
+            self.device = device;
+            self.queue = queue;
+
+            // Keep the egui context (window positions, fonts, text fields);
+            // only its textures lived on the old device
+            self.ui_renderer =
+                create_ui_renderer(&self.device, self.config.format, self.msaa_samples);
+            self.reupload_ui_textures();
+
+            self.map_system
+                .recreate_gpu_resources(&self.device, self.config.format);
+            self.surface.configure(&self.device, &self.config);
+            self.msaa_view = create_msaa_view(&self.device, &self.config, self.msaa_samples);
+            if self.readback.is_some() {
+                self.readback = Some(FrameReadback::default());
+            }
+            self.acquire_backoff.record_success();
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            // Cannot block on a new device here; the page has to be reloaded
+            if self.device_lost.swap(false, Ordering::AcqRel) {
+                log::error!("GPU device lost, reload the page to continue");
+            }
+        }
+    }
+
+    /// Upload egui's font atlas to a new UI renderer, and have images load
+    /// again
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reupload_ui_textures(&mut self) {
+        // Fonts are only built by the first pass
+        if self.egui_ctx.cumulative_pass_nr() > 0 {
+            let atlas = self.egui_ctx.fonts(|fonts| fonts.image());
+            let delta = egui::epaint::ImageDelta::full(atlas, egui::TextureOptions::default());
+            self.ui_renderer.update_texture(
+                &self.device,
+                &self.queue,
+                egui::TextureId::default(),
+                &delta,
+            );
+        }
+        self.egui_ctx.forget_all_images();
+    }
+
+    /// Keep a copy of every frame so `read_pixel` can inspect it
+    ///
+    /// Costs a full-frame copy per frame; meant for automated rendering
+    /// tests. Returns false if the surface cannot be copied from.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn enable_readback(&mut self) -> bool {
+        if !self.surface_usages.contains(wgpu::TextureUsages::COPY_SRC) {
+            log::warn!("Surface does not support copies, frame readback unavailable");
+            return false;
+        }
+        self.config.usage |= wgpu::TextureUsages::COPY_SRC;
+        self.surface.configure(&self.device, &self.config);
+        self.readback.get_or_insert_with(FrameReadback::default);
+        true
+    }
+
+    /// RGBA color of the window pixel at (`x`, `y`) in the last rendered frame
+    ///
+    /// Requires `enable_readback` before the frame was rendered; None
+    /// otherwise or if the pixel is outside the window. An `Option` rather
+    /// than a bare `[u8; 4]` so a test can't mistake a missing frame for a
+    /// black pixel. Blocks until the GPU finished the copy.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        self.readback
+            .as_ref()?
+            .read_pixel(&self.device, &self.queue, x, y)
+    }
+
+    /// When the next frame should be drawn, or None to wait for input
+    pub fn next_frame(&self) -> Option<Instant> {
+        self.frame_pacer.next_frame()
+    }
+
+    /// Try again once the acquire backoff allows it
+    fn retry_frame(&mut self, now: Instant) {
+        let at = self.acquire_backoff.retry_at().unwrap_or(now);
+        self.frame_pacer.request_frame(at);
+    }
+
+    /// Ask for follow-up frames while something is still changing
+    fn request_follow_up_frames(&mut self, now: Instant) {
+        if self.map_system.is_zooming() || self.map_system.is_playing_commands() {
+            self.frame_pacer.request_frame(now);
+        }
+        // Downloads and decodes finish on other threads; while throttled the
+        // slower poll below is enough
+        if self.map_system.pending_tiles() > 0 && !self.map_system.is_throttled() {
+            self.frame_pacer.request_frame(now + TILE_POLL_INTERVAL);
+        }
+        if self.map_system.is_highlighting() {
+            self.frame_pacer.request_frame(now + HIGHLIGHT_FRAME_INTERVAL);
+        }
+        if self.map_system.is_throttled() {
+            // Requests resume by themselves only if something redraws
+            self.frame_pacer.request_frame(now + THROTTLE_POLL_INTERVAL);
+        }
+        if self.map_system.is_sync_connected() {
+            self.frame_pacer.request_frame(now + SYNC_POLL_INTERVAL);
+        }
+        // Region tiles are handed out a batch per update
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.map_system.is_downloading_region() {
+            self.frame_pacer.request_frame(now + THROTTLE_POLL_INTERVAL);
+        }
+        if self.geocoder.is_busy() {
+            self.frame_pacer.request_frame(now + GEOCODER_POLL_INTERVAL);
+        }
+        if let Some(at) = self.map_system.settle_due_at() {
+            self.frame_pacer.request_frame(at);
+        }
+        // Show where the map came to rest
+        if let Some(at) = self.location_title.as_ref().and_then(LocationTitle::next_update) {
+            self.frame_pacer.request_frame(at);
+        }
+        if let Some(at) = now.checked_add(self.egui_repaint_delay) {
+            self.frame_pacer.request_frame(at);
+        }
     }
 
     pub fn render(&mut self) -> Result<(), SurfaceError> {
-        self.window.request_redraw();
+        let now = Instant::now();
+        self.frame_pacer.frame_drawn(now);
 
+        // The first resize requests a frame
         if !self.is_surface_configured {
             return Ok(());
         }
@@ -262,11 +1268,32 @@ impl State {
             self.apply_size(width, height)
         }
 
-        let frame = match self.surface.get_current_texture() {
-            Ok(frame) => frame,
-            Err(_) => {
-                self.surface.configure(&self.device, &self.config);
-                self.surface.get_current_texture()?
+        if self.device_lost.load(Ordering::Acquire) {
+            self.recover_device();
+            self.retry_frame(now);
+            return Ok(());
+        }
+
+        if !self.acquire_backoff.ready(now) {
+            self.retry_frame(now);
+            return Ok(());
+        }
+
+        let frame = match self.acquire_frame() {
+            Ok(frame) => {
+                self.acquire_backoff.record_success();
+                frame
+            }
+            // Transient; try again next frame
+            Err(SurfaceError::Timeout) => {
+                self.retry_frame(now);
+                return Ok(());
+            }
+            Err(SurfaceError::OutOfMemory) => return Err(SurfaceError::OutOfMemory),
+            Err(e) => {
+                self.recover_surface(e);
+                self.retry_frame(now);
+                return Ok(());
             }
         };
 
@@ -278,6 +1305,13 @@ impl State {
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
+        // With MSAA, draw into the multisampled target and resolve into the frame
+        let msaa_view = self.msaa_view.clone();
+        let (target, resolve_target, store) = match &msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&view), wgpu::StoreOp::Discard),
+            None => (&view, None, wgpu::StoreOp::Store),
+        };
+
         // Render map tiles first
         {
             let output = self.draw_egui();
@@ -286,8 +1320,11 @@ impl State {
                 textures_delta,
                 shapes,
                 pixels_per_point,
-                .. // viewport is ignored
+                viewport_output,
             } = output;
+            self.egui_repaint_delay = viewport_output
+                .get(&ViewportId::ROOT)
+                .map_or(Duration::MAX, |viewport| viewport.repaint_delay);
 
             self.egui_state
                 .handle_platform_output(self.window.as_ref(), platform_output);
@@ -312,16 +1349,11 @@ impl State {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: target,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.8,
-                            g: 0.85,
-                            b: 0.9,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
+                        load: wgpu::LoadOp::Clear(CLEAR_COLOR),
+                        store,
                     },
                     depth_slice: None,
                 })],
@@ -330,7 +1362,7 @@ impl State {
                 timestamp_writes: None,
             });
 
-            self.map_system.render(&mut render_pass, &self.device);
+            self.map_system.render(&mut render_pass);
             let mut render_pass = render_pass.forget_lifetime();
             if self.draw_egui {
                 self.ui_renderer
@@ -341,9 +1373,188 @@ impl State {
             }
         }
 
+        if let Some(readback) = &mut self.readback {
+            readback.capture(&self.device, &mut encoder, &frame.texture);
+        }
+
         self.queue.submit(std::iter::once(encoder.finish()));
         frame.present();
+        self.request_follow_up_frames(now);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if std::mem::take(&mut self.export_requested) {
+            let path = export_path();
+            match self.export_georeferenced(&path) {
+                Ok(()) => log::info!("Exported the view to {}", path.display()),
+                Err(e) => log::error!("Failed to export the view: {}", e),
+            }
+        }
 
         Ok(())
     }
+
+    /// Save the map as shown, without the UI, as an image at `path` with a
+    /// world file next to it (see `map::worldfile`)
+    ///
+    /// The map is drawn again into a texture the size of the window, so
+    /// frame readback doesn't need to be enabled. The format follows the
+    /// extension of `path`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_georeferenced(&self, path: &Path) -> anyhow::Result<()> {
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Export Texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let (target, resolve_target, store) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&view), wgpu::StoreOp::Discard),
+            None => (&view, None, wgpu::StoreOp::Store),
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Export Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Export Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(CLEAR_COLOR),
+                        store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.map_system.render(&mut render_pass);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let image = readback::read_image(&self.device, &self.queue, &texture)
+            .ok_or_else(|| anyhow::anyhow!("Cannot read {:?} frames", self.config.format))?;
+        image.save(path)?;
+        let world_file = WorldFile::for_view(&self.map_system.camera);
+        std::fs::write(WorldFile::path_for(path), world_file.to_string())?;
+        Ok(())
+    }
+}
+
+/// File name for an export from the toolbar, unique per second
+#[cfg(not(target_arch = "wasm32"))]
+fn export_path() -> PathBuf {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    PathBuf::from(format!("cplace-{}.png", seconds))
+}
+
+/// Device settings used at startup and when recovering from device loss
+fn device_descriptor() -> wgpu::DeviceDescriptor<'static> {
+    wgpu::DeviceDescriptor {
+        label: Some("Main Device"),
+        required_features: Features::empty(),
+        required_limits: if cfg!(target_arch = "wasm32") {
+            wgpu::Limits::downlevel_webgl2_defaults()
+        } else {
+            wgpu::Limits::default()
+        },
+        experimental_features: ExperimentalFeatures::disabled(),
+        memory_hints: MemoryHints::Performance,
+        trace: Trace::Off,
+    }
+}
+
+/// Raise `flag` when the device is lost (driver reset, GPU switch, sleep)
+fn watch_device_lost(device: &wgpu::Device, flag: &Arc<AtomicBool>) {
+    let flag = flag.clone();
+    device.set_device_lost_callback(move |reason, message| {
+        // Dropping the device on purpose also reports a loss
+        if reason != wgpu::DeviceLostReason::Destroyed {
+            log::error!("GPU device lost ({:?}): {}", reason, message);
+            flag.store(true, Ordering::Release);
+        }
+    });
+}
+
+/// Multisampled color target matching the surface, None without MSAA
+fn create_msaa_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    samples: u32,
+) -> Option<wgpu::TextureView> {
+    if samples <= 1 {
+        return None;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Color Target"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: samples,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+/// Create the egui renderer for a device
+fn create_ui_renderer(
+    device: &wgpu::Device,
+    texture_format: wgpu::TextureFormat,
+    msaa_samples: u32,
+) -> Renderer {
+    Renderer::new(
+        device,
+        texture_format,
+        RendererOptions {
+            msaa_samples,
+            depth_stencil_format: None,
+            dithering: false,
+            predictable_texture_filtering: false,
+        },
+    )
+}
+
+/// Create the egui renderer, context and winit integration
+fn create_ui(
+    window: &Window,
+    device: &wgpu::Device,
+    texture_format: wgpu::TextureFormat,
+    msaa_samples: u32,
+) -> (Renderer, Context, egui_winit::State) {
+    let ui_renderer = create_ui_renderer(device, texture_format, msaa_samples);
+    let egui_ctx = Context::default();
+
+    let egui_state = egui_winit::State::new(
+        egui_ctx.clone(),
+        egui_ctx.viewport_id(),
+        window,
+        egui_ctx.native_pixels_per_point(),
+        window.theme(),
+        None,
+    );
+
+    (ui_renderer, egui_ctx, egui_state)
 }
@@ -0,0 +1,96 @@
+//! Redraw scheduling: frames are only drawn when something asked for one
+
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Tracks when the next frame is wanted and enforces the frame rate cap
+#[derive(Debug, Default)]
+pub struct FramePacer {
+    /// Frames per second limit (None = unlimited)
+    max_fps: Option<u32>,
+    last_frame: Option<Instant>,
+    /// Earliest time a frame was asked for since the last one was drawn
+    wanted: Option<Instant>,
+}
+
+impl FramePacer {
+    pub fn new(max_fps: Option<u32>) -> Self {
+        Self {
+            max_fps,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_fps(&self) -> Option<u32> {
+        self.max_fps
+    }
+
+    /// Limit the frame rate (None or 0 = unlimited)
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.max_fps = max_fps.filter(|&fps| fps > 0);
+    }
+
+    /// Ask for a frame no later than `at` (earlier requests win)
+    pub fn request_frame(&mut self, at: Instant) {
+        self.wanted = Some(self.wanted.map_or(at, |wanted| wanted.min(at)));
+    }
+
+    /// Record that a frame was drawn, clearing outstanding requests
+    pub fn frame_drawn(&mut self, now: Instant) {
+        self.last_frame = Some(now);
+        self.wanted = None;
+    }
+
+    /// When the next frame should be drawn, or None to wait for input
+    ///
+    /// Requests are pushed back so frames are at least `1 / max_fps` apart.
+    pub fn next_frame(&self) -> Option<Instant> {
+        let wanted = self.wanted?;
+        let earliest = self
+            .max_fps
+            .zip(self.last_frame)
+            .map(|(fps, last)| last + Duration::from_secs(1) / fps);
+        Some(earliest.map_or(wanted, |earliest| wanted.max(earliest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_until_requested() {
+        let mut pacer = FramePacer::new(None);
+        let now = Instant::now();
+        assert_eq!(pacer.next_frame(), None);
+
+        pacer.request_frame(now + Duration::from_secs(1));
+        pacer.request_frame(now);
+        assert_eq!(pacer.next_frame(), Some(now));
+
+        pacer.frame_drawn(now);
+        assert_eq!(pacer.next_frame(), None);
+    }
+
+    #[test]
+    fn test_max_fps_spaces_frames() {
+        let mut pacer = FramePacer::new(Some(10));
+        let now = Instant::now();
+        pacer.frame_drawn(now);
+
+        pacer.request_frame(now);
+        assert_eq!(pacer.next_frame(), Some(now + Duration::from_millis(100)));
+
+        // Requests further out than the cap are kept as is
+        pacer.frame_drawn(now);
+        pacer.request_frame(now + Duration::from_secs(1));
+        assert_eq!(pacer.next_frame(), Some(now + Duration::from_secs(1)));
+
+        pacer.set_max_fps(Some(0));
+        assert_eq!(pacer.max_fps(), None);
+        pacer.frame_drawn(now);
+        pacer.request_frame(now);
+        assert_eq!(pacer.next_frame(), Some(now));
+    }
+}
@@ -0,0 +1,215 @@
+//! Reading back rendered pixels, for automated rendering tests and exports
+
+use wgpu::TextureFormat;
+
+/// Keeps a copy of the last presented frame so pixels can be read after it
+/// was handed to the compositor
+#[derive(Debug, Default)]
+pub struct FrameReadback {
+    frame: Option<wgpu::Texture>,
+}
+
+impl FrameReadback {
+    /// Record a copy of `frame` into `encoder`, resizing the copy as needed
+    ///
+    /// `frame` needs `COPY_SRC` usage.
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::Texture,
+    ) {
+        let size = frame.size();
+        let reusable = self
+            .frame
+            .as_ref()
+            .filter(|copy| copy.size() == size && copy.format() == frame.format());
+        let copy = match reusable {
+            Some(copy) => copy.clone(),
+            None => {
+                let copy = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Frame Readback Texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: frame.format(),
+                    usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                });
+                self.frame = Some(copy.clone());
+                copy
+            }
+        };
+        encoder.copy_texture_to_texture(
+            frame.as_image_copy(),
+            copy.as_image_copy(),
+            size,
+        );
+    }
+
+    /// Color of the pixel at (`x`, `y`) of the last captured frame as RGBA
+    ///
+    /// Blocks until the GPU finished the copy. None if no frame was captured
+    /// yet, the pixel is outside it, or the format isn't 8-bit RGBA/BGRA.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_pixel(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        x: u32,
+        y: u32,
+    ) -> Option<[u8; 4]> {
+        let frame = self.frame.as_ref()?;
+        if x >= frame.width() || y >= frame.height() || !is_readable(frame.format()) {
+            return None;
+        }
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pixel Readback Buffer"),
+            size: 4,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pixel Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: frame,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = tx.send(result);
+            });
+        if let Err(e) = device.poll(wgpu::PollType::wait_indefinitely()) {
+            log::error!("Failed to wait for pixel readback: {}", e);
+            return None;
+        }
+        rx.recv().ok()?.ok()?;
+
+        let texel: [u8; 4] = buffer.slice(..).get_mapped_range()[..4].try_into().ok()?;
+        Some(to_rgba(frame.format(), texel))
+    }
+}
+
+/// Copy a whole texture to an RGBA image
+///
+/// Blocks until the GPU finished the copy. `texture` needs `COPY_SRC` usage;
+/// None if its format isn't 8-bit RGBA/BGRA or mapping failed.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Option<image::RgbaImage> {
+    let (width, height) = (texture.width(), texture.height());
+    if !is_readable(texture.format()) {
+        return None;
+    }
+
+    // Buffer rows are padded to the copy alignment
+    let row_bytes = 4 * width;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Image Readback Buffer"),
+        size: padded_row_bytes as u64 * height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Image Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+    if let Err(e) = device.poll(wgpu::PollType::wait_indefinitely()) {
+        log::error!("Failed to wait for image readback: {}", e);
+        return None;
+    }
+    rx.recv().ok()?.ok()?;
+
+    let mapped = buffer.slice(..).get_mapped_range();
+    let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+    for row in mapped.chunks_exact(padded_row_bytes as usize) {
+        for texel in row[..row_bytes as usize].chunks_exact(4) {
+            let texel: [u8; 4] = texel.try_into().ok()?;
+            pixels.extend_from_slice(&to_rgba(texture.format(), texel));
+        }
+    }
+    image::RgbaImage::from_raw(width, height, pixels)
+}
+
+/// Check if `read_pixel` understands texels of `format`
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn is_readable(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::Rgba8Unorm
+            | TextureFormat::Rgba8UnormSrgb
+            | TextureFormat::Bgra8Unorm
+            | TextureFormat::Bgra8UnormSrgb
+    )
+}
+
+/// Reorder an 8-bit texel of `format` to RGBA
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn to_rgba(format: TextureFormat, [a, b, c, d]: [u8; 4]) -> [u8; 4] {
+    match format {
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => [c, b, a, d],
+        _ => [a, b, c, d],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rgba() {
+        let texel = [10, 20, 30, 255];
+        assert_eq!(to_rgba(TextureFormat::Rgba8Unorm, texel), texel);
+        assert_eq!(to_rgba(TextureFormat::Bgra8UnormSrgb, texel), [30, 20, 10, 255]);
+        assert!(!is_readable(TextureFormat::Rgba16Float));
+    }
+}
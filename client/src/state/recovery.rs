@@ -0,0 +1,98 @@
+//! Backoff for recovering from surface acquisition failures
+
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Consecutive failures after which the surface is recreated from scratch
+const RECREATE_AFTER: u32 = 3;
+
+/// Delay after the first failure, doubled per further failure
+const BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// Upper bound on the delay between attempts
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// What to do after a failed frame acquisition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// Reconfigure the existing surface
+    Reconfigure,
+    /// Drop the surface and create a new one for the window
+    Recreate,
+}
+
+/// Tracks consecutive acquisition failures and spaces out retries
+#[derive(Debug, Default)]
+pub struct AcquireBackoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl AcquireBackoff {
+    /// Check if enough time passed since the last failure to try again
+    pub fn ready(&self, now: Instant) -> bool {
+        self.retry_at.is_none_or(|at| now >= at)
+    }
+
+    /// When the next attempt is allowed, if waiting after a failure
+    pub fn retry_at(&self) -> Option<Instant> {
+        self.retry_at
+    }
+
+    /// Record a failure, returning how to recover
+    pub fn record_failure(&mut self, now: Instant) -> Recovery {
+        self.failures = self.failures.saturating_add(1);
+        let delay = BASE_DELAY
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(MAX_DELAY);
+        self.retry_at = Some(now + delay);
+
+        if self.failures.is_multiple_of(RECREATE_AFTER) {
+            Recovery::Recreate
+        } else {
+            Recovery::Reconfigure
+        }
+    }
+
+    /// Record a successful acquisition
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+
+    /// Consecutive failures so far
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_recreates() {
+        let mut backoff = AcquireBackoff::default();
+        let now = Instant::now();
+        assert!(backoff.ready(now));
+
+        assert_eq!(backoff.record_failure(now), Recovery::Reconfigure);
+        assert!(!backoff.ready(now));
+        assert!(backoff.ready(now + BASE_DELAY));
+
+        assert_eq!(backoff.record_failure(now), Recovery::Reconfigure);
+        assert!(!backoff.ready(now + BASE_DELAY));
+        assert_eq!(backoff.record_failure(now), Recovery::Recreate);
+
+        // Delay is capped
+        for _ in 0..40 {
+            backoff.record_failure(now);
+        }
+        assert!(backoff.ready(now + MAX_DELAY));
+
+        backoff.record_success();
+        assert_eq!(backoff.failures(), 0);
+        assert!(backoff.ready(now));
+    }
+}
@@ -0,0 +1,75 @@
+//! Rulers along the map's left and top edges, dragged from to add guides
+
+use egui::{Color32, Painter, Rect, vec2};
+
+/// Thickness of the rulers in egui points
+const RULER_POINTS: f32 = 10.0;
+
+/// Fill of the ruler strips
+const RULER_COLOR: Color32 = Color32::from_rgba_premultiplied(24, 24, 24, 120);
+
+/// Edge of the map a ruler runs along
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ruler {
+    /// Dragging from it adds a vertical guide
+    Left,
+    /// Dragging from it adds a horizontal guide
+    Top,
+}
+
+/// Where the rulers are, in physical pixels like the cursor position
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rulers {
+    left: f32,
+    top: f32,
+    thickness: f32,
+}
+
+impl Rulers {
+    /// Rulers along the edges of the map area left by egui's panels
+    pub fn new(map_area: Rect, pixels_per_point: f32) -> Self {
+        Self {
+            left: map_area.left() * pixels_per_point,
+            top: map_area.top() * pixels_per_point,
+            thickness: RULER_POINTS * pixels_per_point,
+        }
+    }
+
+    /// Ruler under a cursor position (the left one where they overlap)
+    pub fn at(&self, x: f32, y: f32) -> Option<Ruler> {
+        if x < self.left || y < self.top {
+            None
+        } else if x < self.left + self.thickness {
+            Some(Ruler::Left)
+        } else if y < self.top + self.thickness {
+            Some(Ruler::Top)
+        } else {
+            None
+        }
+    }
+}
+
+/// Paint the ruler strips along the left and top of `map_area`
+pub fn draw_rulers(painter: &Painter, map_area: Rect) {
+    let left = Rect::from_min_size(map_area.min, vec2(RULER_POINTS, map_area.height()));
+    let top = Rect::from_min_size(map_area.min, vec2(map_area.width(), RULER_POINTS));
+    painter.rect_filled(left, 0.0, RULER_COLOR);
+    painter.rect_filled(top, 0.0, RULER_COLOR);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruler_hits_in_physical_pixels() {
+        // Below a 30 point toolbar at 2 physical pixels per point
+        let area = Rect::from_min_max(egui::pos2(0.0, 30.0), egui::pos2(400.0, 300.0));
+        let rulers = Rulers::new(area, 2.0);
+        assert_eq!(rulers.at(5.0, 200.0), Some(Ruler::Left));
+        assert_eq!(rulers.at(300.0, 70.0), Some(Ruler::Top));
+        assert_eq!(rulers.at(10.0, 65.0), Some(Ruler::Left));
+        assert_eq!(rulers.at(300.0, 50.0), None); // On the toolbar
+        assert_eq!(rulers.at(300.0, 90.0), None);
+    }
+}
@@ -0,0 +1,60 @@
+//! Rectangular cell selection
+
+use crate::map::grid::GridCoord;
+
+/// Selection dragged from an anchor cell to the cell under the cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection {
+    pub anchor: GridCoord,
+    pub corner: GridCoord,
+}
+
+impl Selection {
+    /// Single-cell selection
+    pub fn new(anchor: GridCoord) -> Self {
+        Self {
+            anchor,
+            corner: anchor,
+        }
+    }
+
+    /// Inclusive (min, max) corners regardless of drag direction
+    pub fn bounds(&self) -> (GridCoord, GridCoord) {
+        (
+            GridCoord::new(
+                self.anchor.x.min(self.corner.x),
+                self.anchor.y.min(self.corner.y),
+            ),
+            GridCoord::new(
+                self.anchor.x.max(self.corner.x),
+                self.anchor.y.max(self.corner.y),
+            ),
+        )
+    }
+
+    /// Size in cells (width, height)
+    pub fn size(&self) -> (u64, u64) {
+        (
+            self.anchor.x.abs_diff(self.corner.x) + 1,
+            self.anchor.y.abs_diff(self.corner.y) + 1,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds_in_any_drag_direction() {
+        let mut selection = Selection::new(GridCoord::new(5, -2));
+        assert_eq!(selection.size(), (1, 1));
+
+        selection.corner = GridCoord::new(-3, 4);
+        assert_eq!(
+            selection.bounds(),
+            (GridCoord::new(-3, -2), GridCoord::new(5, 4))
+        );
+        assert_eq!(selection.size(), (9, 7));
+    }
+}
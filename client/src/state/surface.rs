@@ -0,0 +1,182 @@
+//! Surface format, present mode and MSAA sample count selection
+
+use wgpu::{PresentMode, SurfaceCapabilities, TextureFormat, TextureFormatFeatureFlags};
+
+/// Present modes for vsync (battery friendly)
+pub const VSYNC_MODES: [PresentMode; 1] = [PresentMode::Fifo];
+
+/// Present modes for low latency, in order of preference
+pub const LOW_LATENCY_MODES: [PresentMode; 2] = [PresentMode::Mailbox, PresentMode::Immediate];
+
+/// Formats that pass colors through unchanged, in order of preference
+///
+/// egui and the vertex colors of the grid and overlays are already
+/// sRGB-encoded, so a non-sRGB target shows them as authored. Tile textures
+/// are then uploaded as plain `Rgba8Unorm` to match (see
+/// `renderer::tile_texture_format`).
+const PREFERRED_FORMATS: [TextureFormat; 2] =
+    [TextureFormat::Rgba8Unorm, TextureFormat::Bgra8Unorm];
+
+/// sRGB fallbacks; the map pipelines linearize their colors for these
+const SRGB_FORMATS: [TextureFormat; 2] =
+    [TextureFormat::Rgba8UnormSrgb, TextureFormat::Bgra8UnormSrgb];
+
+/// Pick the surface format, or None if the surface supports no formats
+///
+/// Prefers 8-bit non-sRGB formats, then their sRGB variants, then whatever
+/// the surface lists first. Fallbacks are logged since they can shift colors
+/// (egui in particular assumes a non-sRGB target).
+pub fn choose_surface_format(caps: &SurfaceCapabilities) -> Option<TextureFormat> {
+    let find = |candidates: &[TextureFormat]| {
+        caps.formats
+            .iter()
+            .find(|format| candidates.contains(format))
+            .copied()
+    };
+
+    if let Some(format) = find(&PREFERRED_FORMATS) {
+        return Some(format);
+    }
+
+    if let Some(format) = find(&SRGB_FORMATS) {
+        log::warn!(
+            "No non-sRGB surface format available, using {:?}; UI colors may look washed out",
+            format
+        );
+        return Some(format);
+    }
+
+    let format = caps.formats.first().copied()?;
+    log::warn!(
+        "No 8-bit RGBA surface format available (have {:?}), using {:?}",
+        caps.formats,
+        format
+    );
+    Some(format)
+}
+
+/// Pick the first of `preferred` the surface supports
+///
+/// Falls back to FIFO, which every surface is required to support, logging
+/// a warning if none of the preferred modes is available.
+pub fn choose_present_mode(available: &[PresentMode], preferred: &[PresentMode]) -> PresentMode {
+    if let Some(mode) = preferred.iter().find(|mode| available.contains(mode)) {
+        return *mode;
+    }
+
+    let fallback = if available.contains(&PresentMode::Fifo) || available.is_empty() {
+        PresentMode::Fifo
+    } else {
+        available[0]
+    };
+    log::warn!(
+        "None of {:?} supported (have {:?}), using {:?}",
+        preferred,
+        available,
+        fallback
+    );
+    fallback
+}
+
+/// Validate the requested MSAA sample count against the format's features
+///
+/// Multisampling needs both the sample count and resolving for the surface
+/// format; anything unsupported falls back to 1 (no MSAA) with a warning.
+pub fn choose_sample_count(requested: u32, features: TextureFormatFeatureFlags) -> u32 {
+    if requested <= 1 {
+        return 1;
+    }
+
+    let resolvable = features.contains(TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+    if resolvable && features.sample_count_supported(requested) {
+        return requested;
+    }
+
+    log::warn!(
+        "{}x MSAA not supported (have {:?}), disabling antialiasing",
+        requested,
+        features.supported_sample_counts()
+    );
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(formats: &[TextureFormat]) -> SurfaceCapabilities {
+        SurfaceCapabilities {
+            formats: formats.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_prefers_non_srgb() {
+        let caps = caps(&[
+            TextureFormat::Bgra8UnormSrgb,
+            TextureFormat::Rgba16Float,
+            TextureFormat::Bgra8Unorm,
+        ]);
+        assert_eq!(
+            choose_surface_format(&caps),
+            Some(TextureFormat::Bgra8Unorm)
+        );
+    }
+
+    #[test]
+    fn test_falls_back_to_srgb_then_first() {
+        let srgb_only = caps(&[TextureFormat::Rgba16Float, TextureFormat::Rgba8UnormSrgb]);
+        assert_eq!(
+            choose_surface_format(&srgb_only),
+            Some(TextureFormat::Rgba8UnormSrgb)
+        );
+
+        let unusual = caps(&[TextureFormat::Rgb10a2Unorm, TextureFormat::Rgba16Float]);
+        assert_eq!(
+            choose_surface_format(&unusual),
+            Some(TextureFormat::Rgb10a2Unorm)
+        );
+
+        assert_eq!(choose_surface_format(&caps(&[])), None);
+    }
+
+    #[test]
+    fn test_present_mode_fallbacks() {
+        let all = [
+            PresentMode::Fifo,
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+        ];
+        assert_eq!(choose_present_mode(&all, &VSYNC_MODES), PresentMode::Fifo);
+        assert_eq!(
+            choose_present_mode(&all, &LOW_LATENCY_MODES),
+            PresentMode::Mailbox
+        );
+
+        let no_mailbox = [PresentMode::Fifo, PresentMode::Immediate];
+        assert_eq!(
+            choose_present_mode(&no_mailbox, &LOW_LATENCY_MODES),
+            PresentMode::Immediate
+        );
+
+        // FIFO-only surfaces (e.g. the web) ignore the low-latency request
+        assert_eq!(
+            choose_present_mode(&[PresentMode::Fifo], &LOW_LATENCY_MODES),
+            PresentMode::Fifo
+        );
+    }
+
+    #[test]
+    fn test_sample_count_fallbacks() {
+        let x4 = TextureFormatFeatureFlags::MULTISAMPLE_X4
+            | TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE;
+        assert_eq!(choose_sample_count(4, x4), 4);
+        assert_eq!(choose_sample_count(8, x4), 1);
+        assert_eq!(choose_sample_count(0, x4), 1);
+
+        // Multisampled but not resolvable
+        let unresolvable = TextureFormatFeatureFlags::MULTISAMPLE_X4;
+        assert_eq!(choose_sample_count(4, unresolvable), 1);
+    }
+}
@@ -0,0 +1,104 @@
+//! Window title showing where the map looks
+
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Shortest time between title changes, unless the zoom level changes
+pub const TITLE_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Title last set
+#[derive(Clone, Debug)]
+struct Shown {
+    title: String,
+    at: Instant,
+    /// Whole zoom level shown
+    level: i32,
+}
+
+/// Follows the map location in the window title, throttled so panning
+/// doesn't rename the window every frame
+#[derive(Clone, Debug)]
+pub struct LocationTitle {
+    /// Title before the location was added, restored when turned off
+    base: String,
+    shown: Option<Shown>,
+    /// The location moved since the title was last set
+    stale: bool,
+}
+
+impl LocationTitle {
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.to_string(),
+            shown: None,
+            stale: false,
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// New title for the map at `center` (lon, lat) and `zoom`, or None to
+    /// keep the current one for now
+    pub fn update(&mut self, center: (f64, f64), zoom: f64, now: Instant) -> Option<String> {
+        let title = format!(
+            "{} — {:.4}, {:.4} @ z{:.1}",
+            self.base, center.1, center.0, zoom
+        );
+        let level = zoom.floor() as i32;
+        self.stale = false;
+        match &self.shown {
+            Some(shown) if shown.title == title => return None,
+            Some(shown)
+                if shown.level == level
+                    && now.saturating_duration_since(shown.at) < TITLE_UPDATE_INTERVAL =>
+            {
+                self.stale = true;
+                return None;
+            }
+            _ => {}
+        }
+        self.shown = Some(Shown {
+            title: title.clone(),
+            at: now,
+            level,
+        });
+        Some(title)
+    }
+
+    /// When a held back location change is due
+    pub fn next_update(&self) -> Option<Instant> {
+        let shown = self.shown.as_ref().filter(|_| self.stale)?;
+        Some(shown.at + TITLE_UPDATE_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_updates_are_throttled() {
+        let mut title = LocationTitle::new("cplace");
+        let start = Instant::now();
+        assert_eq!(
+            title.update((126.978, 37.5665), 12.0, start).as_deref(),
+            Some("cplace — 37.5665, 126.9780 @ z12.0")
+        );
+        assert_eq!(title.update((126.978, 37.5665), 12.0, start), None);
+
+        // Small moves wait for the interval
+        let soon = start + Duration::from_millis(200);
+        assert_eq!(title.update((126.98, 37.5665), 12.4, soon), None);
+        assert_eq!(title.next_update(), Some(start + TITLE_UPDATE_INTERVAL));
+        let later = start + TITLE_UPDATE_INTERVAL;
+        assert!(title.update((126.98, 37.5665), 12.4, later).is_some());
+        assert_eq!(title.next_update(), None);
+
+        // A new zoom level shows at once
+        let next = later + Duration::from_millis(10);
+        assert!(title.update((126.98, 37.5665), 13.0, next).is_some());
+    }
+}